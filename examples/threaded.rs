@@ -0,0 +1,122 @@
+//! Runs emulation on a thread of its own, the way a GUI frontend would, so that the UI thread never
+//! waits on the emulator or the disk. The two threads only share channels:
+//!
+//! - commands flow from the UI to the emulator, which drains them between frames
+//! - finished frames flow back through a bounded channel, so the emulator runs at most
+//!   [`FRAMES_AHEAD`] frames ahead of what the UI has shown
+//!
+//! `cargo run --example threaded [ROM]`
+//!
+//! Without a ROM it runs a small built-in program. The "UI" here just holds Start for a while,
+//! takes a save state and loads it back half a second later, and quits after a few seconds' worth
+//! of frames. The frames it receives lag what it asked for by up to [`FRAMES_AHEAD`].
+
+use std::env;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+
+use nes_emulator::console::Console;
+use nes_emulator::joypad::JoypadButton;
+use nes_emulator::rom::Rom;
+
+/// Frames the emulator may finish before the UI picks them up
+const FRAMES_AHEAD: usize = 2;
+/// Frames the demo UI shows before quitting
+const FRAMES_TO_SHOW: u64 = 180;
+
+/// Requests from the UI thread
+enum Command {
+    SetButtons(JoypadButton),
+    /// Reply with a save state on the given channel
+    SaveState(Sender<Vec<u8>>),
+    LoadState(Vec<u8>),
+    Quit,
+}
+
+/// A finished frame, copied out of the console
+struct FrameReady {
+    number: u64,
+    pixels: Vec<u8>,
+}
+
+/// Owns the console: loads the ROM, then alternates between handling commands and running frames
+/// until told to quit or the UI hangs up
+fn emulate(
+    rom_path: Option<String>,
+    commands: Receiver<Command>,
+    frames: SyncSender<FrameReady>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut console = Console::new();
+    match rom_path {
+        // Reading the ROM happens here, off the UI thread
+        Some(path) => console.load_rom(&Rom::from_path(&path)?)?,
+        // INC $00, JMP $8000
+        None => console.load(&[0xe6, 0x00, 0x4c, 0x00, 0x80]),
+    }
+
+    loop {
+        for command in commands.try_iter() {
+            match command {
+                Command::SetButtons(buttons) => console.joypad_mut(0).set_buttons(buttons),
+                Command::SaveState(reply) => {
+                    let _ = reply.send(console.save_state());
+                }
+                Command::LoadState(state) => console.load_state(&state)?,
+                Command::Quit => return Ok(()),
+            }
+        }
+
+        console.run_frame();
+        let frame = console.frame_ref();
+        let ready = FrameReady {
+            number: frame.number(),
+            pixels: frame.pixels().to_vec(),
+        };
+        // Blocks while the UI is FRAMES_AHEAD behind, and fails once it has gone away
+        if frames.send(ready).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (command_tx, command_rx) = mpsc::channel();
+    let (frame_tx, frame_rx) = mpsc::sync_channel(FRAMES_AHEAD);
+    let rom_path = env::args().nth(1);
+    let emulator = thread::spawn(move || emulate(rom_path, command_rx, frame_tx));
+
+    // The UI loop: a real frontend would draw each frame and forward key events instead
+    let mut saved = None;
+    for (shown, frame) in frame_rx.iter().enumerate() {
+        if shown as u64 >= FRAMES_TO_SHOW {
+            break;
+        }
+        match shown {
+            30 => command_tx.send(Command::SetButtons(JoypadButton::START))?,
+            40 => command_tx.send(Command::SetButtons(JoypadButton::empty()))?,
+            90 => {
+                let (reply_tx, reply_rx) = mpsc::channel();
+                command_tx.send(Command::SaveState(reply_tx))?;
+                saved = reply_rx.recv().ok();
+            }
+            120 => {
+                if let Some(state) = saved.take() {
+                    command_tx.send(Command::LoadState(state))?;
+                }
+            }
+            _ => {}
+        }
+        if shown % 30 == 0 {
+            println!(
+                "showing frame {}: {} bytes of pixels",
+                frame.number,
+                frame.pixels.len()
+            );
+        }
+    }
+
+    command_tx.send(Command::Quit)?;
+    drop(frame_rx);
+    emulator.join().expect("emulator thread panicked")
+}
@@ -0,0 +1,49 @@
+//! Runs arbitrary bytes as a program for a bounded number of instructions.
+//!
+//! The first 6 bytes pick the chip variant and seed A, X, Y, the status and the stack pointer; the
+//! rest is loaded at `0x8000`, where the CPU starts. Fuzz builds keep debug assertions on, so arithmetic overflow
+//! panics as well.
+//!
+//! `cargo +nightly fuzz run cpu`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_emulator::cpu::{CpuVariant, Registers, CPU};
+
+/// Instructions to run before giving up on a program that doesn't stop
+const MAX_STEPS: usize = 10_000;
+const VARIANTS: [CpuVariant; 4] = [
+    CpuVariant::Ricoh2A03,
+    CpuVariant::Ricoh2A07,
+    CpuVariant::Nmos6502,
+    CpuVariant::Cmos65C02,
+];
+/// Room between `0x8000` and the end of the address space
+const MAX_PROGRAM: usize = 0x8000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((registers, program)) = data.split_first_chunk::<6>() else {
+        return;
+    };
+
+    let mut cpu = CPU::new();
+    cpu.load(&program[..program.len().min(MAX_PROGRAM)]);
+    cpu.reset();
+    let [variant, a, x, y, status, sp] = *registers;
+    cpu.set_variant(VARIANTS[variant as usize % VARIANTS.len()]);
+    cpu.set_registers(Registers {
+        a,
+        x,
+        y,
+        status,
+        sp,
+        ..cpu.registers()
+    });
+
+    for _ in 0..MAX_STEPS {
+        if !cpu.step() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,17 @@
+//! Applies arbitrary patches to arbitrary ROMs. The first byte picks how many of the following
+//! bytes are the ROM; the rest are the patch.
+//!
+//! `cargo +nightly fuzz run patch`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_emulator::patch;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&rom_len, rest)) = data.split_first() else {
+        return;
+    };
+    let (rom, patch) = rest.split_at((rom_len as usize).min(rest.len()));
+    let _ = patch::apply(rom, patch);
+});
@@ -0,0 +1,27 @@
+//! Feeds arbitrary bytes to the ROM parser, which handles iNES, NES 2.0 and UNIF images, and runs
+//! whatever parses and loads for a couple of frames.
+//!
+//! `cargo +nightly fuzz run rom`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_emulator::console::Console;
+use nes_emulator::rom::Rom;
+
+/// Frames to run a ROM that loads
+const FRAMES: usize = 2;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(rom) = Rom::new(data) else {
+        return;
+    };
+    rom.hash();
+
+    let mut console = Console::new();
+    if console.load_rom(&rom).is_ok() {
+        for _ in 0..FRAMES {
+            console.run_frame();
+        }
+    }
+});
@@ -0,0 +1,284 @@
+//! Color-blind palettes and flash reduction.
+//!
+//! [`ColorVision`] recolors a palette for players with a color-vision deficiency. It daltonizes
+//! each of the 64 colors: the color is simulated as the player would see it, and the detail lost
+//! is shifted into the channels they can still tell apart, so red and green that look the same
+//! to them come out different. Only the palette changes, so this costs nothing per frame.
+//!
+//! A [`FlashFilter`] limits how far the average brightness of the picture can move from one
+//! frame to the next. Screen flashes, like an explosion or a lightning strike, get spread over
+//! several frames instead of strobing. The filter works on the converted pixels, so it does
+//! nothing for [`PixelFormat::Indexed`] output.
+//!
+//! ```
+//! use nes_emulator::accessibility::{ColorVision, FlashFilter};
+//! use nes_emulator::console::Console;
+//! use nes_emulator::frame::SYSTEM_PALETTE;
+//!
+//! let mut console = Console::new();
+//! console.set_palette(ColorVision::Deuteranopia.apply(&SYSTEM_PALETTE));
+//! console.set_flash_filter(Some(FlashFilter::default()));
+//! ```
+
+use std::fmt;
+
+use crate::frame::{Palette, PixelFormat};
+
+/// A color-vision deficiency to adjust the palette for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorVision {
+    #[default]
+    Normal,
+    /// No red cones
+    Protanopia,
+    /// No green cones, the most common
+    Deuteranopia,
+    /// No blue cones
+    Tritanopia,
+}
+
+#[rustfmt::skip]
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+
+#[rustfmt::skip]
+const LMS_TO_RGB: [[f32; 3]; 3] = [
+    [0.080_944_45, -0.130_504_41, 0.116_721_07],
+    [-0.010_248_534, 0.054_019_33, -0.113_614_71],
+    [-0.000_365_296_94, -0.004_121_614_7, 0.693_511_4],
+];
+
+/// Moves the error of the lost channel into the others: red differences show as green and blue
+#[rustfmt::skip]
+const ERROR_SHIFT: [[f32; 3]; 3] = [
+    [0.0, 0.0, 0.0],
+    [0.7, 1.0, 0.0],
+    [0.7, 0.0, 1.0],
+];
+
+impl ColorVision {
+    pub fn all() -> [ColorVision; 4] {
+        [
+            ColorVision::Normal,
+            ColorVision::Protanopia,
+            ColorVision::Deuteranopia,
+            ColorVision::Tritanopia,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorVision::Normal => "normal",
+            ColorVision::Protanopia => "protanopia",
+            ColorVision::Deuteranopia => "deuteranopia",
+            ColorVision::Tritanopia => "tritanopia",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<ColorVision> {
+        ColorVision::all()
+            .into_iter()
+            .find(|vision| vision.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The one after this in [`ColorVision::all`], wrapping around, for cycling with a hotkey
+    pub fn next(self) -> ColorVision {
+        let all = ColorVision::all();
+        let index = all.iter().position(|&vision| vision == self).unwrap();
+        all[(index + 1) % all.len()]
+    }
+
+    /// `palette` daltonized for this deficiency. [`ColorVision::Normal`] returns it unchanged.
+    pub fn apply(self, palette: &Palette) -> Palette {
+        if self == ColorVision::Normal {
+            return *palette;
+        }
+        let mut colors = palette.0;
+        for color in &mut colors {
+            *color = self.daltonize(*color);
+        }
+        Palette(colors)
+    }
+
+    /// What someone with this deficiency sees of a color, in LMS cone space
+    fn simulate(self, [l, m, s]: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorVision::Normal => [l, m, s],
+            ColorVision::Protanopia => [2.02344 * m - 2.52581 * s, m, s],
+            ColorVision::Deuteranopia => [l, 0.494207 * l + 1.24827 * s, s],
+            ColorVision::Tritanopia => [l, m, -0.395913 * l + 0.801109 * m],
+        }
+    }
+
+    fn daltonize(self, (r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+        let rgb = [r as f32, g as f32, b as f32];
+        let seen = multiply(&LMS_TO_RGB, self.simulate(multiply(&RGB_TO_LMS, rgb)));
+        let error = [rgb[0] - seen[0], rgb[1] - seen[1], rgb[2] - seen[2]];
+        let shift = multiply(&ERROR_SHIFT, error);
+        let channel = |i: usize| (rgb[i] + shift[i]).round().clamp(0.0, 255.0) as u8;
+        (channel(0), channel(1), channel(2))
+    }
+}
+
+impl fmt::Display for ColorVision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Caps the change in average brightness between frames, blending a frame with the one shown
+/// before it when the jump is bigger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashFilter {
+    max_step: u8,
+    /// The last frame shown, after filtering, in the output format
+    previous: Vec<u8>,
+}
+
+impl Default for FlashFilter {
+    fn default() -> Self {
+        Self::new(FlashFilter::DEFAULT_MAX_STEP)
+    }
+}
+
+impl FlashFilter {
+    /// About an eighth of the brightness range per frame, so a black to white flash takes a
+    /// little over 1/8 of a second
+    pub const DEFAULT_MAX_STEP: u8 = 32;
+
+    /// Lets the average brightness, from 0 to 255, move at most `max_step` per frame
+    pub fn new(max_step: u8) -> Self {
+        Self {
+            max_step: max_step.max(1),
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn max_step(&self) -> u8 {
+        self.max_step
+    }
+
+    /// Filters a converted frame in place. The first frame, and the first after the format
+    /// changes, pass through.
+    pub fn apply(&mut self, format: PixelFormat, pixels: &mut [u8]) {
+        if format == PixelFormat::Indexed {
+            return;
+        }
+        if self.previous.len() != pixels.len() {
+            self.previous.clear();
+            self.previous.extend_from_slice(pixels);
+            return;
+        }
+        let before = brightness(format, &self.previous);
+        let after = brightness(format, pixels);
+        let jump = after.abs_diff(before);
+        if jump > self.max_step as u64 {
+            let weight = self.max_step as u64 * 256 / jump;
+            let size = format.bytes_per_pixel();
+            for (pixel, previous) in pixels
+                .chunks_exact_mut(size)
+                .zip(self.previous.chunks_exact(size))
+            {
+                let blend = |new: u8, old: u8| {
+                    (old as i64 + (new as i64 - old as i64) * weight as i64 / 256) as u8
+                };
+                let (r, g, b) = decode(format, pixel);
+                let (pr, pg, pb) = decode(format, previous);
+                encode(format, (blend(r, pr), blend(g, pg), blend(b, pb)), pixel);
+            }
+        }
+        self.previous.copy_from_slice(pixels);
+    }
+}
+
+/// Average luma of a frame, from 0 to 255
+fn brightness(format: PixelFormat, pixels: &[u8]) -> u64 {
+    let size = format.bytes_per_pixel();
+    let total: u64 = pixels
+        .chunks_exact(size)
+        .map(|pixel| {
+            let (r, g, b) = decode(format, pixel);
+            r as u64 * 299 + g as u64 * 587 + b as u64 * 114
+        })
+        .sum();
+    total / 1000 / (pixels.len() / size).max(1) as u64
+}
+
+pub(crate) fn decode(format: PixelFormat, pixel: &[u8]) -> (u8, u8, u8) {
+    match format {
+        PixelFormat::Rgb565 => {
+            let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+            let expand =
+                |bits: u16, width: u32| ((bits << (8 - width)) | (bits >> (2 * width - 8))) as u8;
+            (
+                expand(value >> 11, 5),
+                expand((value >> 5) & 0x3F, 6),
+                expand(value & 0x1F, 5),
+            )
+        }
+        _ => (pixel[0], pixel[1], pixel[2]),
+    }
+}
+
+pub(crate) fn encode(format: PixelFormat, (r, g, b): (u8, u8, u8), pixel: &mut [u8]) {
+    match format {
+        PixelFormat::Rgb565 => {
+            let value = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+            pixel[..2].copy_from_slice(&value.to_le_bytes());
+        }
+        _ => pixel[..3].copy_from_slice(&[r, g, b]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::{HEIGHT, SYSTEM_PALETTE, WIDTH};
+
+    #[test]
+    fn test_color_vision_palettes() {
+        assert_eq!(ColorVision::Normal.apply(&SYSTEM_PALETTE), SYSTEM_PALETTE);
+        // Grays carry no color to lose
+        let palette = ColorVision::Deuteranopia.apply(&SYSTEM_PALETTE);
+        let gray = palette.rgb(0x00);
+        assert!(gray.0.abs_diff(0x80) <= 1 && gray.1.abs_diff(0x80) <= 1);
+        // Pure red gains blue, which a deuteranope can tell from green
+        assert!(palette.rgb(0x16).2 > SYSTEM_PALETTE.rgb(0x16).2);
+        assert_eq!(
+            ColorVision::parse("Tritanopia"),
+            Some(ColorVision::Tritanopia)
+        );
+        assert_eq!(ColorVision::Tritanopia.next(), ColorVision::Normal);
+    }
+
+    #[test]
+    fn test_flash_filter_limits_brightness_jumps() {
+        let mut filter = FlashFilter::new(32);
+        let format = PixelFormat::Rgba8888;
+        let frame = |value: u8| {
+            let mut pixels = vec![value; WIDTH * HEIGHT * 4];
+            pixels.iter_mut().skip(3).step_by(4).for_each(|a| *a = 0xFF);
+            pixels
+        };
+        let mut black = frame(0);
+        filter.apply(format, &mut black);
+        assert_eq!(black, frame(0), "first frame passes through");
+
+        let mut levels = Vec::new();
+        for _ in 0..10 {
+            let mut white = frame(0xFF);
+            filter.apply(format, &mut white);
+            levels.push(brightness(format, &white));
+        }
+        assert!(levels.windows(2).all(|pair| pair[1] - pair[0] <= 32));
+        assert!(levels[0] <= 32);
+        assert_eq!(*levels.last().unwrap(), 0xFF);
+    }
+}
@@ -0,0 +1,1343 @@
+//! The audio processing unit.
+//!
+//! The CPU talks to the [`Apu`] through registers at `0x4000..=0x4017`:
+//!
+//! | Address | Channel | |
+//! | :--- | :--- | :--- |
+//! | `0x4000..=0x4003` | pulse 1 | duty and envelope, sweep, timer low, length and timer high |
+//! | `0x4004..=0x4007` | pulse 2 | the same |
+//! | `0x4008..=0x400B` | triangle | linear counter, unused, timer low, length and timer high |
+//! | `0x400C..=0x400F` | noise | envelope, unused, mode and period, length |
+//! | `0x4010..=0x4013` | DMC | flags and rate, direct load, sample address, sample length |
+//! | `0x4015` | status | writes enable channels; reads report length counters and IRQs |
+//! | `0x4017` | frame counter | 4 or 5 step sequence and IRQ inhibit (reads are controller 2) |
+//!
+//! The channels are mixed with the nonlinear formulas of the real mixer, then averaged down to
+//! [`Apu::sample_rate`] mono samples in `-1.0..=1.0` for frontends to queue. Visualizers can also
+//! ask for each channel's own output with [`Apu::set_channel_taps`]. [`Apu::set_gain`] balances the
+//! APU against cartridge sound chips. [`Apu::track_register_writes`] logs every register write
+//! with its timing, for ripping music (see [`crate::apu_log`]).
+//!
+//! Frame counter and DMC interrupts show up in `0x4015` and hold the CPU's IRQ line until they're
+//! acknowledged.
+
+use std::hash::{Hash, Hasher};
+
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+pub(crate) const NTSC_CPU_HZ: u64 = 1_789_773;
+pub(crate) const PAL_CPU_HZ: u64 = 1_662_607;
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// CPU cycles at which the frame counter clocks the envelopes and length counters: three steps,
+/// then the end of the 4 step and 5 step sequences
+const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+const TRIANGLE_STEPS: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+/// Noise and DMC timer periods in CPU cycles
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+const NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+/// CPU cycles a DMC sample fetch stalls for, ignoring where in the instruction it lands
+const DMC_FETCH_CYCLES: u64 = 4;
+
+const STATUS_DMC_ACTIVE: u8 = 0b0001_0000;
+const STATUS_FRAME_IRQ: u8 = 0b0100_0000;
+const STATUS_DMC_IRQ: u8 = 0b1000_0000;
+
+/// A sound source, for [`ChannelTaps`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    /// Sound from the cartridge. No board emulated so far has any, so it stays silent.
+    Expansion,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+        Channel::Expansion,
+    ];
+
+    /// Highest level the channel's DAC puts out
+    fn max_level(self) -> f32 {
+        match self {
+            Channel::Dmc => 127.0,
+            Channel::Expansion => 1.0,
+            _ => 15.0,
+        }
+    }
+}
+
+/// A write to an APU register, timed in CPU cycles since logging started, as of the start of the
+/// instruction that made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegisterWrite {
+    pub cycle: u64,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// The note a channel is playing, from its timer period and volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    /// Pitch in Hz. For noise, the rate its shift register clocks at, which only makes sense
+    /// relative to other noise tones.
+    pub frequency: f32,
+    /// `1..=15`; the triangle has no volume control and always plays at 15
+    pub volume: u8,
+    /// The timer period the pitch comes from; for noise, the 4 bit period setting
+    pub period: u16,
+}
+
+/// Where mixed audio comes from: the console's own APU or the sound chip on a cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioSource {
+    Apu,
+    /// Konami VRC6: two pulses and a sawtooth
+    Vrc6,
+    /// Konami VRC7: six FM channels
+    Vrc7,
+    /// The Famicom Disk System's wavetable channel
+    Fds,
+    /// Nintendo MMC5: two more pulses and a PCM channel
+    Mmc5,
+    /// Namco 163: up to eight wavetable channels
+    N163,
+    /// Sunsoft 5B: three square wave channels
+    Sunsoft5B,
+}
+
+impl AudioSource {
+    pub const ALL: [AudioSource; 7] = [
+        AudioSource::Apu,
+        AudioSource::Vrc6,
+        AudioSource::Vrc7,
+        AudioSource::Fds,
+        AudioSource::Mmc5,
+        AudioSource::N163,
+        AudioSource::Sunsoft5B,
+    ];
+
+    /// Roughly how loud the source's loudest channel plays on a Famicom, relative to an APU pulse
+    /// at full volume. Boards scale their chip's output by this before the gain is applied, so a
+    /// gain of 1.0 sounds like the real console. Cartridges varied, N163 boards especially.
+    pub fn hardware_level(self) -> f32 {
+        match self {
+            AudioSource::Apu | AudioSource::Vrc6 | AudioSource::Mmc5 => 1.0,
+            AudioSource::Vrc7 => 1.5,
+            AudioSource::Fds => 2.4,
+            AudioSource::N163 => 3.0,
+            AudioSource::Sunsoft5B => 1.7,
+        }
+    }
+}
+
+/// Each channel's output on its own, averaged down to [`ChannelTaps::rate`] samples per second
+/// in `0.0..=1.0` of its loudest level. Cheap enough for oscilloscope and piano roll views.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelTaps {
+    rate: u32,
+    samples: [Vec<f32>; 6],
+}
+
+impl ChannelTaps {
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channel(&self, channel: Channel) -> &[f32] {
+        &self.samples[channel as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.iter().all(Vec::is_empty)
+    }
+}
+
+#[derive(Debug, Clone, Default, Hash)]
+struct Envelope {
+    start: bool,
+    /// Also halts the length counter
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        for flag in [self.start, self.looping, self.constant] {
+            w.write_bool(flag);
+        }
+        for value in [self.volume, self.divider, self.decay] {
+            w.write_u8(value);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.start = r.read_bool()?;
+        self.looping = r.read_bool()?;
+        self.constant = r.read_bool()?;
+        self.volume = r.read_u8()?;
+        self.divider = r.read_u8()?;
+        self.decay = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Hash)]
+struct Pulse {
+    /// Pulse 1's sweep subtracts one more than pulse 2's when lowering the period
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every other CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            self.timer_period
+                .saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0
+            || self.muted()
+            || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        for flag in [
+            self.enabled,
+            self.sweep_enabled,
+            self.sweep_negate,
+            self.sweep_reload,
+        ] {
+            w.write_bool(flag);
+        }
+        for value in [
+            self.duty,
+            self.step,
+            self.length,
+            self.sweep_period,
+            self.sweep_shift,
+            self.sweep_divider,
+        ] {
+            w.write_u8(value);
+        }
+        w.write_u16(self.timer_period);
+        w.write_u16(self.timer);
+        self.envelope.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.enabled = r.read_bool()?;
+        self.sweep_enabled = r.read_bool()?;
+        self.sweep_negate = r.read_bool()?;
+        self.sweep_reload = r.read_bool()?;
+        self.duty = r.read_u8()? & 3;
+        self.step = r.read_u8()? & 7;
+        self.length = r.read_u8()?;
+        self.sweep_period = r.read_u8()?;
+        self.sweep_shift = r.read_u8()?;
+        self.sweep_divider = r.read_u8()?;
+        self.timer_period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        self.envelope.load_state(r)
+    }
+}
+
+#[derive(Debug, Clone, Default, Hash)]
+struct Triangle {
+    enabled: bool,
+    /// Halts the length counter and keeps reloading the linear counter
+    control: bool,
+    linear_reload_value: u8,
+    linear: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    step: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length > 0 && self.linear > 0 {
+                self.step = (self.step + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_STEPS[self.step as usize]
+    }
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        for flag in [self.enabled, self.control, self.linear_reload] {
+            w.write_bool(flag);
+        }
+        for value in [
+            self.linear_reload_value,
+            self.linear,
+            self.length,
+            self.step,
+        ] {
+            w.write_u8(value);
+        }
+        w.write_u16(self.timer_period);
+        w.write_u16(self.timer);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.enabled = r.read_bool()?;
+        self.control = r.read_bool()?;
+        self.linear_reload = r.read_bool()?;
+        self.linear_reload_value = r.read_u8()?;
+        self.linear = r.read_u8()?;
+        self.length = r.read_u8()?;
+        self.step = r.read_u8()? & 31;
+        self.timer_period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Hash)]
+struct Noise {
+    enabled: bool,
+    envelope: Envelope,
+    /// Short mode: feedback from bit 6 instead of bit 1, for metallic tones
+    short: bool,
+    period: u8,
+    timer: u16,
+    shift: u16,
+    length: u8,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            envelope: Envelope::default(),
+            short: false,
+            period: 0,
+            timer: 0,
+            shift: 1,
+            length: 0,
+        }
+    }
+}
+
+impl Noise {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.envelope.write(data),
+            1 => {}
+            2 => {
+                self.short = data & 0x80 != 0;
+                self.period = data & 0x0F;
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    fn clock_timer(&mut self, pal: bool) {
+        if self.timer == 0 {
+            let periods = if pal {
+                PAL_NOISE_PERIODS
+            } else {
+                NTSC_NOISE_PERIODS
+            };
+            self.timer = periods[self.period as usize] - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.enabled);
+        w.write_bool(self.short);
+        w.write_u8(self.period);
+        w.write_u8(self.length);
+        w.write_u16(self.timer);
+        w.write_u16(self.shift);
+        self.envelope.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.enabled = r.read_bool()?;
+        self.short = r.read_bool()?;
+        self.period = r.read_u8()? & 0x0F;
+        self.length = r.read_u8()?;
+        self.timer = r.read_u16()?;
+        self.shift = r.read_u16()?;
+        self.envelope.load_state(r)
+    }
+}
+
+/// The delta modulation channel, which plays 1 bit samples straight out of CPU memory
+#[derive(Debug, Clone, Default, Hash)]
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    rate: u8,
+    timer: u16,
+    level: u8,
+    sample_addr: u16,
+    sample_len: u16,
+    addr: u16,
+    remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.rate = data & 0x0F;
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_addr = 0xC000 | ((data as u16) << 6),
+            _ => self.sample_len = ((data as u16) << 4) | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.addr = self.sample_addr;
+        self.remaining = self.sample_len;
+    }
+
+    /// Clocked every CPU cycle. Returns the cycles the CPU stalls for while a sample byte is
+    /// fetched from `memory`.
+    fn clock(&mut self, memory: &[u8], pal: bool) -> u64 {
+        let mut stall = 0;
+        if self.buffer.is_none() && self.remaining > 0 {
+            self.buffer = Some(memory[self.addr as usize]);
+            self.addr = self.addr.checked_add(1).unwrap_or(0x8000);
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                if self.looping {
+                    self.restart();
+                } else if self.irq_enabled {
+                    self.irq = true;
+                }
+            }
+            stall = DMC_FETCH_CYCLES;
+        }
+
+        if self.timer == 0 {
+            let rates = if pal { PAL_DMC_RATES } else { NTSC_DMC_RATES };
+            self.timer = rates[self.rate as usize] - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+        stall
+    }
+
+    fn clock_output(&mut self) {
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.shift = byte;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits -= 1;
+    }
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        for flag in [
+            self.irq_enabled,
+            self.looping,
+            self.buffer.is_some(),
+            self.silence,
+            self.irq,
+        ] {
+            w.write_bool(flag);
+        }
+        for value in [
+            self.rate,
+            self.level,
+            self.buffer.unwrap_or(0),
+            self.shift,
+            self.bits,
+        ] {
+            w.write_u8(value);
+        }
+        for value in [
+            self.timer,
+            self.sample_addr,
+            self.sample_len,
+            self.addr,
+            self.remaining,
+        ] {
+            w.write_u16(value);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.irq_enabled = r.read_bool()?;
+        self.looping = r.read_bool()?;
+        let has_buffer = r.read_bool()?;
+        self.silence = r.read_bool()?;
+        self.irq = r.read_bool()?;
+        self.rate = r.read_u8()? & 0x0F;
+        self.level = r.read_u8()? & 0x7F;
+        let buffer = r.read_u8()?;
+        self.buffer = has_buffer.then_some(buffer);
+        self.shift = r.read_u8()?;
+        self.bits = r.read_u8()?.min(8);
+        self.timer = r.read_u16()?;
+        self.sample_addr = r.read_u16()?;
+        self.sample_len = r.read_u16()?;
+        self.addr = r.read_u16()?;
+        self.remaining = r.read_u16()?;
+        Ok(())
+    }
+}
+
+/// Room for one frame of samples at `rate`, so the frame loop doesn't allocate. PAL frames are
+/// the longest at just under 1/50s.
+fn frame_samples(rate: u32) -> usize {
+    rate as usize / 50 + 2
+}
+
+/// Averages a signal clocked at the CPU rate down to `rate` samples per second
+#[derive(Debug, Clone)]
+struct Resampler {
+    rate: u32,
+    fraction: u64,
+    count: u32,
+}
+
+impl Resampler {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            fraction: 0,
+            count: 0,
+        }
+    }
+
+    /// Counts one CPU cycle, returning how many cycles to average over when a sample is due
+    fn clock(&mut self, cpu_hz: u64) -> Option<u32> {
+        self.count += 1;
+        self.fraction += self.rate as u64;
+        if self.fraction < cpu_hz {
+            return None;
+        }
+        self.fraction -= cpu_hz;
+        Some(std::mem::take(&mut self.count))
+    }
+}
+
+/// Everything on the way out of the APU: host samples and taps rather than emulated state, so
+/// it's left out of hashes and save states
+#[derive(Debug, Clone)]
+struct Output {
+    resampler: Resampler,
+    sum: f32,
+    samples: Vec<f32>,
+    /// DC blocking filter state, like the coupling capacitor on the console's audio out
+    last_in: f32,
+    last_out: f32,
+    taps: Option<(Resampler, [f32; 6], ChannelTaps)>,
+    /// Per [`AudioSource`] gains
+    gains: [f32; 7],
+    /// Cycles ticked since logging started, and the writes not taken yet
+    writes: Option<(u64, Vec<RegisterWrite>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    /// Pulse timers tick on every other CPU cycle
+    odd_cycle: bool,
+    pal: bool,
+    output: Output,
+}
+
+/// Output buffers are host state, not part of the emulated machine
+impl Hash for Apu {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pulse1.hash(state);
+        self.pulse2.hash(state);
+        self.triangle.hash(state);
+        self.noise.hash(state);
+        self.dmc.hash(state);
+        self.five_step.hash(state);
+        self.irq_inhibit.hash(state);
+        self.frame_irq.hash(state);
+        self.frame_cycle.hash(state);
+        self.odd_cycle.hash(state);
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse {
+                ones_complement: true,
+                ..Pulse::default()
+            },
+            pulse2: Pulse::default(),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            pal: false,
+            output: Output {
+                resampler: Resampler::new(DEFAULT_SAMPLE_RATE),
+                sum: 0.0,
+                samples: Vec::with_capacity(frame_samples(DEFAULT_SAMPLE_RATE)),
+                last_in: 0.0,
+                last_out: 0.0,
+                taps: None,
+                writes: None,
+                gains: [1.0; 7],
+            },
+        }
+    }
+
+    /// Silences every channel, keeping the output settings
+    pub fn power_on(&mut self) {
+        let output = self.output.clone();
+        *self = Self {
+            pal: self.pal,
+            output,
+            ..Self::new()
+        };
+    }
+
+    /// Switches to the 2A07's frame counter, noise and DMC timing
+    pub fn set_pal(&mut self, pal: bool) {
+        self.pal = pal;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.output.resampler.rate
+    }
+
+    /// Sets how many samples per second [`Apu::samples`] collects
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        assert!(rate > 0, "sample rate must be positive");
+        self.output.resampler = Resampler::new(rate);
+        let reserve = frame_samples(rate).saturating_sub(self.output.samples.len());
+        self.output.samples.reserve(reserve);
+    }
+
+    /// Mono samples produced since the last [`Apu::clear_samples`], oldest first
+    pub fn samples(&self) -> &[f32] {
+        &self.output.samples
+    }
+
+    pub fn clear_samples(&mut self) {
+        self.output.samples.clear();
+    }
+
+    /// Replaces the samples with `len` of them ramping from the last one down to silence
+    pub(crate) fn fade_out(&mut self, len: usize) {
+        let level = self.output.samples.last().copied().unwrap_or(0.0);
+        self.output.samples.clear();
+        let ramp = (0..len).map(|i| level * (len - 1 - i) as f32 / len as f32);
+        self.output.samples.extend(ramp);
+    }
+
+    /// Ramps the first `len` samples up from silence
+    pub(crate) fn fade_in(&mut self, len: usize) {
+        for (i, sample) in self.output.samples.iter_mut().take(len).enumerate() {
+            *sample *= i as f32 / len as f32;
+        }
+    }
+
+    /// How loud `source` is mixed, relative to how it sounds on a Famicom
+    pub fn gain(&self, source: AudioSource) -> f32 {
+        self.output.gains[source as usize]
+    }
+
+    /// Mixes `source` at `gain` times its Famicom level: 1.0, the default, for hardware balance,
+    /// 0.0 to mute it. Gains for expansion chips take effect once a board with that chip is
+    /// emulated.
+    pub fn set_gain(&mut self, source: AudioSource, gain: f32) {
+        assert!(gain >= 0.0, "gain must not be negative");
+        self.output.gains[source as usize] = gain;
+    }
+
+    /// Puts every source back at its Famicom level
+    pub fn reset_gains(&mut self) {
+        self.output.gains = [1.0; 7];
+    }
+
+    /// Starts collecting each channel's output at `rate` samples per second, or stops when
+    /// `None`. A few thousand samples per second is plenty for drawing waveforms.
+    pub fn set_channel_taps(&mut self, rate: Option<u32>) {
+        self.output.taps = rate.filter(|&rate| rate > 0).map(|rate| {
+            let taps = ChannelTaps {
+                rate,
+                ..ChannelTaps::default()
+            };
+            (Resampler::new(rate), [0.0; 6], taps)
+        });
+    }
+
+    /// Returns the channel samples collected since the last call; empty while not tapping
+    pub fn take_channel_taps(&mut self) -> ChannelTaps {
+        match &mut self.output.taps {
+            Some((_, _, taps)) => {
+                let rate = taps.rate;
+                std::mem::replace(
+                    taps,
+                    ChannelTaps {
+                        rate,
+                        ..ChannelTaps::default()
+                    },
+                )
+            }
+            None => ChannelTaps::default(),
+        }
+    }
+
+    /// What `channel`'s DAC is putting out right now: `0..=15`, or `0..=127` for the DMC
+    pub fn channel_level(&self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Pulse1 => self.pulse1.output(),
+            Channel::Pulse2 => self.pulse2.output(),
+            Channel::Triangle => self.triangle.output(),
+            Channel::Noise => self.noise.output(),
+            Channel::Dmc => self.dmc.level,
+            Channel::Expansion => 0,
+        }
+    }
+
+    /// What `channel` is playing, or `None` while it's silent. The DMC plays samples rather than
+    /// notes and never has a tone, nor does the expansion channel.
+    pub fn tone(&self, channel: Channel) -> Option<Tone> {
+        let cpu_hz = if self.pal { PAL_CPU_HZ } else { NTSC_CPU_HZ } as f32;
+        let (frequency, volume, period) = match channel {
+            Channel::Pulse1 | Channel::Pulse2 => {
+                let pulse = if channel == Channel::Pulse1 {
+                    &self.pulse1
+                } else {
+                    &self.pulse2
+                };
+                if pulse.length == 0 || pulse.muted() {
+                    return None;
+                }
+                let period = pulse.timer_period;
+                let frequency = cpu_hz / (16.0 * (period as f32 + 1.0));
+                (frequency, pulse.envelope.output(), period)
+            }
+            Channel::Triangle => {
+                let triangle = &self.triangle;
+                // Periods under 2 are ultrasonic; games use them to silence the channel
+                if triangle.length == 0 || triangle.linear == 0 || triangle.timer_period < 2 {
+                    return None;
+                }
+                let period = triangle.timer_period;
+                (cpu_hz / (32.0 * (period as f32 + 1.0)), 15, period)
+            }
+            Channel::Noise => {
+                if self.noise.length == 0 {
+                    return None;
+                }
+                let periods = if self.pal {
+                    PAL_NOISE_PERIODS
+                } else {
+                    NTSC_NOISE_PERIODS
+                };
+                let period = self.noise.period;
+                let frequency = cpu_hz / periods[period as usize] as f32;
+                (frequency, self.noise.envelope.output(), period as u16)
+            }
+            Channel::Dmc | Channel::Expansion => return None,
+        };
+        (volume > 0).then_some(Tone {
+            frequency,
+            volume,
+            period,
+        })
+    }
+
+    /// Whether the frame counter or DMC is asserting an interrupt
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    pub(crate) fn frame_irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    pub(crate) fn dmc_irq(&self) -> bool {
+        self.dmc.irq
+    }
+
+    /// Starts or stops logging register writes. The log's clock starts at 0 when it's turned on.
+    pub fn track_register_writes(&mut self, enabled: bool) {
+        self.output.writes = enabled.then(|| (0, Vec::new()));
+    }
+
+    /// Register writes since the last call, oldest first; empty while logging is off
+    pub fn take_register_writes(&mut self) -> Vec<RegisterWrite> {
+        self.output
+            .writes
+            .as_mut()
+            .map(|(_, writes)| std::mem::take(writes))
+            .unwrap_or_default()
+    }
+
+    /// Handles a CPU write to `0x4000..=0x4013`, `0x4015` or `0x4017`
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        if let Some((cycle, writes)) = &mut self.output.writes {
+            writes.push(RegisterWrite {
+                cycle: *cycle,
+                addr,
+                value: data,
+            });
+        }
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.enabled = data & 0x01 != 0;
+                self.pulse2.enabled = data & 0x02 != 0;
+                self.triangle.enabled = data & 0x04 != 0;
+                self.noise.enabled = data & 0x08 != 0;
+                for (enabled, length) in [
+                    (self.pulse1.enabled, &mut self.pulse1.length),
+                    (self.pulse2.enabled, &mut self.pulse2.length),
+                    (self.triangle.enabled, &mut self.triangle.length),
+                    (self.noise.enabled, &mut self.noise.length),
+                ] {
+                    if !enabled {
+                        *length = 0;
+                    }
+                }
+                if data & STATUS_DMC_ACTIVE == 0 {
+                    self.dmc.remaining = 0;
+                } else if self.dmc.remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a CPU read of `0x4015`, which acknowledges the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    /// Returns what reading `0x4015` would without acknowledging anything
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        for (i, length) in [
+            self.pulse1.length,
+            self.pulse2.length,
+            self.triangle.length,
+            self.noise.length,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if length > 0 {
+                status |= 1 << i;
+            }
+        }
+        if self.dmc.remaining > 0 {
+            status |= STATUS_DMC_ACTIVE;
+        }
+        if self.frame_irq {
+            status |= STATUS_FRAME_IRQ;
+        }
+        if self.dmc.irq {
+            status |= STATUS_DMC_IRQ;
+        }
+        status
+    }
+
+    /// Advances the APU by `cpu_cycles` CPU cycles. The DMC reads its samples from `memory`;
+    /// returns the cycles those reads stall the CPU for.
+    pub fn tick(&mut self, cpu_cycles: u64, memory: &[u8]) -> u64 {
+        let cpu_hz = if self.pal { PAL_CPU_HZ } else { NTSC_CPU_HZ };
+        if let Some((cycle, _)) = &mut self.output.writes {
+            *cycle += cpu_cycles;
+        }
+        let mut stall = 0;
+        for _ in 0..cpu_cycles {
+            self.clock_frame_counter();
+            if self.odd_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+            self.odd_cycle = !self.odd_cycle;
+            self.triangle.clock_timer();
+            self.noise.clock_timer(self.pal);
+            stall += self.dmc.clock(memory, self.pal);
+            self.clock_output(cpu_hz);
+        }
+        stall
+    }
+
+    fn clock_frame_counter(&mut self) {
+        let steps = if self.pal {
+            PAL_FRAME_STEPS
+        } else {
+            NTSC_FRAME_STEPS
+        };
+        self.frame_cycle += 1;
+        let cycle = self.frame_cycle;
+        if cycle == steps[0] || cycle == steps[2] {
+            self.clock_quarter_frame();
+        } else if cycle == steps[1] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        } else if (!self.five_step && cycle == steps[3]) || cycle == steps[4] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            if !self.five_step && !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length();
+        self.pulse2.clock_length();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    /// The channels through the nonlinear mixer, `0.0..=1.0`
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+
+    fn clock_output(&mut self, cpu_hz: u64) {
+        let level = self.mix();
+        self.output.sum += level;
+        if let Some(count) = self.output.resampler.clock(cpu_hz) {
+            let input = std::mem::take(&mut self.output.sum) / count as f32
+                * self.output.gains[AudioSource::Apu as usize];
+            let out = input - self.output.last_in + 0.996 * self.output.last_out;
+            self.output.last_in = input;
+            self.output.last_out = out;
+            self.output.samples.push(out.clamp(-1.0, 1.0));
+        }
+
+        if self.output.taps.is_none() {
+            return;
+        }
+        let levels = Channel::ALL.map(|channel| self.channel_level(channel));
+        let Some((resampler, sums, taps)) = &mut self.output.taps else {
+            return;
+        };
+        for (sum, level) in sums.iter_mut().zip(levels) {
+            *sum += level as f32;
+        }
+        if let Some(count) = resampler.clock(cpu_hz) {
+            for (channel, sum) in Channel::ALL.into_iter().zip(sums.iter_mut()) {
+                let value = std::mem::take(sum) / count as f32 / channel.max_level();
+                taps.samples[channel as usize].push(value);
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut ChunkWriter) {
+        self.pulse1.save_state(w);
+        self.pulse2.save_state(w);
+        self.triangle.save_state(w);
+        self.noise.save_state(w);
+        self.dmc.save_state(w);
+        for flag in [
+            self.five_step,
+            self.irq_inhibit,
+            self.frame_irq,
+            self.odd_cycle,
+        ] {
+            w.write_bool(flag);
+        }
+        w.write_u64(self.frame_cycle as u64);
+    }
+
+    pub fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.pulse1.load_state(r)?;
+        self.pulse2.load_state(r)?;
+        self.triangle.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.dmc.load_state(r)?;
+        self.five_step = r.read_bool()?;
+        self.irq_inhibit = r.read_bool()?;
+        self.frame_irq = r.read_bool()?;
+        self.odd_cycle = r.read_bool()?;
+        self.frame_cycle = r.read_u64()? as u32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pulse_plays_and_length_counter_stops_it() {
+        let memory = [0; 0x10000];
+        let mut apu = Apu::new();
+        apu.set_channel_taps(Some(4000));
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0b1001_1111); // 50% duty, constant volume 15
+        apu.write_register(0x4002, 0xFD); // ~440Hz
+        apu.write_register(0x4003, 0b0000_1000); // length 254 half frames
+        assert_eq!(apu.peek_status() & 1, 1);
+
+        apu.tick(29_830, &memory);
+        let taps = apu.take_channel_taps();
+        let pulse = taps.channel(Channel::Pulse1);
+        assert!(pulse.iter().any(|&level| level > 0.9));
+        assert!(pulse.iter().any(|&level| level < 0.1));
+        assert!(taps
+            .channel(Channel::Triangle)
+            .iter()
+            .all(|&level| level == 1.0)); // step 0
+        assert!(taps
+            .channel(Channel::Noise)
+            .iter()
+            .all(|&level| level == 0.0));
+        assert!(apu.samples().len().abs_diff(735) <= 1); // 1/60s at 44.1kHz
+
+        // A length of 2 half frames runs out after one frame
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.tick(29_830, &memory);
+        assert_eq!(apu.peek_status() & 1, 0);
+        assert_eq!(apu.channel_level(Channel::Pulse1), 0);
+        assert_eq!(apu.read_status() & STATUS_FRAME_IRQ, STATUS_FRAME_IRQ);
+        assert_eq!(apu.peek_status() & STATUS_FRAME_IRQ, 0);
+    }
+
+    #[test]
+    fn test_gain_scales_output() {
+        let memory = [0; 0x10000];
+        let run = |gain| {
+            let mut apu = Apu::new();
+            apu.set_gain(AudioSource::Apu, gain);
+            apu.write_register(0x4015, 0x01);
+            apu.write_register(0x4000, 0b1001_1111);
+            apu.write_register(0x4002, 0xFD);
+            apu.write_register(0x4003, 0b0000_1000);
+            apu.tick(10_000, &memory);
+            apu.samples().to_vec()
+        };
+        let full = run(1.0);
+        let half = run(0.5);
+        assert!(full.iter().any(|&sample| sample > 0.05));
+        for (full, half) in full.iter().zip(&half) {
+            assert!((full / 2.0 - half).abs() < 1e-6);
+        }
+        assert!(run(0.0).iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_dmc_plays_samples_from_memory() {
+        let mut memory = [0; 0x10000];
+        memory[0xC000] = 0xFF; // every bit raises the level
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x8F); // IRQ at the end, fastest rate
+        apu.write_register(0x4011, 0x40);
+        apu.write_register(0x4012, 0x00);
+        apu.write_register(0x4013, 0x00); // one byte
+        apu.write_register(0x4015, STATUS_DMC_ACTIVE);
+        assert_ne!(apu.peek_status() & STATUS_DMC_ACTIVE, 0);
+
+        assert_eq!(apu.tick(1000, &memory), DMC_FETCH_CYCLES);
+        assert_eq!(apu.channel_level(Channel::Dmc), 0x40 + 2 * 8);
+        assert_eq!(apu.peek_status() & STATUS_DMC_ACTIVE, 0);
+        assert!(apu.irq_pending());
+    }
+}
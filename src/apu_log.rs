@@ -0,0 +1,177 @@
+//! APU register logs: capturing a game's music as the writes that play it.
+//!
+//! Most games have an NSF rip, but plenty of homebrew, prototypes and hacks don't. An [`ApuLog`]
+//! records every write the game makes to the APU registers while it plays, timed to the CPU
+//! cycle and tagged with the frame it happened in. That's what NSFe and VGM style players replay,
+//! so the log is the raw material for building either; [`ApuLog::to_text`] writes it out as
+//! plain text for scripts that do, and [`crate::vgm`] as a VGM file.
+//!
+//! No board emulated so far has expansion audio, so only the 2A03's own registers show up.
+//!
+//! ```
+//! use nes_emulator::apu_log::ApuLog;
+//! use nes_emulator::console::Console;
+//!
+//! let mut console = Console::new();
+//! #[rustfmt::skip]
+//! console.load(&[
+//!     0xa9, 0x01, 0x8d, 0x15, 0x40, // enable pulse 1
+//!     0xa9, 0x9f, 0x8d, 0x00, 0x40, // 50% duty, constant volume 15
+//!     0x4c, 0x0a, 0x80,             // spin
+//! ]);
+//! let mut log = ApuLog::start(&mut console);
+//! console.run_frame();
+//! log.capture(&mut console);
+//! log.stop(&mut console);
+//! let addrs: Vec<u16> = log.writes().iter().map(|write| write.addr).collect();
+//! assert_eq!(addrs, [0x4015, 0x4000]);
+//! ```
+
+use std::fmt::Write;
+
+use crate::console::Console;
+
+/// One register write in an [`ApuLog`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoggedWrite {
+    /// The console frame the write happened in
+    pub frame: u64,
+    /// CPU cycles since the log started
+    pub cycle: u64,
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApuLog {
+    cpu_hz: u64,
+    start_frame: u64,
+    /// The console's CPU cycle count when logging started
+    start_cycle: u64,
+    /// Cycles covered by the log, as of the last capture
+    cycles: u64,
+    writes: Vec<LoggedWrite>,
+}
+
+impl ApuLog {
+    /// Starts logging `console`'s APU writes from the next frame
+    pub fn start(console: &mut Console) -> Self {
+        console.apu_mut().track_register_writes(true);
+        Self {
+            cpu_hz: console.region().cpu_hz(),
+            start_frame: console.frame(),
+            start_cycle: console.cpu().cycles(),
+            cycles: 0,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Collects the writes made since the last capture. Call after each [`Console::run_frame`]
+    /// so every write is tagged with the right frame.
+    pub fn capture(&mut self, console: &mut Console) {
+        // The frame counter has already moved on to the next frame
+        let frame = console.frame().saturating_sub(1);
+        self.cycles = console.cpu().cycles() - self.start_cycle;
+        let writes = console.apu_mut().take_register_writes();
+        self.writes
+            .extend(writes.into_iter().map(|write| LoggedWrite {
+                frame,
+                cycle: write.cycle,
+                addr: write.addr,
+                value: write.value,
+            }));
+    }
+
+    /// Collects what's left and stops logging
+    pub fn stop(&mut self, console: &mut Console) {
+        self.capture(console);
+        console.apu_mut().track_register_writes(false);
+    }
+
+    /// The CPU clock the cycle counts are in, in Hz
+    pub fn cpu_hz(&self) -> u64 {
+        self.cpu_hz
+    }
+
+    /// The frame logging started on
+    pub fn start_frame(&self) -> u64 {
+        self.start_frame
+    }
+
+    /// CPU cycles from the start of the log to the last capture
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Every write so far, oldest first
+    pub fn writes(&self) -> &[LoggedWrite] {
+        &self.writes
+    }
+
+    /// The writes made in each frame, in order, skipping frames without any. An NSF style
+    /// player routine runs once per frame, so this is what each call of it would write.
+    pub fn frames(&self) -> impl Iterator<Item = (u64, &[LoggedWrite])> {
+        self.writes
+            .chunk_by(|a, b| a.frame == b.frame)
+            .map(|writes| (writes[0].frame, writes))
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+
+    /// A `clock` line with [`ApuLog::cpu_hz`], then one line per write: cycle, frame, and the
+    /// address and value in hex
+    pub fn to_text(&self) -> String {
+        let mut text = format!("clock {}\n# cycle frame addr value\n", self.cpu_hz);
+        for write in &self.writes {
+            writeln!(
+                text,
+                "{} {} {:04X} {:02X}",
+                write.cycle, write.frame, write.addr, write.value
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_logs_writes_with_timing() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x01, 0x8d, 0x15, 0x40, // LDA #$01; STA $4015
+            0xe6, 0x10,                   // loop: INC $10
+            0xd0, 0xfc,                   //       BNE loop
+            0x8d, 0x02, 0x40,             //       STA $4002, every 256 loops
+            0x4c, 0x05, 0x80,             //       JMP loop
+        ]);
+        let mut log = ApuLog::start(&mut console);
+        for _ in 0..2 {
+            console.run_frame();
+            log.capture(&mut console);
+        }
+        log.stop(&mut console);
+        console.run_frame();
+        log.capture(&mut console);
+
+        let writes = log.writes();
+        assert_eq!(writes[0].addr, 0x4015);
+        assert_eq!(writes[0].frame, 0);
+        assert!(writes[1..].iter().all(|write| write.addr == 0x4002));
+        // STA and JMP, 255 taken INC and BNE pairs, then INC and BNE falling through
+        assert_eq!(writes[2].cycle - writes[1].cycle, 4 + 3 + 255 * 8 + 5 + 2);
+        assert!(writes.windows(2).all(|pair| pair[0].cycle < pair[1].cycle));
+        let frames: Vec<u64> = log.frames().map(|(frame, _)| frame).collect();
+        assert_eq!(frames, [0, 1], "nothing after stopping");
+        assert!(log.to_text().starts_with("clock 1789773\n"));
+        assert!(log
+            .to_text()
+            .contains(&format!("{} 0 4015 01\n", writes[0].cycle)));
+    }
+}
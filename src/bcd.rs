@@ -0,0 +1,70 @@
+//! Binary coded decimal values in RAM.
+//!
+//! The 6502 in the NES has its decimal mode disabled, but games still keep scores, lives and
+//! timers as decimal digits so they're cheap to draw. These helpers read and write values stored
+//! as packed BCD: two digits per byte, the most significant byte first, so `00 12 34` in RAM is
+//! 1234 and reads the same way in a hex dump.
+//!
+//! ```
+//! use nes_emulator::bcd;
+//! use nes_emulator::bus::Bus;
+//!
+//! let mut ram = Bus::new();
+//! bcd::write_bcd(&mut ram, 0x07DD, 3, 1234);
+//! assert_eq!(bcd::read_bcd(&ram, 0x07DD, 3), Some(1234));
+//! ```
+
+use crate::cpu::Mem;
+
+/// The value of packed BCD `bytes`, or `None` if a nibble isn't a decimal digit
+pub fn decode(bytes: &[u8]) -> Option<u64> {
+    bytes.iter().try_fold(0u64, |value, &byte| {
+        let (hi, lo) = (byte >> 4, byte & 0x0F);
+        (hi < 10 && lo < 10).then(|| value * 100 + (hi * 10 + lo) as u64)
+    })
+}
+
+/// Writes `value` as packed BCD filling `bytes`, keeping only the last `2 * bytes.len()` digits
+/// like a counter that rolls over
+pub fn encode(mut value: u64, bytes: &mut [u8]) {
+    for byte in bytes.iter_mut().rev() {
+        let pair = (value % 100) as u8;
+        *byte = ((pair / 10) << 4) | (pair % 10);
+        value /= 100;
+    }
+}
+
+/// Reads the `len` byte BCD value at `addr` without disturbing `mem`
+pub fn read_bcd(mem: &impl Mem, addr: u16, len: u16) -> Option<u64> {
+    let bytes: Vec<u8> = (0..len)
+        .map(|i| mem.mem_peek(addr.wrapping_add(i)))
+        .collect();
+    decode(&bytes)
+}
+
+/// Writes `value` as a `len` byte BCD value at `addr`, see [`encode`]
+pub fn write_bcd(mem: &mut impl Mem, addr: u16, len: u16, value: u64) {
+    let mut bytes = vec![0; len as usize];
+    encode(value, &mut bytes);
+    for (i, byte) in (0..len).zip(bytes) {
+        mem.mem_write(addr.wrapping_add(i), byte);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut bytes = [0; 3];
+        encode(90210, &mut bytes);
+        assert_eq!(bytes, [0x09, 0x02, 0x10]);
+        assert_eq!(decode(&bytes), Some(90210));
+
+        encode(1_234_567, &mut bytes); // only six digits fit
+        assert_eq!(decode(&bytes), Some(234_567));
+        assert_eq!(decode(&[0x1A]), None);
+        assert_eq!(decode(&[]), Some(0));
+    }
+}
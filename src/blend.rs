@@ -0,0 +1,111 @@
+//! Frame blending, for the phosphor persistence of a CRT.
+//!
+//! A CRT's phosphors keep glowing a little after the beam has passed, and games relied on it:
+//! a sprite drawn only every other frame, as many games do for shadows, ghosts and flames or to
+//! get past the eight sprites per line limit, looked translucent on a TV. Shown as sharp 60Hz
+//! frames on a modern display it flickers hard instead. A [`FrameBlend`] mixes the previous
+//! frame into each new one, with the share of the previous frame set by its persistence, so such
+//! effects look as they were meant to. Like the
+//! [`FlashFilter`](crate::accessibility::FlashFilter) it works on the converted pixels, so it
+//! does nothing for [`PixelFormat::Indexed`] output.
+//!
+//! ```
+//! use nes_emulator::blend::FrameBlend;
+//! use nes_emulator::console::Console;
+//!
+//! let mut console = Console::new();
+//! console.set_frame_blend(Some(FrameBlend::new(0.5)));
+//! ```
+
+use crate::accessibility::{decode, encode};
+use crate::frame::PixelFormat;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBlend {
+    /// The previous frame's share of each pixel, out of 256
+    weight: u16,
+    /// The previous frame as the PPU drew it, before blending
+    previous: Vec<u8>,
+}
+
+impl Default for FrameBlend {
+    fn default() -> Self {
+        Self::new(FrameBlend::DEFAULT_PERSISTENCE)
+    }
+}
+
+impl FrameBlend {
+    /// An even mix of the two frames, which shows a sprite flickering at 30Hz at half strength
+    pub const DEFAULT_PERSISTENCE: f32 = 0.5;
+
+    /// Mixes in the previous frame as `persistence` of each pixel, clamped to `0.0..=1.0`. At
+    /// 0.0 frames pass through unchanged; at 1.0 every frame shows the one before it.
+    pub fn new(persistence: f32) -> Self {
+        Self {
+            weight: (persistence.clamp(0.0, 1.0) * 256.0).round() as u16,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn persistence(&self) -> f32 {
+        self.weight as f32 / 256.0
+    }
+
+    /// Blends a converted frame in place. The first frame, and the first after the format
+    /// changes, pass through.
+    pub fn apply(&mut self, format: PixelFormat, pixels: &mut [u8]) {
+        if format == PixelFormat::Indexed {
+            return;
+        }
+        if self.previous.len() != pixels.len() {
+            self.previous.clear();
+            self.previous.extend_from_slice(pixels);
+            return;
+        }
+        let weight = self.weight as u32;
+        let size = format.bytes_per_pixel();
+        for (pixel, previous) in pixels
+            .chunks_exact_mut(size)
+            .zip(self.previous.chunks_exact_mut(size))
+        {
+            let current = decode(format, pixel);
+            let (pr, pg, pb) = decode(format, previous);
+            previous.copy_from_slice(pixel);
+            let (r, g, b) = current;
+            let blend = |new: u8, old: u8| {
+                ((new as u32 * (256 - weight) + old as u32 * weight) / 256) as u8
+            };
+            encode(format, (blend(r, pr), blend(g, pg), blend(b, pb)), pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mixes_in_the_previous_frame() {
+        let mut blend = FrameBlend::new(0.25);
+        let white = [255, 255, 255, 255];
+        let black = [0, 0, 0, 255];
+        let mut frame = white;
+        blend.apply(PixelFormat::Rgba8888, &mut frame);
+        assert_eq!(frame, white);
+        let mut frame = black;
+        blend.apply(PixelFormat::Rgba8888, &mut frame);
+        assert_eq!(frame, [63, 63, 63, 255]);
+        // Blends with the frame as drawn, not as shown, so flicker settles at a steady mix
+        let mut frame = white;
+        blend.apply(PixelFormat::Rgba8888, &mut frame);
+        assert_eq!(frame, [191, 191, 191, 255]);
+
+        let mut frame = [0xFF, 0xFF];
+        blend.apply(PixelFormat::Rgb565, &mut frame);
+        assert_eq!(frame, [0xFF, 0xFF]);
+        let mut indexed = [0x0F];
+        blend.apply(PixelFormat::Indexed, &mut indexed);
+        assert_eq!(indexed, [0x0F]);
+        assert_eq!(FrameBlend::new(2.0).persistence(), 1.0);
+    }
+}
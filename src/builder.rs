@@ -0,0 +1,387 @@
+//! Assembling a configured console in one place.
+//!
+//! [`ConsoleBuilder`] gathers everything decided before power on: what to run, the region,
+//! RAM contents and seed, palette, a Game Genie, video and audio output, and tooling such as
+//! rewind and developer warnings. [`ConsoleBuilder::build`] checks all of it before touching a
+//! console and reports every problem at once, so a frontend can show the user one complete list.
+//!
+//! ```
+//! use nes_emulator::builder::ConsoleBuilder;
+//! use nes_emulator::console::Region;
+//!
+//! let console = ConsoleBuilder::new()
+//!     .program(&[0x4c, 0x00, 0x80])
+//!     .region(Region::Pal)
+//!     .sample_rate(48_000)
+//!     .rewind(600)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(console.region(), Region::Pal);
+//!
+//! let err = ConsoleBuilder::new()
+//!     .rom_path("missing.nes")
+//!     .sample_rate(0)
+//!     .build()
+//!     .err()
+//!     .unwrap();
+//! assert_eq!(err.problems.len(), 2);
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::console::{Console, Region};
+use crate::frame::{Palette, PixelFormat};
+use crate::game_genie::GameGenie;
+use crate::mapper::MapperRegistry;
+use crate::rng::RamInit;
+use crate::rom::{Rom, RomError};
+
+/// Sample rates [`ConsoleBuilder::sample_rate`] accepts
+pub const SAMPLE_RATES: RangeInclusive<u32> = 8_000..=192_000;
+
+#[derive(Debug)]
+enum Program {
+    Rom(Rom),
+    RomPath(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug)]
+enum PaletteSource {
+    Palette(Palette),
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+enum GameGenieSource {
+    GameGenie(GameGenie),
+    /// A dump of its ROM
+    File(PathBuf),
+}
+
+/// One thing wrong with a [`ConsoleBuilder`]
+#[derive(Debug)]
+pub enum BuildProblem {
+    /// The ROM couldn't be read, or the console can't run it
+    Rom {
+        path: Option<PathBuf>,
+        error: RomError,
+    },
+    /// The palette file couldn't be read or isn't a 64 or 512 color `.pal` file
+    Palette { path: PathBuf, message: String },
+    /// The Game Genie ROM couldn't be read or isn't
+    /// [`BIOS_SIZE`](crate::game_genie::BIOS_SIZE) bytes
+    GameGenie { path: PathBuf, message: String },
+    /// Outside [`SAMPLE_RATES`]
+    SampleRate(u32),
+    /// Rewind asked to keep no frames
+    RewindFrames,
+}
+
+impl fmt::Display for BuildProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildProblem::Rom {
+                path: Some(path),
+                error,
+            } => write!(f, "{}: {error}", path.display()),
+            BuildProblem::Rom { path: None, error } => write!(f, "ROM: {error}"),
+            BuildProblem::Palette { path, message } => write!(f, "{}: {message}", path.display()),
+            BuildProblem::GameGenie { path, message } => {
+                write!(f, "{}: {message}", path.display())
+            }
+            BuildProblem::SampleRate(rate) => write!(
+                f,
+                "sample rate {rate} is outside {}..={}",
+                SAMPLE_RATES.start(),
+                SAMPLE_RATES.end()
+            ),
+            BuildProblem::RewindFrames => write!(f, "rewind needs room for at least one frame"),
+        }
+    }
+}
+
+/// Every problem found by [`ConsoleBuilder::build`], in the order the settings are listed there
+#[derive(Debug)]
+pub struct BuildError {
+    pub problems: Vec<BuildProblem>,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BuildError {}
+
+#[derive(Debug, Default)]
+pub struct ConsoleBuilder {
+    program: Option<Program>,
+    region: Option<Region>,
+    seed: u64,
+    ram_init: RamInit,
+    palette: Option<PaletteSource>,
+    game_genie: Option<GameGenieSource>,
+    pixel_format: PixelFormat,
+    sample_rate: Option<u32>,
+    rewind: Option<usize>,
+    instruction_history: Option<usize>,
+    diagnostics: bool,
+    mappers: MapperRegistry,
+    fast_boot: bool,
+}
+
+impl ConsoleBuilder {
+    /// An NTSC console with zeroed RAM and nothing loaded
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `rom`. Replaces any ROM or program set earlier.
+    pub fn rom(mut self, rom: Rom) -> Self {
+        self.program = Some(Program::Rom(rom));
+        self
+    }
+
+    /// Runs the ROM at `path`, read at [`ConsoleBuilder::build`] with [`Rom::from_path`]
+    pub fn rom_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.program = Some(Program::RomPath(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Runs a bare `program` mapped at `0x8000`, see [`Console::load`]
+    pub fn program(mut self, program: &[u8]) -> Self {
+        self.program = Some(Program::Bytes(program.to_vec()));
+        self
+    }
+
+    /// Forces `region`, see [`Console::force_region`]. Without it the ROM's header picks the
+    /// region, NTSC if it doesn't say.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// See [`Console::set_seed`]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.ram_init = ram_init;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(PaletteSource::Palette(palette));
+        self
+    }
+
+    /// Uses the `.pal` file at `path`, read at [`ConsoleBuilder::build`]
+    pub fn palette_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.palette = Some(PaletteSource::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Plugs in a [Game Genie](crate::game_genie) in front of the ROM
+    pub fn game_genie(mut self, genie: GameGenie) -> Self {
+        self.game_genie = Some(GameGenieSource::GameGenie(genie));
+        self
+    }
+
+    /// Plugs in a Game Genie running its menu from the ROM dump at `path`
+    pub fn game_genie_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.game_genie = Some(GameGenieSource::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn pixel_format(mut self, format: PixelFormat) -> Self {
+        self.pixel_format = format;
+        self
+    }
+
+    /// Audio samples per second, within [`SAMPLE_RATES`]
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    /// Keeps about `frames` frames of rewind history, see [`Console::enable_rewind`]
+    pub fn rewind(mut self, frames: usize) -> Self {
+        self.rewind = Some(frames);
+        self
+    }
+
+    /// Keeps the last `len` instructions for [crash dumps](crate::crash)
+    pub fn instruction_history(mut self, len: usize) -> Self {
+        self.instruction_history = Some(len);
+        self
+    }
+
+    /// Turns on [developer warnings](crate::diagnostics)
+    pub fn diagnostics(mut self, enabled: bool) -> Self {
+        self.diagnostics = enabled;
+        self
+    }
+
+    /// Runs through the first frames while loading, see [`Console::set_fast_boot`]
+    pub fn fast_boot(mut self, enabled: bool) -> Self {
+        self.fast_boot = enabled;
+        self
+    }
+
+    /// Runs boards beyond NROM with the [mappers](crate::mapper) in `mappers`
+    pub fn mappers(mut self, mappers: MapperRegistry) -> Self {
+        self.mappers = mappers;
+        self
+    }
+
+    /// Checks every setting, then powers on a console with them and loads the ROM or program
+    pub fn build(self) -> Result<Console, BuildError> {
+        let mut problems = Vec::new();
+        let (program, rom_path) = match self.program {
+            Some(Program::RomPath(path)) => match Rom::from_path(&path) {
+                Ok(rom) => (Some(Program::Rom(rom)), Some(path)),
+                Err(error) => {
+                    problems.push(BuildProblem::Rom {
+                        path: Some(path),
+                        error,
+                    });
+                    (None, None)
+                }
+            },
+            program => (program, None),
+        };
+        if let Some(Program::Rom(rom)) = &program {
+            if let Err(error) = self.mappers.check(rom) {
+                problems.push(BuildProblem::Rom {
+                    path: rom_path.clone(),
+                    error,
+                });
+            }
+        }
+        let palette = match self.palette {
+            Some(PaletteSource::File(path)) => {
+                let palette = fs::read(&path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|data| {
+                        Palette::from_pal(&data)
+                            .ok_or_else(|| "not a 64 or 512 color .pal file".into())
+                    });
+                match palette {
+                    Ok(palette) => Some(palette),
+                    Err(message) => {
+                        problems.push(BuildProblem::Palette { path, message });
+                        None
+                    }
+                }
+            }
+            Some(PaletteSource::Palette(palette)) => Some(palette),
+            None => None,
+        };
+        let game_genie = match self.game_genie {
+            Some(GameGenieSource::File(path)) => {
+                let genie = fs::read(&path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|bios| GameGenie::new(&bios).map_err(|err| err.to_string()));
+                match genie {
+                    Ok(genie) => Some(genie),
+                    Err(message) => {
+                        problems.push(BuildProblem::GameGenie { path, message });
+                        None
+                    }
+                }
+            }
+            Some(GameGenieSource::GameGenie(genie)) => Some(genie),
+            None => None,
+        };
+        if let Some(rate) = self.sample_rate.filter(|rate| !SAMPLE_RATES.contains(rate)) {
+            problems.push(BuildProblem::SampleRate(rate));
+        }
+        if self.rewind == Some(0) {
+            problems.push(BuildProblem::RewindFrames);
+        }
+        if !problems.is_empty() {
+            return Err(BuildError { problems });
+        }
+
+        let mut console = Console::new();
+        *console.mappers_mut() = self.mappers;
+        console.force_region(self.region);
+        console.set_seed(self.seed);
+        console.set_ram_init(self.ram_init);
+        console.set_diagnostics(self.diagnostics);
+        if let Some(palette) = palette {
+            console.set_palette(palette);
+        }
+        console.set_pixel_format(self.pixel_format);
+        if let Some(rate) = self.sample_rate {
+            console.apu_mut().set_sample_rate(rate);
+        }
+        if let Some(frames) = self.rewind {
+            console.enable_rewind(frames);
+        }
+        if let Some(len) = self.instruction_history {
+            console.enable_instruction_history(len);
+        }
+        console.set_fast_boot(self.fast_boot);
+        console.set_game_genie(game_genie);
+        match program {
+            Some(Program::Rom(rom)) => console.load_rom(&rom).map_err(|error| BuildError {
+                problems: vec![BuildProblem::Rom {
+                    path: rom_path,
+                    error,
+                }],
+            })?,
+            Some(Program::Bytes(program)) => console.load(&program),
+            Some(Program::RomPath(_)) | None => {}
+        }
+        Ok(console)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collects_every_problem() {
+        let err = ConsoleBuilder::new()
+            .rom_path("/nonexistent/game.nes")
+            .palette_file("/nonexistent/palette.pal")
+            .sample_rate(1_000_000)
+            .rewind(0)
+            .build()
+            .err()
+            .unwrap();
+        let problems: Vec<_> = err.problems.iter().map(ToString::to_string).collect();
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(problems[0].starts_with("/nonexistent/game.nes: "));
+        assert!(problems[1].starts_with("/nonexistent/palette.pal: "));
+        assert_eq!(problems[2], "sample rate 1000000 is outside 8000..=192000");
+        assert_eq!(problems[3], "rewind needs room for at least one frame");
+
+        let mut console = ConsoleBuilder::new()
+            .program(&[0xe6, 0x10, 0x4c, 0x00, 0x80])
+            .region(Region::Pal)
+            .sample_rate(22_050)
+            .build()
+            .unwrap();
+        console.run_frame();
+        assert_eq!(console.region(), Region::Pal);
+        assert_eq!(console.apu().sample_rate(), 22_050);
+        assert!(console.audio_samples().len().abs_diff(22_050 / 50) <= 1);
+    }
+}
@@ -0,0 +1,823 @@
+//! The CPU address bus.
+//!
+//! The [`Bus`] owns everything the CPU can address and routes reads and writes to it. Memory is
+//! still one flat 64KB space, with these devices wired in:
+//!
+//! | Address | Device |
+//! | :--- | :--- |
+//! | `0x2000..=0x3FFF` | the [`Ppu`]'s registers, mirrored every 8 bytes |
+//! | `0x4000..=0x4013`, `0x4015` | the [`Apu`]'s channel and status registers |
+//! | `0x4014` | OAM DMA: copies a page of memory into the PPU's sprite memory |
+//! | `0x4016`, `0x4017` | the two [`Joypad`]s; writes to `0x4017` set the APU's frame counter |
+//! | `0x4020..=0x5FFF` | [`Device`]s attached by the embedder, then the board's [`Mapper`]; plain memory on NROM |
+//! | `0x6000..=0x7FFF` | cartridge save RAM, battery backed on some boards |
+//! | `0x8000..=0xFFFF` | PRG ROM; writes go to the board's [`Mapper`], if it has one |
+//!
+//! For comparing against hardware traces the bus can also record every access it sees; see
+//! [`Bus::start_trace`], [`Bus::start_capture`] for accesses stamped with their cycle, or only
+//! the writes to save RAM with [`Bus::track_sram_writes`]. Debugging tools that need to tell
+//! mirrored or switched PRG ROM apart ask [`Bus::bank_address`] which bank a CPU address is
+//! reading.
+//!
+//! Test harnesses can wire their own hardware into the unused expansion range with
+//! [`Bus::attach_device`], e.g. the "write a byte here to print it" port many homebrew test ROMs
+//! expect:
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! use nes_emulator::bus::{Bus, Device};
+//! use nes_emulator::cpu::Mem;
+//!
+//! #[derive(Default)]
+//! struct Printer(String);
+//!
+//! impl Device for Printer {
+//!     fn write(&mut self, _addr: u16, value: u8) {
+//!         self.0.push(value as char);
+//!     }
+//! }
+//!
+//! let printer = Arc::new(Mutex::new(Printer::default()));
+//! let mut bus = Bus::new();
+//! bus.attach_device(0x5000..=0x5000, printer.clone())?;
+//! for byte in b"ok" {
+//!     bus.mem_write(0x5000, *byte);
+//! }
+//! assert_eq!(printer.lock().unwrap().0, "ok");
+//! # Ok::<(), nes_emulator::bus::DeviceError>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::apu::Apu;
+use crate::capture::Capture;
+use crate::cpu::Mem;
+use crate::feedback::{Feedback, FeedbackEvent, FeedbackRule};
+use crate::game_genie::{self, GameGenie};
+use crate::joypad::Joypad;
+use crate::mapper::{Board, Cartridge, Mapper, PRG_SLOTS, PRG_SLOT_SIZE};
+use crate::ppu::Ppu;
+use crate::savestate::{self, ChunkReader, ChunkWriter, StateError, StateWriter};
+
+const PPU_REGISTERS_START: u16 = 0x2000;
+const PPU_REGISTERS_END: u16 = 0x3FFF;
+const APU_REGISTERS_START: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const APU_STATUS: u16 = 0x4015;
+const OAM_DMA: u16 = 0x4014;
+/// CPU cycles an OAM DMA stalls for, ignoring the extra cycle when it starts on an odd one
+const OAM_DMA_CYCLES: u64 = 513;
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;
+/// Addresses [`Bus::attach_device`] accepts, which nothing on the console or an NROM board uses
+pub const DEVICE_RANGE: RangeInclusive<u16> = 0x4020..=0x5FFF;
+const DEVICE_START: u16 = *DEVICE_RANGE.start();
+const DEVICE_END: u16 = *DEVICE_RANGE.end();
+const SRAM_START: u16 = 0x6000;
+const SRAM_END: u16 = 0x7FFF;
+/// Bytes of cartridge save RAM
+pub const SRAM_SIZE: usize = 0x2000;
+const PRG_ROM_START: u16 = 0x8000;
+/// Size of the PRG ROM banks [`BankAddress`] counts in
+pub const PRG_BANK_SIZE: usize = 0x4000;
+
+/// One CPU write to cartridge save RAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SramWrite {
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// Where a CPU address reads from in the cartridge: which 16KB PRG ROM bank and how far into it.
+/// Unlike the CPU address, it's the same for every window the bank is mapped into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BankAddress {
+    /// `None` outside PRG ROM, where `offset` is the CPU address
+    pub bank: Option<u8>,
+    pub offset: u16,
+}
+
+/// `bank:offset` in hex, e.g. `01:3F00`, or `--:0300` outside PRG ROM
+impl fmt::Display for BankAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{bank:02X}:{:04X}", self.offset),
+            None => write!(f, "--:{:04X}", self.offset),
+        }
+    }
+}
+
+/// Host side hardware on the bus, called for CPU accesses to the range it's attached at.
+///
+/// Devices are outside the emulated machine: they're not part of save states or the state hash,
+/// and a device whose reads depend on the host makes runs depend on it too.
+pub trait Device: Send {
+    /// A CPU read. `None` reads what's behind the device, as if it weren't there: the board's
+    /// expansion area, or memory on NROM.
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        let _ = addr;
+        None
+    }
+
+    /// What [`Device::read`] would return, without side effects, for debuggers
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let _ = addr;
+        None
+    }
+
+    /// A CPU write, which also reaches what's behind the device
+    fn write(&mut self, addr: u16, value: u8) {
+        let _ = (addr, value);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// Not inside [`DEVICE_RANGE`], with the range asked for
+    OutsideRange(RangeInclusive<u16>),
+    /// Overlapping the range of a device already attached
+    Overlaps(RangeInclusive<u16>),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::OutsideRange(range) => write!(
+                f,
+                "{:#06x}..={:#06x} is outside {:#06x}..={:#06x}",
+                range.start(),
+                range.end(),
+                DEVICE_RANGE.start(),
+                DEVICE_RANGE.end()
+            ),
+            DeviceError::Overlaps(range) => write!(
+                f,
+                "{:#06x}..={:#06x} overlaps another device",
+                range.start(),
+                range.end()
+            ),
+        }
+    }
+}
+
+impl Error for DeviceError {}
+
+/// A [`Device`] and the addresses it answers to. Clones of the bus share the device.
+#[derive(Clone)]
+struct AttachedDevice {
+    range: RangeInclusive<u16>,
+    device: Arc<Mutex<dyn Device>>,
+}
+
+/// One bus cycle recorded by a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+#[derive(Clone)]
+pub struct Bus {
+    memory: [u8; 0x10000],
+    pub(crate) ppu: Ppu,
+    pub(crate) apu: Apu,
+    pub(crate) joypad1: Joypad,
+    pub(crate) joypad2: Joypad,
+    /// CPU cycles spent on DMA that the CPU hasn't been charged for yet
+    dma_cycles: u64,
+    /// Whether one 16KB PRG ROM bank fills both halves of `0x8000..=0xFFFF`
+    pub(crate) prg_mirrored: bool,
+    /// Where in PRG ROM each 4KB of `0x8000..=0xFFFF` comes from, on boards with a mapper
+    prg_map: [usize; PRG_SLOTS],
+    /// The registered mapper of boards other than NROM
+    board: Option<Board>,
+    trace: Option<Vec<BusAccess>>,
+    capture: Option<Capture>,
+    sram_writes: Option<Vec<SramWrite>>,
+    feedback: Option<Feedback>,
+    devices: Vec<AttachedDevice>,
+    /// Plugged in between the console and the cartridge
+    game_genie: Option<GameGenie>,
+}
+
+/// The trace, capture, save RAM log, feedback rules and devices are tooling state, not part of
+/// the emulated machine. The PRG ROM layout belongs to the cartridge, which is hashed through
+/// memory and the mapper's registers.
+impl Hash for Bus {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.memory.hash(state);
+        self.ppu.hash(state);
+        self.apu.hash(state);
+        self.joypad1.hash(state);
+        self.joypad2.hash(state);
+        self.dma_cycles.hash(state);
+        if let Some(board) = &self.board {
+            let mut registers = Vec::new();
+            StateWriter::new(&mut registers)
+                .chunk(savestate::MAPPER, |w| board.mapper.save_state(w));
+            registers.hash(state);
+        }
+        if let Some(genie) = &self.game_genie {
+            genie.hash(state);
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self {
+            memory: [0u8; 0x10000],
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            dma_cycles: 0,
+            prg_mirrored: false,
+            prg_map: [0; PRG_SLOTS],
+            board: None,
+            trace: None,
+            capture: None,
+            sram_writes: None,
+            feedback: None,
+            devices: Vec::new(),
+            game_genie: None,
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Controller plugged into port `player` (0 or 1)
+    pub fn joypad(&self, player: usize) -> &Joypad {
+        match player {
+            0 => &self.joypad1,
+            1 => &self.joypad2,
+            _ => panic!("no controller port {player}"),
+        }
+    }
+
+    pub fn joypad_mut(&mut self, player: usize) -> &mut Joypad {
+        match player {
+            0 => &mut self.joypad1,
+            1 => &mut self.joypad2,
+            _ => panic!("no controller port {player}"),
+        }
+    }
+
+    /// Starts recording every read and write, discarding any trace in progress
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// Stops recording and returns the accesses since [`Bus::start_trace`], oldest first
+    pub fn take_trace(&mut self) -> Vec<BusAccess> {
+        self.trace.take().unwrap_or_default()
+    }
+
+    /// Starts a cycle-stamped [`Capture`] of the next `limit` accesses, discarding any capture in
+    /// progress. [`Console`](crate::console::Console) keeps the stamps in step with the CPU; on a
+    /// bare bus or CPU they count up from 0.
+    pub fn start_capture(&mut self, limit: usize) {
+        self.capture = Some(Capture::new(limit));
+    }
+
+    /// Stops capturing and returns the capture, full or not. `None` if none was started.
+    pub fn take_capture(&mut self) -> Option<Capture> {
+        self.capture.take()
+    }
+
+    /// Stamps the next access, if capturing, with the CPU cycle it happens on
+    pub(crate) fn sync_capture(&mut self, cycle: u64) {
+        if let Some(capture) = &mut self.capture {
+            capture.sync(cycle);
+        }
+    }
+
+    /// Starts or stops logging writes to save RAM
+    pub fn track_sram_writes(&mut self, enabled: bool) {
+        self.sram_writes = enabled.then(Vec::new);
+    }
+
+    /// Returns the save RAM writes since the last call, oldest first. Empty while not tracking.
+    pub fn take_sram_writes(&mut self) -> Vec<SramWrite> {
+        match &mut self.sram_writes {
+            Some(writes) => std::mem::take(writes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Turns writes matching `rules` into [`FeedbackEvent`]s for [`Bus::take_feedback`], in
+    /// place of any rules set before. No rules turns feedback off.
+    pub fn set_feedback(&mut self, rules: Vec<FeedbackRule>) {
+        self.feedback = (!rules.is_empty()).then(|| Feedback::new(rules));
+    }
+
+    /// Returns the feedback events since the last call, oldest first. Empty without rules.
+    pub fn take_feedback(&mut self) -> Vec<FeedbackEvent> {
+        match &mut self.feedback {
+            Some(feedback) => std::mem::take(&mut feedback.events),
+            None => Vec::new(),
+        }
+    }
+
+    /// Routes CPU accesses to `range`, which must be inside [`DEVICE_RANGE`], to `device`. Keep a
+    /// clone of the `Arc` to look at the device as the game runs.
+    pub fn attach_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Arc<Mutex<dyn Device>>,
+    ) -> Result<(), DeviceError> {
+        if range.is_empty()
+            || !DEVICE_RANGE.contains(range.start())
+            || !DEVICE_RANGE.contains(range.end())
+        {
+            return Err(DeviceError::OutsideRange(range));
+        }
+        if self.devices.iter().any(|attached| {
+            attached.range.start() <= range.end() && range.start() <= attached.range.end()
+        }) {
+            return Err(DeviceError::Overlaps(range));
+        }
+        self.devices.push(AttachedDevice { range, device });
+        Ok(())
+    }
+
+    pub fn detach_devices(&mut self) {
+        self.devices.clear();
+    }
+
+    fn device_at(&self, addr: u16) -> Option<&Arc<Mutex<dyn Device>>> {
+        self.devices
+            .iter()
+            .find(|attached| attached.range.contains(&addr))
+            .map(|attached| &attached.device)
+    }
+
+    /// What the CPU reads from the cartridge through the Game Genie
+    fn peek_game_genie(&self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.game_genie
+            .as_ref()
+            .map_or(value, |genie| genie.read(addr, value))
+    }
+
+    fn read_device(&mut self, addr: u16) -> u8 {
+        if let Some(value) = self
+            .device_at(addr)
+            .and_then(|device| lock(device).read(addr))
+        {
+            return value;
+        }
+        match self.with_board(|mapper, cart| mapper.read_expansion(cart, addr)) {
+            Some(value) => value.unwrap_or_else(|| open_bus(addr)),
+            None => self.memory[addr as usize],
+        }
+    }
+
+    fn peek_device(&self, addr: u16) -> u8 {
+        if let Some(value) = self
+            .device_at(addr)
+            .and_then(|device| lock(device).peek(addr))
+        {
+            return value;
+        }
+        match &self.board {
+            Some(board) => board
+                .mapper
+                .peek_expansion(addr)
+                .unwrap_or_else(|| open_bus(addr)),
+            None => self.memory[addr as usize],
+        }
+    }
+
+    /// The cartridge's save RAM at `0x6000..=0x7FFF`
+    pub fn sram(&self) -> &[u8] {
+        &self.memory[SRAM_START as usize..=SRAM_END as usize]
+    }
+
+    /// Restores save RAM from a battery save, which may be shorter than [`SRAM_SIZE`]
+    pub fn load_sram(&mut self, data: &[u8]) {
+        let len = data.len().min(SRAM_SIZE);
+        self.load(SRAM_START, &data[..len]);
+    }
+
+    /// The cycles [`Bus::take_dma_cycles`] would return
+    pub(crate) fn pending_dma_cycles(&self) -> u64 {
+        self.dma_cycles
+    }
+
+    /// Returns the cycles DMA has stalled the CPU for since the last call
+    pub fn take_dma_cycles(&mut self) -> u64 {
+        std::mem::take(&mut self.dma_cycles)
+    }
+
+    /// Advances the APU by `cpu_cycles`, charging the CPU for the DMC's sample fetches like DMA
+    pub fn tick_apu(&mut self, cpu_cycles: u64) {
+        self.dma_cycles += self.apu.tick(cpu_cycles, &self.memory);
+    }
+
+    /// Advances the board's mapper by `cpu_cycles`
+    pub fn tick_board(&mut self, cpu_cycles: u64) {
+        if let Some(board) = &mut self.board {
+            board.mapper.tick(cpu_cycles);
+        }
+    }
+
+    /// Whether the APU or the board is holding the CPU's IRQ line
+    pub fn irq_pending(&self) -> bool {
+        self.apu.irq_pending() || self.mapper_irq()
+    }
+
+    pub(crate) fn mapper_irq(&self) -> bool {
+        self.board
+            .as_ref()
+            .is_some_and(|board| board.mapper.irq_pending())
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as usize) << 8;
+        let mut data = [0; 256];
+        data.copy_from_slice(&self.memory[start..start + 256]);
+        self.ppu.write_oam_dma(&data);
+        self.dma_cycles += OAM_DMA_CYCLES;
+    }
+
+    fn record(&mut self, addr: u16, value: u8, write: bool) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(BusAccess { addr, value, write });
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.record(addr, value, write);
+        }
+    }
+
+    /// Copies `data` into memory starting at `addr`, bypassing any memory mapped devices. Whatever
+    /// runs past `0xFFFF` is dropped.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let memory = &mut self.memory[addr as usize..];
+        let len = data.len().min(memory.len());
+        memory[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Maps a cartridge's PRG ROM at `0x8000`, mirroring a single 16KB bank into `0xC000`
+    pub fn load_prg_rom(&mut self, prg_rom: &[u8]) {
+        self.load(PRG_ROM_START, prg_rom);
+        self.prg_mirrored = prg_rom.len() == PRG_BANK_SIZE;
+        if self.prg_mirrored {
+            self.load(PRG_ROM_START + PRG_BANK_SIZE as u16, prg_rom);
+        }
+    }
+
+    /// Plugs in a board's mapper and powers it on, or unplugs it with `None`. The mapper starts
+    /// with whatever PRG and CHR ROM is already mapped.
+    pub(crate) fn set_board(&mut self, board: Option<Board>) {
+        self.board = board;
+        // Until the mapper switches banks, the start of PRG ROM is mapped as is
+        let len = self
+            .board
+            .as_ref()
+            .map_or(0, |board| board.prg_rom.len())
+            .max(1);
+        self.prg_map = std::array::from_fn(|slot| slot * PRG_SLOT_SIZE % len);
+        self.power_on_board();
+    }
+
+    pub(crate) fn has_board(&self) -> bool {
+        self.board.is_some()
+    }
+
+    /// All of the board's CHR ROM, not just the banks mapped in. Empty for NROM and boards with
+    /// CHR RAM.
+    pub fn chr_rom(&self) -> &[u8] {
+        self.board.as_ref().map_or(&[], |board| &board.chr_rom)
+    }
+
+    pub(crate) fn power_on_board(&mut self) {
+        self.with_board(|mapper, cart| mapper.power_on(cart));
+        if let Some(genie) = &mut self.game_genie {
+            genie.power_on();
+        }
+        self.sync_game_genie();
+    }
+
+    pub(crate) fn game_genie(&self) -> Option<&GameGenie> {
+        self.game_genie.as_ref()
+    }
+
+    pub(crate) fn set_game_genie(&mut self, genie: Option<GameGenie>) {
+        self.game_genie = genie;
+        self.sync_game_genie();
+    }
+
+    pub(crate) fn save_game_genie(&self, w: &mut ChunkWriter) {
+        if let Some(genie) = &self.game_genie {
+            genie.save_state(w);
+        }
+    }
+
+    pub(crate) fn load_game_genie(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        if let Some(genie) = &mut self.game_genie {
+            genie.load_state(r)?;
+        }
+        self.sync_game_genie();
+        Ok(())
+    }
+
+    /// Shows the Game Genie's pattern tables while its menu runs
+    fn sync_game_genie(&mut self) {
+        let menu = self.game_genie.as_ref().is_some_and(GameGenie::in_menu);
+        if menu != self.ppu.has_chr_overlay() {
+            self.ppu
+                .set_chr_overlay(menu.then(game_genie::menu_pattern_tables));
+        }
+    }
+
+    pub(crate) fn save_board(&self, w: &mut ChunkWriter) {
+        if let Some(board) = &self.board {
+            board.mapper.save_state(w);
+        }
+    }
+
+    pub(crate) fn load_board(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.with_board(|mapper, cart| mapper.load_state(cart, r))
+            .unwrap_or(Ok(()))
+    }
+
+    fn with_board<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Mapper, &mut Cartridge<'_>) -> R,
+    ) -> Option<R> {
+        let board = self.board.as_mut()?;
+        let mut cart = Cartridge {
+            prg_rom: &board.prg_rom,
+            chr_rom: &board.chr_rom,
+            memory: &mut self.memory,
+            ppu: &mut self.ppu,
+            prg_map: &mut self.prg_map,
+        };
+        Some(f(board.mapper.as_mut(), &mut cart))
+    }
+
+    /// The PRG ROM bank and offset `addr` currently reads, without side effects. Anything below
+    /// `0x8000` isn't ROM. Bare programs count as one 32KB ROM, and on boards with a mapper it's
+    /// whichever bank the mapper last switched in.
+    pub fn bank_address(&self, addr: u16) -> BankAddress {
+        if addr < PRG_ROM_START {
+            return BankAddress {
+                bank: None,
+                offset: addr,
+            };
+        }
+        let mut rom_offset = (addr - PRG_ROM_START) as usize;
+        if self.board.is_some() {
+            rom_offset = self.prg_map[rom_offset / PRG_SLOT_SIZE] + rom_offset % PRG_SLOT_SIZE;
+        }
+        let bank = if self.prg_mirrored && self.board.is_none() {
+            0
+        } else {
+            rom_offset / PRG_BANK_SIZE
+        };
+        BankAddress {
+            bank: Some(bank as u8),
+            offset: (rom_offset % PRG_BANK_SIZE) as u16,
+        }
+    }
+
+    /// Writes the full 64KB address space to a save-state chunk
+    pub fn save_memory(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.memory);
+    }
+
+    pub fn load_memory(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        let len = self.memory.len();
+        self.memory.copy_from_slice(r.read_bytes(len)?);
+        Ok(())
+    }
+
+    pub fn save_joypads(&self, w: &mut ChunkWriter) {
+        self.joypad1.save_state(w);
+        self.joypad2.save_state(w);
+    }
+
+    pub fn load_joypads(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.joypad1.load_state(r)?;
+        self.joypad2.load_state(r)
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = match addr {
+            PPU_REGISTERS_START..=PPU_REGISTERS_END => self.ppu.read_register(addr),
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD_1 => self.joypad1.read(),
+            JOYPAD_2 => self.joypad2.read(),
+            DEVICE_START..=DEVICE_END => self.read_device(addr),
+            PRG_ROM_START..=0xFFFF if self.game_genie.is_some() => self.peek_game_genie(addr),
+            _ => self.memory[addr as usize],
+        };
+        self.record(addr, value, false);
+        value
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            PPU_REGISTERS_START..=PPU_REGISTERS_END => self.ppu.peek_register(addr),
+            APU_STATUS => self.apu.peek_status(),
+            JOYPAD_1 => self.joypad1.peek(),
+            JOYPAD_2 => self.joypad2.peek(),
+            DEVICE_START..=DEVICE_END => self.peek_device(addr),
+            PRG_ROM_START..=0xFFFF if self.game_genie.is_some() => self.peek_game_genie(addr),
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.record(addr, data, true);
+        if let Some(feedback) = &mut self.feedback {
+            feedback.observe(addr, data);
+        }
+        match addr {
+            PPU_REGISTERS_START..=PPU_REGISTERS_END => self.ppu.write_register(addr, data),
+            APU_REGISTERS_START..=APU_REGISTERS_END | APU_STATUS | JOYPAD_2 => {
+                self.apu.write_register(addr, data)
+            }
+            OAM_DMA => self.oam_dma(data),
+            // Both controllers share the strobe line on 0x4016
+            JOYPAD_1 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            SRAM_START..=SRAM_END => {
+                self.memory[addr as usize] = data;
+                if let Some(writes) = &mut self.sram_writes {
+                    writes.push(SramWrite { addr, value: data });
+                }
+            }
+            DEVICE_START..=DEVICE_END => {
+                if let Some(device) = self.device_at(addr) {
+                    lock(device).write(addr, data);
+                }
+                if self
+                    .with_board(|mapper, cart| mapper.write_expansion(cart, addr, data))
+                    .is_none()
+                {
+                    self.memory[addr as usize] = data;
+                }
+            }
+            PRG_ROM_START..=0xFFFF if self.game_genie.as_ref().is_some_and(GameGenie::in_menu) => {
+                if let Some(genie) = &mut self.game_genie {
+                    genie.write(addr, data);
+                }
+                self.sync_game_genie();
+            }
+            PRG_ROM_START..=0xFFFF if self.board.is_some() => {
+                self.with_board(|mapper, cart| mapper.write(cart, addr, data));
+            }
+            _ => self.memory[addr as usize] = data,
+        }
+    }
+}
+
+/// What a read nothing answers returns: the last byte on the data bus, which for the absolute
+/// addressed reads that reach the expansion area is the high byte of the address
+fn open_bus(addr: u16) -> u8 {
+    (addr >> 8) as u8
+}
+
+/// Locks a device, carrying on if a panic poisoned it: a device has no invariants to protect
+/// that the emulator relies on
+fn lock<'a>(device: &'a Mutex<dyn Device + 'static>) -> MutexGuard<'a, dyn Device + 'static> {
+    device
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::JoypadButton;
+
+    #[test]
+    fn test_joypad_ports() {
+        let mut bus = Bus::new();
+        bus.joypad2.set_buttons(JoypadButton::BUTTON_B);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        assert_eq!(bus.mem_read(0x4016), 0); // player 1 A
+        assert_eq!(bus.mem_read(0x4017), 0); // player 2 A
+        assert_eq!(bus.mem_peek(0x4017), 1); // player 2 B, not shifted out yet
+        assert_eq!(bus.mem_read(0x4017), 1);
+    }
+
+    #[test]
+    fn test_trace_records_accesses() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0010, 7);
+        bus.start_trace();
+        bus.mem_write(0x0011, 8);
+        bus.mem_read(0x0010);
+        bus.mem_peek(0x0011);
+
+        let access = |addr, value, write| BusAccess { addr, value, write };
+        assert_eq!(
+            bus.take_trace(),
+            [access(0x0011, 8, true), access(0x0010, 7, false)]
+        );
+        bus.mem_read(0x0010);
+        assert!(bus.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_bank_address() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.bank_address(0x0300).to_string(), "--:0300");
+        assert_eq!(bus.bank_address(0xC123).to_string(), "01:0123");
+        bus.load_prg_rom(&[0; PRG_BANK_SIZE]);
+        assert_eq!(bus.bank_address(0xC123), bus.bank_address(0x8123));
+        assert_eq!(bus.bank_address(0xFFFF).to_string(), "00:3FFF");
+    }
+
+    #[test]
+    fn test_load_drops_what_runs_past_the_end() {
+        let mut bus = Bus::new();
+        bus.load(0xFFFE, &[1, 2, 3]);
+        assert_eq!((bus.mem_peek(0xFFFE), bus.mem_peek(0xFFFF)), (1, 2));
+        assert_eq!(bus.mem_peek(0x0000), 0);
+        // A program longer than the 32KB of PRG ROM space
+        bus.load(PRG_ROM_START, &[0xEA; 0x9000]);
+        assert_eq!(bus.mem_peek(0xFFFF), 0xEA);
+    }
+
+    #[test]
+    fn test_devices_take_their_range() {
+        /// Counts writes and reads back the count, at either address
+        #[derive(Default)]
+        struct Counter(u8);
+
+        impl Device for Counter {
+            fn read(&mut self, _addr: u16) -> Option<u8> {
+                Some(self.0)
+            }
+
+            fn peek(&self, _addr: u16) -> Option<u8> {
+                Some(self.0)
+            }
+
+            fn write(&mut self, _addr: u16, _value: u8) {
+                self.0 += 1;
+            }
+        }
+
+        let counter = Arc::new(Mutex::new(Counter::default()));
+        let mut bus = Bus::new();
+        bus.attach_device(0x5000..=0x5001, counter.clone()).unwrap();
+        bus.mem_write(0x5000, 0xAA);
+        bus.mem_write(0x5001, 0xBB);
+        bus.mem_write(0x5002, 0xCC);
+        assert_eq!(bus.mem_read(0x5000), 2);
+        assert_eq!(bus.mem_peek(0x5001), 2);
+        assert_eq!(bus.mem_read(0x5002), 0xCC);
+        assert_eq!(counter.lock().unwrap().0, 2);
+
+        let other = Arc::new(Mutex::new(Counter::default()));
+        assert_eq!(
+            bus.attach_device(0x4FFF..=0x5000, other.clone()),
+            Err(DeviceError::Overlaps(0x4FFF..=0x5000))
+        );
+        assert_eq!(
+            bus.attach_device(0x6000..=0x6000, other)
+                .unwrap_err()
+                .to_string(),
+            "0x6000..=0x6000 is outside 0x4020..=0x5fff"
+        );
+        bus.detach_devices();
+        assert_eq!(bus.mem_read(0x5000), 0xAA);
+    }
+}
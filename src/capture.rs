@@ -0,0 +1,185 @@
+//! Cycle-stamped bus captures, for comparing against real hardware.
+//!
+//! A [`Bus::start_trace`](crate::bus::Bus::start_trace) says what the CPU accessed, but not
+//! when. A [`Capture`] also stamps every access with the CPU cycle it happened on, which is what
+//! lines up against a logic analyzer hooked to a real console, or a Visual6502 or Visual2C02
+//! trace, when chasing the last few accuracy bugs. It holds a bounded window of accesses packed
+//! into 8 bytes each, so a capture of a few million cycles stays small, and
+//! [`Capture::to_csv`] exports it in the column layout logic analyzer software imports.
+//!
+//! Start one on the console's bus; [`Console::run_frame`] keeps the stamps in step with the CPU:
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0xa9, 0x42, 0x85, 0x10, 0x4c, 0x04, 0x80]); // LDA #$42; STA $10; spin
+//! let cycle = console.cpu().cycles();
+//! console.cpu_mut().bus_mut().start_capture(4);
+//! console.run_frame();
+//! let capture = console.cpu_mut().bus_mut().take_capture().unwrap();
+//! assert!(capture.is_full());
+//! assert_eq!(
+//!     capture.to_csv(),
+//!     format!(
+//!         "cycle,address,data,rw\n{},8000,A9,1\n{},8001,42,1\n{},8002,85,1\n{},8003,10,1\n",
+//!         cycle,
+//!         cycle + 1,
+//!         cycle + 2,
+//!         cycle + 3
+//!     )
+//! );
+//! ```
+//!
+//! OAM and DMC DMA copy memory without going through the bus here, so their reads don't show
+//! up, and stalls only appear as a gap in the cycle stamps.
+//!
+//! [`Console::run_frame`]: crate::console::Console::run_frame
+
+use std::fmt::Write;
+
+/// Bits of a packed access holding the cycle, counted from the capture's first access: enough for
+/// about 85 hours of emulation
+const CYCLE_BITS: u32 = 39;
+const CYCLE_SHIFT: u32 = 64 - CYCLE_BITS;
+const WRITE_BIT: u64 = 1 << 24;
+
+/// One bus access in a [`Capture`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapturedAccess {
+    /// The CPU cycle count when the access happened
+    pub cycle: u64,
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    /// Cycle, write flag, value and address, from the top bit down
+    accesses: Vec<u64>,
+    limit: usize,
+    /// The cycle of the first access, which the packed cycles count from
+    start: Option<u64>,
+    /// The cycle the next access happens on
+    cycle: u64,
+}
+
+impl Capture {
+    /// An empty capture that keeps the first `limit` accesses. The whole buffer is allocated
+    /// up front, so capturing doesn't slow emulation down as it fills.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            accesses: Vec::with_capacity(limit),
+            limit,
+            start: None,
+            cycle: 0,
+        }
+    }
+
+    /// Stamps the next access with `cycle`. Accesses after it count up one cycle each, as every
+    /// CPU access takes a cycle.
+    pub(crate) fn sync(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    pub(crate) fn record(&mut self, addr: u16, value: u8, write: bool) {
+        if self.is_full() {
+            return;
+        }
+        let start = *self.start.get_or_insert(self.cycle);
+        let cycle = self.cycle - start;
+        self.accesses.push(
+            (cycle << CYCLE_SHIFT)
+                | if write { WRITE_BIT } else { 0 }
+                | (value as u64) << 16
+                | addr as u64,
+        );
+        self.cycle += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.accesses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accesses.is_empty()
+    }
+
+    /// Whether the capture holds as many accesses as it was asked to, and takes no more
+    pub fn is_full(&self) -> bool {
+        self.accesses.len() >= self.limit
+    }
+
+    /// The accesses, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = CapturedAccess> + '_ {
+        let start = self.start.unwrap_or(0);
+        self.accesses.iter().map(move |&packed| CapturedAccess {
+            cycle: start + (packed >> CYCLE_SHIFT),
+            addr: packed as u16,
+            value: (packed >> 16) as u8,
+            write: packed & WRITE_BIT != 0,
+        })
+    }
+
+    /// A `cycle,address,data,rw` header, then one line per access with the cycle in decimal,
+    /// address and data in hex, and `rw` as the 6502's R/W pin: 1 for reads, 0 for writes
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("cycle,address,data,rw\n");
+        for access in self.iter() {
+            writeln!(
+                csv,
+                "{},{:04X},{:02X},{}",
+                access.cycle,
+                access.addr,
+                access.value,
+                u8::from(!access.write)
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+
+    #[test]
+    fn test_stamps_follow_the_cpu() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xe6, 0x10,       // loop: INC $10
+            0x4c, 0x00, 0x80, // JMP loop
+        ]);
+        console.cpu_mut().bus_mut().start_capture(1000);
+        console.run_frame();
+        let capture = console.cpu_mut().bus_mut().take_capture().unwrap();
+        assert!(capture.is_full());
+        assert!(console.cpu_mut().bus_mut().take_capture().is_none());
+
+        let accesses: Vec<CapturedAccess> = capture.iter().collect();
+        let first = accesses[0].cycle;
+        // INC zero page is 5 cycles and JMP is 3, each cycle one access
+        for (i, access) in accesses.iter().enumerate() {
+            assert_eq!(access.cycle, first + i as u64);
+        }
+        assert_eq!(
+            accesses[..5]
+                .iter()
+                .map(|a| (a.addr, a.write))
+                .collect::<Vec<_>>(),
+            [
+                (0x8000, false),
+                (0x8001, false),
+                (0x0010, false),
+                (0x0010, true),
+                (0x0010, true),
+            ]
+        );
+        assert_eq!(accesses[8].addr, 0x8000);
+        assert_eq!(accesses[4].value, 1);
+    }
+}
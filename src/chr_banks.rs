@@ -0,0 +1,195 @@
+//! Inspecting the CHR bank switches behind mapper-driven tile animation.
+//!
+//! Boards like the MMC3 animate waterfalls, conveyor belts and title screens by switching which
+//! CHR ROM banks sit in the pattern tables, every few frames or even mid-frame for a status bar.
+//! Turn on [`Ppu::track_chr_switches`](crate::ppu::Ppu::track_chr_switches), run a frame, then
+//! take its [`ChrSwitch`]es with [`Ppu::take_chr_switches`](crate::ppu::Ppu::take_chr_switches):
+//! each says which bank went where, and on which scanline and dot. [`to_text`] lists them,
+//! [`tile_sets`] picks out the distinct banks, and [`tile_set`] draws the tiles of one straight
+//! from CHR ROM, whether or not it's mapped in right now.
+//!
+//! ```no_run
+//! use nes_emulator::chr_banks;
+//! use nes_emulator::console::Console;
+//! use nes_emulator::rom::Rom;
+//!
+//! let mut console = Console::new();
+//! console.load_rom(&Rom::from_path("game.nes")?)?;
+//! console.ppu_mut().track_chr_switches(true);
+//! let mut switches = Vec::new();
+//! for _ in 0..60 {
+//!     console.run_frame();
+//!     switches.extend(console.ppu_mut().take_chr_switches());
+//! }
+//! print!("{}", chr_banks::to_text(&switches));
+//! let chr_rom = console.cpu().bus().chr_rom();
+//! for (size, bank) in chr_banks::tile_sets(&switches) {
+//!     chr_banks::tile_set(chr_rom, size, bank, console.ppu(), 0, console.palette())
+//!         .save_png(format!("bank_{size:x}_{bank}.png"))?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The picture is drawn at the end of each frame with the banks mapped in then, so a switch
+//! mid-frame shows up in the log but not on screen.
+
+use crate::frame::Palette;
+use crate::mapper::copy_bank;
+use crate::ppu::{ChrSwitch, Ppu};
+use crate::ripper::{palette_color, Sheet, TILES_PER_ROW};
+
+const TILE_SIZE: usize = 16;
+
+/// One line per switch: scanline and dot, where the bank went, its size and its number
+pub fn to_text(switches: &[ChrSwitch]) -> String {
+    let mut text = String::new();
+    for switch in switches {
+        text += &format!(
+            "{:3}:{:3} ${:04X} {}KB bank {}\n",
+            switch.scanline,
+            switch.dot,
+            switch.addr,
+            switch.size as f64 / 1024.0,
+            switch.bank
+        );
+    }
+    text
+}
+
+/// The distinct banks mapped in, as `(size, bank)` in the order first seen
+pub fn tile_sets(switches: &[ChrSwitch]) -> Vec<(usize, usize)> {
+    let mut sets = Vec::new();
+    for switch in switches {
+        if !sets.contains(&(switch.size, switch.bank)) {
+            sets.push((switch.size, switch.bank));
+        }
+    }
+    sets
+}
+
+/// The tiles of CHR ROM bank `bank`, counted in `size` byte banks as a [`ChrSwitch`] counts
+/// them, 16 to a row in PPU palette `palette`
+pub fn tile_set(
+    chr_rom: &[u8],
+    size: usize,
+    bank: usize,
+    ppu: &Ppu,
+    palette: u8,
+    colors: &Palette,
+) -> Sheet {
+    let mut data = vec![0; size];
+    copy_bank(chr_rom, size, bank, &mut data);
+    let tiles = size / TILE_SIZE;
+    let mut sheet = Sheet::new(TILES_PER_ROW * 8, tiles.div_ceil(TILES_PER_ROW) * 8);
+    for (tile, bytes) in data.chunks_exact(TILE_SIZE).enumerate() {
+        let (left, top) = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let bit = 7 - x;
+                let color = ((bytes[y + 8] >> bit) & 1) << 1 | (bytes[y] >> bit) & 1;
+                let rgb = palette_color(ppu, colors, palette, color);
+                sheet.set_color(left + x, top + y, rgb);
+            }
+        }
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+    use crate::mapper::{Cartridge, Mapper};
+    use crate::rom::{Format, Mirroring, Rom};
+    use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+    /// A 4KB CHR bank at 0x1000 picked by any write
+    #[derive(Clone, Default)]
+    struct UpperChr;
+
+    impl Mapper for UpperChr {
+        fn power_on(&mut self, cart: &mut Cartridge<'_>) {
+            cart.map_chr(0x1000, 0x1000, 0);
+        }
+
+        fn write(&mut self, cart: &mut Cartridge<'_>, _addr: u16, value: u8) {
+            cart.map_chr(0x1000, 0x1000, value as usize);
+        }
+
+        fn save_state(&self, _w: &mut ChunkWriter) {}
+
+        fn load_state(
+            &mut self,
+            _cart: &mut Cartridge<'_>,
+            _r: &mut ChunkReader,
+        ) -> Result<(), StateError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_switches_are_logged_and_drawn() {
+        #[rustfmt::skip]
+        let mut prg_rom = [
+            0xa9, 0x03,       // LDA #3
+            0x8d, 0x00, 0x80, // STA $8000
+            0x4c, 0x05, 0x80, // loop: JMP loop
+        ].to_vec();
+        prg_rom.resize(0x4000, 0);
+        prg_rom[0x3FFD] = 0x80;
+        // Bank 3's first tile has its top row in color 1
+        let mut chr_rom = vec![0; 0x4000];
+        chr_rom[0x3000] = 0xFF;
+        let rom = Rom {
+            prg_rom,
+            chr_rom,
+            format: Format::INes,
+            mapper: 3,
+            submapper: 0,
+            board: None,
+            screen_mirroring: Mirroring::Vertical,
+            battery: false,
+            timing: None,
+        };
+        let mut console = Console::new();
+        console.mappers_mut().register(3, |_rom| Box::new(UpperChr));
+        console.load_rom(&rom).unwrap();
+        console.ppu_mut().track_chr_switches(true);
+        console.run_frame();
+
+        let switches = console.ppu_mut().take_chr_switches();
+        assert_eq!(switches.len(), 1);
+        let switch = switches[0];
+        assert_eq!((switch.addr, switch.size, switch.bank), (0x1000, 0x1000, 3));
+        assert_eq!(switch.scanline, 241);
+        assert_eq!(
+            to_text(&switches),
+            format!("241:{:3} $1000 4KB bank 3\n", switch.dot)
+        );
+        assert_eq!(tile_sets(&[switch, switch]), [(0x1000, 3)]);
+        console.run_frame();
+        assert!(console.ppu_mut().take_chr_switches().is_empty());
+
+        // Color 1 of background palette 0
+        let ppu = console.ppu_mut();
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x01);
+        ppu.write_register(0x2007, 0x16);
+        let colors = console.palette();
+        let sheet = tile_set(
+            console.cpu().bus().chr_rom(),
+            0x1000,
+            3,
+            console.ppu(),
+            0,
+            colors,
+        );
+        assert_eq!((sheet.width(), sheet.height()), (128, 128));
+        let rgba = |index| {
+            let (r, g, b) = colors.rgb(index);
+            [r, g, b, 0xFF]
+        };
+        assert_eq!(sheet.get_pixel(7, 0), rgba(0x16));
+        assert_eq!(sheet.get_pixel(7, 1), rgba(0x00));
+    }
+}
@@ -0,0 +1,232 @@
+//! A/B comparison: two emulators run in lockstep until their states diverge.
+//!
+//! Checking that a refactor or a faster code path didn't change behavior means running both
+//! versions on the same game and input and finding the first frame they disagree on. A
+//! [`Comparison`] holds two [`Machine`]s, starts them from the same save state with
+//! [`Comparison::sync`], runs them a frame at a time on the same input and compares
+//! their state hashes after every frame. The first [`Divergence`] comes back with the registers
+//! and memory of both sides, and its `Display` shows only what differs:
+//!
+//! ```text
+//! frame 41: state hash 8f2c91d04a7be315 != 09b1c6e2d3f4a857
+//! a     $0C  $0B
+//! $0010 $02  $01
+//! ```
+//!
+//! [`Console`] is a `Machine`; comparing against an older release means adding it as a renamed
+//! dependency and implementing `Machine` for its console too.
+//!
+//! ```
+//! use nes_emulator::compare::Comparison;
+//! use nes_emulator::console::Console;
+//! use nes_emulator::joypad::JoypadButton;
+//!
+//! let program = [0xe6, 0x10, 0x4c, 0x00, 0x80]; // loop: INC $10; JMP loop
+//! let (mut a, mut b) = (Console::new(), Console::new());
+//! a.load(&program);
+//! b.load(&program);
+//! a.run_frame();
+//! let mut comparison = Comparison::new(a, b);
+//! comparison.sync()?;
+//! let inputs = vec![[JoypadButton::empty(); 2]; 10];
+//! assert!(comparison.run(inputs.iter().copied()).is_none());
+//! # Ok::<(), nes_emulator::savestate::StateError>(())
+//! ```
+
+use std::fmt;
+
+use crate::console::Console;
+use crate::cpu::{Mem, Registers};
+use crate::joypad::JoypadButton;
+use crate::savestate::StateError;
+
+/// Bytes of internal RAM compared in a [`Snapshot`]
+const RAM_SIZE: u16 = 0x0800;
+
+/// An emulator a [`Comparison`] can run
+pub trait Machine {
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError>;
+    /// Runs one frame with both controllers holding `input`
+    fn run_frame(&mut self, input: [JoypadButton; 2]);
+    /// A hash of all emulated state, equal for equal states
+    fn state_hash(&self) -> u64;
+    /// What to show when the hashes differ
+    fn snapshot(&self) -> Snapshot;
+}
+
+impl Machine for Console {
+    fn save_state(&self) -> Vec<u8> {
+        Console::save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        Console::load_state(self, state)
+    }
+
+    fn run_frame(&mut self, input: [JoypadButton; 2]) {
+        for (player, buttons) in input.into_iter().enumerate() {
+            self.joypad_mut(player).set_buttons(buttons);
+        }
+        Console::run_frame(self);
+    }
+
+    fn state_hash(&self) -> u64 {
+        Console::state_hash(self)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.cpu().registers(),
+            cycles: self.cpu().cycles(),
+            ram: (0..RAM_SIZE)
+                .map(|addr| self.cpu().mem_peek(addr))
+                .collect(),
+            sram: self.cpu().bus().sram().to_vec(),
+        }
+    }
+}
+
+/// The CPU's view of a machine at the end of a frame
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub cycles: u64,
+    /// Internal RAM, `$0000-$07FF`
+    pub ram: Vec<u8>,
+    /// Cartridge save RAM, `$6000-$7FFF`
+    pub sram: Vec<u8>,
+}
+
+/// The first frame two machines disagreed on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Frames run before it, counted from the start of [`Comparison::run`]
+    pub frame: u64,
+    pub hashes: [u64; 2],
+    pub snapshots: [Snapshot; 2],
+}
+
+impl Divergence {
+    /// `(addr, a, b)` for every byte of RAM or save RAM that differs
+    pub fn memory_diff(&self) -> Vec<(u16, u8, u8)> {
+        let [a, b] = &self.snapshots;
+        let ram = (0..).zip(a.ram.iter().zip(&b.ram));
+        let sram = (0x6000..).zip(a.sram.iter().zip(&b.sram));
+        ram.chain(sram)
+            .filter(|(_, (a, b))| a != b)
+            .map(|(addr, (&a, &b))| (addr, a, b))
+            .collect()
+    }
+}
+
+/// A header line, then one line per register or byte that differs: its name, A's value and B's
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b] = &self.snapshots;
+        write!(
+            f,
+            "frame {}: state hash {:016x} != {:016x}",
+            self.frame, self.hashes[0], self.hashes[1]
+        )?;
+        let (ra, rb) = (a.registers, b.registers);
+        for (name, a, b) in [
+            ("a", ra.a, rb.a),
+            ("x", ra.x, rb.x),
+            ("y", ra.y, rb.y),
+            ("p", ra.status, rb.status),
+            ("sp", ra.sp, rb.sp),
+        ] {
+            if a != b {
+                write!(f, "\n{name:<5} ${a:02X}  ${b:02X}")?;
+            }
+        }
+        if ra.pc != rb.pc {
+            write!(f, "\npc    ${:04X}  ${:04X}", ra.pc, rb.pc)?;
+        }
+        if a.cycles != b.cycles {
+            write!(f, "\ncycle {}  {}", a.cycles, b.cycles)?;
+        }
+        let memory = self.memory_diff();
+        for (addr, a, b) in &memory {
+            write!(f, "\n${addr:04X} ${a:02X}  ${b:02X}")?;
+        }
+        if ra == rb && a.cycles == b.cycles && memory.is_empty() {
+            write!(
+                f,
+                "\nregisters and memory match; the difference is elsewhere"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Comparison<A: Machine, B: Machine> {
+    pub a: A,
+    pub b: B,
+}
+
+impl<A: Machine, B: Machine> Comparison<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+
+    /// Loads a save state of A into B, so both go on from the same state
+    pub fn sync(&mut self) -> Result<(), StateError> {
+        self.b.load_state(&self.a.save_state())
+    }
+
+    /// Runs both machines a frame per input until their state hashes differ after a frame, and
+    /// returns that frame. `None` if they agreed on every frame.
+    pub fn run(
+        &mut self,
+        inputs: impl IntoIterator<Item = [JoypadButton; 2]>,
+    ) -> Option<Divergence> {
+        for (frame, input) in (0..).zip(inputs) {
+            self.a.run_frame(input);
+            self.b.run_frame(input);
+            let hashes = [self.a.state_hash(), self.b.state_hash()];
+            if hashes[0] != hashes[1] {
+                return Some(Divergence {
+                    frame,
+                    hashes,
+                    snapshots: [self.a.snapshot(), self.b.snapshot()],
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_first_divergence() {
+        // Copies the byte at $8010 to $10
+        #[rustfmt::skip]
+        let mut program = vec![
+            0xad, 0x10, 0x80, // LDA $8010
+            0x85, 0x10,       // STA $10
+            0x4c, 0x05, 0x80, // loop: JMP loop
+        ];
+        program.resize(0x10, 0xea);
+        program.push(1);
+        let mut a = Console::new();
+        a.load(&program);
+        let mut b = Console::new();
+        b.load(&program);
+        let mut comparison = Comparison::new(a, b);
+        comparison.sync().unwrap();
+        // The state carries A's PRG ROM, so B has to be patched after syncing
+        comparison.b.cpu_mut().bus_mut().load(0x8010, &[2]);
+
+        let inputs = [[JoypadButton::empty(); 2]; 5];
+        let divergence = comparison.run(inputs).unwrap();
+        assert_eq!(divergence.frame, 0);
+        assert_eq!(divergence.memory_diff(), [(0x0010, 0x01, 0x02)]);
+        let text = divergence.to_string();
+        assert!(text.ends_with("\na     $01  $02\n$0010 $01  $02"), "{text}");
+    }
+}
@@ -0,0 +1,2001 @@
+//! The [`Console`] ties the emulated hardware together and is the entry point for frontends,
+//! scripts, and bots.
+//!
+//! Emulation advances one video frame at a time with [`Console::run_frame`]. Controller input can be
+//! set directly through [`Console::joypad_mut`], or scheduled ahead of time with
+//! [`Console::queue_input`] so it lands on an exact frame, or left to an
+//! [input provider](crate::input) polled at the start of each frame.
+//!
+//! ### Threading
+//!
+//! A `Console` is [`Send`] but not shared: it has no internal locking, and every method either
+//! reads it or takes it by `&mut`. GUI frontends should give it a thread of its own that owns it
+//! outright, and talk to it over channels rather than wrapping it in a mutex the UI would wait on:
+//!
+//! - The UI thread sends commands (input, save, load, quit) and receives finished frames.
+//! - The emulation thread drains the commands between frames, runs a frame, and sends a copy of
+//!   [`Console::frame_ref`]'s pixels. A bounded frame channel keeps it from running ahead.
+//! - Blocking work belongs on the emulation thread as well: [`Rom::from_path`] and the file IO
+//!   around [`Console::save_state`] and [`Console::load_state`]. Async runtimes can run those
+//!   calls under their blocking task API instead.
+//!
+//! `examples/threaded.rs` puts this together.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crate::accessibility::FlashFilter;
+use crate::apu::{self, Apu};
+use crate::apu_log::ApuLog;
+use crate::blend::FrameBlend;
+use crate::cpu::{CpuVariant, Mem, CPU};
+use crate::crash::{InstructionHistory, TraceEntry};
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::frame::{Frame, FrameInfo, FrameRef, Palette, PixelFormat};
+use crate::game_genie::GameGenie;
+use crate::hash::Fnv1a;
+use crate::input::InputProvider;
+use crate::journal::{EntryKind, Journal};
+use crate::joypad::{Joypad, JoypadButton};
+use crate::mapper::{Board, MapperRegistry};
+use crate::ppu::Ppu;
+use crate::ram_map::RamMap;
+use crate::rewind::Rewind;
+use crate::rng::{RamInit, Rng};
+use crate::rom::{Rom, RomError, Timing, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use crate::savestate::{self, SaveState, StateError, StateWriter};
+use crate::thumbnail::Thumbnail;
+use crate::timeline::Timeline;
+use crate::vgm;
+use crate::video_timing::{FrameTiming, VideoTiming};
+use crate::watchdog::{StopReason, Watchdog};
+
+/// PPU dots in one NTSC frame (341 dots x 262 scanlines). The CPU runs one cycle per 3 dots.
+const NTSC_DOTS_PER_FRAME: u64 = 341 * 262;
+/// PPU dots in one PAL frame (341 dots x 312 scanlines). The CPU runs one cycle per 3.2 dots.
+const PAL_DOTS_PER_FRAME: u64 = 341 * 312;
+
+/// CPU cycle count at which frame `frame` ends, with the PAL timing for a 2A07
+fn frame_end_cycle(variant: CpuVariant, frame: u64) -> u64 {
+    match variant {
+        CpuVariant::Ricoh2A07 => (frame + 1) * PAL_DOTS_PER_FRAME * 5 / 16,
+        _ => (frame + 1) * NTSC_DOTS_PER_FRAME / 3,
+    }
+}
+
+/// Frames [`Console::set_fast_boot`] runs through at power on. The PPU ignores writes for about
+/// 29658 CPU cycles after power on, and games wait out two vblanks before touching it; two frames
+/// cover both.
+pub const FAST_BOOT_FRAMES: u64 = 2;
+
+/// Pausing fades the audio out, and resuming fades it back in, over a 200th of a second
+const PAUSE_FADES_PER_SECOND: u32 = 200;
+
+/// The TV system a console is built for, which decides its CPU and frame timing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Region {
+    /// 60Hz, with a 2A03
+    #[default]
+    Ntsc,
+    /// 50Hz, with a 2A07
+    Pal,
+}
+
+impl Region {
+    pub fn cpu_variant(self) -> CpuVariant {
+        match self {
+            Region::Ntsc => CpuVariant::Ricoh2A03,
+            Region::Pal => CpuVariant::Ricoh2A07,
+        }
+    }
+
+    /// The CPU clock, in Hz
+    pub fn cpu_hz(self) -> u64 {
+        match self {
+            Region::Ntsc => apu::NTSC_CPU_HZ,
+            Region::Pal => apu::PAL_CPU_HZ,
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+        }
+    }
+}
+
+/// A ROM loaded with the region [forced](Console::force_region) to one it isn't made for. Its
+/// music and game speed come out wrong: a PAL game runs a fifth too fast on an NTSC console, and
+/// an NTSC game a sixth too slow on a PAL one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegionMismatch {
+    /// The region the ROM's header says it's made for
+    pub rom: Region,
+    pub forced: Region,
+}
+
+impl fmt::Display for RegionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} game running on a console forced to {}; its music and speed will be off",
+            self.rom, self.forced
+        )
+    }
+}
+
+/// Host time spent in the parts of the last [`Console::run_frame`].
+///
+/// The PPU and APU are kept in step with the CPU, so CPU time covers all emulated hardware except
+/// drawing the picture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Recording the rewind state at the start of the frame
+    pub rewind: Duration,
+    /// Executing instructions
+    pub cpu: Duration,
+    /// Drawing the finished frame and converting it to the output pixel format
+    pub video: Duration,
+    /// CPU cycles the frame advanced by
+    pub cycles: u64,
+}
+
+/// Where in a frame the game first latched the controllers. Frames start at vblank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PollPoint {
+    /// CPU cycles since the frame started
+    pub cycle: u64,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+impl FrameStats {
+    /// Host time for the whole frame
+    pub fn total(&self) -> Duration {
+        self.rewind + self.cpu + self.video
+    }
+}
+
+pub struct Console {
+    cpu: CPU,
+    ram_map: RamMap,
+    frame: u64,
+    halted: bool,
+    paused: bool,
+    /// Whether the audio has faded out for the pause, and so fades back in once it's over
+    paused_audio: bool,
+    /// [`Fnv1a`] hash of the loaded program, used to match save states to their ROM
+    rom_hash: u64,
+    /// What drives each controller, where something does; see [`crate::input`]
+    input_providers: [Option<Box<dyn InputProvider>>; 2],
+    /// Button states to apply at the start of a frame, in the order they were queued
+    input_queue: BTreeMap<u64, Vec<(usize, JoypadButton)>>,
+    /// Resets to apply at the start of a frame, before its input
+    reset_queue: BTreeMap<u64, ResetKind>,
+    rewind: Option<Rewind>,
+    /// Reused buffer for the state recorded into `rewind` each frame
+    rewind_scratch: Vec<u8>,
+    /// Recently executed instructions, for crash dumps
+    history: Option<InstructionHistory>,
+    /// What happened this session, when enabled
+    journal: Option<Journal>,
+    /// Last completed frame as palette indices
+    frame_buffer: Frame,
+    /// What's known about the picture in `frame_buffer`
+    frame_info: FrameInfo,
+    /// Frame being rendered, swapped with `frame_buffer` when it completes
+    back_buffer: Frame,
+    palette: Palette,
+    pixel_format: PixelFormat,
+    /// `frame_buffer` converted to `pixel_format`; unused for [`PixelFormat::Indexed`]
+    pixels: Vec<u8>,
+    /// Mixes the previous frame into `pixels`, when enabled
+    frame_blend: Option<FrameBlend>,
+    /// Smooths out flashes in `pixels`, when enabled
+    flash_filter: Option<FlashFilter>,
+    stats: FrameStats,
+    /// The only source of randomness; see [`crate::rng`]
+    rng: Rng,
+    ram_init: RamInit,
+    /// What each controller held when the game last latched it during the last frame
+    latched_input: [Option<JoypadButton>; 2],
+    /// When the game first polled the controllers during the last frame
+    poll_point: Option<PollPoint>,
+    /// The APU log being recorded for [`Console::stop_vgm`], and the DMC sample memory it
+    /// started with
+    vgm: Option<(ApuLog, Vec<u8>)>,
+    /// Boards [`Console::load_rom`] can run beyond NROM
+    mappers: MapperRegistry,
+    fast_boot: bool,
+    jam_recovery: JamRecovery,
+    /// Jam opcodes hit since the program was loaded
+    jams: u64,
+    /// The region to stay at whatever ROMs say, if the user picked one
+    forced_region: Option<Region>,
+    /// Set when the last ROM loaded is made for the other region than the forced one
+    region_mismatch: Option<RegionMismatch>,
+    /// Interrupts and DMA during the last frame, when tracking
+    timeline: Option<Timeline>,
+}
+
+/// The console's two ways of starting over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResetKind {
+    /// The reset button: the CPU restarts at the reset vector and RAM keeps its contents
+    Soft,
+    /// Switching the console off and on: RAM is filled again and the PPU and APU start over
+    Power,
+}
+
+/// What the console does when the CPU hits one of the [jam opcodes](crate::opcodes::JAM_OPCODES)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum JamRecovery {
+    /// Stay jammed until reset, as the hardware does
+    #[default]
+    Halt,
+    /// Press the reset button, taking the 7 cycles of a reset
+    Reset,
+    /// Carry on past the opcode as a 2 cycle `NOP`
+    Skip,
+}
+
+/// Internal RAM, filled according to [`RamInit`] when a program is loaded
+const RAM_SIZE: usize = 0x800;
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let mut console = Self {
+            cpu: CPU::new(),
+            ram_map: RamMap::new(),
+            frame: 0,
+            halted: false,
+            paused: false,
+            paused_audio: false,
+            rom_hash: 0,
+            input_providers: [None, None],
+            input_queue: BTreeMap::new(),
+            reset_queue: BTreeMap::new(),
+            rewind: None,
+            rewind_scratch: Vec::new(),
+            history: None,
+            journal: None,
+            frame_buffer: Frame::new(),
+            frame_info: FrameInfo::default(),
+            back_buffer: Frame::new(),
+            palette: Palette::default(),
+            pixel_format: PixelFormat::default(),
+            pixels: Vec::new(),
+            frame_blend: None,
+            flash_filter: None,
+            stats: FrameStats::default(),
+            rng: Rng::default(),
+            ram_init: RamInit::default(),
+            latched_input: [None; 2],
+            poll_point: None,
+            vgm: None,
+            mappers: MapperRegistry::new(),
+            fast_boot: false,
+            jam_recovery: JamRecovery::default(),
+            jams: 0,
+            forced_region: None,
+            region_mismatch: None,
+            timeline: None,
+        };
+        console.update_pixels();
+        console
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.cpu.bus.ppu
+    }
+
+    /// The PPU, e.g. to [hide a layer](Ppu::set_layer_enabled) while debugging
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.cpu.bus.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.cpu.bus.apu
+    }
+
+    /// The APU, e.g. to [change the sample rate](Apu::set_sample_rate) or
+    /// [tap its channels](Apu::set_channel_taps)
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.cpu.bus.apu
+    }
+
+    /// Audio produced by the last [`Console::run_frame`], as mono samples at
+    /// [`Apu::sample_rate`]
+    pub fn audio_samples(&self) -> &[f32] {
+        self.cpu.bus.apu.samples()
+    }
+
+    /// Starts recording the APU for a [VGM file](crate::vgm) from the next frame. This uses the
+    /// APU's register log, so don't run an [`ApuLog`] at the same time.
+    pub fn start_vgm(&mut self) {
+        let dmc_ram = (vgm::DMC_RAM_START..=0xFFFF)
+            .map(|addr| self.cpu.bus.mem_peek(addr))
+            .collect();
+        self.vgm = Some((ApuLog::start(self), dmc_ram));
+    }
+
+    pub fn is_recording_vgm(&self) -> bool {
+        self.vgm.is_some()
+    }
+
+    /// Stops recording and returns the VGM file, or `None` if there was no recording
+    pub fn stop_vgm(&mut self) -> Option<Vec<u8>> {
+        let (mut log, dmc_ram) = self.vgm.take()?;
+        log.stop(self);
+        let frame_rate = match self.region() {
+            Region::Ntsc => 60,
+            Region::Pal => 50,
+        };
+        Some(vgm::encode(&log, frame_rate, &dmc_ram))
+    }
+
+    /// Loads `program` at `0x8000` and resets the CPU so the next frame starts executing it. Only
+    /// the first 32KB fit, less the reset vector at `0xFFFC`.
+    pub fn load(&mut self, program: &[u8]) {
+        self.rom_hash = Fnv1a::hash_of(program);
+        self.init_ram();
+        self.cpu.bus.prg_mirrored = false;
+        self.cpu.bus.set_board(None);
+        self.cpu.load(program);
+        self.restart_diagnostics();
+        self.clear_rewind();
+        self.jams = 0;
+        self.reset_cpu();
+        self.run_boot_frames();
+    }
+
+    /// Maps the cartridge's PRG ROM at `0x8000` and its CHR ROM into the PPU, and resets into it.
+    ///
+    /// NROM boards run as they are: a 16KB PRG ROM is mirrored into both halves of
+    /// `0x8000..=0xFFFF`. Other boards need a mapper registered in [`Console::mappers_mut`].
+    ///
+    /// A ROM whose header says it's only made for NTSC or only for PAL switches the console to
+    /// that region, and with it the frame rate, [frame timing](Console::frame_timing) and APU
+    /// pitch, unless the region is [forced](Console::force_region).
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        self.mappers.check(rom)?;
+        let factory = self.mappers.get(rom);
+        if rom.prg_rom.is_empty()
+            || (factory.is_none() && rom.prg_rom.len() > 2 * PRG_ROM_PAGE_SIZE)
+        {
+            return Err(RomError::Truncated);
+        }
+
+        self.init_ram();
+        match factory {
+            Some(factory) => {
+                let prg_len = rom.prg_rom.len().min(2 * PRG_ROM_PAGE_SIZE);
+                let chr_len = rom.chr_rom.len().min(CHR_ROM_PAGE_SIZE);
+                self.cpu.bus.load_prg_rom(&rom.prg_rom[..prg_len]);
+                self.cpu
+                    .bus
+                    .ppu
+                    .load_cartridge(&rom.chr_rom[..chr_len], rom.screen_mirroring);
+                self.cpu.bus.set_board(Some(Board {
+                    mapper: factory(rom),
+                    prg_rom: rom.prg_rom.as_slice().into(),
+                    chr_rom: rom.chr_rom.as_slice().into(),
+                }));
+            }
+            None => {
+                self.cpu.bus.load_prg_rom(&rom.prg_rom);
+                self.cpu
+                    .bus
+                    .ppu
+                    .load_cartridge(&rom.chr_rom, rom.screen_mirroring);
+                self.cpu.bus.set_board(None);
+            }
+        }
+        self.cpu.bus.apu.power_on();
+        self.rom_hash = rom.hash();
+        self.restart_diagnostics();
+        self.clear_rewind();
+        self.jams = 0;
+        self.region_mismatch = None;
+        if let Some(region) = rom.timing.and_then(Timing::region) {
+            match self.forced_region {
+                None => self.set_region(region),
+                Some(forced) if forced != region => {
+                    self.region_mismatch = Some(RegionMismatch {
+                        rom: region,
+                        forced,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        self.reset_cpu();
+        if self.journal.is_some() {
+            self.note(
+                EntryKind::RomLoaded,
+                &format!("loaded {:016x}", self.rom_hash),
+            );
+        }
+        self.run_boot_frames();
+        Ok(())
+    }
+
+    /// Swaps in a rebuilt version of the running game. With `keep_state` the CPU, RAM, PPU and APU
+    /// carry on where they were, now running the new PRG and CHR ROM; otherwise this is
+    /// [`Console::load_rom`] and the game starts over from power on. So does a rebuild the state
+    /// doesn't fit, e.g. one that moved from CHR ROM to CHR RAM or to another mapper.
+    pub fn reload_rom(&mut self, rom: &Rom, keep_state: bool) -> Result<(), RomError> {
+        if !keep_state {
+            return self.load_rom(rom);
+        }
+        let state = self.save_state();
+        let old_hash = self.rom_hash;
+        // Journaled as one reload, not the load and state load it's made of, and the state
+        // replaces anything a fast boot would run
+        let journal = self.journal.take();
+        let fast_boot = std::mem::replace(&mut self.fast_boot, false);
+        let loaded = self.load_rom(rom);
+        self.journal = journal;
+        self.fast_boot = fast_boot;
+        loaded?;
+        // The state's memory still holds the old PRG ROM, so map the new one over it afterwards
+        let new_hash = std::mem::replace(&mut self.rom_hash, old_hash);
+        if self.restore_state(&state).is_err() {
+            self.rom_hash = new_hash;
+            return self.load_rom(rom);
+        }
+        if self.journal.is_some() {
+            self.note(EntryKind::RomLoaded, &format!("reloaded {new_hash:016x}"));
+        }
+        self.rom_hash = new_hash;
+        // A mapper maps the new ROM's banks as it loads its registers
+        if self.cpu.bus.has_board() {
+            return Ok(());
+        }
+        self.cpu.bus.load_prg_rom(&rom.prg_rom);
+        if !rom.chr_rom.is_empty() {
+            self.cpu
+                .bus
+                .ppu
+                .load_cartridge(&rom.chr_rom, rom.screen_mirroring);
+        }
+        Ok(())
+    }
+
+    /// [`Region::Pal`] while running a 2A07, otherwise [`Region::Ntsc`]
+    pub fn region(&self) -> Region {
+        match self.cpu.variant {
+            CpuVariant::Ricoh2A07 => Region::Pal,
+            _ => Region::Ntsc,
+        }
+    }
+
+    /// Switches to `region`'s CPU and timing from the next frame. The next ROM loaded may switch
+    /// it back; see [`Console::force_region`].
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.variant = region.cpu_variant();
+    }
+
+    /// Switches to `region` and keeps it, whatever the ROMs loaded afterwards are made for. A ROM
+    /// made for the other region then leaves a [`RegionMismatch`] to warn about. `None` lets
+    /// ROMs pick the region again, staying at the current one until one does.
+    pub fn force_region(&mut self, region: Option<Region>) {
+        self.forced_region = region;
+        if let Some(region) = region {
+            self.set_region(region);
+        }
+    }
+
+    pub fn forced_region(&self) -> Option<Region> {
+        self.forced_region
+    }
+
+    /// The mismatch between the forced region and the ROM loaded last, if there was one,
+    /// clearing it
+    pub fn take_region_mismatch(&mut self) -> Option<RegionMismatch> {
+        self.region_mismatch.take()
+    }
+
+    /// Restarts the random number generator from `seed`. Runs that set the same seed before
+    /// loading a program are identical, whatever [`RamInit`] is in use.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// The console's random number generator, for tooling that needs randomness reproducible
+    /// with the seed
+    pub fn rng_mut(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Picks what internal RAM holds after the next [`Console::load`] or [`Console::load_rom`]
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    pub fn mappers(&self) -> &MapperRegistry {
+        &self.mappers
+    }
+
+    /// Where to [register](crate::mapper) mappers for boards beyond NROM, before loading their
+    /// ROMs
+    pub fn mappers_mut(&mut self) -> &mut MapperRegistry {
+        &mut self.mappers
+    }
+
+    fn init_ram(&mut self) {
+        let mut ram = [0; RAM_SIZE];
+        self.ram_init.fill(&mut ram, &mut self.rng);
+        self.cpu.bus.load(0, &ram);
+    }
+
+    /// Turns the [developer warnings](crate::diagnostics) on or off. Turning them on starts
+    /// tracking RAM from scratch, so load the program afterwards to avoid spurious uninitialized
+    /// reads.
+    pub fn set_diagnostics(&mut self, enabled: bool) {
+        self.cpu.diagnostics = enabled.then(Diagnostics::new);
+    }
+
+    /// Starts keeping the last `len` instructions executed, for [crash dumps](crate::crash)
+    pub fn enable_instruction_history(&mut self, len: usize) {
+        self.history = Some(InstructionHistory::new(len));
+    }
+
+    pub fn disable_instruction_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Starts or stops recording a [`Timeline`] of interrupts and DMA for every frame
+    pub fn track_interrupts(&mut self, enabled: bool) {
+        self.timeline = enabled.then(Timeline::new);
+    }
+
+    /// The interrupts and DMA of the last frame, while tracking
+    pub fn interrupt_timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    /// Starts a [`Journal`] of resets, state and ROM loads, and whatever [`Console::note`] adds
+    pub fn enable_journal(&mut self) {
+        self.journal.get_or_insert_with(Journal::new);
+    }
+
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// The journal so far, or `None` while it's off
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Adds an entry stamped with the current frame, if the journal is on
+    pub fn note(&mut self, kind: EntryKind, text: &str) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(self.frame, kind, text);
+        }
+    }
+
+    /// The recent instructions, oldest first, or `None` while history is off
+    pub fn instruction_history(&self) -> Option<&InstructionHistory> {
+        self.history.as_ref()
+    }
+
+    /// Developer warnings reported since the last call, oldest first; empty while they're off
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.cpu
+            .diagnostics
+            .as_mut()
+            .map(Diagnostics::take)
+            .unwrap_or_default()
+    }
+
+    /// A newly loaded program starts with nothing in RAM written and nothing reported
+    fn restart_diagnostics(&mut self) {
+        if self.cpu.diagnostics.is_some() {
+            self.set_diagnostics(true);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.reset_cpu();
+        self.note(EntryKind::Reset, "");
+    }
+
+    fn reset_cpu(&mut self) {
+        self.cpu.reset();
+        self.halted = false;
+    }
+
+    /// Turns the console off and on again with the same cartridge in. RAM is filled according
+    /// to [`RamInit`], so with the same seed a power cycle is as reproducible as the first boot.
+    /// Save RAM keeps its contents, as the cartridge's battery would.
+    pub fn power_cycle(&mut self) {
+        self.init_ram();
+        self.cpu.bus.ppu.power_on();
+        self.cpu.bus.apu.power_on();
+        self.cpu.bus.power_on_board();
+        self.restart_diagnostics();
+        self.reset_cpu();
+        self.note(EntryKind::PowerCycle, "");
+        self.run_boot_frames();
+    }
+
+    /// Turns fast boot on or off. With it on, loading a program or ROM and power cycling run
+    /// straight through the first [`FAST_BOOT_FRAMES`] frames, past the PPU warm-up and the
+    /// games' vblank waits, so tests and development builds start nearer to gameplay.
+    ///
+    /// The boot frames are ordinary frames run back to back: the machine ends up exactly where
+    /// running them one by one would leave it, with the same [`Console::frame`] count. Only the
+    /// last of them is drawn, and only its audio is kept.
+    pub fn set_fast_boot(&mut self, enabled: bool) {
+        self.fast_boot = enabled;
+    }
+
+    pub fn fast_boot(&self) -> bool {
+        self.fast_boot
+    }
+
+    /// Runs the boot frames when fast boot is on, paused or not
+    fn run_boot_frames(&mut self) {
+        if !self.fast_boot {
+            return;
+        }
+        let paused = std::mem::replace(&mut self.paused, false);
+        for i in 0..FAST_BOOT_FRAMES {
+            let _ = self.run_frame_split(None, |_, _| {}, i + 1 == FAST_BOOT_FRAMES, None);
+        }
+        self.paused = paused;
+    }
+
+    pub fn apply_reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Soft => self.reset(),
+            ResetKind::Power => self.power_cycle(),
+        }
+    }
+
+    pub fn load_and_run(&mut self, program: &[u8]) {
+        self.cpu.load_and_run(program)
+    }
+
+    /// Whether the program has hit an unofficial opcode, or a `BRK` with
+    /// [halting](Console::set_halt_on_brk) on, and stopped executing
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether the CPU is halted on a jam opcode, which only a reset gets it out of
+    pub fn is_jammed(&self) -> bool {
+        self.halted && self.cpu.is_jammed()
+    }
+
+    /// Sets what happens when the CPU jams. Staying jammed is what the hardware does; resetting
+    /// or skipping the opcode lets batch runs carry on past a crashed ROM, counting the jams in
+    /// [`Console::jams`]. Like fast boot, the setting isn't part of save states or the state hash.
+    pub fn set_jam_recovery(&mut self, recovery: JamRecovery) {
+        self.jam_recovery = recovery;
+    }
+
+    pub fn jam_recovery(&self) -> JamRecovery {
+        self.jam_recovery
+    }
+
+    /// Jam opcodes the CPU has hit since the program was loaded, recovered from or not
+    pub fn jams(&self) -> u64 {
+        self.jams
+    }
+
+    /// Number of frames emulated so far; also the number of the next frame [`Console::run_frame`] runs
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Freezes or resumes emulation.
+    ///
+    /// While paused [`Console::run_frame`] does nothing: no cycles run, no queued input is applied,
+    /// the frame counter and rewind history stay put, and [`Console::frame_ref`] keeps returning
+    /// the last completed frame. Resuming continues from the exact cycle emulation stopped at, so a
+    /// paused run stays in lockstep with one that never paused. Save states, rewind and reset still
+    /// work while paused.
+    ///
+    /// So that pausing doesn't pop, the first paused frame's [`Console::audio_samples`] fade from
+    /// where the audio was down to silence over 5 milliseconds, the frames after it have none, and
+    /// the first frame after resuming fades back in over the same time.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Makes `BRK` halt the CPU instead of taking the interrupt, so test programs can end with
+    /// one. Off by default, as games use `BRK` as a software interrupt. Like jam recovery, the
+    /// setting isn't part of save states or the state hash.
+    pub fn set_halt_on_brk(&mut self, halt: bool) {
+        self.cpu.set_halt_on_brk(halt);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs the CPU until the end of the current frame.
+    ///
+    /// Resets and then inputs queued for this frame are applied before the first instruction
+    /// executes. Once the program hits an unofficial opcode, or a halting `BRK`, the CPU stays
+    /// halted and frames pass without executing anything, unless it's a jam opcode and
+    /// [jam recovery](Console::set_jam_recovery) is on. Does nothing while
+    /// [paused](Console::set_paused).
+    pub fn run_frame(&mut self) {
+        let _ = self.run_frame_split(None, |_, _| {}, true, None);
+    }
+
+    /// Runs a frame like [`Console::run_frame`], stopping before the next instruction once
+    /// `watchdog` says to. A stopped frame is left unfinished: the picture and [`Console::frame`]
+    /// stay at the last completed frame, and the next run picks up at the instruction it stopped
+    /// before, applying the frame's queued input again on the way.
+    ///
+    /// A CPU left [jammed](Console::is_jammed) is [`StopReason::Jammed`]: the frame it jammed in
+    /// still runs to the end, and watched runs after it stop straight away until a reset.
+    pub fn run_frame_watched(&mut self, watchdog: &mut Watchdog) -> Result<(), StopReason> {
+        self.run_frame_split(None, |_, _| {}, true, Some(watchdog))
+    }
+
+    /// Runs a frame like [`Console::run_frame`], but stops `lead` CPU cycles before the point the
+    /// game polled the controllers last frame (see [`Console::poll_point`]) and calls `sample`
+    /// with both controllers there. Frontends sample the host's input in `sample` so it's as fresh
+    /// as possible when the game reads it. Without a poll last frame, `sample` runs at the start.
+    pub fn run_frame_sampling_input(
+        &mut self,
+        lead: u64,
+        sample: impl FnOnce(&mut Joypad, &mut Joypad),
+    ) {
+        let at = self
+            .poll_point
+            .map_or(0, |poll| poll.cycle.saturating_sub(lead));
+        let _ = self.run_frame_split(Some(at), sample, true, None);
+    }
+
+    /// Runs a frame, calling `sample` once `split` cycles in, and draws it if `video`. Stops
+    /// early if `watchdog` says to.
+    fn run_frame_split(
+        &mut self,
+        split: Option<u64>,
+        sample: impl FnOnce(&mut Joypad, &mut Joypad),
+        video: bool,
+        mut watchdog: Option<&mut Watchdog>,
+    ) -> Result<(), StopReason> {
+        if self.paused {
+            self.stats = FrameStats::default();
+            if std::mem::replace(&mut self.paused_audio, true) {
+                self.cpu.bus.apu.clear_samples();
+            } else {
+                self.cpu.bus.apu.fade_out(self.pause_fade_len());
+            }
+            return Ok(());
+        }
+        if let Some(watchdog) = &watchdog {
+            watchdog.check()?;
+            if self.is_jammed() {
+                return Err(StopReason::Jammed);
+            }
+        }
+        let start = Instant::now();
+        let start_cycles = self.cpu.cycles;
+        if let Some(mut rewind) = self.rewind.take() {
+            let mut state = std::mem::take(&mut self.rewind_scratch);
+            self.write_state(&mut state, false);
+            rewind.push(&state);
+            self.rewind_scratch = state;
+            self.rewind = Some(rewind);
+        }
+        self.apply_queued_resets();
+        self.apply_queued_input();
+        self.poll_input_providers();
+        for player in 0..2 {
+            self.joypad_mut(player).take_latched();
+        }
+        let rewind_done = Instant::now();
+
+        let frame_start = self.cpu.cycles;
+        let mut sample = split.map(|split| (frame_start + split, sample));
+        self.poll_point = None;
+        let end = frame_end_cycle(self.cpu.variant, self.frame);
+        let pal = self.cpu.variant == CpuVariant::Ricoh2A07;
+        self.cpu.bus.ppu.set_pal(pal);
+        self.cpu.bus.apu.set_pal(pal);
+        self.cpu.bus.apu.clear_samples();
+        if let Some(timeline) = &mut self.timeline {
+            timeline.begin_frame();
+        }
+        while !self.halted && self.cpu.cycles < end {
+            if let Some(watchdog) = &watchdog {
+                watchdog.check()?;
+            }
+            if sample
+                .as_ref()
+                .is_some_and(|(at, _)| self.cpu.cycles >= *at)
+            {
+                let (_, sample) = sample.take().unwrap();
+                sample(&mut self.cpu.bus.joypad1, &mut self.cpu.bus.joypad2);
+            }
+            let before = self.cpu.cycles;
+            if let Some(history) = &mut self.history {
+                history.push(TraceEntry::capture(&self.cpu));
+            }
+            self.cpu.bus.sync_capture(before);
+            self.halted = !self.cpu.step();
+            if self.halted && self.cpu.is_jammed() {
+                self.recover_from_jam();
+            }
+            let stall = self.cpu.bus.take_dma_cycles();
+            if let Some(timeline) = &mut self.timeline {
+                timeline.after_step(self.cpu.cycles - frame_start, stall, &self.cpu.bus);
+            }
+            self.cpu.cycles += stall;
+            if !self.halted && self.cpu.bus.ppu.take_nmi() {
+                if let Some(timeline) = &mut self.timeline {
+                    timeline.nmi_serviced(self.cpu.cycles - frame_start, &self.cpu.bus);
+                }
+                self.cpu.bus.sync_capture(self.cpu.cycles);
+                self.cpu.nmi();
+            } else if !self.halted && self.cpu.bus.irq_pending() {
+                let cycle = self.cpu.cycles - frame_start;
+                self.cpu.bus.sync_capture(self.cpu.cycles);
+                if self.cpu.irq() {
+                    if let Some(timeline) = &mut self.timeline {
+                        timeline.irq_serviced(cycle, &self.cpu.bus);
+                    }
+                }
+            }
+            self.cpu.bus.ppu.tick(self.cpu.cycles - before);
+            self.cpu.bus.tick_apu(self.cpu.cycles - before);
+            self.cpu.bus.tick_board(self.cpu.cycles - before);
+            if let Some(timeline) = &mut self.timeline {
+                timeline.observe(self.cpu.cycles - frame_start, &self.cpu.bus);
+            }
+            if let Some(watchdog) = &mut watchdog {
+                watchdog.spend(self.cpu.cycles - before);
+            }
+            // Both controllers share the strobe, so the first one is enough
+            if self.poll_point.is_none() && self.cpu.bus.joypad1.latched().is_some() {
+                self.poll_point = Some(PollPoint {
+                    cycle: self.cpu.cycles - frame_start,
+                    scanline: self.cpu.bus.ppu.scanline(),
+                    dot: self.cpu.bus.ppu.dot(),
+                });
+            }
+        }
+        if let Some((_, sample)) = sample {
+            sample(&mut self.cpu.bus.joypad1, &mut self.cpu.bus.joypad2);
+        }
+        if self.halted {
+            let before = self.cpu.cycles;
+            self.cpu.cycles = self.cpu.cycles.max(end);
+            self.cpu.bus.ppu.tick(self.cpu.cycles - before);
+            self.cpu.bus.tick_apu(self.cpu.cycles - before);
+            self.cpu.bus.tick_board(self.cpu.cycles - before);
+            self.cpu.bus.take_dma_cycles(); // nothing left to stall
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.end_frame(self.cpu.cycles - frame_start);
+        }
+        self.frame += 1;
+        if let Some((mut log, dmc_ram)) = self.vgm.take() {
+            log.capture(self);
+            self.vgm = Some((log, dmc_ram));
+        }
+        self.latched_input = [0, 1].map(|player| self.joypad_mut(player).take_latched());
+        if std::mem::take(&mut self.paused_audio) {
+            self.cpu.bus.apu.fade_in(self.pause_fade_len());
+        }
+        let cpu_done = Instant::now();
+
+        if video {
+            self.cpu.bus.ppu.render(&mut self.back_buffer);
+            std::mem::swap(&mut self.frame_buffer, &mut self.back_buffer);
+            let number = self.frame - 1;
+            self.frame_info = FrameInfo {
+                number,
+                odd: number % 2 == 1,
+                start_cycle: frame_start,
+                cycles: self.cpu.cycles - frame_start,
+                rendering: self.cpu.bus.ppu.rendering_enabled(),
+            };
+            self.update_pixels();
+        }
+
+        self.stats = FrameStats {
+            rewind: rewind_done - start,
+            cpu: cpu_done - rewind_done,
+            video: cpu_done.elapsed(),
+            cycles: self.cpu.cycles - start_cycles,
+        };
+        if watchdog.is_some() && self.is_jammed() {
+            return Err(StopReason::Jammed);
+        }
+        Ok(())
+    }
+
+    fn pause_fade_len(&self) -> usize {
+        (self.cpu.bus.apu.sample_rate() / PAUSE_FADES_PER_SECOND) as usize
+    }
+
+    fn recover_from_jam(&mut self) {
+        self.jams += 1;
+        match self.jam_recovery {
+            JamRecovery::Halt => {}
+            JamRecovery::Reset => {
+                self.reset_cpu();
+                self.cpu.cycles += 7;
+                self.note(EntryKind::Reset, "after a CPU jam");
+            }
+            JamRecovery::Skip => {
+                self.halted = false;
+                self.cpu.cycles += 2;
+            }
+        }
+    }
+
+    /// When the last completed frame, the one in [`Console::frame_ref`], ran on the emulated
+    /// video clock of the current region
+    pub fn frame_timing(&self) -> FrameTiming {
+        FrameTiming::new(
+            &VideoTiming::of(self.region()),
+            self.frame.saturating_sub(1),
+        )
+    }
+
+    /// Timing of the last [`Console::run_frame`], for performance overlays
+    pub fn frame_stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// The last completed frame, in the chosen [`PixelFormat`] and as palette indices.
+    ///
+    /// Pair [`PixelFormat::Indexed`] output with [`Palette::to_table`] to apply colors on the
+    /// frontend, skipping conversion on the core entirely.
+    pub fn frame_ref(&self) -> FrameRef<'_> {
+        let pixels = match self.pixel_format {
+            PixelFormat::Indexed => &self.frame_buffer.pixels,
+            _ => &self.pixels[..],
+        };
+        FrameRef::new(
+            &self.frame_buffer,
+            pixels,
+            self.pixel_format,
+            self.frame_info,
+        )
+    }
+
+    /// What's known about the picture [`Console::frame_ref`] returns. Frames run without
+    /// drawing, and loading a state, leave both alone.
+    pub fn frame_info(&self) -> FrameInfo {
+        self.frame_info
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+        self.update_pixels();
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.update_pixels();
+    }
+
+    pub fn game_genie(&self) -> Option<&GameGenie> {
+        self.cpu.bus.game_genie()
+    }
+
+    /// Plugs a [Game Genie](crate::game_genie) in between the console and the cartridge, or
+    /// takes it out with `None`. One made from its ROM runs its menu from the next power on, by
+    /// [`Console::load_rom`] or [`Console::power_cycle`].
+    pub fn set_game_genie(&mut self, genie: Option<GameGenie>) {
+        self.cpu.bus.set_game_genie(genie);
+    }
+
+    pub fn frame_blend(&self) -> Option<&FrameBlend> {
+        self.frame_blend.as_ref()
+    }
+
+    /// Mixes the previous frame into the converted frames, or stops with `None`. Blending comes
+    /// before the flash filter, and the palette indices in [`FrameRef::indices`] are never blended.
+    pub fn set_frame_blend(&mut self, blend: Option<FrameBlend>) {
+        self.frame_blend = blend;
+    }
+
+    pub fn flash_filter(&self) -> Option<&FlashFilter> {
+        self.flash_filter.as_ref()
+    }
+
+    /// Limits how fast the brightness of the converted frames can change, or stops limiting it
+    /// with `None`. The palette indices in [`FrameRef::indices`] are never filtered.
+    pub fn set_flash_filter(&mut self, filter: Option<FlashFilter>) {
+        self.flash_filter = filter;
+    }
+
+    fn update_pixels(&mut self) {
+        if self.pixel_format == PixelFormat::Indexed {
+            self.pixels = Vec::new();
+            return;
+        }
+        self.pixels.resize(self.pixel_format.frame_len(), 0);
+        self.frame_buffer
+            .write_pixels(self.pixel_format, &self.palette, &mut self.pixels);
+        if let Some(blend) = &mut self.frame_blend {
+            blend.apply(self.pixel_format, &mut self.pixels);
+        }
+        if let Some(filter) = &mut self.flash_filter {
+            filter.apply(self.pixel_format, &mut self.pixels);
+        }
+    }
+
+    /// Hash of all emulated state: CPU registers, memory, controllers, and the frame counter.
+    ///
+    /// Two consoles that produce the same hash at the same frame are in the same state, so
+    /// comparing hashes frame by frame finds the exact frame where two runs diverge. The hash is
+    /// stable across runs and platforms. Host side bookkeeping (the RAM map and inputs queued for
+    /// future frames) is not part of it. The random number generator is, since two consoles with
+    /// the same memory but different generators part ways at their next power cycle.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        self.cpu.hash(&mut hasher);
+        self.frame.hash(&mut hasher);
+        self.halted.hash(&mut hasher);
+        self.rng.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Hash identifying the loaded program, as reported by [`savestate::state_info`]
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Serializes all emulated state into the [`savestate`] format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.save_state_into(&mut out);
+        out
+    }
+
+    /// Like [`Console::save_state`], but reuses `out`'s allocation
+    pub fn save_state_into(&self, out: &mut Vec<u8>) {
+        self.write_state(out, true);
+    }
+
+    /// Writes every chunk of the save state; the thumbnail is left out of rewind history, where it
+    /// would only bloat the deltas
+    fn write_state(&self, out: &mut Vec<u8>, with_thumbnail: bool) {
+        let mut state = StateWriter::new(out);
+        state.chunk(savestate::META, |meta| {
+            meta.write_u64(self.rom_hash);
+            meta.write_u64(self.frame);
+            meta.write_bool(self.halted);
+        });
+        state.chunk(savestate::CPU, |cpu| self.cpu.save_state(cpu));
+        state.chunk(savestate::RAM, |ram| self.cpu.bus.save_memory(ram));
+        state.chunk(savestate::JOYPADS, |joypads| {
+            self.cpu.bus.save_joypads(joypads)
+        });
+        state.chunk(savestate::PPU, |ppu| self.cpu.bus.ppu.save_state(ppu));
+        state.chunk(savestate::APU, |apu| self.cpu.bus.apu.save_state(apu));
+        state.chunk(savestate::RNG, |rng| self.rng.save_state(rng));
+        if self.cpu.bus.has_board() {
+            state.chunk(savestate::MAPPER, |mapper| self.cpu.bus.save_board(mapper));
+        }
+        if self.cpu.bus.game_genie().is_some() {
+            state.chunk(savestate::GAME_GENIE, |genie| {
+                self.cpu.bus.save_game_genie(genie)
+            });
+        }
+        if with_thumbnail {
+            state.chunk(savestate::THUMBNAIL, |thumbnail| {
+                Thumbnail::save_frame(&self.frame_buffer, &self.palette, thumbnail)
+            });
+        }
+    }
+
+    /// Restores state written by [`Console::save_state`], migrating states from older versions.
+    ///
+    /// The state must have been saved with the same ROM loaded. On error the console is left
+    /// untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let from = self.frame;
+        self.restore_state(data)?;
+        if self.journal.is_some() {
+            self.note(EntryKind::StateLoaded, &format!("from frame {from}"));
+        }
+        Ok(())
+    }
+
+    /// [`Console::load_state`] without a journal entry, for rewinding and reloading
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = SaveState::parse(data)?;
+        savestate::migrate(&mut state)?;
+
+        let mut meta = state.chunk(savestate::META)?;
+        let rom_hash = meta.read_u64()?;
+        if rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_hash,
+                found: rom_hash,
+            });
+        }
+        let frame = meta.read_u64()?;
+        let halted = meta.read_bool()?;
+
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&mut state.chunk(savestate::CPU)?)?;
+        cpu.bus.load_memory(&mut state.chunk(savestate::RAM)?)?;
+        cpu.bus
+            .load_joypads(&mut state.chunk(savestate::JOYPADS)?)?;
+        match state.chunk(savestate::PPU) {
+            Ok(mut ppu) => cpu.bus.ppu.load_state(&mut ppu)?,
+            // Saved before there was a PPU to save
+            Err(StateError::MissingChunk(_)) => cpu.bus.ppu.power_on(),
+            Err(err) => return Err(err),
+        }
+        match state.chunk(savestate::APU) {
+            Ok(mut apu) => cpu.bus.apu.load_state(&mut apu)?,
+            // Saved before there was an APU to save
+            Err(StateError::MissingChunk(_)) => cpu.bus.apu.power_on(),
+            Err(err) => return Err(err),
+        }
+        match state.chunk(savestate::MAPPER) {
+            Ok(mut mapper) => cpu.bus.load_board(&mut mapper)?,
+            // Saved without a mapper, e.g. by the NROM build of a game being reloaded
+            Err(StateError::MissingChunk(_)) => cpu.bus.power_on_board(),
+            Err(err) => return Err(err),
+        }
+        match state.chunk(savestate::GAME_GENIE) {
+            Ok(mut genie) => cpu.bus.load_game_genie(&mut genie)?,
+            // Saved without the Game Genie plugged in; it keeps its registers
+            Err(StateError::MissingChunk(_)) => {}
+            Err(err) => return Err(err),
+        }
+        let mut rng = self.rng.clone();
+        match state.chunk(savestate::RNG) {
+            Ok(mut chunk) => rng.load_state(&mut chunk)?,
+            // Saved before there was an RNG; keep the current sequence
+            Err(StateError::MissingChunk(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        // Whatever the state holds in RAM was written before it was saved
+        if let Some(diagnostics) = &mut cpu.diagnostics {
+            diagnostics.assume_initialized();
+        }
+        self.cpu = cpu;
+        self.rng = rng;
+        self.frame = frame;
+        self.halted = halted;
+        Ok(())
+    }
+
+    /// Starts recording the state at the start of every frame, keeping about the last `frames`
+    pub fn enable_rewind(&mut self, frames: usize) {
+        self.rewind = Some(Rewind::new(frames, 60));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Steps back to the start of the previous frame, returning `false` once the history runs out.
+    /// Loading a ROM starts the history over, so it never steps back into another game.
+    pub fn rewind(&mut self) -> bool {
+        let Some(state) = self.rewind.as_mut().and_then(Rewind::pop) else {
+            return false;
+        };
+        if self.restore_state(&state).is_err() {
+            self.clear_rewind();
+            return false;
+        }
+        true
+    }
+
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /// Controller plugged into port `player` (0 or 1)
+    pub fn joypad(&self, player: usize) -> &Joypad {
+        self.cpu.bus.joypad(player)
+    }
+
+    /// The buttons the game read from `player`'s controller during the last frame, as latched the
+    /// last time it polled, or `None` if it didn't poll. This is the input after movies and turbo
+    /// have had their say, so input displays should draw it rather than the host's controller.
+    pub fn latched_input(&self, player: usize) -> Option<JoypadButton> {
+        assert!(player < 2, "no controller port {player}");
+        self.latched_input[player]
+    }
+
+    /// Where the game first polled the controllers during the last frame, or `None` if it didn't
+    pub fn poll_point(&self) -> Option<PollPoint> {
+        self.poll_point
+    }
+
+    pub fn joypad_mut(&mut self, player: usize) -> &mut Joypad {
+        self.cpu.bus.joypad_mut(player)
+    }
+
+    /// Schedules `player`'s controller to hold exactly `buttons` from the start of `frame_number`
+    /// until another input is applied.
+    ///
+    /// Inputs queued for a frame that has already run are applied at the start of the next frame.
+    /// When several inputs target the same player and frame, the last one queued wins.
+    pub fn queue_input(&mut self, frame_number: u64, player: usize, buttons: JoypadButton) {
+        assert!(player < 2, "no controller port {player}");
+        self.input_queue
+            .entry(frame_number)
+            .or_default()
+            .push((player, buttons));
+    }
+
+    /// Holds `buttons` for every frame in `frames`, releasing everything on the frame after
+    pub fn hold_input(
+        &mut self,
+        frames: RangeInclusive<u64>,
+        player: usize,
+        buttons: JoypadButton,
+    ) {
+        self.queue_input(*frames.start(), player, buttons);
+        self.queue_input(frames.end() + 1, player, JoypadButton::empty());
+    }
+
+    /// Schedules a reset or power cycle at the start of `frame_number`, before that frame's
+    /// input. Resets queued for a frame that has already run happen at the start of the next
+    /// frame, and of several for the same frame the last one queued wins.
+    pub fn queue_reset(&mut self, frame_number: u64, kind: ResetKind) {
+        self.reset_queue.insert(frame_number, kind);
+    }
+
+    /// Lets `provider` set `player`'s buttons at the start of every frame from now on, or hands
+    /// the controller back with `None`
+    pub fn set_input_provider(&mut self, player: usize, provider: Option<Box<dyn InputProvider>>) {
+        assert!(player < 2, "no controller port {player}");
+        self.input_providers[player] = provider;
+    }
+
+    /// Removes and returns `player`'s input provider, leaving the buttons it last set held
+    pub fn take_input_provider(&mut self, player: usize) -> Option<Box<dyn InputProvider>> {
+        self.input_providers.get_mut(player)?.take()
+    }
+
+    fn poll_input_providers(&mut self) {
+        for player in 0..2 {
+            if let Some(mut provider) = self.input_providers[player].take() {
+                let buttons = provider.poll(self);
+                self.joypad_mut(player).set_buttons(buttons);
+                self.input_providers[player] = Some(provider);
+            }
+        }
+    }
+
+    /// Drops every input and reset that has been queued but not applied yet
+    pub fn clear_input_queue(&mut self) {
+        self.input_queue.clear();
+        self.reset_queue.clear();
+    }
+
+    fn apply_queued_resets(&mut self) {
+        while let Some(entry) = self.reset_queue.first_entry() {
+            if *entry.key() > self.frame {
+                break;
+            }
+            let kind = entry.remove();
+            self.apply_reset(kind);
+        }
+    }
+
+    fn apply_queued_input(&mut self) {
+        while let Some(entry) = self.input_queue.first_entry() {
+            if *entry.key() > self.frame {
+                break;
+            }
+            for (player, buttons) in entry.remove() {
+                self.joypad_mut(player).set_buttons(buttons);
+            }
+        }
+    }
+
+    /// The RAM fields registered for the running game
+    pub fn ram_map(&self) -> &RamMap {
+        &self.ram_map
+    }
+
+    pub fn ram_map_mut(&mut self) -> &mut RamMap {
+        &mut self.ram_map
+    }
+
+    pub fn set_ram_map(&mut self, ram_map: RamMap) {
+        self.ram_map = ram_map;
+    }
+
+    /// Snapshot of every field in the [`RamMap`], keyed by field name
+    pub fn observations(&self) -> HashMap<String, i64> {
+        self.ram_map.observations(&self.cpu)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ram_map::{RamField, Width};
+    use crate::watchdog::CancelToken;
+
+    #[test]
+    fn test_observations_follow_ram() {
+        let mut console = Console::new();
+        console
+            .ram_map_mut()
+            .register("lives", RamField::new(0x075A, Width::U8));
+        assert_eq!(console.observations()["lives"], 0);
+
+        console.cpu_mut().mem_write(0x075A, 5);
+        assert_eq!(console.observations()["lives"], 5);
+    }
+
+    #[test]
+    fn test_frames_advance_cpu_cycles() {
+        let mut console = Console::new();
+
+        // Increment register_x forever (the program never reaches a break within two frames)
+        console.load(&[0xe8; 0x7FF0]);
+        console.run_frame();
+        console.run_frame();
+
+        assert_eq!(console.frame(), 2);
+        assert!(console.cpu().cycles >= frame_end_cycle(CpuVariant::Ricoh2A03, 1));
+        assert!(console.cpu().cycles < frame_end_cycle(CpuVariant::Ricoh2A03, 1) + 7);
+        // overshoots by at most one instruction
+    }
+
+    #[test]
+    fn test_console_is_send() {
+        fn assert_send<T: Send + 'static>() {}
+        assert_send::<Console>();
+        assert_send::<Rom>();
+        assert_send::<RomError>();
+        assert_send::<StateError>();
+    }
+
+    #[test]
+    fn test_vblank_nmi_runs_handler() {
+        let mut program = vec![0; 0x8000];
+        #[rustfmt::skip]
+        program[..8].copy_from_slice(&[
+            0xa9, 0x80,       // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000, enabling NMI
+            0x4c, 0x05, 0x80, // JMP $8005
+        ]);
+        program[0x10..0x13].copy_from_slice(&[0xe6, 0x00, 0x40]); // INC $00, RTI
+        program[0x7FFA..0x7FFC].copy_from_slice(&[0x10, 0x80]);
+
+        let mut console = Console::new();
+        console.load(&program);
+        for _ in 0..3 {
+            console.run_frame();
+        }
+        // One NMI at the start of each frame's vblank
+        assert_eq!(console.cpu().mem_peek(0x0000), 3);
+    }
+
+    #[test]
+    fn test_seed_reproduces_random_ram() {
+        let run = |seed| {
+            let mut console = Console::new();
+            console.set_seed(seed);
+            console.set_ram_init(RamInit::Random);
+            console.set_halt_on_brk(true);
+            // LDA $10, STA $0200, BRK
+            console.load(&[0xa5, 0x10, 0x8d, 0x00, 0x02, 0x00]);
+            console.run_frame();
+            console.state_hash()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_pal_frames_are_longer() {
+        let mut console = Console::new();
+        console.cpu_mut().variant = CpuVariant::Ricoh2A07;
+        console.load(&[0xe8; 0x7FF0]);
+        console.run_frame();
+        console.run_frame();
+
+        // 33247.5 cycles per frame
+        assert!(console.cpu().cycles >= 66_495);
+        assert!(console.cpu().cycles < 66_495 + 7);
+    }
+
+    #[test]
+    fn test_queued_input_lands_on_exact_frame() {
+        let mut console = Console::new();
+        console.load(&[0x00]);
+        console.queue_input(2, 0, JoypadButton::START);
+        console.hold_input(3..=4, 1, JoypadButton::RIGHT);
+
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            console.run_frame();
+            seen.push((console.joypad(0).buttons(), console.joypad(1).buttons()));
+        }
+
+        let none = JoypadButton::empty();
+        let start = JoypadButton::START;
+        let right = JoypadButton::RIGHT;
+        assert_eq!(
+            seen,
+            vec![
+                (none, none),
+                (none, none),
+                (start, none),
+                (start, right),
+                (start, right),
+                (start, none),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_state_hash_detects_divergence() {
+        let mut a = Console::new();
+        let mut b = Console::new();
+        a.load(&[0xe8; 0x7FF0]);
+        b.load(&[0xe8; 0x7FF0]);
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        b.queue_input(2, 0, JoypadButton::UP);
+        for _ in 0..4 {
+            a.run_frame();
+            b.run_frame();
+            if a.frame() <= 2 {
+                assert_eq!(a.state_hash(), b.state_hash());
+            } else {
+                assert_ne!(a.state_hash(), b.state_hash());
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_hash_covers_memory() {
+        let mut console = Console::new();
+        let before = console.state_hash();
+
+        console.cpu_mut().mem_write(0x0300, 1);
+        assert_ne!(console.state_hash(), before);
+
+        // The same memory, but the next power cycle fills RAM differently
+        let (mut a, mut b) = (Console::new(), Console::new());
+        b.set_seed(1);
+        assert_ne!(a.state_hash(), b.state_hash());
+        a.set_seed(1);
+        assert_eq!(a.state_hash(), b.state_hash());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut console = Console::new();
+        console.load(&[0xe8; 0x7FF0]);
+        console.queue_input(1, 1, JoypadButton::SELECT);
+        console.run_frame();
+        console.run_frame();
+
+        let saved = console.save_state();
+        let hash = console.state_hash();
+        let info = savestate::state_info(&saved).unwrap();
+        assert_eq!(info.frame, 2);
+        assert_eq!(info.rom_hash, console.rom_hash());
+        assert_eq!(
+            info.thumbnail(),
+            Some(&Thumbnail::from_frame(
+                &console.frame_buffer,
+                &console.palette
+            ))
+        );
+
+        console.run_frame();
+        assert_ne!(console.state_hash(), hash);
+
+        console.load_state(&saved).unwrap();
+        assert_eq!(console.state_hash(), hash);
+    }
+
+    #[test]
+    fn test_state_loads_under_other_settings() {
+        let mut tooled = Console::new();
+        tooled.set_diagnostics(true);
+        tooled.enable_instruction_history(16);
+        tooled.enable_journal();
+        tooled.enable_rewind(10);
+        tooled.set_flash_filter(Some(FlashFilter::default()));
+        tooled.set_frame_blend(Some(FrameBlend::default()));
+        tooled.set_pixel_format(PixelFormat::Indexed);
+        tooled.load(&[0xe8; 0x7FF0]);
+        tooled.run_frame();
+        tooled.run_frame();
+
+        let mut plain = Console::new();
+        plain.load(&[0xe8; 0x7FF0]);
+        plain.load_state(&tooled.save_state()).unwrap();
+        assert_eq!(plain.state_hash(), tooled.state_hash());
+
+        // And back the other way, after both have run on
+        plain.run_frame();
+        tooled.run_frame();
+        assert_eq!(plain.state_hash(), tooled.state_hash());
+        tooled.load_state(&plain.save_state()).unwrap();
+        assert_eq!(tooled.state_hash(), plain.state_hash());
+    }
+
+    #[test]
+    fn test_fast_boot_matches_running_the_frames() {
+        let program = [0xe6, 0x10, 0x4c, 0x00, 0x80]; // loop: INC $10; JMP loop
+        let mut slow = Console::new();
+        slow.load(&program);
+        slow.run_frame();
+        slow.run_frame();
+
+        let mut fast = Console::new();
+        fast.set_fast_boot(true);
+        fast.set_paused(true);
+        fast.load(&program);
+        assert_eq!(fast.frame(), FAST_BOOT_FRAMES);
+        assert_eq!(fast.state_hash(), slow.state_hash());
+        assert_eq!(fast.frame_ref().pixels(), slow.frame_ref().pixels());
+        assert!(fast.is_paused());
+
+        fast.set_paused(false);
+        fast.run_frame();
+        slow.run_frame();
+        assert_eq!(fast.state_hash(), slow.state_hash());
+
+        fast.power_cycle();
+        assert_eq!(fast.frame(), 3 + FAST_BOOT_FRAMES);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut console = Console::new();
+        console.load(&[0xe8; 0x7FF0]);
+        console.enable_rewind(100);
+
+        let mut hashes = Vec::new();
+        for _ in 0..5 {
+            hashes.push(console.state_hash());
+            console.run_frame();
+        }
+
+        while let Some(hash) = hashes.pop() {
+            assert!(console.rewind());
+            assert_eq!(console.state_hash(), hash);
+        }
+        assert!(!console.rewind());
+        assert_eq!(console.frame(), 0);
+
+        // Going over a history shorter than a keyframe interval keeps the newest frames
+        console.enable_rewind(10);
+        for _ in 0..11 {
+            console.run_frame();
+        }
+        assert!(console.rewind());
+        assert_eq!(console.frame(), 10);
+
+        // Another game's history is gone once it's swapped out
+        for _ in 0..10 {
+            console.run_frame();
+        }
+        console.load(&[0xc8; 0x7FF0]);
+        let hash = console.state_hash();
+        assert!(!console.rewind());
+        assert_eq!(console.state_hash(), hash);
+        console.run_frame();
+        assert!(console.rewind());
+        assert_eq!(console.state_hash(), hash);
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_rom() {
+        let mut a = Console::new();
+        a.load(&[0xe8, 0x00]);
+        let mut b = Console::new();
+        b.load(&[0xaa, 0x00]);
+
+        let result = b.load_state(&a.save_state());
+        assert!(matches!(result, Err(StateError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn test_frame_pixel_formats() {
+        let mut console = Console::new();
+        assert_eq!(console.frame_ref().pixels().len(), 256 * 240 * 4);
+
+        console.set_pixel_format(PixelFormat::Rgb565);
+        assert_eq!(console.frame_ref().pixels().len(), 256 * 240 * 2);
+
+        console.set_pixel_format(PixelFormat::Indexed);
+        console.run_frame();
+        let frame = console.frame_ref();
+        assert_eq!(frame.pixels(), &frame.indices().pixels[..]);
+    }
+
+    #[test]
+    fn test_frames_swap_buffers() {
+        let mut console = Console::new();
+        // Backdrop color 0x30, drawn at the end of frame 0
+        console.cpu_mut().mem_write(0x2006, 0x3F);
+        console.cpu_mut().mem_write(0x2006, 0x00);
+        console.cpu_mut().mem_write(0x2007, 0x30);
+
+        console.run_frame();
+        assert_eq!(console.frame_ref().number(), 0);
+        assert_eq!(console.frame_ref().indices().get_pixel(0, 0), 0x30);
+        assert_eq!(
+            &console.frame_ref().pixels()[0..4],
+            &[0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(console.back_buffer.get_pixel(0, 0), 0); // previous front buffer
+
+        console.run_frame();
+        let info = console.frame_info();
+        assert_eq!(info.number, 1);
+        assert!(info.odd);
+        assert_eq!(info.start_cycle + info.cycles, console.cpu().cycles);
+        assert!(!info.rendering);
+        assert_eq!(console.frame_ref().info(), info);
+    }
+
+    #[test]
+    fn test_load_rom_mirrors_16kb_prg() {
+        let mut raw = crate::rom::test::ines(1, 0, 0, 0, 0xE8);
+        let vectors = raw.len() - 4; // reset vector at 0xFFFC
+        raw[vectors..vectors + 2].copy_from_slice(&[0x00, 0xC0]);
+        let rom = Rom::new(&raw).unwrap();
+
+        let mut console = Console::new();
+        console.load_rom(&rom).unwrap();
+
+        assert_eq!(console.cpu().program_counter, 0xC000);
+        assert_eq!(console.cpu().mem_peek(0x8000), 0xE8);
+        assert_eq!(console.rom_hash(), rom.hash());
+
+        let unsupported = Rom::new(&crate::rom::test::ines(1, 0, 0x10, 0, 0)).unwrap();
+        assert!(matches!(
+            console.load_rom(&unsupported),
+            Err(RomError::UnsupportedMapper(1))
+        ));
+    }
+
+    #[test]
+    fn test_rom_timing_picks_the_region() {
+        let mut rom = Rom::new(&crate::rom::test::ines(1, 0, 0, 0, 0xEA)).unwrap();
+        let mut console = Console::new();
+        rom.timing = Some(Timing::Pal);
+        console.load_rom(&rom).unwrap();
+        assert_eq!(console.region(), Region::Pal);
+        rom.timing = Some(Timing::MultiRegion);
+        console.load_rom(&rom).unwrap();
+        assert_eq!(console.region(), Region::Pal);
+
+        console.force_region(Some(Region::Ntsc));
+        rom.timing = Some(Timing::Dendy);
+        console.load_rom(&rom).unwrap();
+        assert_eq!(console.region(), Region::Ntsc);
+        let mismatch = console.take_region_mismatch().unwrap();
+        assert_eq!(
+            mismatch.to_string(),
+            "PAL game running on a console forced to NTSC; its music and speed will be off"
+        );
+        assert_eq!(console.take_region_mismatch(), None);
+    }
+
+    #[test]
+    fn test_reload_rom_keeps_state() {
+        // INC $10; JMP $8000, then the rebuild increments $11 instead
+        let nrom = |zero_page: u8, chr_pages: u8| {
+            let mut raw = crate::rom::test::ines(1, chr_pages, 0, 0, 0);
+            raw[16..21].copy_from_slice(&[0xe6, zero_page, 0x4c, 0x00, 0x80]);
+            let vectors = 16 + PRG_ROM_PAGE_SIZE - 4;
+            raw[vectors..vectors + 2].copy_from_slice(&[0x00, 0x80]);
+            Rom::new(&raw).unwrap()
+        };
+        let mut console = Console::new();
+        console.load_rom(&nrom(0x10, 0)).unwrap();
+        console.run_frame();
+        let counted = console.cpu().mem_peek(0x10);
+        assert_ne!(counted, 0);
+
+        let rebuilt = nrom(0x11, 0);
+        console.reload_rom(&rebuilt, true).unwrap();
+        assert_eq!(console.frame(), 1);
+        assert_eq!(console.rom_hash(), rebuilt.hash());
+        console.run_frame();
+        assert_eq!(console.cpu().mem_peek(0x10), counted);
+        assert_ne!(console.cpu().mem_peek(0x11), 0);
+
+        console.reload_rom(&rebuilt, false).unwrap();
+        assert_eq!(console.cpu().mem_peek(0x11), 0);
+
+        // A state saved with CHR ROM doesn't hold the CHR RAM the rebuild needs, so it starts over
+        console.load_rom(&nrom(0x10, 1)).unwrap();
+        console.run_frame();
+        assert_ne!(console.cpu().mem_peek(0x10), 0);
+        console.reload_rom(&rebuilt, true).unwrap();
+        assert_eq!(console.rom_hash(), rebuilt.hash());
+        assert_eq!(console.cpu().mem_peek(0x10), 0);
+        console.run_frame();
+        assert_ne!(console.cpu().mem_peek(0x11), 0);
+    }
+
+    #[test]
+    fn test_latched_input_is_what_the_game_polled() {
+        let mut console = Console::new();
+        console.set_halt_on_brk(true);
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0x16, 0x40, // STA $4016
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x16, 0x40, // STA $4016
+            0x00,             // BRK
+        ]);
+        console.queue_input(0, 0, JoypadButton::START);
+        console.run_frame();
+        assert_eq!(console.latched_input(0), Some(JoypadButton::START));
+        assert_eq!(console.latched_input(1), Some(JoypadButton::empty()));
+
+        let poll = console.poll_point().unwrap();
+        assert_eq!(poll.cycle, 2 + 4 + 2 + 4);
+        assert_eq!(poll.scanline, 241);
+
+        // Held on the controller, but the game has stopped polling
+        console.joypad_mut(0).set_buttons(JoypadButton::BUTTON_A);
+        console.run_frame();
+        assert_eq!(console.latched_input(0), None);
+        assert_eq!(console.poll_point(), None);
+    }
+
+    #[test]
+    fn test_input_is_sampled_just_before_the_poll() {
+        let mut program = vec![0; 0x8000];
+        #[rustfmt::skip]
+        program[..8].copy_from_slice(&[
+            0xa9, 0x80,       // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000, enabling NMI
+            0x4c, 0x05, 0x80, // JMP $8005
+        ]);
+        // The NMI handler waits a while, then polls and keeps the first button in $11
+        #[rustfmt::skip]
+        program[0x10..0x2a].copy_from_slice(&[
+            0xa2, 0x64,       // LDX #100
+            0xca, 0xd0, 0xfd, // DEX; BNE
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // LDA #1; STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #0; STA $4016
+            0xad, 0x16, 0x40, // LDA $4016
+            0x29, 0x01,       // AND #1
+            0x85, 0x11,       // STA $11
+            0x40, 0xea, 0xea, 0xea, // RTI
+        ]);
+        program[0x7FFA..0x7FFC].copy_from_slice(&[0x10, 0x80]);
+
+        let mut console = Console::new();
+        console.load(&program);
+        console.run_frame();
+        console.run_frame();
+        let poll = console.poll_point().unwrap();
+        assert!(poll.cycle > 500, "{poll:?}");
+
+        console.run_frame_sampling_input(50, |joypad, _| {
+            joypad.set_buttons(JoypadButton::BUTTON_A);
+        });
+        assert_eq!(console.cpu().mem_peek(0x11), 1);
+        // The NMI lands on an instruction boundary, so the poll moves by a few cycles
+        let again = console.poll_point().unwrap();
+        assert!(again.cycle.abs_diff(poll.cycle) < 8, "{again:?}");
+    }
+
+    #[test]
+    fn test_late_input_applies_next_frame() {
+        let mut console = Console::new();
+        console.load(&[0x00]);
+        console.run_frame();
+        console.run_frame();
+
+        console.queue_input(0, 0, JoypadButton::BUTTON_A);
+        console.run_frame();
+        assert_eq!(console.joypad(0).buttons(), JoypadButton::BUTTON_A);
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let mut console = Console::new();
+        console.load(&[0xe8; 0x7FF0]);
+        console.run_frame();
+        console.run_frame();
+
+        // About 29780 cycles per frame, give or take the instruction that crosses the boundary
+        let stats = console.frame_stats();
+        assert!((29_770..29_790).contains(&stats.cycles));
+        assert!(stats.total() >= stats.cpu);
+    }
+
+    #[test]
+    fn test_pause_freezes_and_resumes_in_lockstep() {
+        let mut program = vec![0xe8; 0x7FF0];
+        program.push(0x00);
+        let mut reference = Console::new();
+        reference.load(&program);
+        let mut console = Console::new();
+        console.load(&program);
+        console.enable_rewind(10);
+        console.queue_input(1, 0, JoypadButton::START);
+
+        console.run_frame();
+        console.set_paused(true);
+        let frozen = console.state_hash();
+        for _ in 0..5 {
+            console.run_frame();
+        }
+        assert_eq!(console.state_hash(), frozen);
+        assert_eq!(console.frame_ref().number(), 0);
+
+        console.set_paused(false);
+        console.run_frame();
+        reference.queue_input(1, 0, JoypadButton::START);
+        reference.run_frame();
+        reference.run_frame();
+        assert_eq!(console.state_hash(), reference.state_hash());
+    }
+
+    #[test]
+    fn test_pause_fades_audio() {
+        #[rustfmt::skip]
+        let program = [
+            0xa9, 0x01, 0x8d, 0x15, 0x40, // LDA #$01; STA $4015
+            0xa9, 0xbf, 0x8d, 0x00, 0x40, // LDA #$BF; STA $4000: constant volume 15
+            0xa9, 0xfd, 0x8d, 0x02, 0x40, // LDA #$FD; STA $4002
+            0xa9, 0x00, 0x8d, 0x03, 0x40, // LDA #$00; STA $4003
+            0x4c, 0x14, 0x80,             // loop: JMP loop
+        ];
+        let mut reference = Console::new();
+        reference.load(&program);
+        let mut console = Console::new();
+        console.load(&program);
+        for _ in 0..3 {
+            console.run_frame();
+            reference.run_frame();
+        }
+        let level = *console.audio_samples().last().unwrap();
+        assert_ne!(level, 0.0);
+
+        let fade = (console.apu().sample_rate() / PAUSE_FADES_PER_SECOND) as usize;
+        console.set_paused(true);
+        console.run_frame();
+        let faded = console.audio_samples();
+        assert_eq!(faded.len(), fade);
+        assert!((faded[0] - level).abs() < level.abs() * 0.01);
+        assert_eq!(faded[fade - 1], 0.0);
+        assert!(faded.windows(2).all(|pair| pair[1].abs() <= pair[0].abs()));
+        console.run_frame();
+        assert!(console.audio_samples().is_empty());
+
+        console.set_paused(false);
+        console.run_frame();
+        reference.run_frame();
+        let (resumed, played) = (console.audio_samples(), reference.audio_samples());
+        assert_eq!(resumed.len(), played.len());
+        assert_eq!(resumed[0], 0.0);
+        for i in 1..fade {
+            assert!(resumed[i].abs() <= played[i].abs());
+        }
+        assert_eq!(resumed[fade..], played[fade..]);
+    }
+
+    #[test]
+    fn test_watched_frames_stop_and_resume() {
+        let program = [0xe6, 0x10, 0x4c, 0x00, 0x80]; // loop: INC $10; JMP loop
+        let mut reference = Console::new();
+        reference.load(&program);
+        let mut console = Console::new();
+        console.load(&program);
+        console.queue_input(0, 0, JoypadButton::START);
+        reference.queue_input(0, 0, JoypadButton::START);
+
+        let mut watchdog = Watchdog::new().max_cycles(10_000);
+        assert_eq!(
+            console.run_frame_watched(&mut watchdog),
+            Err(StopReason::CycleLimit)
+        );
+        assert_eq!(console.frame(), 0);
+        // 1250 rounds of 8 cycles, stopped before the next INC
+        assert_eq!(console.cpu().mem_peek(0x10), (1250 % 256) as u8);
+        console.run_frame();
+        reference.run_frame();
+        assert_eq!(console.state_hash(), reference.state_hash());
+
+        let token = CancelToken::new();
+        let mut watchdog = Watchdog::new().cancel_on(&token);
+        console.run_frame_watched(&mut watchdog).unwrap();
+        token.cancel();
+        assert_eq!(
+            console.run_frame_watched(&mut watchdog),
+            Err(StopReason::Cancelled)
+        );
+        assert_eq!(console.frame(), 2);
+
+        let mut watchdog = Watchdog::new().max_cycles(80);
+        assert_eq!(
+            console.cpu_mut().run_watched(&mut watchdog),
+            Err(StopReason::CycleLimit)
+        );
+        assert_eq!(watchdog.spent(), 80);
+    }
+
+    #[test]
+    fn test_jam_recovery() {
+        // INC $10; LDA $10; CMP #1; BNE loop; KIL; loop: JMP loop
+        let program = [
+            0xe6, 0x10, 0xa5, 0x10, 0xc9, 0x01, 0xd0, 0x01, 0x02, 0x4c, 0x09, 0x80,
+        ];
+        let mut console = Console::new();
+        console.load(&program);
+        let mut watchdog = Watchdog::new();
+        assert_eq!(
+            console.run_frame_watched(&mut watchdog),
+            Err(StopReason::Jammed)
+        );
+        assert_eq!(console.frame(), 1);
+        assert!(console.is_jammed());
+        assert_eq!(
+            console.run_frame_watched(&mut watchdog),
+            Err(StopReason::Jammed)
+        );
+        assert_eq!(console.frame(), 1);
+
+        // Skipping runs on into the loop; the reset runs the program again with $10 kept
+        for (recovery, count) in [(JamRecovery::Skip, 1), (JamRecovery::Reset, 2)] {
+            console.load(&program);
+            console.set_jam_recovery(recovery);
+            console.run_frame_watched(&mut watchdog).unwrap();
+            assert!(!console.is_jammed());
+            assert_eq!(console.jams(), 1);
+            assert_eq!(console.cpu().mem_peek(0x10), count);
+        }
+    }
+}
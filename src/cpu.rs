@@ -44,76 +44,543 @@
 //!
 //! - Processor status (P) - 8-bit register represents 7 status flags that can be set or unset depending on the result of the last executed instruction (for example Z flag is set (1) if the result of an operation is 0, and is unset/erased (0) otherwise)
 //!
+//! ---
+//!
+//! #### Outside the NES
+//!
+//! [`CPU`] is generic over the memory it's attached to, so it also works as a plain 6502 core
+//! for other machines. [`CpuVariant`] picks the chip: the default [`CpuVariant::Ricoh2A03`]
+//! behaves like the NES's CPU, while [`CpuVariant::Nmos6502`] adds the decimal mode the 2A03
+//! lacks.
+//!
+//! ```
+//! use nes_emulator::cpu::{CpuVariant, Mem, CPU};
+//!
+//! struct Ram([u8; 0x10000]);
+//!
+//! impl Mem for Ram {
+//!     fn mem_read(&mut self, addr: u16) -> u8 {
+//!         self.0[addr as usize]
+//!     }
+//!     fn mem_peek(&self, addr: u16) -> u8 {
+//!         self.0[addr as usize]
+//!     }
+//!     fn mem_write(&mut self, addr: u16, data: u8) {
+//!         self.0[addr as usize] = data;
+//!     }
+//! }
+//!
+//! // SED, CLC, LDA #$19, ADC #$03, BRK
+//! let mut ram = Ram([0; 0x10000]);
+//! ram.0[0x0600..0x0608].copy_from_slice(&[0xf8, 0x18, 0xa9, 0x19, 0x69, 0x03, 0x00, 0x00]);
+//! ram.mem_write_u16(0xFFFC, 0x0600);
+//!
+//! let mut cpu = CPU::with_bus(ram, CpuVariant::Nmos6502);
+//! cpu.set_halt_on_brk(true);
+//! cpu.reset();
+//! cpu.run();
+//! assert_eq!(cpu.registers().a, 0x22);
+//! ```
+
+use std::hash::{Hash, Hasher};
+
+use crate::bus::Bus;
+use crate::diagnostics::Diagnostics;
+use crate::opcodes;
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+use crate::watchdog::{StopReason, Watchdog};
+
+/// Byte addressable memory as seen from the CPU.
+///
+/// Implemented by the [`CPU`] and [`Bus`] so that tooling (RAM maps, scripting, debuggers) can read
+/// memory without caring how it is stored.
+pub trait Mem {
+    /// Returns data stored within memory, triggering any side effects of the read (e.g. shifting
+    /// a controller's button state)
+    /// * `addr` - An u16 sized address that corresponds to an address in memory
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    /// Returns data stored within memory without side effects, for tooling that must not disturb
+    /// the running program
+    fn mem_peek(&self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    /// Reads a little endian u16 starting at `pos`
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /// Writes `data` as a little endian u16 starting at `pos`
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+}
+
+/// Bits of the processor status register
+pub mod flags {
+    pub const CARRY: u8 = 0b0000_0001;
+    pub const ZERO: u8 = 0b0000_0010;
+    pub const INTERRUPT_DISABLE: u8 = 0b0000_0100;
+    /// Decimal mode; the NES's 2A03 ignores it, but the flag itself still works
+    pub const DECIMAL: u8 = 0b0000_1000;
+    /// Only exists in the copy of the status pushed to the stack, set by `BRK` and `PHP`
+    pub const BREAK: u8 = 0b0001_0000;
+    /// Unused bit, always set when the status is pushed
+    pub const BREAK2: u8 = 0b0010_0000;
+    pub const OVERFLOW: u8 = 0b0100_0000;
+    pub const NEGATIVE: u8 = 0b1000_0000;
+}
+
+/// Start of the stack page; the stack pointer is an offset into it
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xFD;
+
+/// How an instruction finds its operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    /// The byte after the opcode
+    Immediate,
+    /// An address in page zero
+    ZeroPage,
+    /// A page zero address plus X, wrapping within page zero
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    /// An absolute address plus X
+    AbsoluteX,
+    AbsoluteY,
+    /// `JMP` only: an absolute address holding the target
+    Indirect,
+    /// A page zero pointer at the operand plus X
+    IndirectX,
+    /// A page zero pointer at the operand, plus Y after dereferencing
+    IndirectY,
+    /// 65C02 only: a page zero pointer at the operand
+    ZeroPageIndirect,
+    /// 65C02 `JMP` only: an absolute address plus X holding the target
+    AbsoluteIndexedIndirect,
+    /// Branches: a signed offset from the next instruction
+    Relative,
+    /// The instruction works on the accumulator
+    Accumulator,
+    /// No operand
+    Implied,
+}
+
+/// Whether an instruction only reads its operand, which changes the bus reads of indexed modes.
+/// Read-modify-write instructions count as writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// The 6502 implementation being emulated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CpuVariant {
+    /// The NES's Ricoh 2A03, a 6502 with decimal mode removed
+    #[default]
+    Ricoh2A03,
+    /// The PAL NES's Ricoh 2A07: the same instructions as the 2A03 at a slower clock, with more
+    /// CPU cycles per (50Hz) frame
+    Ricoh2A07,
+    /// The original NMOS 6502, as in the Apple II, Commodore 64 and Atari 8-bit computers
+    Nmos6502,
+    /// The CMOS 65C02, as in the enhanced Apple IIe. It adds instructions and fixes the
+    /// `JMP ($xxFF)` bug, executes undefined opcodes as `NOP`s, and sets N and Z properly in
+    /// decimal mode. The Rockwell and WDC bit instructions, `WAI` and `STP` run as one byte `NOP`s.
+    Cmos65C02,
+}
+
+impl CpuVariant {
+    /// Whether `ADC` and `SBC` do BCD arithmetic while the decimal flag is set
+    pub fn has_decimal_mode(self) -> bool {
+        match self {
+            CpuVariant::Ricoh2A03 | CpuVariant::Ricoh2A07 => false,
+            CpuVariant::Nmos6502 | CpuVariant::Cmos65C02 => true,
+        }
+    }
+}
 
-pub struct CPU {
-    pub register_a: u8,
-    pub register_x: u8,
+/// The registers a program can see, as read and set through [`CPU::registers`] and
+/// [`CPU::set_registers`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    /// See [`flags`]
     pub status: u8,
-    pub program_counter: u16,
-    memory: [u8; 0xFFFF],
+    pub sp: u8,
+    pub pc: u16,
 }
 
-impl Default for CPU {
-    fn default() -> Self {
-        Self {
-            register_a: 0u8,
-            register_x: 0u8,
-            status: 0u8,
-            program_counter: 0u16,
-            memory: [0u8; 0xFFFF],
+/// The fields are internal so their layout can change with the emulation; use the accessors.
+#[derive(Clone, Default)]
+pub struct CPU<B: Mem = Bus> {
+    pub(crate) register_a: u8,
+    pub(crate) register_x: u8,
+    pub(crate) register_y: u8,
+    pub(crate) status: u8,
+    pub(crate) stack_pointer: u8,
+    pub(crate) program_counter: u16,
+    /// Total cycles executed since power on
+    pub(crate) cycles: u64,
+    pub(crate) variant: CpuVariant,
+    pub(crate) bus: B,
+    /// Developer warnings checked while executing, off unless set
+    pub(crate) diagnostics: Option<Diagnostics>,
+    /// Whether `BRK` stops [`CPU::step`] instead of taking the interrupt, for test programs
+    pub(crate) halt_on_brk: bool,
+}
+
+/// Diagnostics and halting on `BRK` are tooling state, not part of the emulated machine
+impl<B: Mem + Hash> Hash for CPU<B> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.register_a.hash(state);
+        self.register_x.hash(state);
+        self.register_y.hash(state);
+        self.status.hash(state);
+        self.stack_pointer.hash(state);
+        self.program_counter.hash(state);
+        self.cycles.hash(state);
+        self.variant.hash(state);
+        self.bus.hash(state);
+    }
+}
+
+/// Reads and writes through the CPU are the ones [`Diagnostics`] check
+impl<B: Mem> Mem for CPU<B> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.check_read(addr);
+        }
+        self.bus.mem_read(addr)
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.bus.mem_peek(addr)
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.check_write(addr, data);
         }
+        self.bus.mem_write(addr, data)
     }
 }
+
+/// Whether `a` and `b` are on different 256 byte pages
+fn page_crossed(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
+
 impl CPU {
+    /// A 2A03 on an NES bus
     pub fn new() -> Self {
+        CPU::with_bus(Bus::new(), CpuVariant::Ricoh2A03)
+    }
+
+    /// Copies `program` to 0x8000, where [`CPU::reset`] starts executing it
+    pub fn load(&mut self, program: &[u8]) {
+        self.bus.load(0x8000, program);
+        self.bus.load(0xFFFC, &[0x00, 0x80]);
+    }
+
+    /// Loads `program` and runs it until it hits `BRK`, which halts it here rather than taking
+    /// the interrupt
+    pub fn load_and_run(&mut self, program: &[u8]) {
+        self.set_halt_on_brk(true);
+        self.load(program);
+        self.reset();
+        self.run()
+    }
+}
+
+impl<B: Mem> CPU<B> {
+    /// A CPU of `variant` attached to `bus`, which must already hold the reset vector before
+    /// [`CPU::reset`]
+    pub fn with_bus(bus: B, variant: CpuVariant) -> Self {
         Self {
             register_a: 0,
             register_x: 0,
+            register_y: 0,
             status: 0,
+            stack_pointer: STACK_RESET,
             program_counter: 0,
-            memory: [0u8; 0xFFFF],
+            cycles: 0,
+            variant,
+            bus,
+            diagnostics: None,
+            halt_on_brk: false,
         }
     }
 
-    /// Returns data stored within CPU memory
-    /// * `addr` - An u16 sized address that corresponds to an address in memory
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            status: self.status,
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+        }
     }
 
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.register_a = registers.a;
+        self.register_x = registers.x;
+        self.register_y = registers.y;
+        self.status = registers.status;
+        self.stack_pointer = registers.sp;
+        self.program_counter = registers.pc;
     }
-    fn mem_read_u16(&mut self, pos: u16) -> u16 {
-        let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+
+    /// Total cycles executed since power on, including DMA stalls
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Switches the instruction set and timing from the next instruction on
+    pub fn set_variant(&mut self, variant: CpuVariant) {
+        self.variant = variant;
+    }
+
+    /// Makes `BRK` stop the CPU, as [`CPU::step`] returning `false`, instead of pushing the
+    /// program counter and status and jumping through the vector at `0xFFFE`. Test programs end
+    /// with a `BRK` this way; off by default.
+    pub fn set_halt_on_brk(&mut self, halt: bool) {
+        self.halt_on_brk = halt;
+    }
+
+    pub fn halt_on_brk(&self) -> bool {
+        self.halt_on_brk
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
     }
 
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Restarts at the reset vector with interrupts disabled, so a program only takes IRQs once
+    /// it clears the flag
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
-        self.status = 0;
+        self.register_y = 0;
+        self.status = flags::INTERRUPT_DISABLE;
+        self.stack_pointer = STACK_RESET;
 
         self.program_counter = self.mem_read_u16(0xFFFC);
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000..(0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x8000);
+    /// Takes a non-maskable interrupt, as the PPU raises at the start of vblank: pushes the
+    /// program counter and status, then jumps through the vector at `0xFFFA`
+    pub fn nmi(&mut self) {
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        self.interrupt(self.program_counter, 0, 0xFFFA);
+        self.cycles += 7;
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run()
+    /// Takes a maskable interrupt, as mappers and the APU raise, unless the interrupt disable
+    /// flag is set: pushes the program counter and status, then jumps through the vector at
+    /// `0xFFFE`. Returns whether the interrupt was taken. The line is level triggered, so call it
+    /// before each instruction for as long as the source holds it.
+    pub fn irq(&mut self) -> bool {
+        if self.status & flags::INTERRUPT_DISABLE != 0 {
+            return false;
+        }
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        self.interrupt(self.program_counter, 0, 0xFFFE);
+        self.cycles += 7;
+        true
+    }
+
+    /// Pushes `return_addr` and the status, with `break_flag` in place of the B flag, sets the
+    /// interrupt disable flag and jumps through `vector`
+    fn interrupt(&mut self, return_addr: u16, break_flag: u8, vector: u16) {
+        self.stack_push_u16(return_addr);
+        self.stack_push((self.status & !flags::BREAK) | break_flag | flags::BREAK2);
+        self.status |= flags::INTERRUPT_DISABLE;
+        if self.variant == CpuVariant::Cmos65C02 {
+            // The 65C02 also leaves decimal mode for the handler
+            self.status &= !flags::DECIMAL;
+        }
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
+    /// Writes the registers and cycle count to a save-state chunk
+    pub fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.register_a);
+        w.write_u8(self.register_x);
+        w.write_u8(self.status);
+        w.write_u16(self.program_counter);
+        w.write_u64(self.cycles);
+        w.write_u8(self.register_y);
+        w.write_u8(self.stack_pointer);
+    }
+
+    pub fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.register_a = r.read_u8()?;
+        self.register_x = r.read_u8()?;
+        self.status = r.read_u8()?;
+        self.program_counter = r.read_u16()?;
+        self.cycles = r.read_u64()?;
+        self.register_y = r.read_u8()?;
+        self.stack_pointer = r.read_u8()?;
+        Ok(())
+    }
+
+    /// Resolves the operand address of the instruction whose operand starts at the program
+    /// counter, making the same bus reads as the hardware along the way.
+    ///
+    /// Indexed modes read from the address before the page is fixed up. Reads only spend that
+    /// cycle when indexing crosses a page, and are then charged an extra cycle; writes always do.
+    fn operand_address(&mut self, mode: AddressingMode, access: Access) -> u16 {
+        let pc = self.program_counter;
+        match mode {
+            AddressingMode::Immediate => pc,
+            AddressingMode::ZeroPage => self.mem_read(pc) as u16,
+            AddressingMode::ZeroPageX => {
+                let base = self.mem_read(pc);
+                self.dummy_read(base as u16);
+                base.wrapping_add(self.register_x) as u16
+            }
+            AddressingMode::ZeroPageY => {
+                let base = self.mem_read(pc);
+                self.dummy_read(base as u16);
+                base.wrapping_add(self.register_y) as u16
+            }
+            AddressingMode::Absolute => self.mem_read_u16(pc),
+            AddressingMode::AbsoluteX => {
+                let base = self.mem_read_u16(pc);
+                self.index(base, self.register_x, access)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.mem_read_u16(pc);
+                self.index(base, self.register_y, access)
+            }
+            AddressingMode::Indirect => {
+                // The NMOS chips read the pointer's high byte without carrying into the next page,
+                // so `JMP ($10FF)` reads its target from 0x10FF and 0x1000
+                let ptr = self.mem_read_u16(pc);
+                let lo = self.mem_read(ptr) as u16;
+                let hi_addr = match self.variant {
+                    CpuVariant::Cmos65C02 => ptr.wrapping_add(1),
+                    _ => (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF),
+                };
+                let hi = self.mem_read(hi_addr) as u16;
+                (hi << 8) | lo
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let ptr = self.mem_read(pc);
+                let lo = self.mem_read(ptr as u16) as u16;
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+                (hi << 8) | lo
+            }
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let ptr = self.mem_read_u16(pc).wrapping_add(self.register_x as u16);
+                self.mem_read_u16(ptr)
+            }
+            AddressingMode::IndirectX => {
+                let base = self.mem_read(pc);
+                self.dummy_read(base as u16);
+                let ptr = base.wrapping_add(self.register_x);
+                let lo = self.mem_read(ptr as u16) as u16;
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+                (hi << 8) | lo
+            }
+            AddressingMode::IndirectY => {
+                let ptr = self.mem_read(pc);
+                let lo = self.mem_read(ptr as u16) as u16;
+                let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+                self.index((hi << 8) | lo, self.register_y, access)
+            }
+            AddressingMode::Relative | AddressingMode::Accumulator | AddressingMode::Implied => {
+                panic!("{mode:?} has no operand address")
+            }
+        }
+    }
+
+    /// Adds an index register to `base`, making the read from the not yet fixed up address
+    fn index(&mut self, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        let crossed = page_crossed(base, addr);
+        if crossed || access == Access::Write {
+            self.dummy_read((base & 0xFF00) | (addr & 0x00FF));
+        }
+        if crossed && access == Access::Read {
+            self.cycles += 1;
+        }
+        addr
+    }
+
+    /// Reads the operand of a read instruction
+    fn read_operand(&mut self, mode: AddressingMode) -> u8 {
+        let addr = self.operand_address(mode, Access::Read);
+        self.mem_read(addr)
+    }
+
+    /// A read the CPU makes only because it can't skip the bus cycle. It goes straight to the bus,
+    /// so diagnostics don't flag a program for reads it never asked for.
+    fn dummy_read(&mut self, addr: u16) {
+        self.bus.mem_read(addr);
+    }
+
+    /// The read the CPU makes from the top of the stack while it adjusts the stack pointer, before
+    /// pulls and in `JSR`
+    fn stack_dummy_read(&mut self) {
+        self.dummy_read(STACK + self.stack_pointer as u16);
+    }
+
+    fn stack_push(&mut self, data: u8) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.check_stack(self.stack_pointer, true);
+        }
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.check_stack(self.stack_pointer, false);
+        }
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xFF) as u8);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        (hi << 8) | lo
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.status |= flag;
+        } else {
+            self.status &= !flag;
+        }
     }
 
     /// ## LDA - Load Accumulator
@@ -123,6 +590,18 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_a);
     }
 
+    /// ## LDX - Load X Register
+    fn ldx(&mut self, value: u8) {
+        self.register_x = value;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    /// ## LDY - Load Y Register
+    fn ldy(&mut self, value: u8) {
+        self.register_y = value;
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
     fn tax(&mut self) {
         self.register_x = self.register_a;
         self.update_zero_and_negative_flags(self.register_x);
@@ -135,6 +614,203 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_x)
     }
 
+    /// ## ADC - Add with Carry
+    /// Adds a byte and the carry flag to the accumulator. Carry is set when the unsigned result
+    /// overflows and overflow when the signed result does.
+    fn adc(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.adc_decimal(value);
+        } else {
+            self.add(value);
+        }
+    }
+
+    /// Binary `ADC`
+    fn add(&mut self, value: u8) {
+        let sum = self.register_a as u16 + value as u16 + (self.status & flags::CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(flags::CARRY, sum > 0xFF);
+        self.set_flag(
+            flags::OVERFLOW,
+            (value ^ result) & (self.register_a ^ result) & 0x80 != 0,
+        );
+        self.lda(result);
+    }
+
+    /// ## SBC - Subtract with Carry
+    /// Subtracts a byte and the inverted carry flag from the accumulator, i.e. adds its complement.
+    fn sbc(&mut self, value: u8) {
+        if self.decimal_mode() {
+            self.sbc_decimal(value);
+        } else {
+            self.add(!value);
+        }
+    }
+
+    fn decimal_mode(&self) -> bool {
+        self.variant.has_decimal_mode() && self.status & flags::DECIMAL != 0
+    }
+
+    /// `ADC` on two BCD bytes. On the NMOS 6502 zero comes from the binary sum, and negative and
+    /// overflow from the sum before the high digit is adjusted. The 65C02 spends a cycle setting
+    /// zero and negative from the result instead.
+    fn adc_decimal(&mut self, value: u8) {
+        let (a, value, carry) = (
+            self.register_a as u16,
+            value as u16,
+            (self.status & flags::CARRY) as u16,
+        );
+        let mut lo = (a & 0x0F) + (value & 0x0F) + carry;
+        if lo > 0x09 {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) + (value & 0xF0) + lo;
+
+        self.set_flag(flags::ZERO, (a + value + carry) as u8 == 0);
+        self.set_flag(flags::NEGATIVE, sum & 0x80 != 0);
+        self.set_flag(flags::OVERFLOW, (a ^ sum) & !(a ^ value) & 0x80 != 0);
+        if sum > 0x9F {
+            sum += 0x60;
+        }
+        self.set_flag(flags::CARRY, sum > 0xFF);
+        self.register_a = sum as u8;
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_zero_and_negative_flags(self.register_a);
+            self.cycles += 1;
+        }
+    }
+
+    /// `SBC` on two BCD bytes. On the NMOS 6502 the flags are those of the binary subtraction;
+    /// the 65C02 spends a cycle setting zero and negative from the result. The two only differ in
+    /// the result for operands that aren't valid BCD.
+    fn sbc_decimal(&mut self, value: u8) {
+        let (a, borrow) = (self.register_a, 1 - (self.status & flags::CARRY));
+        let lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow as i16;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut difference = a as i16 - value as i16 - borrow as i16;
+            if difference < 0 {
+                difference -= 0x60;
+            }
+            if lo < 0 {
+                difference -= 0x06;
+            }
+            difference as u8
+        } else {
+            let (mut lo, mut hi) = (lo, (a >> 4) as i16 - (value >> 4) as i16);
+            if lo < 0 {
+                lo -= 6;
+                hi -= 1;
+            }
+            if hi < 0 {
+                hi -= 6;
+            }
+            ((hi << 4) | (lo & 0x0F)) as u8
+        };
+
+        self.add(!value);
+        self.register_a = result;
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_zero_and_negative_flags(result);
+            self.cycles += 1;
+        }
+    }
+
+    /// ## CMP, CPX, CPY - Compare
+    /// Sets carry if `register >= value`, and zero and negative from `register - value`.
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(flags::CARRY, register >= value);
+        self.update_zero_and_negative_flags(register.wrapping_sub(value));
+    }
+
+    /// ## BIT - Bit Test
+    /// Sets zero from `A & value`, and overflow and negative from bits 6 and 7 of the value.
+    fn bit(&mut self, value: u8) {
+        self.set_flag(flags::ZERO, self.register_a & value == 0);
+        self.set_flag(flags::OVERFLOW, value & 0b0100_0000 != 0);
+        self.set_flag(flags::NEGATIVE, value & 0b1000_0000 != 0);
+    }
+
+    /// ## TSB, TRB - Test and Set/Reset Bits
+    /// Sets zero from `A & value` like `BIT`, then replaces the byte with `op(A, value)`.
+    fn test_bits(&mut self, mode: AddressingMode, op: impl FnOnce(u8, u8) -> u8) {
+        let a = self.register_a;
+        let mut zero = false;
+        self.read_modify_write(mode, |value| {
+            zero = a & value == 0;
+            op(a, value)
+        });
+        self.set_flag(flags::ZERO, zero);
+    }
+
+    /// Applies a shift or rotate to the accumulator or memory, setting carry from the bit shifted
+    /// out. `op` gets the operand and the carry flag and returns the result and the new carry.
+    fn shift(&mut self, mode: AddressingMode, op: impl Fn(u8, bool) -> (u8, bool)) {
+        let carry = self.status & flags::CARRY != 0;
+        let result = if mode == AddressingMode::Accumulator {
+            let (result, carry) = op(self.register_a, carry);
+            self.register_a = result;
+            self.set_flag(flags::CARRY, carry);
+            result
+        } else {
+            let mut carry_out = carry;
+            let result = self.read_modify_write(mode, |value| {
+                let (result, carry) = op(value, carry);
+                carry_out = carry;
+                result
+            });
+            self.set_flag(flags::CARRY, carry_out);
+            result
+        };
+        self.update_zero_and_negative_flags(result);
+    }
+
+    /// Adds `delta` to the byte at the operand address, for `INC` and `DEC`
+    fn step_memory(&mut self, mode: AddressingMode, delta: u8) {
+        let result = self.read_modify_write(mode, |value| value.wrapping_add(delta));
+        self.update_zero_and_negative_flags(result);
+    }
+
+    /// Replaces the byte at the operand address with `op` of it. Like the hardware, this writes
+    /// the unmodified byte back before the result, as a dummy access diagnostics ignore.
+    fn read_modify_write(&mut self, mode: AddressingMode, op: impl FnOnce(u8) -> u8) -> u8 {
+        let addr = self.operand_address(mode, Access::Write);
+        let value = self.mem_read(addr);
+        self.bus.mem_write(addr, value);
+        let result = op(value);
+        self.mem_write(addr, result);
+        result
+    }
+
+    fn store(&mut self, mode: AddressingMode, value: u8) {
+        let addr = self.operand_address(mode, Access::Write);
+        self.mem_write(addr, value);
+    }
+
+    /// Takes a branch with the offset at the program counter when `condition` holds. A taken
+    /// branch costs a cycle, and another if it lands on a different page.
+    fn branch(&mut self, condition: bool) {
+        let offset = self.mem_read(self.program_counter) as i8;
+        let next = self.program_counter.wrapping_add(1);
+        self.program_counter = next;
+        if condition {
+            // The CPU reads the next opcode while adding the offset to the low byte, and again
+            // from the unfixed page if the high byte needs a carry
+            self.dummy_read(next);
+            let target = next.wrapping_add(offset as u16);
+            self.cycles += 1;
+            if page_crossed(next, target) {
+                self.dummy_read((next & 0xFF00) | (target & 0x00FF));
+                self.cycles += 1;
+            }
+            self.program_counter = target;
+        }
+    }
+
+    /// Pulls the status from the stack; the break bits only exist in the pushed copy
+    fn pull_status(&mut self) {
+        self.status = (self.stack_pop() & !flags::BREAK) | flags::BREAK2;
+    }
+
     /// Helper function that manipulates CPU status on zero and negative flags
     fn update_zero_and_negative_flags(&mut self, register: u8) {
         self.update_zero_flag(register);
@@ -157,24 +833,232 @@ impl CPU {
         }
     }
 
+    /// Runs until the program stops; see [`CPU::step`]
     pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Runs like [`CPU::run`] until the program halts, or stops before the next instruction once
+    /// `watchdog` says to. Halting on a jam opcode is [`StopReason::Jammed`].
+    pub fn run_watched(&mut self, watchdog: &mut Watchdog) -> Result<(), StopReason> {
         loop {
-            let opcode = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+            watchdog.check()?;
+            let before = self.cycles;
+            let running = self.step();
+            watchdog.spend(self.cycles - before);
+            if !running {
+                return match self.is_jammed() {
+                    true => Err(StopReason::Jammed),
+                    false => Ok(()),
+                };
+            }
+        }
+    }
+
+    /// Whether the CPU stopped on one of the [jam opcodes](opcodes::JAM_OPCODES), rather than
+    /// on a [halting `BRK`](CPU::set_halt_on_brk) or another unimplemented opcode. Only meaningful once [`CPU::step`] has returned
+    /// `false`.
+    pub fn is_jammed(&self) -> bool {
+        opcodes::is_jam(
+            self.variant,
+            self.mem_peek(self.program_counter.wrapping_sub(1)),
+        )
+    }
+
+    /// Executes a single instruction, returning `false` once the program hits an unofficial
+    /// opcode, which aren't implemented, or `BRK` with [halting](CPU::set_halt_on_brk) on. The
+    /// program counter is left just past the opcode that stopped it. Jam opcodes stop it the same
+    /// way; see [`CPU::is_jammed`].
+    pub fn step(&mut self) -> bool {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.begin_instruction(self.program_counter, self.cycles);
+        }
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let Some(opcode) = opcodes::lookup_for(self.variant, code) else {
+            return false;
+        };
+        let mode = opcode.mode;
+        let operand_pc = self.program_counter;
+        self.cycles += opcode.cycles as u64;
+        if matches!(mode, AddressingMode::Implied | AddressingMode::Accumulator) {
+            // One byte instructions still read the byte after the opcode
+            self.dummy_read(self.program_counter);
+        }
+
+        match code {
+            // Loads, stores and transfers
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 | 0xB2 => {
+                let value = self.read_operand(mode);
+                self.lda(value);
+            }
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                let value = self.read_operand(mode);
+                self.ldx(value);
+            }
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
+                let value = self.read_operand(mode);
+                self.ldy(value);
+            }
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 | 0x92 => {
+                self.store(mode, self.register_a)
+            }
+            0x86 | 0x96 | 0x8E => self.store(mode, self.register_x),
+            0x84 | 0x94 | 0x8C => self.store(mode, self.register_y),
+            0xAA => self.tax(),
+            0xA8 => self.ldy(self.register_a),
+            0xBA => self.ldx(self.stack_pointer),
+            0x8A => self.lda(self.register_x),
+            0x9A => self.stack_pointer = self.register_x,
+            0x98 => self.lda(self.register_y),
+
+            // Arithmetic and logic
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 | 0x72 => {
+                let value = self.read_operand(mode);
+                self.adc(value);
+            }
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 | 0xF2 => {
+                let value = self.read_operand(mode);
+                self.sbc(value);
+            }
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 | 0x32 => {
+                let value = self.read_operand(mode);
+                self.lda(self.register_a & value);
+            }
+            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 | 0x12 => {
+                let value = self.read_operand(mode);
+                self.lda(self.register_a | value);
+            }
+            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 | 0x52 => {
+                let value = self.read_operand(mode);
+                self.lda(self.register_a ^ value);
+            }
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 | 0xD2 => {
+                let value = self.read_operand(mode);
+                self.compare(self.register_a, value);
+            }
+            0xE0 | 0xE4 | 0xEC => {
+                let value = self.read_operand(mode);
+                self.compare(self.register_x, value);
+            }
+            0xC0 | 0xC4 | 0xCC => {
+                let value = self.read_operand(mode);
+                self.compare(self.register_y, value);
+            }
+            0x24 | 0x2C | 0x34 | 0x3C => {
+                let value = self.read_operand(mode);
+                self.bit(value);
+            }
+
+            0x89 => {
+                // Immediate BIT only sets zero; there are no memory bits to copy
+                let value = self.read_operand(mode);
+                self.set_flag(flags::ZERO, self.register_a & value == 0);
+            }
+            0x64 | 0x74 | 0x9C | 0x9E => self.store(mode, 0),
+            0x04 | 0x0C => self.test_bits(mode, |a, value| value | a),
+            0x14 | 0x1C => self.test_bits(mode, |a, value| value & !a),
+
+            // Shifts, rotates, increments and decrements
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => self.shift(mode, |v, _| (v << 1, v & 0x80 != 0)),
+            0x4A | 0x46 | 0x56 | 0x4E | 0x5E => self.shift(mode, |v, _| (v >> 1, v & 1 != 0)),
+            0x2A | 0x26 | 0x36 | 0x2E | 0x3E => {
+                self.shift(mode, |v, c| ((v << 1) | c as u8, v & 0x80 != 0))
+            }
+            0x6A | 0x66 | 0x76 | 0x6E | 0x7E => {
+                self.shift(mode, |v, c| ((v >> 1) | ((c as u8) << 7), v & 1 != 0))
+            }
+            0xE6 | 0xF6 | 0xEE | 0xFE => self.step_memory(mode, 1),
+            0xC6 | 0xD6 | 0xCE | 0xDE => self.step_memory(mode, 0xFF),
+            0xE8 => self.inx(),
+            0xC8 => self.ldy(self.register_y.wrapping_add(1)),
+            0xCA => self.ldx(self.register_x.wrapping_sub(1)),
+            0x88 => self.ldy(self.register_y.wrapping_sub(1)),
+            0x1A => self.lda(self.register_a.wrapping_add(1)),
+            0x3A => self.lda(self.register_a.wrapping_sub(1)),
 
-            match opcode {
-                0xA9 => {
-                    let param = self.mem_read(self.program_counter);
-                    self.program_counter += 1;
-                    self.lda(param);
-                }
+            // Jumps, calls and branches
+            0x4C | 0x6C | 0x7C => self.program_counter = self.operand_address(mode, Access::Read),
+            0x20 => {
+                // Pushes the address of the JSR's last byte, in between reading the target's two
+                // bytes; RTS adds the missing one
+                let lo = self.mem_read(operand_pc) as u16;
+                self.stack_dummy_read();
+                self.stack_push_u16(operand_pc.wrapping_add(1));
+                let hi = self.mem_read(operand_pc.wrapping_add(1)) as u16;
+                self.program_counter = (hi << 8) | lo;
+            }
+            0x60 => {
+                self.stack_dummy_read();
+                let ret = self.stack_pop_u16();
+                self.dummy_read(ret);
+                self.program_counter = ret.wrapping_add(1);
+            }
+            0x40 => {
+                self.stack_dummy_read();
+                self.pull_status();
+                self.program_counter = self.stack_pop_u16();
+            }
+            0x80 => self.branch(true),
+            0x90 => self.branch(self.status & flags::CARRY == 0),
+            0xB0 => self.branch(self.status & flags::CARRY != 0),
+            0xD0 => self.branch(self.status & flags::ZERO == 0),
+            0xF0 => self.branch(self.status & flags::ZERO != 0),
+            0x10 => self.branch(self.status & flags::NEGATIVE == 0),
+            0x30 => self.branch(self.status & flags::NEGATIVE != 0),
+            0x50 => self.branch(self.status & flags::OVERFLOW == 0),
+            0x70 => self.branch(self.status & flags::OVERFLOW != 0),
 
-                0xAA => self.tax(),
-                0xE8 => self.inx(),
-                0x00 => return,
-                _ => todo!(),
+            // Stack
+            0x48 => self.stack_push(self.register_a),
+            0x08 => self.stack_push(self.status | flags::BREAK | flags::BREAK2),
+            0x68 => {
+                self.stack_dummy_read();
+                let value = self.stack_pop();
+                self.lda(value);
             }
+            0x28 => {
+                self.stack_dummy_read();
+                self.pull_status();
+            }
+            0xDA => self.stack_push(self.register_x),
+            0x5A => self.stack_push(self.register_y),
+            0xFA => {
+                self.stack_dummy_read();
+                let value = self.stack_pop();
+                self.ldx(value);
+            }
+            0x7A => {
+                self.stack_dummy_read();
+                let value = self.stack_pop();
+                self.ldy(value);
+            }
+
+            // Flags
+            0x18 => self.set_flag(flags::CARRY, false),
+            0x38 => self.set_flag(flags::CARRY, true),
+            0x58 => self.set_flag(flags::INTERRUPT_DISABLE, false),
+            0x78 => self.set_flag(flags::INTERRUPT_DISABLE, true),
+            0xD8 => self.set_flag(flags::DECIMAL, false),
+            0xF8 => self.set_flag(flags::DECIMAL, true),
+            0xB8 => self.set_flag(flags::OVERFLOW, false),
+
+            0xEA => {}
+            0x00 if self.halt_on_brk => return false,
+            // The byte after BRK is padding, skipped by the address it pushes
+            0x00 => self.interrupt(self.program_counter.wrapping_add(1), flags::BREAK, 0xFFFE),
+            // The 65C02 runs all of its undefined opcodes as NOPs
+            _ if self.variant == CpuVariant::Cmos65C02 => {}
+            _ => unreachable!("{} is in the opcode table", opcode.mnemonic),
+        }
+
+        // Jumps and branches set the program counter themselves; everything else skips its operand
+        let sets_pc = mode == AddressingMode::Relative
+            || matches!(code, 0x00 | 0x4C | 0x6C | 0x7C | 0x20 | 0x60 | 0x40);
+        if !sets_pc {
+            self.program_counter = operand_pc.wrapping_add(opcode.len as u16 - 1);
         }
+        true
     }
 }
 
@@ -188,10 +1072,10 @@ mod test {
 
         // Assign value 0x05 to register_a, break
         let program = vec![0xa9, 0x05, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
 
         assert_eq!(cpu.register_a, 0x05); // Register A should hold 0x05
-        assert_eq!(cpu.status, 0); // Status should not change
+        assert_eq!(cpu.status, flags::INTERRUPT_DISABLE); // Status should not change from reset
     }
 
     #[test]
@@ -200,7 +1084,7 @@ mod test {
 
         // Assign zero to accumulator, break
         let program = vec![0xa9, 0x00, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
 
         assert_eq!(cpu.status & 0b0000_0010, 0b10); // Ensure zero flag is set
     }
@@ -211,7 +1095,7 @@ mod test {
 
         // Assign negative to accumulator, break
         let program = vec![0xa9, 0x80, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
 
         assert_eq!(cpu.status & 0x80, 0x80); // Ensure negative flag is set
     }
@@ -221,7 +1105,7 @@ mod test {
 
         // Move 0xff into register_a, copy register_a to register_x, break
         let program = vec![0xa9, 0xff, 0xaa, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
 
         assert_eq!(cpu.register_x, 0xFF); // register_x should hold register_a value
     }
@@ -232,10 +1116,10 @@ mod test {
 
         // Move 0xc0 into register_a, copy register_a to register_x, increment register_x, break;
         let program = vec![0xa9, 126, 0xaa, 0xe8, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
 
         assert_eq!(cpu.register_x, 127); // register_x should hold register_a value + 1
-        assert_eq!(cpu.status, 0);
+        assert_eq!(cpu.status, flags::INTERRUPT_DISABLE);
     }
 
     #[test]
@@ -244,7 +1128,236 @@ mod test {
 
         // add 1 to register x, add 1 to register x, break
         let program = vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00];
-        cpu.load_and_run(program);
+        cpu.load_and_run(&program);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_adc_sets_carry_and_overflow() {
+        let mut cpu = CPU::new();
+
+        // 0x50 + 0x50 overflows into the sign bit, then 0xA0 + 0x70 carries out
+        cpu.load_and_run(&[0xa9, 0x50, 0x69, 0x50, 0x00]);
+        assert_eq!(cpu.register_a, 0xA0);
+        assert_eq!(
+            cpu.status & (flags::OVERFLOW | flags::CARRY | flags::NEGATIVE),
+            flags::OVERFLOW | flags::NEGATIVE
+        );
+
+        cpu.load_and_run(&[0xa9, 0xa0, 0x69, 0x70, 0x69, 0x00, 0x00]);
+        assert_eq!(cpu.register_a, 0x11); // 0x110 plus the carry on the second ADC
+        assert_eq!(cpu.status & flags::CARRY, 0);
+    }
+
+    #[test]
+    fn test_sbc_borrows() {
+        let mut cpu = CPU::new();
+
+        // Set carry (no borrow), 5 - 6
+        cpu.load_and_run(&[0x38, 0xa9, 0x05, 0xe9, 0x06, 0x00]);
+        assert_eq!(cpu.register_a, 0xFF);
+        assert_eq!(cpu.status & flags::CARRY, 0); // borrowed
+        assert_eq!(cpu.status & flags::NEGATIVE, flags::NEGATIVE);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+
+        // JSR sub, LDX #1, BRK, sub: LDA #7, RTS
+        cpu.load_and_run(&[0x20, 0x06, 0x80, 0xa2, 0x01, 0x00, 0xa9, 0x07, 0x60]);
+        assert_eq!(cpu.register_a, 7);
         assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[test]
+    fn test_branch_loop() {
+        let mut cpu = CPU::new();
+
+        // LDX #5, loop: INY, DEX, BNE loop, BRK
+        cpu.load_and_run(&[0xa2, 0x05, 0xc8, 0xca, 0xd0, 0xfc, 0x00]);
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.register_y, 5);
+        // LDX 2 + 5 * (INY 2 + DEX 2) + 4 taken BNE 3 + 1 untaken BNE 2 + BRK 7
+        assert_eq!(cpu.cycles, 2 + 5 * 4 + 4 * 3 + 2 + 7);
+    }
+
+    #[test]
+    fn test_php_plp() {
+        let mut cpu = CPU::new();
+
+        // SEC, PHP, CLC, PLP
+        cpu.load_and_run(&[0x38, 0x08, 0x18, 0x28, 0x00]);
+        let reset = flags::INTERRUPT_DISABLE;
+        assert_eq!(cpu.status, reset | flags::CARRY | flags::BREAK2);
+        assert_eq!(
+            cpu.mem_peek(0x01FD),
+            reset | flags::CARRY | flags::BREAK | flags::BREAK2
+        );
+    }
+
+    #[test]
+    fn test_indirect_y_page_cross_costs_a_cycle() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x0010, 0x06FF);
+        cpu.mem_write(0x0700, 0x42);
+
+        // LDY #1, LDA ($10),Y
+        cpu.load_and_run(&[0xa0, 0x01, 0xb1, 0x10, 0x00]);
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.cycles, 2 + 5 + 1 + 7);
+    }
+
+    #[test]
+    fn test_jmp_indirect_wraps_within_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10FF, 0x06);
+        cpu.mem_write(0x1000, 0x80); // read instead of 0x1100
+        cpu.mem_write(0x1100, 0x90);
+
+        // JMP ($10FF), LDA #1, BRK, LDA #2 (at 0x8006)
+        cpu.load_and_run(&[0x6c, 0xff, 0x10, 0xa9, 0x01, 0x00, 0xa9, 0x02, 0x00]);
+        assert_eq!(cpu.register_a, 2);
+    }
+
+    #[test]
+    fn test_zero_page_x_wraps_and_rotates() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0001, 0b1000_0001);
+
+        // LDX #2, SEC, ROL $FF,X (0x01), LSR $01
+        cpu.load_and_run(&[0xa2, 0x02, 0x38, 0x36, 0xff, 0x46, 0x01, 0x00]);
+        assert_eq!(cpu.mem_peek(0x0001), 0b0000_0001);
+        assert_eq!(cpu.status & flags::CARRY, flags::CARRY); // bit 0 of 0b0000_0011
+    }
+
+    #[test]
+    fn test_unofficial_opcode_stops() {
+        let mut cpu = CPU::new();
+        // LDA #1, KIL
+        cpu.load_and_run(&[0xa9, 0x01, 0x02, 0xa9, 0x02]);
+        assert_eq!(cpu.register_a, 1);
+        assert_eq!(cpu.program_counter, 0x8003);
+        assert!(cpu.is_jammed());
+
+        let mut cpu = CPU::new();
+        cpu.load_and_run(&[0xa9, 0x01, 0x00]); // LDA #1, BRK
+        assert!(!cpu.is_jammed());
+        cpu.load(&[0xb2]);
+        cpu.reset();
+        assert_eq!(
+            cpu.run_watched(&mut Watchdog::new()),
+            Err(StopReason::Jammed)
+        );
+    }
+
+    #[test]
+    fn test_brk_and_irq() {
+        let mut cpu = CPU::new();
+        // BRK and its padding byte, then LDA #1, with an RTI handler at $9000
+        cpu.load(&[0x00, 0xff, 0xa9, 0x01]);
+        cpu.bus.load(0x9000, &[0x40]);
+        cpu.bus.load(0xFFFE, &[0x00, 0x90]);
+        cpu.reset();
+        cpu.status = flags::CARRY;
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.status, flags::CARRY | flags::INTERRUPT_DISABLE);
+        assert_eq!(cpu.mem_peek(0x01FD), 0x80);
+        assert_eq!(cpu.mem_peek(0x01FC), 0x02);
+        assert_eq!(
+            cpu.mem_peek(0x01FB),
+            flags::CARRY | flags::BREAK | flags::BREAK2
+        );
+
+        // The handler runs with interrupts disabled, so an IRQ waits for its RTI
+        assert!(!cpu.irq());
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert!(cpu.irq());
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.cycles, 7 + 6 + 7);
+        assert_eq!(cpu.mem_peek(0x01FB), flags::CARRY | flags::BREAK2);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.register_a, 1);
+    }
+
+    #[test]
+    fn test_dummy_accesses() {
+        let mut cpu = CPU::new();
+        cpu.load(&[0xfe, 0xff, 0x02]); // INC $02FF,X
+        cpu.reset();
+        cpu.register_x = 1;
+        cpu.mem_write(0x0300, 0x41);
+        cpu.bus.start_trace();
+        cpu.step();
+
+        let trace: Vec<_> = cpu
+            .bus
+            .take_trace()
+            .iter()
+            .map(|a| (a.addr, a.value, a.write))
+            .collect();
+        assert_eq!(
+            trace[3..],
+            [
+                (0x0200, 0x00, false), // before the page is fixed up
+                (0x0300, 0x41, false),
+                (0x0300, 0x41, true), // the unmodified value written back
+                (0x0300, 0x42, true),
+            ]
+        );
+        assert_eq!(cpu.cycles, trace.len() as u64);
+    }
+
+    #[test]
+    fn test_decimal_mode_depends_on_variant() {
+        // SED, LDA #$09, ADC #$01, BRK
+        let program = [0xf8, 0xa9, 0x09, 0x69, 0x01, 0x00];
+        let mut cpu = CPU::new();
+        cpu.load_and_run(&program);
+        assert_eq!(cpu.register_a, 0x0A); // the 2A03 ignores the decimal flag
+
+        cpu.variant = CpuVariant::Nmos6502;
+        cpu.load_and_run(&program);
+        assert_eq!(cpu.register_a, 0x10);
+
+        // SED, SEC, LDA #$00, SBC #$01, BRK
+        cpu.load_and_run(&[0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x99);
+        assert_eq!(cpu.status & flags::CARRY, 0);
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let mut cpu = CPU::new();
+        cpu.variant = CpuVariant::Cmos65C02;
+        cpu.mem_write(0x10FF, 0x0A);
+        cpu.mem_write(0x1100, 0x80); // the 65C02 reads the high byte from the next page
+        cpu.mem_write(0x0020, 0xF0);
+
+        cpu.load_and_run(&[
+            0x6c, 0xff, 0x10, // JMP ($10FF)
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0x80, 0x02, // 0x800A: BRA +2
+            0xa9, 0x01, // LDA #1 (skipped)
+            0xa2, 0x05, // LDX #5
+            0xda, // PHX
+            0x7a, // PLY
+            0x02, 0xff, // undefined: two byte NOP
+            0xa9, 0x0f, // LDA #$0F
+            0x04, 0x20, // TSB $20
+            0x64, 0x21, // STZ $21
+            0x1a, // INC A
+            0x00,
+        ]);
+        assert_eq!(cpu.register_y, 5);
+        assert_eq!(cpu.register_a, 0x10);
+        assert_eq!(cpu.mem_peek(0x0020), 0xFF);
+        assert_eq!(cpu.mem_peek(0x0021), 0x00);
+        assert_eq!(cpu.status & flags::ZERO, 0);
     }
 }
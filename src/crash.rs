@@ -0,0 +1,311 @@
+//! Crash dump bundles: everything needed to look into a crash without reproducing it.
+//!
+//! With [`Console::enable_instruction_history`] on, the console keeps the last few hundred
+//! instructions it executed. When the emulator panics or the game jams the CPU, a frontend
+//! captures a [`CrashBundle`] and writes it next to the user's data, one file holding:
+//!
+//! | File | Contents |
+//! | :--- | :--- |
+//! | `report.txt` | why it was written, emulator version, ROM hash, frame, CPU registers |
+//! | `trace.txt` | the instruction history, oldest first |
+//! | `state.sav` | a save state, to load and step through the crash |
+//! | `config.ini` | the frontend configuration, when given |
+//! | `journal.txt` | the [session journal](crate::journal), when it's on |
+//!
+//! With the `zip` feature the bundle is one zip archive; without it, a directory of those files.
+//!
+//! ```no_run
+//! use nes_emulator::console::Console;
+//! use nes_emulator::crash::CrashBundle;
+//!
+//! let mut console = Console::new();
+//! console.enable_instruction_history(256);
+//! console.load(&[0x02]); // a JAM opcode
+//! console.run_frame();
+//! if console.is_halted() {
+//!     let path = CrashBundle::capture(&console, "CPU halted").write_to("crashes".as_ref())?;
+//!     eprintln!("Wrote {}", path.display());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bus::BankAddress;
+use crate::console::Console;
+use crate::cpu::CPU;
+use crate::journal::Journal;
+use crate::opcodes;
+
+/// Instructions kept by frontends writing crash dumps
+pub const DEFAULT_HISTORY_LEN: usize = 256;
+
+/// One executed instruction, with the registers as they were before it ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceEntry {
+    pub pc: u16,
+    /// Where `pc` was in PRG ROM, to tell apart code that runs at the same address
+    pub location: BankAddress,
+    /// The instruction's bytes; only the first `len` mean anything
+    pub bytes: [u8; 3],
+    pub len: u8,
+    /// `"???"` for opcodes the CPU doesn't implement
+    pub mnemonic: &'static str,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub sp: u8,
+    pub cycles: u64,
+}
+
+impl TraceEntry {
+    /// The instruction `cpu` is about to execute, read without side effects
+    pub fn capture(cpu: &CPU) -> Self {
+        use crate::cpu::Mem;
+        let pc = cpu.program_counter;
+        let opcode = opcodes::lookup_for(cpu.variant, cpu.mem_peek(pc));
+        let bytes = [0, 1, 2].map(|i| cpu.mem_peek(pc.wrapping_add(i)));
+        Self {
+            pc,
+            location: cpu.bus.bank_address(pc),
+            bytes,
+            len: opcode.map_or(1, |op| op.len),
+            mnemonic: opcode.map_or("???", |op| op.mnemonic),
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            status: cpu.status,
+            sp: cpu.stack_pointer,
+            cycles: cpu.cycles,
+        }
+    }
+}
+
+/// In the layout of the nestest log with the bank after the PC:
+/// `C000  00:0000  4C F5 C5  JMP  A:00 X:00 Y:00 P:24 SP:FD CYC:7`
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = String::new();
+        for byte in &self.bytes[..self.len as usize] {
+            write!(bytes, "{byte:02X} ")?;
+        }
+        write!(
+            f,
+            "{:04X}  {}  {bytes:9} {:4} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc,
+            self.location,
+            self.mnemonic,
+            self.a,
+            self.x,
+            self.y,
+            self.status,
+            self.sp,
+            self.cycles
+        )
+    }
+}
+
+/// The last `capacity` instructions executed. Room for all of them is reserved up front, so
+/// recording never allocates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionHistory {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl InstructionHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashBundle {
+    pub report: String,
+    pub trace: String,
+    pub state: Vec<u8>,
+    pub config: Option<String>,
+    pub journal: Option<String>,
+    /// Base name for the bundle, from the ROM hash and frame
+    name: String,
+}
+
+impl CrashBundle {
+    /// Collects the report, history and a save state from `console`, noting `reason` as the cause
+    pub fn capture(console: &Console, reason: &str) -> Self {
+        let cpu = console.cpu();
+        let mut report = String::new();
+        writeln!(report, "reason: {reason}").unwrap();
+        writeln!(report, "version: {}", env!("CARGO_PKG_VERSION")).unwrap();
+        writeln!(report, "rom hash: {:016x}", console.rom_hash()).unwrap();
+        writeln!(report, "frame: {}", console.frame()).unwrap();
+        writeln!(report, "halted: {}", console.is_halted()).unwrap();
+        writeln!(report, "cpu: {:?}", cpu.variant).unwrap();
+        writeln!(
+            report,
+            "registers: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            cpu.program_counter,
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.status,
+            cpu.stack_pointer,
+            cpu.cycles
+        )
+        .unwrap();
+
+        let mut trace = String::new();
+        match console.instruction_history() {
+            Some(history) => {
+                for entry in history.entries() {
+                    writeln!(trace, "{entry}").unwrap();
+                }
+            }
+            None => trace.push_str("instruction history was off\n"),
+        }
+        Self {
+            report,
+            trace,
+            state: console.save_state(),
+            config: None,
+            journal: console.journal().map(Journal::to_text),
+            name: format!("crash-{:016x}-{}", console.rom_hash(), console.frame()),
+        }
+    }
+
+    /// Includes the frontend configuration `text` as `config.ini`
+    pub fn with_config(mut self, text: &str) -> Self {
+        self.config = Some(text.to_string());
+        self
+    }
+
+    /// Each file in the bundle, by name
+    pub fn files(&self) -> Vec<(&'static str, &[u8])> {
+        let mut files = vec![
+            ("report.txt", self.report.as_bytes()),
+            ("trace.txt", self.trace.as_bytes()),
+            ("state.sav", &self.state[..]),
+        ];
+        if let Some(config) = &self.config {
+            files.push(("config.ini", config.as_bytes()));
+        }
+        if let Some(journal) = &self.journal {
+            files.push(("journal.txt", journal.as_bytes()));
+        }
+        files
+    }
+
+    /// Writes the bundle into `dir`, creating it if needed, and returns the path written
+    pub fn write_to(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        self.write_bundle(dir)
+    }
+
+    #[cfg(feature = "zip")]
+    fn write_bundle(&self, dir: &Path) -> io::Result<PathBuf> {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let path = dir.join(format!("{}.zip", self.name));
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path)?);
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in self.files() {
+            zip.start_file(name, options).map_err(io::Error::other)?;
+            zip.write_all(data)?;
+        }
+        zip.finish().map_err(io::Error::other)?;
+        Ok(path)
+    }
+
+    #[cfg(not(feature = "zip"))]
+    fn write_bundle(&self, dir: &Path) -> io::Result<PathBuf> {
+        let path = dir.join(&self.name);
+        fs::create_dir_all(&path)?;
+        for (name, data) in self.files() {
+            fs::write(path.join(name), data)?;
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bundle_has_history_of_the_crash() {
+        let mut console = Console::new();
+        console.enable_instruction_history(2);
+        // LDA #$42; INX; JAM
+        console.load(&[0xa9, 0x42, 0xe8, 0x02]);
+        console.run_frame();
+        assert!(console.is_halted());
+
+        let bundle = CrashBundle::capture(&console, "CPU halted").with_config("[frontend]\n");
+        let lines: Vec<_> = bundle.trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].starts_with("8002  00:0002  E8        INX  A:42 X:00"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].starts_with("8003  00:0003  02        ???  A:42 X:01"),
+            "{}",
+            lines[1]
+        );
+        assert!(bundle.report.contains("reason: CPU halted\n"));
+        assert!(bundle.report.contains("PC:8004 A:42 X:01"));
+        let names: Vec<_> = bundle.files().iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            ["report.txt", "trace.txt", "state.sav", "config.ini"]
+        );
+
+        let dir = std::env::temp_dir().join(format!("nes_emulator_crash_{}", std::process::id()));
+        let path = bundle.write_to(&dir).unwrap();
+        assert!(path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut restored = Console::new();
+        restored.load(&[0xa9, 0x42, 0xe8, 0x02]);
+        restored.load_state(&bundle.state).unwrap();
+        assert_eq!(restored.state_hash(), console.state_hash());
+    }
+}
@@ -0,0 +1,218 @@
+//! A crosshair drawn over the picture, for playing light gun games with a mouse.
+//!
+//! Zapper games aim wherever the gun points, and with the host cursor hidden or captured by the
+//! window there's nothing showing where that is. A [`Crosshair`] is drawn onto a finished frame
+//! like the [`Osd`](crate::osd::Osd), on exactly the NES pixel the frontend puts it on, so what
+//! the player sees is what the game will be asked to check. Frontends that get the mouse in
+//! window coordinates convert with [`Crosshair::set_window_position`].
+//!
+//! ```
+//! use nes_emulator::crosshair::{Crosshair, CrosshairStyle};
+//! use nes_emulator::frame::Frame;
+//!
+//! let mut crosshair = Crosshair::new(CrosshairStyle::Cross);
+//! // The mouse in the middle of a 3x scaled 768x720 window
+//! crosshair.set_window_position(384.0, 360.0, 768.0, 720.0);
+//! assert_eq!(crosshair.position(), Some((128, 120)));
+//! let mut frame = Frame::new();
+//! crosshair.draw(&mut frame);
+//! assert_eq!(frame.get_pixel(128, 120), crosshair.color);
+//! ```
+
+use crate::frame::{Frame, Palette, PixelFormat, HEIGHT, WIDTH};
+
+/// Offsets the outline is drawn at around every lit pixel
+#[rustfmt::skip]
+const NEIGHBORS: [(isize, isize); 8] = [
+    (-1, -1), (0, -1), (1, -1),
+    (-1, 0),           (1, 0),
+    (-1, 1),  (0, 1),  (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrosshairStyle {
+    /// Horizontal and vertical lines through the aimed pixel
+    Cross,
+    /// The aimed pixel alone, for games with small targets
+    Dot,
+    /// A ring around the aimed pixel, leaving the target around it visible
+    Circle,
+}
+
+impl CrosshairStyle {
+    pub fn all() -> [CrosshairStyle; 3] {
+        [
+            CrosshairStyle::Cross,
+            CrosshairStyle::Dot,
+            CrosshairStyle::Circle,
+        ]
+    }
+
+    /// Name for config files
+    pub fn name(self) -> &'static str {
+        match self {
+            CrosshairStyle::Cross => "cross",
+            CrosshairStyle::Dot => "dot",
+            CrosshairStyle::Circle => "circle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CrosshairStyle> {
+        CrosshairStyle::all()
+            .into_iter()
+            .find(|style| style.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crosshair {
+    pub style: CrosshairStyle,
+    /// Palette index it's drawn in
+    pub color: u8,
+    /// Palette index of a one pixel border keeping it visible on any background, or `None`
+    pub outline: Option<u8>,
+    /// Pixels the cross's arms or the circle's radius reach out from the aimed pixel
+    pub size: usize,
+    /// The aimed pixel, or `None` while hidden
+    position: Option<(usize, usize)>,
+}
+
+impl Crosshair {
+    /// A hidden white crosshair with a black outline
+    pub fn new(style: CrosshairStyle) -> Self {
+        Self {
+            style,
+            color: 0x30,
+            outline: Some(0x0F),
+            size: 4,
+            position: None,
+        }
+    }
+
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.position
+    }
+
+    /// Aims at the pixel (`x`, `y`) of the frame, or hides the crosshair with coordinates
+    /// outside it
+    pub fn set_position(&mut self, x: usize, y: usize) {
+        self.position = (x < WIDTH && y < HEIGHT).then_some((x, y));
+    }
+
+    /// Aims at the pixel under (`x`, `y`) in a window `width` by `height` the frame is stretched
+    /// over, e.g. the mouse position
+    pub fn set_window_position(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        if !(0.0..width).contains(&x) || !(0.0..height).contains(&y) {
+            self.hide();
+            return;
+        }
+        self.set_position(
+            (x * WIDTH as f64 / width) as usize,
+            (y * HEIGHT as f64 / height) as usize,
+        );
+    }
+
+    pub fn hide(&mut self) {
+        self.position = None;
+    }
+
+    /// Draws onto the palette indices of `frame`
+    pub fn draw(&self, frame: &mut Frame) {
+        self.render(|x, y, index| frame.set_pixel(x, y, index));
+    }
+
+    /// Draws onto `out`, a whole frame of pixels in `format`
+    pub fn draw_pixels(&self, format: PixelFormat, palette: &Palette, out: &mut [u8]) {
+        assert_eq!(out.len(), format.frame_len(), "output buffer size");
+        let size = format.bytes_per_pixel();
+        self.render(|x, y, index| {
+            palette.write_color(index, format, &mut out[(y * WIDTH + x) * size..])
+        });
+    }
+
+    fn render(&self, mut plot: impl FnMut(usize, usize, u8)) {
+        let Some((x, y)) = self.position else {
+            return;
+        };
+        let shape = self.shape();
+        let mut plot_at = |dx: isize, dy: isize, index: u8| {
+            let (px, py) = (x as isize + dx, y as isize + dy);
+            if (0..WIDTH as isize).contains(&px) && (0..HEIGHT as isize).contains(&py) {
+                plot(px as usize, py as usize, index);
+            }
+        };
+        if let Some(outline) = self.outline {
+            for &(dx, dy) in &shape {
+                for (ox, oy) in NEIGHBORS {
+                    plot_at(dx + ox, dy + oy, outline);
+                }
+            }
+        }
+        for &(dx, dy) in &shape {
+            plot_at(dx, dy, self.color);
+        }
+    }
+
+    /// Offsets of the lit pixels from the aimed one
+    fn shape(&self) -> Vec<(isize, isize)> {
+        let r = self.size as isize;
+        match self.style {
+            CrosshairStyle::Dot => vec![(0, 0)],
+            CrosshairStyle::Cross => (-r..=r).flat_map(|d| [(d, 0), (0, d)]).collect(),
+            CrosshairStyle::Circle => {
+                let mut shape = vec![(0, 0)];
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let distance = ((dx * dx + dy * dy) as f64).sqrt().round() as isize;
+                        if distance == r {
+                            shape.push((dx, dy));
+                        }
+                    }
+                }
+                shape
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_draws_on_the_aimed_pixel() {
+        let mut crosshair = Crosshair::new(CrosshairStyle::Cross);
+        let mut frame = Frame::new();
+        crosshair.draw(&mut frame);
+        assert!(frame.pixels.iter().all(|&index| index == 0));
+
+        crosshair.set_position(0, 100);
+        crosshair.draw(&mut frame);
+        assert_eq!(frame.get_pixel(0, 100), 0x30);
+        assert_eq!(frame.get_pixel(4, 100), 0x30);
+        assert_eq!(frame.get_pixel(5, 100), 0x0F);
+        assert_eq!(frame.get_pixel(1, 101), 0x0F);
+        assert_eq!(frame.get_pixel(3, 102), 0);
+
+        crosshair.style = CrosshairStyle::Circle;
+        crosshair.outline = None;
+        crosshair.set_position(50, 50);
+        let mut frame = Frame::new();
+        crosshair.draw(&mut frame);
+        assert_eq!(frame.get_pixel(50, 50), 0x30);
+        assert_eq!(frame.get_pixel(54, 50), 0x30);
+        assert_eq!(frame.get_pixel(52, 50), 0);
+        let mut pixels = Frame::new().to_pixels(PixelFormat::Rgba8888, &Palette::default());
+        crosshair.draw_pixels(PixelFormat::Rgba8888, &Palette::default(), &mut pixels);
+        assert_eq!(
+            pixels,
+            frame.to_pixels(PixelFormat::Rgba8888, &Palette::default())
+        );
+
+        crosshair.set_window_position(-1.0, 10.0, 512.0, 480.0);
+        assert_eq!(crosshair.position(), None);
+        crosshair.set_window_position(511.9, 479.9, 512.0, 480.0);
+        assert_eq!(crosshair.position(), Some((255, 239)));
+        assert_eq!(CrosshairStyle::from_name("dot"), Some(CrosshairStyle::Dot));
+    }
+}
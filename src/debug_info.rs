@@ -0,0 +1,388 @@
+//! Source-level debug information from ld65's `--dbgfile` output.
+//!
+//! Homebrew built with ca65 and ld65 can ship a `.dbg` file next to the ROM. [`DebugInfo`] reads
+//! it and maps PRG ROM back to the assembly that produced it: which file and line a byte came
+//! from, and the nearest label before it. Lookups take a [`BankAddress`] rather than a CPU
+//! address, so they stay right when the same code is mirrored or banked into another window.
+//!
+//! Only the records needed for that are read (`file`, `line`, `seg`, `span` and `sym`); scopes,
+//! types and C symbols are skipped. PRG ROM is assumed to start right after a 16 byte iNES header
+//! in the linker's output file.
+//!
+//! ```
+//! use nes_emulator::bus::Bus;
+//! use nes_emulator::debug_info::DebugInfo;
+//!
+//! let dbg = "\
+//! file\tid=0,name=\"main.s\",size=100,mtime=0x00000000,mod=0
+//! line\tid=0,file=0,line=7,span=0
+//! seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+//! span\tid=0,seg=0,start=0,size=3
+//! sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=0,val=0x8000,seg=0,type=lab
+//! ";
+//! let info = DebugInfo::parse(dbg).unwrap();
+//! let at = Bus::new().bank_address(0x8001);
+//! assert_eq!(info.describe(at).unwrap(), "main.s:7 reset+1");
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::bus::{BankAddress, PRG_BANK_SIZE};
+
+/// Bytes of iNES header before PRG ROM in the linker's output file
+const INES_HEADER_SIZE: usize = 16;
+/// `line` record type for lines inside a macro expansion
+const LINE_TYPE_MACRO: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugInfoError {
+    /// A line that isn't `keyword<tab>key=value,...`, with its 1-based line number
+    BadLine(usize),
+    /// A record missing a field it needs, or with a field that isn't a number
+    BadField { line: usize, field: &'static str },
+    /// A record referring to a file, segment or span id that isn't defined
+    UnknownId { line: usize, id: u32 },
+}
+
+impl fmt::Display for DebugInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugInfoError::BadLine(line) => write!(f, "line {line}: malformed record"),
+            DebugInfoError::BadField { line, field } => {
+                write!(f, "line {line}: missing or bad `{field}`")
+            }
+            DebugInfoError::UnknownId { line, id } => write!(f, "line {line}: unknown id {id}"),
+        }
+    }
+}
+
+impl Error for DebugInfoError {}
+
+/// Where a byte of PRG ROM was assembled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine<'a> {
+    pub file: &'a str,
+    /// 1-based
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// A source line's code, as a range of PRG ROM
+#[derive(Debug, Clone, Copy)]
+struct LineSpan {
+    rom_start: usize,
+    size: usize,
+    file: usize,
+    line: u32,
+    is_macro: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Label {
+    name: String,
+    rom_offset: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    lines: Vec<LineSpan>,
+    /// Sorted by PRG ROM offset
+    labels: Vec<Label>,
+    /// Every symbol's value, including RAM variables and constants
+    symbols: HashMap<String, u16>,
+}
+
+/// A `seg` record: where the segment runs in CPU space and, for ROM, in the output file
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: u16,
+    rom_start: Option<usize>,
+}
+
+impl DebugInfo {
+    /// Parses the text of a `.dbg` file
+    pub fn parse(text: &str) -> Result<Self, DebugInfoError> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut line_records = Vec::new();
+        let mut sym_records = Vec::new();
+
+        for (i, record) in text.lines().enumerate() {
+            let number = i + 1;
+            if record.trim().is_empty() {
+                continue;
+            }
+            let (keyword, fields) = record
+                .split_once('\t')
+                .ok_or(DebugInfoError::BadLine(number))?;
+            let fields = Fields::parse(fields, number)?;
+            match keyword {
+                "file" => {
+                    files.insert(fields.number("id")?, fields.string("name")?.to_string());
+                }
+                "seg" => {
+                    // The header segment and RAM segments aren't in PRG ROM
+                    let rom_start = match fields.get("ooffs") {
+                        Some(_) => (fields.number("ooffs")? as usize).checked_sub(INES_HEADER_SIZE),
+                        None => None,
+                    };
+                    let start = fields.number("start")? as u16;
+                    segments.insert(fields.number("id")?, Segment { start, rom_start });
+                }
+                "span" => {
+                    let span = (
+                        fields.number("seg")?,
+                        fields.number("start")? as usize,
+                        fields.number("size")? as usize,
+                    );
+                    spans.insert(fields.number("id")?, span);
+                }
+                "line" => line_records.push((number, fields)),
+                "sym" => sym_records.push((number, fields)),
+                _ => {}
+            }
+        }
+
+        let mut file_ids: Vec<_> = files.keys().copied().collect();
+        file_ids.sort_unstable();
+        let file_index: HashMap<_, _> = file_ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, index))
+            .collect();
+        let mut info = DebugInfo {
+            files: file_ids.iter().map(|id| files[id].clone()).collect(),
+            ..DebugInfo::default()
+        };
+
+        for (number, fields) in &line_records {
+            let Some(span_ids) = fields.get("span") else {
+                continue; // a line that produced no code
+            };
+            let file_id = fields.number("file")?;
+            let file = *file_index.get(&file_id).ok_or(DebugInfoError::UnknownId {
+                line: *number,
+                id: file_id,
+            })?;
+            let line = fields.number("line")?;
+            let is_macro =
+                fields.get("type").is_some() && fields.number("type")? == LINE_TYPE_MACRO;
+            for span_id in span_ids.split('+') {
+                let span_id = parse_number(span_id).ok_or(DebugInfoError::BadField {
+                    line: *number,
+                    field: "span",
+                })?;
+                let &(seg, start, size) = spans.get(&span_id).ok_or(DebugInfoError::UnknownId {
+                    line: *number,
+                    id: span_id,
+                })?;
+                let segment = segments.get(&seg).ok_or(DebugInfoError::UnknownId {
+                    line: *number,
+                    id: seg,
+                })?;
+                if let Some(rom_start) = segment.rom_start {
+                    info.lines.push(LineSpan {
+                        rom_start: rom_start + start,
+                        size,
+                        file,
+                        line,
+                        is_macro,
+                    });
+                }
+            }
+        }
+
+        for (number, fields) in &sym_records {
+            let name = fields.string("name")?;
+            let value = fields.number("val")? as u16;
+            info.symbols.insert(name.to_string(), value);
+            if fields.get("type") != Some("lab") || fields.get("seg").is_none() {
+                continue;
+            }
+            let seg = fields.number("seg")?;
+            let segment = segments.get(&seg).ok_or(DebugInfoError::UnknownId {
+                line: *number,
+                id: seg,
+            })?;
+            if let Some(rom_start) = segment.rom_start {
+                info.labels.push(Label {
+                    name: name.to_string(),
+                    rom_offset: rom_start + value.wrapping_sub(segment.start) as usize,
+                });
+            }
+        }
+        info.labels.sort_by_key(|label| label.rom_offset);
+        Ok(info)
+    }
+
+    /// The source line that assembled the byte at `at`. Macro expansions give way to the line
+    /// that used the macro, and nested spans to the innermost one.
+    pub fn source_line(&self, at: BankAddress) -> Option<SourceLine<'_>> {
+        let offset = rom_offset(at)?;
+        self.lines
+            .iter()
+            .filter(|span| (span.rom_start..span.rom_start + span.size).contains(&offset))
+            .min_by_key(|span| (span.is_macro, span.size))
+            .map(|span| SourceLine {
+                file: &self.files[span.file],
+                line: span.line,
+            })
+    }
+
+    /// The nearest label at or before `at`, and how many bytes past it `at` is
+    pub fn label(&self, at: BankAddress) -> Option<(&str, usize)> {
+        let offset = rom_offset(at)?;
+        let index = self
+            .labels
+            .partition_point(|label| label.rom_offset <= offset);
+        let label = &self.labels[index.checked_sub(1)?];
+        Some((&label.name, offset - label.rom_offset))
+    }
+
+    /// The value of the symbol `name`: a label's CPU address, a variable's RAM address, or a
+    /// constant
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    /// `file:line label+offset` for `at`, leaving out whichever part isn't known
+    pub fn describe(&self, at: BankAddress) -> Option<String> {
+        let line = self.source_line(at).map(|line| line.to_string());
+        let label = self.label(at).map(|(name, past)| match past {
+            0 => name.to_string(),
+            past => format!("{name}+{past}"),
+        });
+        match (line, label) {
+            (Some(line), Some(label)) => Some(format!("{line} {label}")),
+            (line, label) => line.or(label),
+        }
+    }
+}
+
+fn rom_offset(at: BankAddress) -> Option<usize> {
+    Some(at.bank? as usize * PRG_BANK_SIZE + at.offset as usize)
+}
+
+/// The `key=value` pairs of one record
+struct Fields<'a> {
+    pairs: Vec<(&'a str, &'a str)>,
+    line: usize,
+}
+
+impl<'a> Fields<'a> {
+    /// Splits on commas outside double quotes
+    fn parse(text: &'a str, line: usize) -> Result<Self, DebugInfoError> {
+        let mut pairs = Vec::new();
+        let mut quoted = false;
+        let mut start = 0;
+        for (i, c) in text.char_indices().chain([(text.len(), ',')]) {
+            match c {
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    let pair = text[start..i]
+                        .split_once('=')
+                        .ok_or(DebugInfoError::BadLine(line))?;
+                    pairs.push(pair);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { pairs, line })
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+
+    fn number(&self, key: &'static str) -> Result<u32, DebugInfoError> {
+        self.get(key)
+            .and_then(parse_number)
+            .ok_or(DebugInfoError::BadField {
+                line: self.line,
+                field: key,
+            })
+    }
+
+    fn string(&self, key: &'static str) -> Result<&'a str, DebugInfoError> {
+        self.get(key)
+            .and_then(|value| value.strip_prefix('"')?.strip_suffix('"'))
+            .ok_or(DebugInfoError::BadField {
+                line: self.line,
+                field: key,
+            })
+    }
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    const DBG: &str = "\
+version\tmajor=2,minor=0
+info\tcsym=0,file=2,lib=0,line=4,mod=1,scope=1,seg=3,span=4,sym=3,type=0
+file\tid=0,name=\"main.s\",size=200,mtime=0x5A3C0B40,mod=0
+file\tid=1,name=\"macros, etc.inc\",size=50,mtime=0x5A3C0B40,mod=0
+line\tid=0,file=0,line=3
+line\tid=1,file=0,line=10,span=0
+line\tid=2,file=0,line=12,span=1+2
+line\tid=3,file=1,line=4,type=2,span=2
+mod\tid=0,name=\"main.o\",file=0
+seg\tid=0,name=\"HEADER\",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=0
+seg\tid=1,name=\"CODE\",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16400
+seg\tid=2,name=\"BSS\",start=0x000300,size=0x0010,addrsize=absolute,type=rw
+span\tid=0,seg=1,start=0,size=2
+span\tid=1,seg=1,start=2,size=3
+span\tid=2,seg=1,start=5,size=1
+span\tid=3,seg=2,start=0,size=1
+sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=1,type=lab
+sym\tid=1,name=\"loop\",addrsize=absolute,scope=0,def=2,val=0xC002,seg=1,type=lab
+sym\tid=2,name=\"lives\",addrsize=absolute,scope=0,def=0,val=0x0300,seg=2,type=lab
+sym\tid=3,name=\"LIVES_START\",addrsize=zeropage,scope=0,def=0,val=0x3,type=equ
+";
+
+    #[test]
+    fn test_maps_rom_back_to_source() {
+        let info = DebugInfo::parse(DBG).unwrap();
+        // CODE is 0x4000 into PRG ROM: bank 1, seen at 0xC000 on a 32KB NROM
+        let bus = Bus::new();
+        let at = |addr| bus.bank_address(addr);
+        assert_eq!(info.describe(at(0xC000)).unwrap(), "main.s:10 reset");
+        assert_eq!(info.describe(at(0xC004)).unwrap(), "main.s:12 loop+2");
+        // Inside a macro, the line using it wins
+        assert_eq!(info.source_line(at(0xC005)).unwrap().line, 12);
+        assert_eq!(info.describe(at(0xC0F0)).unwrap(), "loop+238");
+        assert_eq!(info.describe(at(0x8000)), None);
+        assert_eq!(info.describe(at(0x0300)), None);
+        assert_eq!(info.symbol("lives"), Some(0x0300));
+        assert_eq!(info.symbol("LIVES_START"), Some(3));
+
+        assert_eq!(
+            DebugInfo::parse("line\tid=0,file=9,line=1,span=0").unwrap_err(),
+            DebugInfoError::UnknownId { line: 1, id: 9 }
+        );
+        assert_eq!(
+            DebugInfo::parse("file\tid=0,size=1")
+                .unwrap_err()
+                .to_string(),
+            "line 1: missing or bad `name`"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Developer warnings for homebrew authors.
+//!
+//! A [`Diagnostics`] attached to the [`CPU`](crate::cpu::CPU) watches for behavior that the
+//! hardware allows but that is almost always a bug in the program:
+//!
+//! | Warning | |
+//! | :--- | :--- |
+//! | [`Warning::StackOverflow`] | a push wrapped the stack pointer from `0x00` to `0xFF` |
+//! | [`Warning::StackUnderflow`] | a pull wrapped the stack pointer from `0xFF` to `0x00` |
+//! | [`Warning::ExecutingStack`] | an instruction was fetched from the stack page |
+//! | [`Warning::RomWrite`] | a write to cartridge ROM, which has no mapper registers on NROM |
+//! | [`Warning::UninitializedRead`] | a read of internal RAM nothing has written since power on |
+//!
+//! Only the accesses an instruction means to make are checked, not the dummy reads and writes
+//! the 6502 makes along the way. Each warning is reported once per instruction address, so a loop
+//! doesn't bury the first report under copies of it.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::diagnostics::Warning;
+//!
+//! let mut console = Console::new();
+//! console.set_diagnostics(true);
+//! console.set_halt_on_brk(true);
+//! // LDA $10, BRK
+//! console.load(&[0xa5, 0x10, 0x00]);
+//! console.run_frame();
+//!
+//! let diagnostics = console.take_diagnostics();
+//! assert_eq!(diagnostics[0].pc, 0x8000);
+//! assert_eq!(diagnostics[0].warning, Warning::UninitializedRead { addr: 0x0010 });
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+
+/// Internal RAM on the NES; the mirrors above it aren't separate memory on real hardware, but are
+/// here, so they aren't tracked
+const NES_RAM: Range<u16> = 0x0000..0x0800;
+const NES_PRG_ROM: RangeInclusive<u16> = 0x8000..=0xFFFF;
+const STACK_PAGE: RangeInclusive<u16> = 0x0100..=0x01FF;
+
+/// Something suspicious a program did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Warning {
+    StackOverflow,
+    StackUnderflow,
+    ExecutingStack,
+    RomWrite { addr: u16, value: u8 },
+    UninitializedRead { addr: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::StackOverflow => write!(f, "stack overflow: pushed with the stack full"),
+            Warning::StackUnderflow => write!(f, "stack underflow: pulled with the stack empty"),
+            Warning::ExecutingStack => write!(f, "executing code in the stack page"),
+            Warning::RomWrite { addr, value } => {
+                write!(f, "wrote ${value:02X} to ROM at ${addr:04X}")
+            }
+            Warning::UninitializedRead { addr } => {
+                write!(f, "read uninitialized RAM at ${addr:04X}")
+            }
+        }
+    }
+}
+
+/// A [`Warning`] and the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    /// Address of the instruction's opcode
+    pub pc: u16,
+    /// CPU cycle count when the instruction started
+    pub cycle: u64,
+    pub warning: Warning,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${:04X} (cycle {}): {}",
+            self.pc, self.cycle, self.warning
+        )
+    }
+}
+
+/// Checks the CPU's accesses and collects [`Diagnostic`]s until they're taken
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    ram: Range<u16>,
+    rom: Option<RangeInclusive<u16>>,
+    /// One flag per byte of `ram`
+    initialized: Vec<bool>,
+    pc: u16,
+    cycle: u64,
+    reported: HashSet<(u16, Warning)>,
+    pending: Vec<Diagnostic>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostics {
+    /// Diagnostics for the NES memory map: 2KB of internal RAM at `0x0000` and PRG ROM at `0x8000`
+    pub fn new() -> Self {
+        Self::with_layout(NES_RAM, Some(NES_PRG_ROM))
+    }
+
+    /// Diagnostics for another memory map. `rom` is `None` when writes to the ROM are expected,
+    /// e.g. because they go to mapper registers.
+    pub fn with_layout(ram: Range<u16>, rom: Option<RangeInclusive<u16>>) -> Self {
+        Self {
+            initialized: vec![false; ram.len()],
+            ram,
+            rom,
+            pc: 0,
+            cycle: 0,
+            reported: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Treats all of RAM as written, e.g. after loading a save state whose RAM history is unknown
+    pub fn assume_initialized(&mut self) {
+        self.initialized.fill(true);
+    }
+
+    /// Returns the diagnostics reported since the last call, oldest first
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Called by the CPU before it fetches the opcode at `pc`
+    pub(crate) fn begin_instruction(&mut self, pc: u16, cycle: u64) {
+        self.pc = pc;
+        self.cycle = cycle;
+        if STACK_PAGE.contains(&pc) {
+            self.report(Warning::ExecutingStack);
+        }
+    }
+
+    pub(crate) fn check_read(&mut self, addr: u16) {
+        if self.ram.contains(&addr) && !self.initialized[(addr - self.ram.start) as usize] {
+            self.report(Warning::UninitializedRead { addr });
+        }
+    }
+
+    pub(crate) fn check_write(&mut self, addr: u16, value: u8) {
+        if self.ram.contains(&addr) {
+            self.initialized[(addr - self.ram.start) as usize] = true;
+        } else if self.rom.as_ref().is_some_and(|rom| rom.contains(&addr)) {
+            self.report(Warning::RomWrite { addr, value });
+        }
+    }
+
+    /// Called by the CPU before a push or pull with the stack pointer it's about to move
+    pub(crate) fn check_stack(&mut self, stack_pointer: u8, push: bool) {
+        match (stack_pointer, push) {
+            (0x00, true) => self.report(Warning::StackOverflow),
+            (0xFF, false) => self.report(Warning::StackUnderflow),
+            _ => {}
+        }
+    }
+
+    fn report(&mut self, warning: Warning) {
+        if self.reported.insert((self.pc, warning)) {
+            self.pending.push(Diagnostic {
+                pc: self.pc,
+                cycle: self.cycle,
+                warning,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_reports_each_warning_once() {
+        let mut cpu = CPU::new();
+        cpu.diagnostics = Some(Diagnostics::new());
+        cpu.set_halt_on_brk(true);
+        #[rustfmt::skip]
+        cpu.load(&[
+            0xa2, 0x00,       // LDX #$00
+            0x9a,             // TXS
+            0x48,             // PHA
+            0x68,             // PLA
+            0x8d, 0x00, 0x80, // STA $8000
+            0x85, 0x20,       // STA $20
+            0xa2, 0x01,       // LDX #$01
+            0xb5, 0x1f,       // LDA $1F,X, whose dummy read of $1F isn't flagged
+            0xa5, 0x21,       // LDA $21
+            0x8d, 0x50, 0x01, // STA $0150
+            0x4c, 0x50, 0x01, // JMP $0150, a BRK
+        ]);
+        cpu.reset();
+        cpu.run();
+
+        let diagnostics = cpu.diagnostics.as_mut().unwrap();
+        let reported: Vec<_> = diagnostics
+            .take()
+            .into_iter()
+            .map(|d| (d.pc, d.warning))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (0x8003, Warning::StackOverflow),
+                (0x8004, Warning::StackUnderflow),
+                (
+                    0x8005,
+                    Warning::RomWrite {
+                        addr: 0x8000,
+                        value: 0
+                    }
+                ),
+                (0x800E, Warning::UninitializedRead { addr: 0x0021 }),
+                (0x0150, Warning::ExecutingStack),
+            ]
+        );
+
+        // Running the same code again reports nothing new
+        cpu.program_counter = 0x8000;
+        cpu.run();
+        assert!(cpu.diagnostics.as_mut().unwrap().take().is_empty());
+    }
+}
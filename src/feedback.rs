@@ -0,0 +1,218 @@
+//! Force feedback: cartridge and expansion writes a frontend can turn into gamepad rumble.
+//!
+//! There's no standard rumble hardware for the NES, but some homebrew and accessories signal
+//! one by writing to a mapper or expansion register, each game its own way. A list of
+//! [`FeedbackRule`]s says which writes mean what, and once [`Bus::set_feedback`] installs them
+//! every matching write becomes a [`FeedbackEvent`] for [`Bus::take_feedback`]. Rules are
+//! written comma separated, each an address at `$4020` or above, optionally `&` a mask of the
+//! bits that count, then the motor:
+//!
+//! ```text
+//! $5000 strong, $8000&$0F weak
+//! ```
+//!
+//! The masked value sets the motor's strength, from off at 0 up to full with every masked bit
+//! set, and it stays at that strength until the next matching write.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::feedback::{FeedbackRule, Motor};
+//!
+//! let mut console = Console::new();
+//! console.load(&[0xa9, 0xf7, 0x8d, 0x00, 0x50, 0x4c, 0x05, 0x80]); // LDA #$F7; STA $5000; spin
+//! let bus = console.cpu_mut().bus_mut();
+//! bus.set_feedback(FeedbackRule::parse_list("$5000&$F0 strong")?);
+//! console.run_frame();
+//! let events = console.cpu_mut().bus_mut().take_feedback();
+//! assert_eq!(events.len(), 1);
+//! assert_eq!(events[0].motor, Motor::Strong);
+//! assert_eq!(events[0].strength, 1.0);
+//! # Ok::<(), nes_emulator::feedback::FeedbackError>(())
+//! ```
+//!
+//! [`Bus::set_feedback`]: crate::bus::Bus::set_feedback
+//! [`Bus::take_feedback`]: crate::bus::Bus::take_feedback
+
+use std::error::Error;
+use std::fmt;
+
+/// The lowest address a rule can watch: everything below is the console's own
+pub const FIRST_ADDRESS: u16 = 0x4020;
+
+/// The two motors of a typical host gamepad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Motor {
+    /// The low frequency motor, for heavy rumble
+    Strong,
+    /// The high frequency motor, for light buzzing
+    Weak,
+}
+
+impl Motor {
+    pub fn all() -> [Motor; 2] {
+        [Motor::Strong, Motor::Weak]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Motor::Strong => "strong",
+            Motor::Weak => "weak",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Motor> {
+        Motor::all().into_iter().find(|motor| motor.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeedbackRule {
+    pub addr: u16,
+    /// The bits of the written value that set the strength; never 0
+    pub mask: u8,
+    pub motor: Motor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedbackError {
+    /// A rule that isn't `address[&mask] motor`
+    BadRule(String),
+    /// An address below [`FIRST_ADDRESS`]
+    ConsoleAddress(u16),
+}
+
+impl fmt::Display for FeedbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedbackError::BadRule(rule) => {
+                write!(f, "`{rule}` is not `address[&mask] strong` or `weak`")
+            }
+            FeedbackError::ConsoleAddress(addr) => write!(
+                f,
+                "${addr:04X} is a console register, rules watch ${FIRST_ADDRESS:04X} and up"
+            ),
+        }
+    }
+}
+
+impl Error for FeedbackError {}
+
+impl FeedbackRule {
+    /// Reads one `address[&mask] motor` rule
+    pub fn parse(text: &str) -> Result<FeedbackRule, FeedbackError> {
+        let bad = || FeedbackError::BadRule(text.trim().to_string());
+        let mut words = text.split_whitespace();
+        let (Some(target), Some(motor), None) = (words.next(), words.next(), words.next()) else {
+            return Err(bad());
+        };
+        let motor = Motor::from_name(motor).ok_or_else(bad)?;
+        let (addr, mask) = match target.split_once('&') {
+            Some((addr, mask)) => (addr, parse_number(mask).ok_or_else(bad)?),
+            None => (target, 0xFF),
+        };
+        let addr: u16 = parse_number(addr).ok_or_else(bad)?;
+        let mask: u8 = mask.try_into().map_err(|_| bad())?;
+        if mask == 0 {
+            return Err(bad());
+        }
+        if addr < FIRST_ADDRESS {
+            return Err(FeedbackError::ConsoleAddress(addr));
+        }
+        Ok(FeedbackRule { addr, mask, motor })
+    }
+
+    /// Reads comma separated rules; an empty list turns feedback off
+    pub fn parse_list(text: &str) -> Result<Vec<FeedbackRule>, FeedbackError> {
+        text.split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(FeedbackRule::parse)
+            .collect()
+    }
+}
+
+fn parse_number<T: TryFrom<u32>>(text: &str) -> Option<T> {
+    let value = match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    value.try_into().ok()
+}
+
+/// A motor set to a new strength by a write some rule matched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedbackEvent {
+    pub motor: Motor,
+    /// From 0.0 for off to 1.0 for full
+    pub strength: f32,
+}
+
+/// The rules installed on a bus and the events they've produced since they were last taken
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Feedback {
+    rules: Vec<FeedbackRule>,
+    pub(crate) events: Vec<FeedbackEvent>,
+}
+
+impl Feedback {
+    pub(crate) fn new(rules: Vec<FeedbackRule>) -> Self {
+        Self {
+            rules,
+            events: Vec::new(),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, addr: u16, value: u8) {
+        for rule in &self.rules {
+            if rule.addr == addr {
+                let bits = value & rule.mask;
+                self.events.push(FeedbackEvent {
+                    motor: rule.motor,
+                    strength: (bits >> rule.mask.trailing_zeros()) as f32
+                        / (rule.mask >> rule.mask.trailing_zeros()) as f32,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            FeedbackRule::parse_list(" $5000 strong, 0x8000&0x0f weak,").unwrap(),
+            [
+                FeedbackRule {
+                    addr: 0x5000,
+                    mask: 0xFF,
+                    motor: Motor::Strong
+                },
+                FeedbackRule {
+                    addr: 0x8000,
+                    mask: 0x0F,
+                    motor: Motor::Weak
+                },
+            ]
+        );
+        assert!(FeedbackRule::parse_list("").unwrap().is_empty());
+        assert_eq!(
+            FeedbackRule::parse("$4016 strong").unwrap_err().to_string(),
+            "$4016 is a console register, rules watch $4020 and up"
+        );
+        for bad in ["$5000", "$5000 loud", "$5000&$100 weak", "$5000&0 weak"] {
+            assert_eq!(
+                FeedbackRule::parse(bad),
+                Err(FeedbackError::BadRule(bad.to_string()))
+            );
+        }
+
+        let mut feedback = Feedback::new(FeedbackRule::parse_list("$8000&$0C weak").unwrap());
+        feedback.observe(0x8000, 0xF4);
+        feedback.observe(0x8001, 0xFF);
+        feedback.observe(0x8000, 0x0C);
+        let strengths: Vec<f32> = feedback.events.iter().map(|e| e.strength).collect();
+        assert_eq!(strengths, [1.0 / 3.0, 1.0]);
+    }
+}
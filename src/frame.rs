@@ -0,0 +1,283 @@
+//! Video frames.
+//!
+//! The NES draws every pixel as an index into its 64 color system palette, so a [`Frame`] stores
+//! exactly that: one palette index per pixel. Frontends pick the [`PixelFormat`] they want to
+//! receive, and [`Frame::write_pixels`] converts into a caller provided buffer:
+//!
+//! | Format | Bytes per pixel | Layout |
+//! | :--- | :---: | :--- |
+//! | [`PixelFormat::Rgba8888`] | 4 | `R, G, B, A` |
+//! | [`PixelFormat::Rgb565`] | 2 | little endian `rrrrrggg_gggbbbbb` |
+//! | [`PixelFormat::Indexed`] | 1 | palette index, looked up in [`Palette::to_table`] |
+
+mod convert;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    #[default]
+    Rgba8888,
+    Rgb565,
+    /// Raw palette indices, for targets that apply the palette themselves (e.g. a GPU lookup)
+    Indexed,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Indexed => 1,
+        }
+    }
+
+    /// Size of a whole frame in this format
+    pub fn frame_len(self) -> usize {
+        WIDTH * HEIGHT * self.bytes_per_pixel()
+    }
+}
+
+/// RGB colors for the 64 palette indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [(u8, u8, u8); 64]);
+
+impl Palette {
+    pub fn rgb(&self, index: u8) -> (u8, u8, u8) {
+        self.0[(index & 0x3F) as usize]
+    }
+
+    /// Writes the color of palette `index` in `format` to the start of `out`. Indexed output is the
+    /// index itself.
+    pub fn write_color(&self, index: u8, format: PixelFormat, out: &mut [u8]) {
+        let (r, g, b) = self.rgb(index);
+        match format {
+            PixelFormat::Rgba8888 => out[..4].copy_from_slice(&[r, g, b, 0xFF]),
+            PixelFormat::Rgb565 => out[..2].copy_from_slice(&rgb565(r, g, b).to_le_bytes()),
+            PixelFormat::Indexed => out[0] = index & 0x3F,
+        }
+    }
+
+    /// The whole palette as a 64 entry lookup table in `format`, used alongside indexed frames.
+    /// Indexed "colors" are the identity table.
+    pub fn to_table(&self, format: PixelFormat) -> Vec<u8> {
+        let size = format.bytes_per_pixel();
+        let mut table = vec![0u8; 64 * size];
+        for (index, color) in table.chunks_exact_mut(size).enumerate() {
+            self.write_color(index as u8, format, color);
+        }
+        table
+    }
+
+    /// Reads a `.pal` file of RGB triples. Files with 512 colors, which add the color emphasis
+    /// variants, use the first 64.
+    pub fn from_pal(data: &[u8]) -> Option<Palette> {
+        if data.len() != 64 * 3 && data.len() != 512 * 3 {
+            return None;
+        }
+        let mut colors = [(0, 0, 0); 64];
+        for (color, rgb) in colors.iter_mut().zip(data.chunks_exact(3)) {
+            *color = (rgb[0], rgb[1], rgb[2]);
+        }
+        Some(Palette(colors))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        SYSTEM_PALETTE
+    }
+}
+
+fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+#[rustfmt::skip]
+pub const SYSTEM_PALETTE: Palette = Palette([
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+]);
+
+/// A 256x240 picture of palette indices, row major from the top left
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Frame {
+    pub pixels: Box<[u8]>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0u8; WIDTH * HEIGHT].into_boxed_slice(),
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, index: u8) {
+        self.pixels[y * WIDTH + x] = index;
+    }
+
+    /// Converts the frame to `format` into `out`, which must hold [`PixelFormat::frame_len`] bytes
+    pub fn write_pixels(&self, format: PixelFormat, palette: &Palette, out: &mut [u8]) {
+        assert_eq!(out.len(), format.frame_len(), "output buffer size");
+        let size = format.bytes_per_pixel();
+        match format {
+            PixelFormat::Indexed => out.copy_from_slice(&self.pixels),
+            _ => {
+                let mut table = [0u8; 64 * 4];
+                for index in 0..64 {
+                    palette.write_color(index as u8, format, &mut table[index * size..]);
+                }
+                convert::convert(&self.pixels, &table, size, out);
+            }
+        }
+    }
+
+    /// Converts the frame to `format` in a new buffer
+    pub fn to_pixels(&self, format: PixelFormat, palette: &Palette) -> Vec<u8> {
+        let mut out = vec![0u8; format.frame_len()];
+        self.write_pixels(format, palette, &mut out);
+        out
+    }
+}
+
+/// A borrowed, completed frame.
+///
+/// The console renders into a back buffer and swaps it with the front buffer when a frame
+/// completes, so a `FrameRef` always points at a finished picture and handing it to the frontend
+/// costs no copy. The borrow keeps the console from running while the frame is being read.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRef<'a> {
+    frame: &'a Frame,
+    pixels: &'a [u8],
+    format: PixelFormat,
+    info: FrameInfo,
+}
+
+/// When a picture was made and how, so recorders, netplay and analysis tools needn't keep their
+/// own counts alongside the console's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FrameInfo {
+    /// Number of the frame that produced the picture, counting from 0
+    pub number: u64,
+    /// Whether `number` is odd. Real NTSC PPUs leave a dot out of odd frames while rendering;
+    /// this one draws them the same length, see [`video_timing`](crate::video_timing).
+    pub odd: bool,
+    /// The CPU cycle count the frame started at, emulated time since power on
+    pub start_cycle: u64,
+    /// CPU cycles the frame lasted, including DMA stalls
+    pub cycles: u64,
+    /// Whether the background or sprites were switched on when the picture was drawn. Games
+    /// blank the screen this way while loading, so a picture without it is usually one color.
+    pub rendering: bool,
+}
+
+impl<'a> FrameRef<'a> {
+    pub fn new(frame: &'a Frame, pixels: &'a [u8], format: PixelFormat, info: FrameInfo) -> Self {
+        Self {
+            frame,
+            pixels,
+            format,
+            info,
+        }
+    }
+
+    /// The frame as palette indices
+    pub fn indices(&self) -> &'a Frame {
+        self.frame
+    }
+
+    /// The frame converted to [`FrameRef::format`]
+    pub fn pixels(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Number of the frame that produced this picture, counting from 0
+    pub fn number(&self) -> u64 {
+        self.info.number
+    }
+
+    pub fn info(&self) -> FrameInfo {
+        self.info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rgba_conversion() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, 0x30);
+        frame.set_pixel(0, 1, 0x01);
+
+        let rgba = frame.to_pixels(PixelFormat::Rgba8888, &SYSTEM_PALETTE);
+        assert_eq!(
+            &rgba[0..8],
+            &[0x80, 0x80, 0x80, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(&rgba[WIDTH * 4..WIDTH * 4 + 4], &[0x00, 0x3D, 0xA6, 0xFF]);
+    }
+
+    #[test]
+    fn test_rgb565_conversion() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x30); // white
+        frame.set_pixel(1, 0, 0x0D); // black
+
+        let rgb565 = frame.to_pixels(PixelFormat::Rgb565, &SYSTEM_PALETTE);
+        assert_eq!(rgb565.len(), WIDTH * HEIGHT * 2);
+        assert_eq!(&rgb565[0..4], &[0xFF, 0xFF, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_indexed_output_with_palette_table() {
+        let mut frame = Frame::new();
+        frame.set_pixel(5, 5, 0x21);
+
+        let indexed = frame.to_pixels(PixelFormat::Indexed, &SYSTEM_PALETTE);
+        let table = SYSTEM_PALETTE.to_table(PixelFormat::Rgba8888);
+        let index = indexed[5 * WIDTH + 5] as usize;
+
+        assert_eq!(index, 0x21);
+        assert_eq!(&table[index * 4..index * 4 + 4], &[0x0F, 0xD7, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_palette_from_pal_file() {
+        let data: Vec<u8> = (0..64 * 3).map(|i| i as u8).collect();
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(1), (3, 4, 5));
+
+        let mut emphasis = data.clone();
+        emphasis.resize(512 * 3, 0xFF);
+        assert_eq!(Palette::from_pal(&emphasis), Some(palette));
+        assert_eq!(Palette::from_pal(&data[1..]), None);
+    }
+}
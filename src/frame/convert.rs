@@ -0,0 +1,121 @@
+//! Palette lookup behind [`Frame::write_pixels`](super::Frame::write_pixels).
+//!
+//! Converting 61,440 pixels is the hottest loop once a frame is rendered, so on x86_64 hosts with
+//! SSSE3 the lookup runs 16 pixels at a time: each output byte of a color is split into four 16
+//! byte tables (one per 16 palette entries), `pshufb` looks up all 16 pixels in each table, and
+//! the per-table results are merged by comparing the top two index bits. Other hosts, and the
+//! tail of the buffer, use the scalar loop.
+
+/// Writes `table[index]` (a color of `size` bytes) for every index into `out`
+pub(super) fn convert(indices: &[u8], table: &[u8; 64 * 4], size: usize, out: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    if (size == 2 || size == 4) && is_x86_feature_detected!("ssse3") {
+        let simd_len = indices.len() / 16 * 16;
+        let (out_simd, out_rest) = out.split_at_mut(simd_len * size);
+        // SAFETY: SSSE3 support was just checked, and both slices hold whole 16 pixel chunks
+        unsafe { ssse3::convert(&indices[..simd_len], table, size, out_simd) };
+        scalar(&indices[simd_len..], table, size, out_rest);
+        return;
+    }
+
+    scalar(indices, table, size, out)
+}
+
+fn scalar(indices: &[u8], table: &[u8; 64 * 4], size: usize, out: &mut [u8]) {
+    for (index, pixel) in indices.iter().zip(out.chunks_exact_mut(size)) {
+        let color = (*index & 0x3F) as usize * size;
+        pixel.copy_from_slice(&table[color..color + size]);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod ssse3 {
+    use std::arch::x86_64::*;
+
+    /// `size` must be 2 or 4, `indices` a multiple of 16 long, and `out` `size` times as long
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn convert(
+        indices: &[u8],
+        table: &[u8; 64 * 4],
+        size: usize,
+        out: &mut [u8],
+    ) {
+        // planes[byte][bank][i] = byte `byte` of color `bank * 16 + i`
+        let mut planes = [[_mm_setzero_si128(); 4]; 4];
+        for (byte, banks) in planes.iter_mut().enumerate().take(size) {
+            for (bank, plane) in banks.iter_mut().enumerate() {
+                let mut bytes = [0u8; 16];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = table[(bank * 16 + i) * size + byte];
+                }
+                *plane = _mm_loadu_si128(bytes.as_ptr() as *const __m128i);
+            }
+        }
+
+        let low_bits = _mm_set1_epi8(0x0F);
+        let bank_bits = _mm_set1_epi8(0x03);
+        let banks = [0, 1, 2, 3].map(|b| _mm_set1_epi8(b));
+
+        for (chunk, out) in indices
+            .chunks_exact(16)
+            .zip(out.chunks_exact_mut(16 * size))
+        {
+            let index = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let low = _mm_and_si128(index, low_bits);
+            let bank = _mm_and_si128(_mm_srli_epi16(index, 4), bank_bits);
+
+            let mut bytes = [_mm_setzero_si128(); 4];
+            for (byte, value) in bytes.iter_mut().enumerate().take(size) {
+                for b in 0..4 {
+                    let hit = _mm_cmpeq_epi8(bank, banks[b]);
+                    let lookup = _mm_shuffle_epi8(planes[byte][b], low);
+                    *value = _mm_or_si128(*value, _mm_and_si128(hit, lookup));
+                }
+            }
+
+            let dst = out.as_mut_ptr() as *mut __m128i;
+            if size == 4 {
+                let rg_lo = _mm_unpacklo_epi8(bytes[0], bytes[1]);
+                let rg_hi = _mm_unpackhi_epi8(bytes[0], bytes[1]);
+                let ba_lo = _mm_unpacklo_epi8(bytes[2], bytes[3]);
+                let ba_hi = _mm_unpackhi_epi8(bytes[2], bytes[3]);
+                _mm_storeu_si128(dst, _mm_unpacklo_epi16(rg_lo, ba_lo));
+                _mm_storeu_si128(dst.add(1), _mm_unpackhi_epi16(rg_lo, ba_lo));
+                _mm_storeu_si128(dst.add(2), _mm_unpacklo_epi16(rg_hi, ba_hi));
+                _mm_storeu_si128(dst.add(3), _mm_unpackhi_epi16(rg_hi, ba_hi));
+            } else {
+                _mm_storeu_si128(dst, _mm_unpacklo_epi8(bytes[0], bytes[1]));
+                _mm_storeu_si128(dst.add(1), _mm_unpackhi_epi8(bytes[0], bytes[1]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(size: usize) -> [u8; 64 * 4] {
+        let mut table = [0u8; 64 * 4];
+        for (i, byte) in table.iter_mut().enumerate().take(64 * size) {
+            *byte = (i as u8).wrapping_mul(37) ^ 0x5A;
+        }
+        table
+    }
+
+    #[test]
+    fn test_matches_scalar_for_every_index() {
+        // Every byte value (the top two bits must be ignored), plus a tail that isn't a whole chunk
+        let indices: Vec<u8> = (0..=255u8).chain(0..7).collect();
+
+        for size in [2, 4] {
+            let table = table(size);
+            let mut expected = vec![0u8; indices.len() * size];
+            let mut actual = vec![0u8; indices.len() * size];
+            scalar(&indices, &table, size, &mut expected);
+            convert(&indices, &table, size, &mut actual);
+
+            assert_eq!(actual, expected, "{size} byte colors");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! What changed between consecutive frames, for sending only the difference.
+//!
+//! A full frame is 61,440 palette indices, 60 times a second: a lot for network streaming, a
+//! terminal over SSH or a browser on the other end of a websocket, when most frames change only a
+//! sprite or two. [`TileDiff`] compares two frames in the 8x8 tiles the picture is built from,
+//! and [`TileDiff::rects`] joins the tiles that differ into a few rectangles to send with
+//! [`DirtyRect::extract`]. A [`FrameDiffer`] keeps the last frame sent and reports everything as
+//! changed the first time, so a viewer that connects late starts from a full picture.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::frame::PixelFormat;
+//! use nes_emulator::frame_diff::FrameDiffer;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! let mut differ = FrameDiffer::new();
+//! for _ in 0..2 {
+//!     console.run_frame();
+//!     let frame = console.frame_ref();
+//!     for rect in differ.diff(frame.indices()).rects() {
+//!         let _bytes = rect.extract(frame.pixels(), frame.format());
+//!     }
+//! }
+//! // Nothing moves on a blank screen
+//! console.run_frame();
+//! assert!(differ.diff(console.frame_ref().indices()).is_empty());
+//! ```
+
+use crate::frame::{Frame, PixelFormat, HEIGHT, WIDTH};
+
+/// Tiles are 8 pixels square
+pub const TILE_SIZE: usize = 8;
+pub const COLUMNS: usize = WIDTH / TILE_SIZE;
+pub const ROWS: usize = HEIGHT / TILE_SIZE;
+
+/// A rectangle of the picture, in pixels from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    /// The rectangle's rows, top first, cut out of a whole frame converted to `format`
+    pub fn extract(&self, pixels: &[u8], format: PixelFormat) -> Vec<u8> {
+        assert_eq!(pixels.len(), format.frame_len(), "frame buffer size");
+        let size = format.bytes_per_pixel();
+        let mut out = Vec::with_capacity(self.width * self.height * size);
+        for y in self.y..self.y + self.height {
+            let start = (y * WIDTH + self.x) * size;
+            out.extend_from_slice(&pixels[start..start + self.width * size]);
+        }
+        out
+    }
+}
+
+/// Which tiles of the picture differ between two frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TileDiff {
+    /// A bit per column in each row, column 0 in the lowest bit
+    rows: [u32; ROWS],
+}
+
+impl TileDiff {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            rows: [u32::MAX; ROWS],
+        }
+    }
+
+    pub fn between(previous: &Frame, next: &Frame) -> Self {
+        let mut diff = Self::none();
+        for y in 0..HEIGHT {
+            let line = y * WIDTH..(y + 1) * WIDTH;
+            let (a, b) = (&previous.pixels[line.clone()], &next.pixels[line]);
+            if a == b {
+                continue;
+            }
+            for column in 0..COLUMNS {
+                let tile = column * TILE_SIZE..(column + 1) * TILE_SIZE;
+                if a[tile.clone()] != b[tile] {
+                    diff.rows[y / TILE_SIZE] |= 1 << column;
+                }
+            }
+        }
+        diff
+    }
+
+    pub fn is_dirty(&self, column: usize, row: usize) -> bool {
+        self.rows[row] & (1 << column) != 0
+    }
+
+    /// Tiles that differ
+    pub fn count(&self) -> usize {
+        self.rows.iter().map(|row| row.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    /// Rectangles covering exactly the tiles that differ, top to bottom then left to right.
+    /// Each row's runs of tiles are joined with the same run on the rows below.
+    pub fn rects(&self) -> Vec<DirtyRect> {
+        let mut rects = Vec::new();
+        let mut open: Vec<DirtyRect> = Vec::new();
+        for (row, &bits) in self.rows.iter().enumerate() {
+            let mut continued = Vec::new();
+            let mut rest = bits;
+            while rest != 0 {
+                let start = rest.trailing_zeros() as usize;
+                let len = (rest >> start).trailing_ones() as usize;
+                rest &= !(((1u64 << len) - 1) << start) as u32;
+                let (x, width) = (start * TILE_SIZE, len * TILE_SIZE);
+                match open.iter().position(|r| r.x == x && r.width == width) {
+                    Some(i) => {
+                        let mut rect = open.swap_remove(i);
+                        rect.height += TILE_SIZE;
+                        continued.push(rect);
+                    }
+                    None => continued.push(DirtyRect {
+                        x,
+                        y: row * TILE_SIZE,
+                        width,
+                        height: TILE_SIZE,
+                    }),
+                }
+            }
+            rects.append(&mut open);
+            open = continued;
+        }
+        rects.append(&mut open);
+        rects.sort_by_key(|rect| (rect.y, rect.x));
+        rects
+    }
+}
+
+/// Diffs each frame against the one before it
+#[derive(Debug, Clone, Default)]
+pub struct FrameDiffer {
+    previous: Option<Frame>,
+}
+
+impl FrameDiffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What changed since the last frame passed in; everything for the first
+    pub fn diff(&mut self, frame: &Frame) -> TileDiff {
+        let diff = match &self.previous {
+            Some(previous) => TileDiff::between(previous, frame),
+            None => TileDiff::all(),
+        };
+        match &mut self.previous {
+            Some(previous) => previous.pixels.copy_from_slice(&frame.pixels),
+            None => self.previous = Some(frame.clone()),
+        }
+        diff
+    }
+
+    /// Forgets the last frame, so the next counts as all changed, e.g. for a viewer that
+    /// reconnected
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changed_tiles_become_rects() {
+        let mut differ = FrameDiffer::new();
+        let mut frame = Frame::new();
+        assert_eq!(
+            differ.diff(&frame).rects(),
+            [DirtyRect {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT,
+            }]
+        );
+        assert!(differ.diff(&frame).is_empty());
+
+        // A 16x16 sprite's corners in four tiles, a pixel in the last column, and a tile below the
+        // sprite narrower than it, which starts a rect of its own
+        for (x, y) in [(8, 8), (23, 8), (8, 23), (23, 23), (255, 0), (8, 24)] {
+            frame.set_pixel(x, y, 0x16);
+        }
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.count(), 6);
+        assert!(diff.is_dirty(31, 0));
+        let rect = |x, y, width, height| DirtyRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            diff.rects(),
+            [rect(248, 0, 8, 8), rect(8, 8, 16, 16), rect(8, 24, 8, 8),]
+        );
+
+        frame.set_pixel(8, 24, 0);
+        let rects = differ.diff(&frame).rects();
+        assert_eq!(rects, [rect(8, 24, 8, 8)]);
+        let pixels = frame.to_pixels(PixelFormat::Indexed, &Default::default());
+        let bytes = rects[0].extract(&pixels, PixelFormat::Indexed);
+        assert_eq!(bytes.len(), 64);
+        assert!(bytes.iter().all(|&index| index == 0));
+    }
+}
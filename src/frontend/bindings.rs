@@ -0,0 +1,454 @@
+//! Key bindings for hotkeys and controllers.
+//!
+//! Bindings come from the `[hotkeys]`, `[controller1]` and `[controller2]` sections of the config
+//! file, each `name = chord[, chord...]`. A chord is a key with optional modifiers, written like
+//! `F5`, `Shift+F1` or `Ctrl+R`; an empty value unbinds the action. Anything not mentioned keeps its
+//! default binding.
+//!
+//! | Hotkey | Default | |
+//! | :--- | :--- | :--- |
+//! | `save_state` | `F5` | save to the selected slot |
+//! | `load_state` | `F7` | load from the selected slot |
+//! | `next_slot` / `prev_slot` | `F6` / `Shift+F6` | |
+//! | `slot_0` .. `slot_9` | `0` .. `9` | select a slot directly |
+//! | `fast_forward` | `Space` | held |
+//! | `rewind` | `Backspace` | held |
+//! | `pause` | `P` | |
+//! | `screenshot` | `F12` | |
+//! | `toggle_fullscreen` | `F11` | |
+//! | `toggle_hud` | `F9` | performance overlay |
+//! | `reset` | `Ctrl+R` | |
+//! | `record_vgm` | `F8` | start or stop recording the music |
+//! | `cycle_color_vision` | `F10` | next color-blind palette |
+//! | `toggle_flash_filter` | `Shift+F10` | flash reduction |
+//! | `toggle_debugger` | `Ctrl+D` | registers, disassembly, memory and PPU beside the game |
+//! | `record_macro_1` .. `record_macro_4` | `Alt+Shift+1` .. `Alt+Shift+4` | start or stop recording a [macro](super::macros) |
+//! | `play_macro_1` .. `play_macro_4` | `Alt+1` .. `Alt+4` | |
+//!
+//! Controller sections bind `a`, `b`, `select`, `start`, `up`, `down`, `left` and `right`. Player 1
+//! defaults to `X`, `Z`, `Tab`, `Enter` and the arrow keys; player 2 is unbound.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use nes_emulator::joypad::{JoypadButton, BUTTON_NAMES};
+
+use super::config::{Config, SettingError};
+use super::macros::MACROS;
+
+/// Hotkeys that act when pressed and released rather than once
+const HOLD_ACTIONS: &[Action] = &[Action::FastForward, Action::Rewind];
+
+/// Named keys a chord can use besides single printable characters
+const NAMED_KEYS: &[&str] = &[
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "SPACE",
+    "TAB",
+    "ENTER",
+    "ESCAPE",
+    "BACKSPACE",
+    "DELETE",
+    "INSERT",
+    "HOME",
+    "END",
+    "PAGEUP",
+    "PAGEDOWN",
+    "UP",
+    "DOWN",
+    "LEFT",
+    "RIGHT",
+];
+
+const BUTTONS: &[(&str, JoypadButton)] = &BUTTON_NAMES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    SaveState,
+    LoadState,
+    NextSlot,
+    PrevSlot,
+    SelectSlot(u8),
+    FastForward,
+    Rewind,
+    Pause,
+    Screenshot,
+    ToggleFullscreen,
+    ToggleHud,
+    Reset,
+    RecordVgm,
+    CycleColorVision,
+    ToggleFlashFilter,
+    ToggleDebugger,
+    /// Macro number from 1
+    RecordMacro(u8),
+    PlayMacro(u8),
+}
+
+impl Action {
+    pub fn all() -> impl Iterator<Item = Action> {
+        [
+            Action::SaveState,
+            Action::LoadState,
+            Action::NextSlot,
+            Action::PrevSlot,
+        ]
+        .into_iter()
+        .chain((0..10).map(Action::SelectSlot))
+        .chain([
+            Action::FastForward,
+            Action::Rewind,
+            Action::Pause,
+            Action::Screenshot,
+            Action::ToggleFullscreen,
+            Action::ToggleHud,
+            Action::Reset,
+            Action::RecordVgm,
+            Action::CycleColorVision,
+            Action::ToggleFlashFilter,
+            Action::ToggleDebugger,
+        ])
+        .chain((1..=MACROS).map(Action::RecordMacro))
+        .chain((1..=MACROS).map(Action::PlayMacro))
+    }
+
+    /// Name used in the `[hotkeys]` config section
+    pub fn name(self) -> String {
+        match self {
+            Action::SaveState => "save_state".to_string(),
+            Action::LoadState => "load_state".to_string(),
+            Action::NextSlot => "next_slot".to_string(),
+            Action::PrevSlot => "prev_slot".to_string(),
+            Action::SelectSlot(slot) => format!("slot_{slot}"),
+            Action::FastForward => "fast_forward".to_string(),
+            Action::Rewind => "rewind".to_string(),
+            Action::Pause => "pause".to_string(),
+            Action::Screenshot => "screenshot".to_string(),
+            Action::ToggleFullscreen => "toggle_fullscreen".to_string(),
+            Action::ToggleHud => "toggle_hud".to_string(),
+            Action::Reset => "reset".to_string(),
+            Action::RecordVgm => "record_vgm".to_string(),
+            Action::CycleColorVision => "cycle_color_vision".to_string(),
+            Action::ToggleFlashFilter => "toggle_flash_filter".to_string(),
+            Action::ToggleDebugger => "toggle_debugger".to_string(),
+            Action::RecordMacro(number) => format!("record_macro_{number}"),
+            Action::PlayMacro(number) => format!("play_macro_{number}"),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::all().find(|action| action.name() == name)
+    }
+
+    /// Whether the action lasts while its key is held, like fast-forward
+    pub fn is_hold(self) -> bool {
+        HOLD_ACTIONS.contains(&self)
+    }
+
+    fn default_chord(self) -> &'static str {
+        match self {
+            Action::SaveState => "F5",
+            Action::LoadState => "F7",
+            Action::NextSlot => "F6",
+            Action::PrevSlot => "Shift+F6",
+            Action::SelectSlot(slot) => {
+                ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"][slot as usize]
+            }
+            Action::FastForward => "Space",
+            Action::Rewind => "Backspace",
+            Action::Pause => "P",
+            Action::Screenshot => "F12",
+            Action::ToggleFullscreen => "F11",
+            Action::ToggleHud => "F9",
+            Action::Reset => "Ctrl+R",
+            Action::RecordVgm => "F8",
+            Action::CycleColorVision => "F10",
+            Action::ToggleFlashFilter => "Shift+F10",
+            Action::ToggleDebugger => "Ctrl+D",
+            Action::RecordMacro(number) => {
+                ["Alt+Shift+1", "Alt+Shift+2", "Alt+Shift+3", "Alt+Shift+4"][number as usize - 1]
+            }
+            Action::PlayMacro(number) => ["Alt+1", "Alt+2", "Alt+3", "Alt+4"][number as usize - 1],
+        }
+    }
+}
+
+/// A key plus the modifiers held with it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// Upper case key name, e.g. `F5`, `SPACE` or `X`
+    pub key: String,
+}
+
+impl Chord {
+    pub fn parse(text: &str) -> Result<Chord, String> {
+        let mut chord = Chord {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key: String::new(),
+        };
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        // `Shift++` binds the plus key
+        if text.trim().ends_with("++") {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let key = parts.pop().unwrap_or_default();
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                _ => return Err(format!("unknown modifier `{modifier}` in `{text}`")),
+            }
+        }
+
+        let key = key.to_ascii_uppercase();
+        let printable = key.chars().count() == 1 && key.chars().all(|c| c.is_ascii_graphic());
+        if !printable && !NAMED_KEYS.contains(&key.as_str()) {
+            return Err(format!("unknown key `{key}` in `{text}`"));
+        }
+        chord.key = key;
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// What a chord is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    Hotkey(Action),
+    Button { player: usize, button: JoypadButton },
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Hotkey(action) => write!(f, "hotkey {}", action.name()),
+            Target::Button { player, button } => {
+                let name = BUTTONS
+                    .iter()
+                    .find(|(_, b)| b == button)
+                    .map_or("?", |(name, _)| name);
+                write!(f, "controller{} {name}", player + 1)
+            }
+        }
+    }
+}
+
+/// One chord bound to several targets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub chord: Chord,
+    pub targets: Vec<Target>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is bound to ", self.chord)?;
+        for (i, target) in self.targets.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" and ")?;
+            }
+            write!(f, "{target}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    chords: BTreeMap<Target, Vec<Chord>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut chords = BTreeMap::new();
+        for action in Action::all() {
+            let chord = Chord::parse(action.default_chord()).expect("default chords parse");
+            chords.insert(Target::Hotkey(action), vec![chord]);
+        }
+        for ((_, button), key) in BUTTONS
+            .iter()
+            .zip(["X", "Z", "Tab", "Enter", "Up", "Down", "Left", "Right"])
+        {
+            let target = Target::Button {
+                player: 0,
+                button: *button,
+            };
+            chords.insert(
+                target,
+                vec![Chord::parse(key).expect("default chords parse")],
+            );
+        }
+        Self { chords }
+    }
+}
+
+impl Bindings {
+    /// The default bindings with the config's hotkey and controller sections applied
+    pub fn from_config(config: &Config) -> Result<Bindings, SettingError> {
+        let mut bindings = Bindings::default();
+        let sections = [
+            ("hotkeys", None),
+            ("controller1", Some(0)),
+            ("controller2", Some(1)),
+        ];
+        for (section, player) in sections {
+            for (key, value) in config.section(section) {
+                let error = |message: String| SettingError {
+                    section: section.to_string(),
+                    key: key.to_string(),
+                    message,
+                };
+                let target = match player {
+                    None => Action::from_name(key)
+                        .map(Target::Hotkey)
+                        .ok_or_else(|| error("unknown hotkey action".to_string()))?,
+                    Some(player) => BUTTONS
+                        .iter()
+                        .find(|(name, _)| *name == key)
+                        .map(|(_, button)| Target::Button {
+                            player,
+                            button: *button,
+                        })
+                        .ok_or_else(|| error("unknown controller button".to_string()))?,
+                };
+                let chords = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|chord| !chord.is_empty())
+                    .map(Chord::parse)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                bindings.chords.insert(target, chords);
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn chords(&self, target: Target) -> &[Chord] {
+        self.chords.get(&target).map_or(&[], Vec::as_slice)
+    }
+
+    /// What `chord` does. When a chord is bound more than once the first target in
+    /// [`Target`] order wins; [`Bindings::conflicts`] reports those.
+    pub fn target(&self, chord: &Chord) -> Option<Target> {
+        self.chords
+            .iter()
+            .find(|(_, chords)| chords.contains(chord))
+            .map(|(target, _)| *target)
+    }
+
+    /// Chords bound to more than one target, in chord order
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut by_chord: BTreeMap<&Chord, Vec<Target>> = BTreeMap::new();
+        for (target, chords) in &self.chords {
+            for chord in chords {
+                let targets = by_chord.entry(chord).or_default();
+                if !targets.contains(target) {
+                    targets.push(*target);
+                }
+            }
+        }
+        by_chord
+            .into_iter()
+            .filter(|(_, targets)| targets.len() > 1)
+            .map(|(chord, targets)| Conflict {
+                chord: chord.clone(),
+                targets,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chords() {
+        let chord = Chord::parse("ctrl + Shift+f1").unwrap();
+        assert!(chord.ctrl && chord.shift && !chord.alt);
+        assert_eq!(chord.key, "F1");
+        assert_eq!(chord.to_string(), "Ctrl+Shift+F1");
+
+        assert_eq!(Chord::parse("Shift++").unwrap().key, "+");
+        assert!(Chord::parse("Hyper+X").is_err());
+        assert!(Chord::parse("F13").is_err());
+    }
+
+    #[test]
+    fn test_defaults_have_no_conflicts() {
+        let bindings = Bindings::default();
+        assert!(bindings.conflicts().is_empty());
+        assert_eq!(
+            bindings.target(&Chord::parse("F5").unwrap()),
+            Some(Target::Hotkey(Action::SaveState))
+        );
+        assert_eq!(
+            bindings.target(&Chord::parse("x").unwrap()),
+            Some(Target::Button {
+                player: 0,
+                button: JoypadButton::BUTTON_A
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_overrides_and_conflicts() {
+        let config = Config::parse(
+            "[hotkeys]\n\
+             pause = Escape, F1\n\
+             screenshot =\n\
+             [controller2]\n\
+             start = F5\n",
+        )
+        .unwrap();
+        let bindings = Bindings::from_config(&config).unwrap();
+
+        assert_eq!(bindings.chords(Target::Hotkey(Action::Pause)).len(), 2);
+        assert!(bindings
+            .chords(Target::Hotkey(Action::Screenshot))
+            .is_empty());
+
+        let conflicts = bindings.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "F5 is bound to hotkey save_state and controller2 start"
+        );
+
+        let config = Config::parse("[hotkeys]\nteleport = T\n").unwrap();
+        let err = Bindings::from_config(&config).unwrap_err();
+        assert_eq!(err.to_string(), "[hotkeys] teleport: unknown hotkey action");
+    }
+}
@@ -0,0 +1,282 @@
+//! The ROM launcher shown when no ROM is passed on the command line.
+//!
+//! It lists the `.nes` and `.unf` files in the configured `rom_dir` along with their mapper and whether the
+//! console can run them, and remembers recently played games in `recent.txt` next to the config
+//! file. There is no ROM database yet, so titles come from the file names.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use nes_emulator::rom::{Rom, NO_MAPPER};
+
+/// Number of games kept in the recently played list
+pub const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+    Supported,
+    UnsupportedMapper,
+    /// The file couldn't be parsed, with the reason
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub mapper: Option<u16>,
+    pub compatibility: Compatibility,
+}
+
+impl RomEntry {
+    pub fn from_path(path: &Path) -> Self {
+        let (mapper, compatibility) = match Rom::from_path(path) {
+            Ok(rom) if rom.is_supported() => (Some(rom.mapper), Compatibility::Supported),
+            Ok(rom) => (
+                (rom.mapper != NO_MAPPER).then_some(rom.mapper),
+                Compatibility::UnsupportedMapper,
+            ),
+            Err(err) => (None, Compatibility::Invalid(err.to_string())),
+        };
+        Self {
+            path: path.to_path_buf(),
+            title: title(path),
+            mapper,
+            compatibility,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mapper = match self.mapper {
+            Some(mapper) => format!("mapper {mapper}"),
+            None => "no mapper".to_string(),
+        };
+        let compatibility = match &self.compatibility {
+            Compatibility::Supported => "ok".to_string(),
+            Compatibility::UnsupportedMapper => "unsupported mapper".to_string(),
+            Compatibility::Invalid(reason) => format!("invalid: {reason}"),
+        };
+        format!("{:<40} {:<10} {}", self.title, mapper, compatibility)
+    }
+}
+
+fn title(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// File extensions listed by [`scan`]; archives are listed whether or not their support is
+/// compiled in, and show up as invalid if it isn't
+const ROM_EXTENSIONS: &[&str] = &["nes", "unf", "unif", "zip", "7z"];
+
+/// Lists the ROM files directly inside `dir`, sorted by title
+pub fn scan(dir: &Path) -> io::Result<Vec<RomEntry>> {
+    let mut entries = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let is_rom = path.extension().is_some_and(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        });
+        if is_rom && path.is_file() {
+            entries.push(RomEntry::from_path(&path));
+        }
+    }
+    entries.sort_by_key(|entry| entry.title.to_lowercase());
+    Ok(entries)
+}
+
+/// Recently played games, most recent first, stored one path per line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recent {
+    path: Option<PathBuf>,
+    games: Vec<PathBuf>,
+}
+
+impl Recent {
+    /// Reads the list at `path`; a missing file is an empty list
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let games = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(PathBuf::from)
+                .take(MAX_RECENT)
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path),
+            games,
+        })
+    }
+
+    pub fn games(&self) -> &[PathBuf] {
+        &self.games
+    }
+
+    /// Moves `game` to the front of the list
+    pub fn add(&mut self, game: &Path) {
+        self.games.retain(|g| g != game);
+        self.games.insert(0, game.to_path_buf());
+        self.games.truncate(MAX_RECENT);
+    }
+
+    /// Writes the list back to the file it was loaded from, creating the directory if needed
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        for game in &self.games {
+            text.push_str(&game.to_string_lossy());
+            text.push('\n');
+        }
+        fs::write(path, text)
+    }
+}
+
+/// Shows the recent games followed by `roms` as a numbered menu and reads the choice from
+/// `input`. Returns `None` when the user quits or input ends.
+pub fn choose(
+    recent: &[PathBuf],
+    roms: &[RomEntry],
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<Option<PathBuf>> {
+    let choices: Vec<&Path> = recent
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(roms.iter().map(|rom| rom.path.as_path()))
+        .collect();
+    if choices.is_empty() {
+        writeln!(
+            output,
+            "No ROMs found. Pass a ROM path or set rom_dir in the [frontend] config section."
+        )?;
+        return Ok(None);
+    }
+
+    if !recent.is_empty() {
+        writeln!(output, "Recently played:")?;
+        for (i, game) in recent.iter().enumerate() {
+            writeln!(output, "{:>3}) {}", i + 1, title(game))?;
+        }
+    }
+    if !roms.is_empty() {
+        writeln!(output, "ROMs:")?;
+        for (i, rom) in roms.iter().enumerate() {
+            writeln!(output, "{:>3}) {}", recent.len() + i + 1, rom.describe())?;
+        }
+    }
+
+    let mut line = String::new();
+    loop {
+        write!(output, "Select a game (1-{}, q to quit): ", choices.len())?;
+        output.flush()?;
+
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let answer = line.trim();
+        if answer.eq_ignore_ascii_case("q") {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=choices.len()).contains(&n) => {
+                return Ok(Some(choices[n - 1].to_path_buf()))
+            }
+            _ => writeln!(output, "`{answer}` is not a listed game")?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes_emulator_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_scan_reports_compatibility() {
+        let dir = temp_dir("scan");
+        let mut nrom = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0];
+        nrom.resize(16 + 0x4000, 0);
+        let mut mmc1 = nrom.clone();
+        mmc1[6] = 0x10;
+        fs::write(dir.join("b.nes"), &nrom).unwrap();
+        fs::write(dir.join("A.NES"), &mmc1).unwrap();
+        fs::write(dir.join("c.nes"), b"junk").unwrap();
+        fs::write(dir.join("notes.txt"), b"not a rom").unwrap();
+
+        let entries = scan(&dir).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.title.as_str(), e.mapper, &e.compatibility))
+            .collect();
+        assert_eq!(summary.len(), 3);
+        assert_eq!(
+            summary[0],
+            ("A", Some(1), &Compatibility::UnsupportedMapper)
+        );
+        assert_eq!(summary[1], ("b", Some(0), &Compatibility::Supported));
+        assert!(matches!(summary[2], ("c", None, Compatibility::Invalid(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recent_list() {
+        let dir = temp_dir("recent");
+        let file = dir.join("recent.txt");
+
+        let mut recent = Recent::load(file.clone()).unwrap();
+        for i in 0..12 {
+            recent.add(Path::new(&format!("game{i}.nes")));
+        }
+        recent.add(Path::new("game5.nes"));
+        recent.save().unwrap();
+
+        let recent = Recent::load(file).unwrap();
+        assert_eq!(recent.games().len(), MAX_RECENT);
+        assert_eq!(recent.games()[0], Path::new("game5.nes"));
+        assert_eq!(recent.games()[1], Path::new("game11.nes"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_choose() {
+        let recent = vec![PathBuf::from("/roms/last.nes")];
+        let roms = vec![RomEntry {
+            path: PathBuf::from("/roms/other.nes"),
+            title: "other".to_string(),
+            mapper: Some(0),
+            compatibility: Compatibility::Supported,
+        }];
+
+        let mut output = Vec::new();
+        let choice = choose(&recent, &roms, &b"7\n2\n"[..], &mut output).unwrap();
+        assert_eq!(choice, Some(PathBuf::from("/roms/other.nes")));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("not a listed game"));
+
+        let choice = choose(&recent, &roms, &b"q\n"[..], io::sink()).unwrap();
+        assert_eq!(choice, None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Frontend configuration.
+//!
+//! The config file is INI style: `[section]` headers followed by `key = value` lines, with `#`
+//! comments. It lives at `$XDG_CONFIG_HOME/nes_emulator/config.ini` (falling back to
+//! `~/.config/nes_emulator/config.ini`) unless `--config` names another file.
+//!
+//! ```ini
+//! [frontend]
+//! # Directory the launcher lists ROMs from
+//! rom_dir = /home/me/roms
+//! # Show the performance overlay at startup
+//! show_hud = true
+//! # Print developer warnings (stack wrapping, ROM writes, uninitialized reads) to stderr
+//! developer_warnings = true
+//! # Power on with random RAM, seeded by --seed or the printed seed
+//! random_ram = true
+//! # Boot into a Game Genie's menu from a dump of its ROM, relative to the config directory
+//! game_genie = genie.rom
+//! # Write a crash dump bundle into the `crashes` directory if the emulator panics or the CPU jams
+//! crash_dumps = true
+//! # Recolor the palette for protanopia, deuteranopia or tritanopia
+//! color_vision = deuteranopia
+//! # Soften screen flashes by limiting how fast the brightness can change
+//! reduce_flashing = true
+//! # Mix this much of the previous frame into each one, like a CRT's afterglow, from 0 to 1
+//! frame_blend = 0.5
+//! # Time-stretch fast-forward's audio to its normal pitch instead of playing only a frame of it
+//! time_stretch = true
+//! # Keep a journal of resets, state loads and saves, written to `journals` on exit
+//! journal = true
+//! # Show the debugger beside the game at startup
+//! debugger = true
+//! # LiveSplit server for games with an `autosplit` file, localhost:16834 by default
+//! livesplit = localhost:16834
+//!
+//! [hotkeys]
+//! save_state = F5
+//!
+//! [controller1]
+//! a = X
+//! ```
+//!
+//! See [`super::bindings`] for the hotkey and controller sections, [`super::macros`] for the
+//! macros section and [`super::game`] for per-game overrides.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{err}"),
+            ConfigError::Parse { line, message } => write!(f, "config line {line}: {message}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+/// A key in a config section that doesn't name a known setting or has a bad value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingError {
+    pub section: String,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.section, self.key, self.message)
+    }
+}
+
+impl Error for SettingError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Keys by section; keys before the first section header live in the `""` section
+    sections: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Config {
+    /// Directory holding the config file and the recently played list
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("nes_emulator"))
+    }
+
+    /// Reads the config at `path`; a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(|| ConfigError::Parse {
+                    line: i + 1,
+                    message: "unterminated section header".to_string(),
+                })?;
+                section = name.trim().to_string();
+                config.sections.entry(section.clone()).or_default();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| ConfigError::Parse {
+                line: i + 1,
+                message: format!("expected `key = value`, found `{line}`"),
+            })?;
+            config
+                .sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(config)
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections.get(section)?.get(key).map(String::as_str)
+    }
+
+    /// All keys of `section`, in key order
+    pub fn section(&self, section: &str) -> impl Iterator<Item = (&str, &str)> {
+        self.sections
+            .get(section)
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// `[frontend] rom_dir`
+    pub fn rom_dir(&self) -> Option<PathBuf> {
+        self.get("frontend", "rom_dir").map(PathBuf::from)
+    }
+}
+
+/// Parses `true`/`false`, also accepting `yes`/`no`, `on`/`off` and `1`/`0`
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# comment\n\
+             top = level\n\
+             [frontend]\n\
+             rom_dir = /roms/nes \n\
+             \n\
+             [other]\n\
+             key=value=with equals\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.get("", "top"), Some("level"));
+        assert_eq!(config.rom_dir(), Some(PathBuf::from("/roms/nes")));
+        assert_eq!(config.get("other", "key"), Some("value=with equals"));
+        assert_eq!(config.get("frontend", "missing"), None);
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        let err = Config::parse("[frontend]\nrom_dir\n").unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { line: 2, .. }));
+    }
+}
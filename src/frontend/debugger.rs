@@ -0,0 +1,261 @@
+//! The debugger view: the game with the CPU, memory and PPU laid out around it.
+//!
+//! [`Debugger::render`] draws one 512x368 picture meant to be shown in a second window, or as the
+//! whole window in place of the game:
+//!
+//! | | |
+//! | :---: | :---: |
+//! | the game | registers, disassembly from the PC, and a memory dump |
+//! | pattern tables 0 and 1 | palette RAM |
+//!
+//! Disassembly lines give each instruction's CPU address and the `bank:offset` it comes from in
+//! PRG ROM, followed by its source line and label when the game's `.dbg` file is loaded.
+//!
+//! Everything is read without side effects, so the view never changes what the game does. It is
+//! drawn from the state as of the end of the last frame; stepping inside a frame is left to the
+//! library's debugging APIs.
+
+use std::fmt::Write as _;
+
+use nes_emulator::console::Console;
+use nes_emulator::cpu::{AddressingMode, Mem, CPU};
+use nes_emulator::debug_info::DebugInfo;
+use nes_emulator::frame::{self, Palette};
+use nes_emulator::opcodes;
+use nes_emulator::osd;
+use nes_emulator::ripper;
+
+pub const WIDTH: usize = frame::WIDTH * 2;
+pub const HEIGHT: usize = frame::HEIGHT + PATTERN_TABLE_SIZE;
+
+const PATTERN_TABLE_SIZE: usize = 128;
+const PANEL_LEFT: usize = frame::WIDTH;
+const LINE_HEIGHT: usize = 9;
+const DISASSEMBLY_LINES: usize = 12;
+const MEMORY_ROWS: u16 = 8;
+const BYTES_PER_ROW: u16 = 8;
+const SWATCH_SIZE: usize = 16;
+/// Behind the panels, between the edges of the game and the sheets
+const BACKGROUND: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    memory_address: u16,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn memory_address(&self) -> u16 {
+        self.memory_address
+    }
+
+    /// Sets where the memory dump starts, rounded down to a whole row
+    pub fn set_memory_address(&mut self, addr: u16) {
+        self.memory_address = addr & !(BYTES_PER_ROW - 1);
+    }
+
+    /// The whole view as packed `R, G, B, A` bytes, [`WIDTH`] by [`HEIGHT`], describing the
+    /// disassembly with `debug_info` if there is any
+    pub fn render(&self, console: &Console, debug_info: Option<&DebugInfo>) -> Vec<u8> {
+        let mut rgba: Vec<u8> = BACKGROUND.repeat(WIDTH * HEIGHT);
+        let palette = console.palette();
+
+        let game = console.frame_ref();
+        for (i, &index) in game.indices().pixels.iter().enumerate() {
+            let (x, y) = (i % frame::WIDTH, i / frame::WIDTH);
+            put(&mut rgba, x, y, palette.rgb(index));
+        }
+
+        let text = self.text(console.cpu(), console.frame(), debug_info);
+        for (line, text) in text.enumerate() {
+            osd::draw_text(4, 4 + line * LINE_HEIGHT, &text, |x, y, index| {
+                put(&mut rgba, PANEL_LEFT + x, y, palette.rgb(index));
+            });
+        }
+
+        for table in 0..2 {
+            let sheet = ripper::pattern_table(console.ppu(), table, 0, palette);
+            let left = table as usize * PATTERN_TABLE_SIZE;
+            for (i, pixel) in sheet.rgba().chunks_exact(4).enumerate() {
+                let (x, y) = (i % sheet.width(), i / sheet.width());
+                put(
+                    &mut rgba,
+                    left + x,
+                    frame::HEIGHT + y,
+                    (pixel[0], pixel[1], pixel[2]),
+                );
+            }
+        }
+        draw_palette_ram(&mut rgba, console, palette);
+        rgba
+    }
+
+    /// The panel's lines: registers, the disassembly, then the memory dump
+    fn text(
+        &self,
+        cpu: &CPU,
+        frame: u64,
+        debug_info: Option<&DebugInfo>,
+    ) -> impl Iterator<Item = String> {
+        let registers = cpu.registers();
+        let mut lines = vec![
+            format!(
+                "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                registers.pc, registers.a, registers.x, registers.y, registers.status, registers.sp
+            ),
+            format!("FRAME {frame}  CYC {}", cpu.cycles()),
+            String::new(),
+        ];
+        lines.extend(disassemble(
+            cpu,
+            registers.pc,
+            DISASSEMBLY_LINES,
+            debug_info,
+        ));
+        lines.push(String::new());
+        for row in 0..MEMORY_ROWS {
+            let start = self.memory_address.wrapping_add(row * BYTES_PER_ROW);
+            let mut line = format!("{start:04X} ");
+            for i in 0..BYTES_PER_ROW {
+                write!(line, " {:02X}", cpu.mem_peek(start.wrapping_add(i))).unwrap();
+            }
+            lines.push(line);
+        }
+        lines.into_iter()
+    }
+}
+
+/// `count` instructions from `pc`, the first marked as the next to run. Bytes that aren't an
+/// instruction show as `.DB`.
+fn disassemble(
+    cpu: &CPU,
+    mut pc: u16,
+    count: usize,
+    debug_info: Option<&DebugInfo>,
+) -> Vec<String> {
+    let mut lines = Vec::with_capacity(count);
+    for i in 0..count {
+        let marker = if i == 0 { '>' } else { ' ' };
+        let at = cpu.bus().bank_address(pc);
+        let mut line = format!("{marker}{pc:04X} {at}  ");
+        let code = cpu.mem_peek(pc);
+        let next = match opcodes::lookup_for(cpu.variant(), code) {
+            Some(op) => {
+                line += &instruction(cpu, pc, op);
+                pc.wrapping_add(op.len as u16)
+            }
+            None => {
+                write!(line, ".DB ${code:02X}").unwrap();
+                pc.wrapping_add(1)
+            }
+        };
+        if let Some(source) = debug_info.and_then(|info| info.describe(at)) {
+            write!(line, "  {source}").unwrap();
+        }
+        lines.push(line);
+        pc = next;
+    }
+    lines
+}
+
+/// The instruction at `pc`, e.g. `LDA ($10),Y`
+fn instruction(cpu: &CPU, pc: u16, op: &opcodes::OpCode) -> String {
+    let lo = cpu.mem_peek(pc.wrapping_add(1));
+    let hi = cpu.mem_peek(pc.wrapping_add(2));
+    let word = u16::from_le_bytes([lo, hi]);
+    let next = pc.wrapping_add(op.len as u16);
+    let operand = match op.mode {
+        AddressingMode::Immediate => format!("#${lo:02X}"),
+        AddressingMode::ZeroPage => format!("${lo:02X}"),
+        AddressingMode::ZeroPageX => format!("${lo:02X},X"),
+        AddressingMode::ZeroPageY => format!("${lo:02X},Y"),
+        AddressingMode::Absolute => format!("${word:04X}"),
+        AddressingMode::AbsoluteX => format!("${word:04X},X"),
+        AddressingMode::AbsoluteY => format!("${word:04X},Y"),
+        AddressingMode::Indirect => format!("(${word:04X})"),
+        AddressingMode::IndirectX => format!("(${lo:02X},X)"),
+        AddressingMode::IndirectY => format!("(${lo:02X}),Y"),
+        AddressingMode::ZeroPageIndirect => format!("(${lo:02X})"),
+        AddressingMode::AbsoluteIndexedIndirect => format!("(${word:04X},X)"),
+        AddressingMode::Relative => {
+            format!("${:04X}", next.wrapping_add(lo as i8 as u16))
+        }
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Implied => String::new(),
+    };
+    match operand.is_empty() {
+        true => op.mnemonic.to_string(),
+        false => format!("{} {operand}", op.mnemonic),
+    }
+}
+
+/// The 32 palette RAM entries in two rows, background palettes on top
+fn draw_palette_ram(rgba: &mut [u8], console: &Console, palette: &Palette) {
+    for entry in 0..32 {
+        let color = palette.rgb(console.ppu().read_vram(0x3F00 + entry as u16));
+        let left = PANEL_LEFT + entry % 16 * SWATCH_SIZE;
+        let top = frame::HEIGHT + entry / 16 * SWATCH_SIZE;
+        for y in top..top + SWATCH_SIZE {
+            for x in left..left + SWATCH_SIZE {
+                put(rgba, x, y, color);
+            }
+        }
+    }
+}
+
+fn put(rgba: &mut [u8], x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+    let i = (y * WIDTH + x) * 4;
+    rgba[i..i + 4].copy_from_slice(&[r, g, b, 0xFF]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_lays_out_panels() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x42,       // LDA #$42
+            0x85, 0x10,       // STA $10
+            0xd0, 0xfe,       // loop: BNE loop
+        ]);
+        console.run_frame();
+        let mut debugger = Debugger::new();
+        debugger.set_memory_address(0x0013);
+        assert_eq!(debugger.memory_address(), 0x0010);
+
+        let lines: Vec<String> = debugger
+            .text(console.cpu(), console.frame(), None)
+            .collect();
+        assert!(lines[0].starts_with("PC:8004 A:42"));
+        assert_eq!(lines[3], ">8004 00:0004  BNE $8004");
+        assert_eq!(lines[4], " 8006 00:0006  BRK");
+        assert_eq!(lines.last().unwrap(), "0048  00 00 00 00 00 00 00 00");
+        assert_eq!(lines[lines.len() - 8], "0010  42 00 00 00 00 00 00 00");
+
+        let rgba = debugger.render(&console, None);
+        assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+        // The panel background shows between the lines of text
+        let i = (3 * WIDTH + WIDTH - 1) * 4;
+        assert_eq!(rgba[i..i + 4], BACKGROUND);
+
+        let info = DebugInfo::parse(
+            "file\tid=0,name=\"main.s\",size=100,mtime=0x00000000,mod=0\n\
+             line\tid=0,file=0,line=9,span=0\n\
+             seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16\n\
+             span\tid=0,seg=0,start=4,size=2\n\
+             sym\tid=0,name=\"loop\",addrsize=absolute,scope=0,def=0,val=0x8004,seg=0,type=lab\n",
+        )
+        .unwrap();
+        let lines: Vec<String> = debugger
+            .text(console.cpu(), console.frame(), Some(&info))
+            .collect();
+        assert_eq!(lines[3], ">8004 00:0004  BNE $8004  main.s:9 loop");
+        assert_eq!(lines[4], " 8006 00:0006  BRK  loop+2");
+    }
+}
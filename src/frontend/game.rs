@@ -0,0 +1,172 @@
+//! Per-game settings.
+//!
+//! A `[game.<hash>]` section in the config applies whenever the ROM with that hash is loaded.
+//! `<hash>` is the 16 digit hex [`Rom::hash`](nes_emulator::rom::Rom::hash) the frontend prints
+//! when a game starts. It covers the PRG and CHR data only, so re-headered dumps match too.
+//!
+//! ```ini
+//! [game.0123456789abcdef]
+//! palette = palettes/composite.pal
+//! show_hud = true
+//! triggers = triggers/smb.txt
+//! autosplit = splits/smb-any.txt
+//! rumble = $5000 strong, $8000&$0F weak
+//! region = pal
+//! cheats = SXIOPO GOSSIP
+//! ```
+//!
+//! | Key | |
+//! | :--- | :--- |
+//! | `palette` | `.pal` file with 64 (or 512) RGB colors, relative to the config directory |
+//! | `show_hud` | overrides `[frontend] show_hud` |
+//! | `triggers` | [RAM triggers](nes_emulator::triggers) to announce on screen, relative to the config directory |
+//! | `autosplit` | triggers to [split LiveSplit](nes_emulator::livesplit) with, relative to the config directory |
+//! | `rumble` | [feedback rules](nes_emulator::feedback) for the gamepad's motors |
+//! | `region` | `ntsc` or `pal`, forced even if the ROM's header says otherwise; by default the header picks |
+//! | `cheats` | [Game Genie codes](nes_emulator::game_genie) separated by spaces, applied without the menu in place of `[frontend] game_genie` |
+//!
+//! `overclock`, `controller` and `accuracy` are reserved for settings the console can't honor
+//! yet; they are collected in [`GameSettings::unsupported`] so the frontend can warn instead of
+//! silently ignoring them.
+
+use std::path::PathBuf;
+
+use nes_emulator::console::Region;
+use nes_emulator::feedback::FeedbackRule;
+use nes_emulator::game_genie::{Code, GameGenie};
+
+use super::config::{parse_bool, Config, SettingError};
+
+const RESERVED: &[&str] = &["overclock", "controller", "accuracy"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameSettings {
+    pub palette: Option<PathBuf>,
+    pub show_hud: Option<bool>,
+    pub triggers: Option<PathBuf>,
+    pub autosplit: Option<PathBuf>,
+    pub rumble: Vec<FeedbackRule>,
+    pub region: Option<Region>,
+    pub cheats: Vec<Code>,
+    /// Reserved keys that were set but have no effect yet
+    pub unsupported: Vec<String>,
+}
+
+impl GameSettings {
+    /// Config section holding the settings for the ROM with `hash`
+    pub fn section(hash: u64) -> String {
+        format!("game.{hash:016x}")
+    }
+
+    /// A Game Genie running the game with [`GameSettings::cheats`], if there are any
+    pub fn game_genie(&self) -> Option<GameGenie> {
+        (!self.cheats.is_empty()).then(|| GameGenie::with_codes(self.cheats.clone()))
+    }
+
+    pub fn from_config(config: &Config, hash: u64) -> Result<GameSettings, SettingError> {
+        let section = GameSettings::section(hash);
+        let mut settings = GameSettings::default();
+        for (key, value) in config.section(&section) {
+            let error = |message: &str| SettingError {
+                section: section.clone(),
+                key: key.to_string(),
+                message: message.to_string(),
+            };
+            match key {
+                "palette" => settings.palette = Some(PathBuf::from(value)),
+                "triggers" => settings.triggers = Some(PathBuf::from(value)),
+                "autosplit" => settings.autosplit = Some(PathBuf::from(value)),
+                "rumble" => {
+                    settings.rumble =
+                        FeedbackRule::parse_list(value).map_err(|err| error(&err.to_string()))?
+                }
+                "region" => {
+                    settings.region = Some(match value.to_ascii_lowercase().as_str() {
+                        "ntsc" => Region::Ntsc,
+                        "pal" => Region::Pal,
+                        _ => return Err(error("expected ntsc or pal")),
+                    })
+                }
+                "cheats" => {
+                    settings.cheats = value
+                        .split_whitespace()
+                        .map(Code::decode)
+                        .collect::<Result<_, _>>()
+                        .map_err(|err| error(&err.to_string()))?
+                }
+                "show_hud" => {
+                    settings.show_hud =
+                        Some(parse_bool(value).ok_or_else(|| error("expected true or false"))?)
+                }
+                _ if RESERVED.contains(&key) => settings.unsupported.push(key.to_string()),
+                _ => return Err(error("unknown per-game setting")),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_emulator::console::Console;
+    use nes_emulator::cpu::Mem;
+
+    #[test]
+    fn test_settings_for_matching_hash() {
+        let config = Config::parse(
+            "[game.00000000000000ff]\n\
+             palette = pal/fceux.pal\n\
+             show_hud = yes\n\
+             region = pal\n\
+             overclock = 2\n\
+             rumble = $6000&1 weak\n\
+             [game.0000000000000001]\n\
+             show_hud = false\n",
+        )
+        .unwrap();
+
+        let settings = GameSettings::from_config(&config, 0xFF).unwrap();
+        assert_eq!(settings.palette, Some(PathBuf::from("pal/fceux.pal")));
+        assert_eq!(settings.show_hud, Some(true));
+        assert_eq!(settings.region, Some(Region::Pal));
+        assert_eq!(settings.unsupported, ["overclock"]);
+        assert_eq!(settings.rumble.len(), 1);
+
+        assert_eq!(
+            GameSettings::from_config(&config, 0x2).unwrap(),
+            GameSettings::default()
+        );
+    }
+
+    #[test]
+    fn test_cheats_patch_the_game() {
+        let config = Config::parse("[game.0000000000000001]\ncheats = GOSSIP  zexpypgl\n").unwrap();
+        let settings = GameSettings::from_config(&config, 1).unwrap();
+        let gossip = Code::decode("GOSSIP").unwrap();
+        assert_eq!(settings.cheats, [gossip, Code::decode("ZEXPYPGL").unwrap()]);
+
+        let mut console = Console::new();
+        console.load(&[0xea; 0x8000]);
+        console.set_game_genie(settings.game_genie());
+        assert_eq!(console.cpu_mut().mem_read(gossip.addr), gossip.value);
+        assert!(GameSettings::default().game_genie().is_none());
+
+        let config = Config::parse("[game.0000000000000001]\ncheats = GOSSIB\n").unwrap();
+        let err = GameSettings::from_config(&config, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[game.0000000000000001] cheats: `B` is not a Game Genie letter"
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        let config = Config::parse("[game.0000000000000001]\nturbo = 11\n").unwrap();
+        let err = GameSettings::from_config(&config, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[game.0000000000000001] turbo: unknown per-game setting"
+        );
+    }
+}
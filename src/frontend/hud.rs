@@ -0,0 +1,121 @@
+//! Performance overlay.
+//!
+//! The HUD averages [`FrameStats`] over half second windows and shows them in the OSD status:
+//!
+//! ```text
+//! 60.0 FPS
+//! EMU 1.23 CPU 1.10 VID 0.08 RWD 0.05 MS
+//! HOST 16.67 MS/TICK
+//! ```
+//!
+//! `EMU` is the core's time per emulated frame, split into CPU, video conversion and rewind
+//! recording. `HOST` is the wall clock time between frontend ticks. When `EMU` approaches `HOST`
+//! emulation is the bottleneck; when it is far below and FPS still drops, the frontend is.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use nes_emulator::console::FrameStats;
+
+/// Length of the averaging window
+pub const WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default)]
+pub struct Hud {
+    window_start: Option<Instant>,
+    frames: u32,
+    ticks: u32,
+    rewind: Duration,
+    cpu: Duration,
+    video: Duration,
+    text: String,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one emulated frame to the current window
+    pub fn record_frame(&mut self, stats: FrameStats) {
+        self.frames += 1;
+        self.rewind += stats.rewind;
+        self.cpu += stats.cpu;
+        self.video += stats.video;
+    }
+
+    /// Marks the end of a frontend tick at `now`, updating the text once the window is full
+    pub fn end_tick(&mut self, now: Instant) {
+        let Some(start) = self.window_start else {
+            self.window_start = Some(now);
+            return;
+        };
+        self.ticks += 1;
+        let elapsed = now - start;
+        if elapsed < WINDOW {
+            return;
+        }
+
+        let secs = elapsed.as_secs_f64();
+        let per_frame = |total: Duration| ms(total) / self.frames.max(1) as f64;
+        self.text.clear();
+        let _ = write!(
+            self.text,
+            "{:.1} FPS\nEMU {:.2} CPU {:.2} VID {:.2} RWD {:.2} MS\nHOST {:.2} MS/TICK",
+            self.frames as f64 / secs,
+            per_frame(self.rewind + self.cpu + self.video),
+            per_frame(self.cpu),
+            per_frame(self.video),
+            per_frame(self.rewind),
+            secs * 1000.0 / self.ticks as f64,
+        );
+
+        *self = Hud {
+            window_start: Some(now),
+            text: std::mem::take(&mut self.text),
+            ..Hud::default()
+        };
+    }
+
+    /// The overlay text, empty until the first window completes
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_averages_over_window() {
+        let mut hud = Hud::new();
+        let start = Instant::now();
+        let stats = FrameStats {
+            rewind: Duration::from_micros(100),
+            cpu: Duration::from_millis(2),
+            video: Duration::from_micros(400),
+            cycles: 29_780,
+        };
+
+        hud.end_tick(start);
+        for tick in 1..=30 {
+            hud.record_frame(stats);
+            hud.record_frame(stats);
+            hud.end_tick(start + Duration::from_millis(20) * tick);
+            if tick < 25 {
+                assert_eq!(hud.text(), "");
+            }
+        }
+
+        // Window closed at tick 25: 50 frames in 0.5s
+        let lines: Vec<_> = hud.text().lines().collect();
+        assert_eq!(lines[0], "100.0 FPS");
+        assert_eq!(lines[1], "EMU 2.50 CPU 2.00 VID 0.40 RWD 0.10 MS");
+        assert_eq!(lines[2], "HOST 20.00 MS/TICK");
+    }
+}
@@ -0,0 +1,287 @@
+//! Controller macros: short button sequences played back on a hotkey.
+//!
+//! A macro is player 1's buttons for a run of frames, such as a frame-perfect jump for practicing
+//! a trick, or a fast sequence of presses for players who can't do them by hand. The
+//! `record_macro_N` hotkeys record one from the controller until pressed again, and the `[macros]`
+//! section of the config writes them out, one `N = frames` line per macro:
+//!
+//! ```ini
+//! [macros]
+//! # Run, then a running jump
+//! 1 = right*20 right+b+a*12 right+b*8
+//! # Mash A for half a second
+//! 2 = a . a . a . a . a . a . a . a . a . a . a . a . a . a . a
+//! ```
+//!
+//! Frames are separated by spaces or commas, each a `+` separated list of
+//! [buttons](nes_emulator::joypad::BUTTON_NAMES) or `.` for none, optionally followed by `*N`
+//! to hold it for `N` frames. `play_macro_N` plays macro `N` from the next frame, adding its
+//! buttons to the ones held on the controller.
+
+use nes_emulator::joypad::{JoypadButton, BUTTON_NAMES};
+
+use super::config::{Config, SettingError};
+
+/// Macros there are hotkeys for, numbered from 1
+pub const MACROS: u8 = 4;
+/// Frames a macro can hold, 10 seconds at 60 frames per second; recording stops there
+pub const MAX_FRAMES: usize = 600;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Macro {
+    frames: Vec<JoypadButton>,
+}
+
+impl Macro {
+    pub fn parse(text: &str) -> Result<Macro, String> {
+        let mut frames = Vec::new();
+        for token in text
+            .split([' ', '\t', ','])
+            .filter(|token| !token.is_empty())
+        {
+            let (buttons, count) = match token.split_once('*') {
+                Some((buttons, count)) => {
+                    let count = count
+                        .parse()
+                        .map_err(|_| format!("`{count}` in `{token}` is not a frame count"))?;
+                    (buttons, count)
+                }
+                None => (token, 1),
+            };
+            let mut pressed = JoypadButton::empty();
+            if buttons != "." {
+                for name in buttons.split('+') {
+                    pressed |= JoypadButton::from_name(name)
+                        .ok_or_else(|| format!("unknown button `{name}` in `{token}`"))?;
+                }
+            }
+            frames.extend(std::iter::repeat_n(pressed, count));
+            if frames.len() > MAX_FRAMES {
+                return Err(format!("longer than {MAX_FRAMES} frames"));
+            }
+        }
+        Ok(Macro { frames })
+    }
+
+    /// The macro in the config format, holds written with `*N`
+    pub fn to_text(&self) -> String {
+        let mut tokens = Vec::new();
+        let mut frames = self.frames.iter().peekable();
+        while let Some(&buttons) = frames.next() {
+            let mut count = 1;
+            while frames.next_if_eq(&&buttons).is_some() {
+                count += 1;
+            }
+            let mut token = BUTTON_NAMES
+                .iter()
+                .filter(|(_, button)| buttons.contains(*button))
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join("+");
+            if token.is_empty() {
+                token.push('.');
+            }
+            if count > 1 {
+                token += &format!("*{count}");
+            }
+            tokens.push(token);
+        }
+        tokens.join(" ")
+    }
+
+    pub fn frames(&self) -> &[JoypadButton] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// The macros of a session, and the one being recorded or played
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Macros {
+    /// Macro `N` at index `N - 1`
+    macros: Vec<Macro>,
+    /// The macro number and the frames so far
+    recording: Option<(u8, Vec<JoypadButton>)>,
+    /// The macro number and the next frame of it to play
+    playing: Option<(u8, usize)>,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self {
+            macros: vec![Macro::default(); MACROS as usize],
+            recording: None,
+            playing: None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Macros, SettingError> {
+        let mut macros = Macros::new();
+        for (key, value) in config.section("macros") {
+            let error = |message: String| SettingError {
+                section: "macros".to_string(),
+                key: key.to_string(),
+                message,
+            };
+            let number = key
+                .parse()
+                .ok()
+                .filter(|number| (1..=MACROS).contains(number))
+                .ok_or_else(|| error(format!("expected a macro number from 1 to {MACROS}")))?;
+            macros.set(number, Macro::parse(value).map_err(error)?);
+        }
+        Ok(macros)
+    }
+
+    /// Macro `number`, counted from 1
+    pub fn get(&self, number: u8) -> Option<&Macro> {
+        self.macros.get(number.checked_sub(1)? as usize)
+    }
+
+    pub fn set(&mut self, number: u8, recorded: Macro) {
+        if let Some(slot) = number
+            .checked_sub(1)
+            .and_then(|i| self.macros.get_mut(i as usize))
+        {
+            *slot = recorded;
+        }
+    }
+
+    pub fn recording(&self) -> Option<u8> {
+        self.recording.as_ref().map(|(number, _)| *number)
+    }
+
+    pub fn playing(&self) -> Option<u8> {
+        self.playing.map(|(number, _)| number)
+    }
+
+    /// The buttons the playing macro pressed this frame
+    pub fn pressed(&self) -> JoypadButton {
+        match self.playing {
+            Some((number, next)) if next > 0 => self.macros[number as usize - 1].frames[next - 1],
+            _ => JoypadButton::empty(),
+        }
+    }
+
+    /// Starts recording macro `number` from the next frame, dropping any recording in progress
+    pub fn start_recording(&mut self, number: u8) {
+        self.recording = Some((number, Vec::new()));
+    }
+
+    /// Stores the recording into its macro and returns the number, with the empty frames before
+    /// the first press and after the last release left out
+    pub fn stop_recording(&mut self) -> Option<u8> {
+        let (number, mut frames) = self.recording.take()?;
+        let last = frames.iter().rposition(|buttons| !buttons.is_empty());
+        frames.truncate(last.map_or(0, |last| last + 1));
+        let first = frames.iter().position(|buttons| !buttons.is_empty());
+        frames.drain(..first.unwrap_or(0));
+        self.set(number, Macro { frames });
+        Some(number)
+    }
+
+    /// Plays macro `number` from the next frame, or returns false if it's empty
+    pub fn play(&mut self, number: u8) -> bool {
+        if self.get(number).is_none_or(Macro::is_empty) {
+            return false;
+        }
+        self.playing = Some((number, 0));
+        true
+    }
+
+    /// Moves on a frame with `held` the buttons held on the controller. Returns the buttons the
+    /// controller should have this frame while a macro plays, and `held` once more on the frame
+    /// after it ends; `None` leaves the controller alone. A recording that reaches [`MAX_FRAMES`]
+    /// stops by itself; [`Macros::recording`] turns `None`.
+    pub fn next_frame(&mut self, held: JoypadButton) -> Option<JoypadButton> {
+        if let Some((_, frames)) = &mut self.recording {
+            frames.push(held);
+            if frames.len() == MAX_FRAMES {
+                self.stop_recording();
+            }
+        }
+        let (number, next) = self.playing.as_mut()?;
+        let frames = self.macros[*number as usize - 1].frames();
+        match frames.get(*next) {
+            Some(&buttons) => {
+                *next += 1;
+                Some(held | buttons)
+            }
+            None => {
+                self.playing = None;
+                Some(held)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_play() {
+        let config = Config::parse("[macros]\n2 = Right*2, right+a .\n").unwrap();
+        let mut macros = Macros::from_config(&config).unwrap();
+        let jump = macros.get(2).unwrap();
+        assert_eq!(jump.len(), 4);
+        assert_eq!(jump.to_text(), "right*2 a+right .");
+        assert_eq!(Macro::parse(&jump.to_text()).unwrap(), *jump);
+        assert_eq!(
+            Macro::parse("a*x").unwrap_err(),
+            "`x` in `a*x` is not a frame count"
+        );
+        let config = Config::parse("[macros]\n5 = a\n").unwrap();
+        assert_eq!(
+            Macros::from_config(&config).unwrap_err().to_string(),
+            "[macros] 5: expected a macro number from 1 to 4"
+        );
+
+        assert!(!macros.play(1));
+        assert!(macros.play(2));
+        let held = JoypadButton::UP;
+        let played: Vec<_> = (0..6).map(|_| macros.next_frame(held)).collect();
+        assert_eq!(
+            played,
+            [
+                Some(held | JoypadButton::RIGHT),
+                Some(held | JoypadButton::RIGHT),
+                Some(held | JoypadButton::RIGHT | JoypadButton::BUTTON_A),
+                Some(held),
+                Some(held),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recording_trims_idle_frames() {
+        let mut macros = Macros::new();
+        macros.start_recording(1);
+        for buttons in [
+            JoypadButton::empty(),
+            JoypadButton::BUTTON_B,
+            JoypadButton::empty(),
+            JoypadButton::BUTTON_A,
+            JoypadButton::empty(),
+        ] {
+            assert_eq!(macros.next_frame(buttons), None);
+        }
+        assert_eq!(macros.stop_recording(), Some(1));
+        assert_eq!(macros.get(1).unwrap().to_text(), "b . a");
+
+        macros.start_recording(3);
+        for _ in 0..MAX_FRAMES {
+            macros.next_frame(JoypadButton::START);
+        }
+        assert_eq!(macros.recording(), None);
+        assert_eq!(macros.get(3).unwrap().len(), MAX_FRAMES);
+    }
+}
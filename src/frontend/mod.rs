@@ -0,0 +1,12 @@
+//! The command line frontend: configuration, the ROM launcher, the emulation loop, and hot
+//! reload for homebrew development.
+
+pub mod bindings;
+pub mod browser;
+pub mod config;
+pub mod debugger;
+pub mod game;
+pub mod hud;
+pub mod macros;
+pub mod session;
+pub mod watch;
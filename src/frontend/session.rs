@@ -0,0 +1,695 @@
+//! A running game and the frontend state around it.
+//!
+//! [`Session`] turns key events into controller input and hotkey actions, and decides how many
+//! frames to emulate per tick based on pause, fast-forward and rewind. Hotkeys report what they
+//! did through the session's [`Osd`].
+//!
+//! With an input lead set, controller keys aren't applied straight away but held until the frame
+//! reaches the point shortly before the game polls, see [`Console::run_frame_sampling_input`].
+//!
+//! With [feedback rules](nes_emulator::feedback) set, [`Session::rumble`] says how hard each of
+//! the host gamepad's motors should be running after the last tick.
+//!
+//! [`Session::audio`] is the sound to play for each tick. Fast-forward keeps only the last frame
+//! of it, unless [time stretching](nes_emulator::time_stretch) is on to fit all of the frames
+//! into the tick at their normal pitch.
+//!
+//! Player 1's [macros](super::macros) play and record through the emulated frames, so each
+//! pressed button lasts exactly the frames it was recorded for whatever the fast-forward, and
+//! pause and rewind hold them where they are.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use nes_emulator::accessibility::{ColorVision, FlashFilter};
+use nes_emulator::blend::FrameBlend;
+use nes_emulator::console::Console;
+use nes_emulator::debug_info::DebugInfo;
+use nes_emulator::feedback::{FeedbackRule, Motor};
+use nes_emulator::frame::{Palette, HEIGHT, WIDTH};
+use nes_emulator::journal::EntryKind;
+use nes_emulator::joypad::JoypadButton;
+use nes_emulator::latency::LatencyProbe;
+use nes_emulator::livesplit::{AutoSplitter, LiveSplit};
+use nes_emulator::metrics::Metrics;
+use nes_emulator::osd::Osd;
+use nes_emulator::rom::{Rom, RomError};
+use nes_emulator::sram::Autosave;
+use nes_emulator::time_stretch::TimeStretch;
+use nes_emulator::triggers::TriggerSet;
+
+use super::bindings::{Action, Bindings, Chord, Target};
+use super::debugger::{self, Debugger};
+use super::hud::Hud;
+use super::macros::{Macro, Macros, MAX_FRAMES};
+
+/// Frames emulated per tick while fast-forward is held
+pub const FAST_FORWARD_FRAMES: u32 = 4;
+/// Frames of rewind history kept, about 20 seconds
+pub const REWIND_FRAMES: usize = 1200;
+pub const SLOTS: u8 = 10;
+/// CPU cycles per NTSC scanline, rounded down, for turning an input lead into cycles
+const CYCLES_PER_SCANLINE: u64 = 341 / 3;
+
+pub struct Session {
+    pub console: Console,
+    pub osd: Osd,
+    /// Performance overlay drawn into the OSD status, when enabled
+    hud: Option<Hud>,
+    /// Service metrics recorded each tick, when enabled
+    metrics: Option<Metrics>,
+    /// Battery save for games that have one
+    autosave: Option<Autosave>,
+    /// CPU cycles before the game's poll to apply controller keys at, when sampling late
+    input_lead: Option<u64>,
+    /// Buttons to apply at the next sample point, per player, while sampling late
+    pending_buttons: [Option<JoypadButton>; 2],
+    /// Buttons held on the keyboard, per player, without the ones a macro adds
+    keys: [JoypadButton; 2],
+    /// Player 1's macros, and the one recording or playing
+    macros: Macros,
+    /// Measures presses to the game reacting, when enabled
+    latency: Option<LatencyProbe>,
+    /// RAM triggers announced on the OSD when they fire
+    triggers: TriggerSet,
+    /// Fits fast-forwarded audio into real time, when enabled
+    time_stretch: Option<TimeStretch>,
+    /// Sound for the last tick, at the APU's sample rate
+    audio: Vec<f32>,
+    /// Reused buffer for the tick's emulated audio before it's time-stretched
+    stretch_input: Vec<f32>,
+    /// Strength of the strong and weak motors the game last asked for
+    rumble: [f32; 2],
+    /// Shown beside the game, when enabled
+    debugger: Option<Debugger>,
+    /// The game's ld65 debug file, for the debugger's disassembly
+    debug_info: Option<DebugInfo>,
+    /// Splits the LiveSplit timer, when connected
+    autosplit: Option<(AutoSplitter, LiveSplit)>,
+    bindings: Bindings,
+    /// Base name for save state and screenshot files
+    name: String,
+    state_dir: PathBuf,
+    screenshot_dir: PathBuf,
+    /// Where recorded VGM files go
+    music_dir: PathBuf,
+    /// The game's own palette, before any color-vision adjustment
+    palette: Palette,
+    color_vision: ColorVision,
+    slot: u8,
+    fast_forward: bool,
+    rewinding: bool,
+    fullscreen: bool,
+}
+
+impl Session {
+    pub fn new(
+        mut console: Console,
+        bindings: Bindings,
+        name: &str,
+        state_dir: PathBuf,
+        screenshot_dir: PathBuf,
+    ) -> Self {
+        console.enable_rewind(REWIND_FRAMES);
+        Self {
+            palette: *console.palette(),
+            color_vision: ColorVision::Normal,
+            console,
+            osd: Osd::new(),
+            hud: None,
+            metrics: None,
+            autosave: None,
+            input_lead: None,
+            pending_buttons: [None; 2],
+            keys: [JoypadButton::empty(); 2],
+            macros: Macros::new(),
+            latency: None,
+            triggers: TriggerSet::new(),
+            time_stretch: None,
+            audio: Vec::new(),
+            stretch_input: Vec::new(),
+            rumble: [0.0; 2],
+            autosplit: None,
+            debugger: None,
+            debug_info: None,
+            bindings,
+            name: name.to_string(),
+            state_dir,
+            music_dir: screenshot_dir.clone(),
+            screenshot_dir,
+            slot: 0,
+            fast_forward: false,
+            rewinding: false,
+            fullscreen: false,
+        }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.console.is_paused()
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    pub fn set_hud_enabled(&mut self, enabled: bool) {
+        self.hud = enabled.then(Hud::new);
+        self.osd.set_status("");
+    }
+
+    pub fn is_hud_enabled(&self) -> bool {
+        self.hud.is_some()
+    }
+
+    /// Records frames emulated and rewound into `metrics` from now on
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Sets where [`Action::RecordVgm`] saves to, by default alongside the screenshots
+    pub fn set_music_dir(&mut self, dir: PathBuf) {
+        self.music_dir = dir;
+    }
+
+    pub fn color_vision(&self) -> ColorVision {
+        self.color_vision
+    }
+
+    /// Recolors the game's palette for `vision`
+    pub fn set_color_vision(&mut self, vision: ColorVision) {
+        self.color_vision = vision;
+        self.console.set_palette(vision.apply(&self.palette));
+    }
+
+    pub fn is_flash_filter_enabled(&self) -> bool {
+        self.console.flash_filter().is_some()
+    }
+
+    /// Mixes `persistence` of the previous frame into each frame, or stops blending with `None`
+    pub fn set_frame_blend(&mut self, persistence: Option<f32>) {
+        self.console
+            .set_frame_blend(persistence.map(FrameBlend::new));
+    }
+
+    pub fn set_flash_filter_enabled(&mut self, enabled: bool) {
+        self.console
+            .set_flash_filter(enabled.then(FlashFilter::default));
+    }
+
+    /// Shows each trigger's name on the OSD when it fires
+    pub fn set_triggers(&mut self, triggers: TriggerSet) {
+        self.triggers = triggers;
+    }
+
+    /// Replaces the macros, stopping any recording or playback
+    pub fn set_macros(&mut self, macros: Macros) {
+        self.macros = macros;
+    }
+
+    /// Runs the motors from writes matching `rules`; no rules stops them
+    pub fn set_rumble(&mut self, rules: Vec<FeedbackRule>) {
+        self.console.cpu_mut().bus_mut().set_feedback(rules);
+        self.rumble = [0.0; 2];
+    }
+
+    /// How hard `motor` should run, from 0.0 to 1.0. Stopped while paused or rewinding, so a
+    /// game left buzzing doesn't keep the controller going.
+    pub fn rumble(&self, motor: Motor) -> f32 {
+        if self.console.is_paused() || self.rewinding {
+            return 0.0;
+        }
+        self.rumble[motor as usize]
+    }
+
+    /// Sends the splitter's signals to `livesplit` after every frame
+    pub fn set_autosplit(&mut self, splitter: AutoSplitter, livesplit: LiveSplit) {
+        self.autosplit = Some((splitter, livesplit));
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Shows the debugger beside the game. Screenshots take the whole view while it's shown.
+    pub fn set_debugger_enabled(&mut self, enabled: bool) {
+        if enabled != self.debugger.is_some() {
+            self.debugger = enabled.then(Debugger::new);
+        }
+    }
+
+    /// Shows the game's source lines and labels in the debugger's disassembly
+    pub fn set_debug_info(&mut self, info: Option<DebugInfo>) {
+        self.debug_info = info;
+    }
+
+    /// The debugger view as RGBA, [`debugger::WIDTH`] by [`debugger::HEIGHT`], when enabled
+    pub fn debug_view(&self) -> Option<Vec<u8>> {
+        let debugger = self.debugger.as_ref()?;
+        Some(debugger.render(&self.console, self.debug_info.as_ref()))
+    }
+
+    /// Applies controller keys `scanlines` before the point the game polled last frame, instead
+    /// of at the start of the frame. `None` goes back to applying them straight away.
+    pub fn set_input_lead(&mut self, scanlines: Option<u32>) {
+        self.flush_pending_buttons();
+        self.input_lead = scanlines.map(|lines| lines as u64 * CYCLES_PER_SCANLINE);
+    }
+
+    /// Reports on the OSD how many frames the game takes to poll and show each press
+    pub fn set_latency_probe(&mut self, enabled: bool) {
+        self.latency = enabled.then(LatencyProbe::new);
+    }
+
+    /// Plays fast-forward's audio time-stretched rather than only its last frame
+    pub fn set_time_stretch(&mut self, enabled: bool) {
+        self.time_stretch = enabled.then(|| TimeStretch::new(self.console.apu().sample_rate()));
+    }
+
+    /// Audio produced by the last [`Session::tick`], as mono samples at the APU's sample rate.
+    /// Empty while paused or rewinding.
+    pub fn audio(&self) -> &[f32] {
+        &self.audio
+    }
+
+    /// Loads the battery save, if the file exists, and keeps it up to date from now on
+    pub fn set_autosave(&mut self, mut autosave: Autosave) -> io::Result<()> {
+        if autosave.attach(&mut self.console)? {
+            self.osd.show("Battery save loaded");
+        }
+        self.autosave = Some(autosave);
+        Ok(())
+    }
+
+    /// Writes any unsaved battery RAM to disk, as on exit
+    pub fn flush_autosave(&mut self) -> io::Result<()> {
+        if let Some(autosave) = &mut self.autosave {
+            autosave.flush(&self.console)?;
+        }
+        Ok(())
+    }
+
+    /// Switches to a new build of the game, see [`Console::reload_rom`]
+    pub fn reload(&mut self, rom: &Rom, keep_state: bool) -> Result<(), RomError> {
+        self.console.reload_rom(rom, keep_state)?;
+        self.osd.show("Reloaded ROM");
+        Ok(())
+    }
+
+    /// Handles a key going down (`pressed`) or up
+    pub fn key_event(&mut self, chord: &Chord, pressed: bool) -> Result<(), Box<dyn Error>> {
+        match self.bindings.target(chord) {
+            Some(Target::Button { player, button }) => {
+                let keys = &mut self.keys[player];
+                if pressed {
+                    keys.insert(button);
+                    if let Some(probe) = &mut self.latency {
+                        probe.press(&self.console, player, button);
+                    }
+                } else {
+                    keys.remove(button);
+                }
+                let mut buttons = *keys;
+                if player == 0 {
+                    buttons |= self.macros.pressed();
+                }
+                self.set_buttons(player, buttons);
+                Ok(())
+            }
+            Some(Target::Hotkey(action)) => self.action(action, pressed),
+            None => Ok(()),
+        }
+    }
+
+    /// Performs `action`. Hold actions start on press and stop on release; the others only act on
+    /// press.
+    pub fn action(&mut self, action: Action, pressed: bool) -> Result<(), Box<dyn Error>> {
+        if !pressed && !action.is_hold() {
+            return Ok(());
+        }
+        match action {
+            Action::SaveState => {
+                fs::create_dir_all(&self.state_dir)?;
+                fs::write(self.state_path(), self.console.save_state())?;
+                self.console
+                    .note(EntryKind::StateSaved, &format!("slot {}", self.slot));
+                self.osd.show(format!("State saved to slot {}", self.slot));
+            }
+            Action::LoadState => {
+                let data = fs::read(self.state_path())?;
+                self.console.load_state(&data)?;
+                self.triggers.reset();
+                if let Some((splitter, _)) = &mut self.autosplit {
+                    splitter.reset_triggers();
+                }
+                self.osd
+                    .show(format!("State loaded from slot {}", self.slot));
+            }
+            Action::NextSlot => self.select_slot((self.slot + 1) % SLOTS),
+            Action::PrevSlot => self.select_slot((self.slot + SLOTS - 1) % SLOTS),
+            Action::SelectSlot(slot) => self.select_slot(slot % SLOTS),
+            Action::FastForward => {
+                self.fast_forward = pressed;
+                if pressed {
+                    self.osd.show("Fast-forward");
+                }
+            }
+            Action::Rewind => {
+                self.rewinding = pressed;
+                if pressed {
+                    self.osd.show("Rewind");
+                }
+            }
+            Action::Pause => {
+                let paused = !self.console.is_paused();
+                self.console.set_paused(paused);
+                self.osd.show(if paused { "Paused" } else { "Resumed" });
+            }
+            Action::Screenshot => {
+                let path = self.screenshot()?;
+                self.osd
+                    .show(format!("Screenshot saved to {}", path.display()));
+            }
+            Action::ToggleFullscreen => self.fullscreen = !self.fullscreen,
+            Action::ToggleHud => self.set_hud_enabled(!self.is_hud_enabled()),
+            Action::Reset => {
+                self.console.reset();
+                self.osd.show("Reset");
+            }
+            Action::RecordVgm => match self.console.stop_vgm() {
+                Some(vgm) => {
+                    fs::create_dir_all(&self.music_dir)?;
+                    let path =
+                        self.music_dir
+                            .join(format!("{}-{}.vgm", self.name, self.console.frame()));
+                    fs::write(&path, vgm)?;
+                    self.osd.show(format!("VGM saved to {}", path.display()));
+                }
+                None => {
+                    self.console.start_vgm();
+                    self.osd.show("Recording VGM");
+                }
+            },
+            Action::ToggleDebugger => self.set_debugger_enabled(self.debugger.is_none()),
+            Action::RecordMacro(number) => match self.macros.stop_recording() {
+                Some(recorded) => match self.macros.get(recorded).map_or(0, Macro::len) {
+                    0 => self.osd.show(format!("Macro {recorded} cleared")),
+                    frames => self
+                        .osd
+                        .show(format!("Macro {recorded} recorded, {frames} frames")),
+                },
+                None => {
+                    self.macros.start_recording(number);
+                    self.osd.show(format!("Recording macro {number}"));
+                }
+            },
+            Action::PlayMacro(number) => {
+                if !self.macros.play(number) {
+                    self.osd.show(format!("Macro {number} is empty"));
+                }
+            }
+            Action::CycleColorVision => {
+                self.set_color_vision(self.color_vision.next());
+                self.osd.show(format!("Palette: {}", self.color_vision));
+            }
+            Action::ToggleFlashFilter => {
+                let enabled = !self.is_flash_filter_enabled();
+                self.set_flash_filter_enabled(enabled);
+                self.osd.show(if enabled {
+                    "Flash reduction on"
+                } else {
+                    "Flash reduction off"
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn select_slot(&mut self, slot: u8) {
+        self.slot = slot;
+        self.osd.show(format!("Slot {slot}"));
+    }
+
+    /// Advances emulation by one frontend tick
+    pub fn tick(&mut self) {
+        self.osd.tick();
+        self.audio.clear();
+        if self.rewinding || self.console.is_paused() {
+            if let Some(stretch) = &mut self.time_stretch {
+                stretch.reset();
+            }
+        }
+        if self.rewinding {
+            if self.console.rewind() {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_rollback();
+                }
+            }
+        } else if !self.console.is_paused() {
+            self.stretch_input.clear();
+            let frames = if self.fast_forward {
+                FAST_FORWARD_FRAMES
+            } else {
+                1
+            };
+            for _ in 0..frames {
+                let recording = self.macros.recording();
+                if let Some(buttons) = self.macros.next_frame(self.keys[0]) {
+                    self.set_buttons(0, buttons);
+                }
+                if let Some(number) = recording.filter(|_| self.macros.recording().is_none()) {
+                    self.osd.show(format!(
+                        "Macro {number} recorded, stopped at {MAX_FRAMES} frames"
+                    ));
+                }
+                match self.input_lead {
+                    Some(lead) => {
+                        let pending = &mut self.pending_buttons;
+                        self.console
+                            .run_frame_sampling_input(lead, |joypad1, joypad2| {
+                                for (joypad, buttons) in [joypad1, joypad2].into_iter().zip(pending)
+                                {
+                                    if let Some(buttons) = buttons.take() {
+                                        joypad.set_buttons(buttons);
+                                    }
+                                }
+                            });
+                    }
+                    None => self.console.run_frame(),
+                }
+                if let Some(latency) = self
+                    .latency
+                    .as_mut()
+                    .and_then(|probe| probe.end_frame(&self.console))
+                {
+                    self.osd.show(format!(
+                        "Input latency: polled after {} frames, shown after {}",
+                        latency.polled, latency.shown
+                    ));
+                }
+                for trigger in self.triggers.check(self.console.cpu()) {
+                    self.osd.show(trigger.name.clone());
+                    self.console.note(EntryKind::Achievement, &trigger.name);
+                }
+                self.autosplit_frame();
+                for event in self.console.cpu_mut().bus_mut().take_feedback() {
+                    self.rumble[event.motor as usize] = event.strength;
+                }
+                let stats = self.console.frame_stats();
+                if let Some(hud) = &mut self.hud {
+                    hud.record_frame(stats);
+                }
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_frame(stats);
+                }
+                if self.time_stretch.is_some() {
+                    self.stretch_input
+                        .extend_from_slice(self.console.audio_samples());
+                }
+            }
+            match &mut self.time_stretch {
+                Some(stretch) => {
+                    stretch.process(&self.stretch_input, frames as f32, &mut self.audio)
+                }
+                None => self.audio.extend_from_slice(self.console.audio_samples()),
+            }
+        }
+
+        if let Some(autosave) = &mut self.autosave {
+            match autosave.end_frame(&mut self.console) {
+                Ok(Some(_)) => self.osd.show("Saved"),
+                Ok(None) => {}
+                Err(err) => self.osd.show(format!("Saving failed: {err}")),
+            }
+        }
+
+        if let Some(hud) = &mut self.hud {
+            hud.end_tick(Instant::now());
+            self.osd.set_status(hud.text());
+        }
+    }
+
+    fn autosplit_frame(&mut self) {
+        let Some((splitter, livesplit)) = &mut self.autosplit else {
+            return;
+        };
+        let sent = splitter
+            .check(self.console.cpu())
+            .into_iter()
+            .try_for_each(|signal| livesplit.send(signal));
+        if let Err(err) = sent {
+            self.osd.show(format!("LiveSplit disconnected: {err}"));
+            self.autosplit = None;
+        }
+    }
+
+    /// Sets a controller's buttons now, or at the next sample point while sampling late
+    fn set_buttons(&mut self, player: usize, buttons: JoypadButton) {
+        if self.input_lead.is_some() {
+            self.pending_buttons[player] = Some(buttons);
+        } else {
+            self.console.joypad_mut(player).set_buttons(buttons);
+        }
+    }
+
+    fn flush_pending_buttons(&mut self) {
+        for player in 0..2 {
+            if let Some(buttons) = self.pending_buttons[player].take() {
+                self.console.joypad_mut(player).set_buttons(buttons);
+            }
+        }
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.state_dir
+            .join(format!("{}.ss{}", self.name, self.slot))
+    }
+
+    /// Writes the current frame, without the OSD, as a binary PPM and returns its path. With the
+    /// debugger shown it writes the whole debugger view instead.
+    pub fn screenshot(&self) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.screenshot_dir)?;
+        let frame = self.console.frame_ref();
+        let path = self
+            .screenshot_dir
+            .join(format!("{}-{}.ppm", self.name, frame.number()));
+
+        let ppm = match self.debug_view() {
+            Some(rgba) => {
+                let (width, height) = (debugger::WIDTH, debugger::HEIGHT);
+                let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+                for pixel in rgba.chunks_exact(4) {
+                    ppm.extend_from_slice(&pixel[..3]);
+                }
+                ppm
+            }
+            None => {
+                let mut ppm = format!("P6\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+                let palette = self.console.palette();
+                for index in frame.indices().pixels.iter() {
+                    let (r, g, b) = palette.rgb(*index);
+                    ppm.extend_from_slice(&[r, g, b]);
+                }
+                ppm
+            }
+        };
+        fs::write(&path, ppm)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nes_emulator::joypad::JoypadButton;
+
+    fn session(name: &str) -> (Session, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("nes_emulator_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut console = Console::new();
+        let mut program = vec![0xE8; 0x7FF0]; // INX forever
+        program.push(0x00);
+        console.load(&program);
+        let session = Session::new(
+            console,
+            Bindings::default(),
+            "test",
+            dir.join("states"),
+            dir.join("screenshots"),
+        );
+        (session, dir)
+    }
+
+    fn key(name: &str) -> Chord {
+        Chord::parse(name).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load_slots() {
+        let (mut session, dir) = session("slots");
+        session.tick();
+        session.key_event(&key("3"), true).unwrap();
+        session.key_event(&key("F5"), true).unwrap();
+        let saved = session.console.state_hash();
+
+        session.tick();
+        assert_ne!(session.console.state_hash(), saved);
+        session.key_event(&key("F7"), true).unwrap();
+        assert_eq!(session.console.state_hash(), saved);
+        assert_eq!(
+            session.osd.messages().last(),
+            Some("State loaded from slot 3")
+        );
+
+        // Slot 4 is empty
+        session.key_event(&key("F6"), true).unwrap();
+        assert_eq!(session.slot(), 4);
+        assert!(session.key_event(&key("F7"), true).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pause_fast_forward_and_rewind() {
+        let (mut session, _) = session("speed");
+        session.key_event(&key("P"), true).unwrap();
+        session.tick();
+        assert_eq!(session.console.frame(), 0);
+
+        session.key_event(&key("P"), true).unwrap();
+        session.key_event(&key("Space"), true).unwrap();
+        session.tick();
+        assert_eq!(session.console.frame(), FAST_FORWARD_FRAMES as u64);
+        let frame_audio = session.console.audio_samples().len();
+        assert_eq!(session.audio().len(), frame_audio);
+
+        // Stretched, a tick's audio comes out in steps of half a 20ms window
+        session.set_time_stretch(true);
+        session.tick();
+        session.tick();
+        let step = session.console.apu().sample_rate() as usize / 100;
+        assert!(session.audio().len().abs_diff(frame_audio) <= step);
+
+        session.key_event(&key("Space"), false).unwrap();
+        session.key_event(&key("Backspace"), true).unwrap();
+        session.tick();
+        assert_eq!(session.console.frame(), 3 * FAST_FORWARD_FRAMES as u64 - 1);
+        assert!(session.audio().is_empty());
+    }
+
+    #[test]
+    fn test_controller_keys() {
+        let (mut session, _) = session("buttons");
+        session.key_event(&key("X"), true).unwrap();
+        session.key_event(&key("Enter"), true).unwrap();
+        session.key_event(&key("X"), false).unwrap();
+        assert_eq!(session.console.joypad(0).buttons(), JoypadButton::START);
+    }
+}
@@ -0,0 +1,105 @@
+//! Hot reload for homebrew development.
+//!
+//! With `--watch`, the frontend polls the ROM file and reloads it whenever the assembler writes a
+//! new build. A change only counts once the file has stayed the same for one more poll, so a
+//! build still being written isn't loaded half finished. `[frontend] reload_keeps_state = true`
+//! carries the running game over into the new build; by default it starts from power on.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the file is checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What identifies a version of the file: its modification time and length
+type Stamp = Option<(SystemTime, u64)>;
+
+#[derive(Debug)]
+pub struct RomWatcher {
+    path: PathBuf,
+    interval: Duration,
+    last_poll: Option<Instant>,
+    /// The version last reported, or seen when watching started
+    loaded: Stamp,
+    /// A different version seen on the last poll, waiting to settle
+    pending: Option<Stamp>,
+}
+
+impl RomWatcher {
+    /// Watches `path`, checking at most once per `interval`
+    pub fn new(path: &Path, interval: Duration) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            interval,
+            last_poll: None,
+            loaded: stamp(path),
+            pending: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a new version of the file has finished being written since the last time this
+    /// returned `true`. Cheap to call every tick.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self
+            .last_poll
+            .is_some_and(|last| now - last < self.interval)
+        {
+            return false;
+        }
+        self.last_poll = Some(now);
+        let current = stamp(&self.path);
+        if current == self.loaded || current.is_none() {
+            self.pending = None;
+            return false;
+        }
+        if self.pending != Some(current) {
+            self.pending = Some(current);
+            return false;
+        }
+        self.loaded = current;
+        self.pending = None;
+        true
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_settled_changes() {
+        let path =
+            std::env::temp_dir().join(format!("nes_emulator_watch_{}.nes", std::process::id()));
+        fs::write(&path, b"build 1").unwrap();
+        let mut watcher = RomWatcher::new(&path, Duration::ZERO);
+        let now = Instant::now();
+        assert!(!watcher.poll(now));
+
+        // A different length, in case the clock is too coarse to change the modification time
+        fs::write(&path, b"build 2, longer").unwrap();
+        assert!(!watcher.poll(now), "not settled yet");
+        assert!(watcher.poll(now));
+        assert!(!watcher.poll(now));
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll(now), "a deleted file isn't a new build");
+        assert!(!watcher.poll(now));
+
+        let mut slow = RomWatcher::new(&path, Duration::from_secs(60));
+        fs::write(&path, b"build 3").unwrap();
+        assert!(!slow.poll(now));
+        assert!(!slow.poll(now + Duration::from_secs(1)), "polled too soon");
+        assert!(slow.poll(now + Duration::from_secs(61)));
+        fs::remove_file(&path).unwrap();
+    }
+}
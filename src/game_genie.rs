@@ -0,0 +1,436 @@
+//! The Game Genie: its codes, and the adapter itself between the console and the cartridge.
+//!
+//! A Game Genie code is six or eight letters standing for a PRG ROM address, the byte to read
+//! there instead, and for eight letter codes a compare byte: the replacement only applies while
+//! the cartridge holds the compare byte at that address, so codes can target one bank of a
+//! switching board. [`Code::decode`] reads them:
+//!
+//! ```
+//! use nes_emulator::game_genie::Code;
+//!
+//! let code = Code::decode("GOSSIP")?;
+//! assert_eq!((code.addr, code.value, code.compare), (0xD1DD, 0x14, None));
+//! assert_eq!(Code::decode(&code.to_string())?, code);
+//! # Ok::<(), nes_emulator::game_genie::GenieError>(())
+//! ```
+//!
+//! A [`GameGenie`] plugged in with [`Console::set_game_genie`] emulates the adapter. Made with
+//! [`GameGenie::new`] from a dump of its 4KB ROM, it boots into its own menu on power on: its ROM
+//! answers the CPU at `0x8000..=0xFFFF` and it makes up the pattern tables, while the cartridge
+//! waits behind it. The menu writes the codes entered into the adapter's registers and then
+//! passes through to the game with them applied, just as on hardware, so the codes can be
+//! entered by hand and what the menu makes of them checked against [`Code::decode`]. Made with
+//! [`GameGenie::with_codes`] it skips the menu and runs the game with codes decoded here.
+//!
+//! The registers, which the menu writes at `0x8000..=0x800C`:
+//!
+//! | Address | |
+//! | :--- | :--- |
+//! | `$8000` | bit 0 set while in the menu, cleared to start the game; bits 1-3 enable the compare of codes 0-2, bits 4-6 turn codes 0-2 off |
+//! | `$8001` + 4n | code n's address, high byte; bit 7 always reads as set |
+//! | `$8002` + 4n | code n's address, low byte |
+//! | `$8003` + 4n | code n's compare byte |
+//! | `$8004` + 4n | code n's replacement byte |
+//!
+//! Once started, the game stays in charge until the next power cycle; a reset restarts the game
+//! with the same codes.
+//!
+//! [`Console::set_game_genie`]: crate::console::Console::set_game_genie
+
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+/// The letters of a code, each standing for its index
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+/// Size of the adapter's ROM, mirrored across `0x8000..=0xFFFF`
+pub const BIOS_SIZE: usize = 0x1000;
+/// Codes the adapter's registers hold
+pub const HARDWARE_CODES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenieError {
+    /// Codes are six or eight letters
+    BadLength(usize),
+    BadLetter(char),
+    /// The ROM isn't [`BIOS_SIZE`] bytes
+    BadBios(usize),
+}
+
+impl fmt::Display for GenieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenieError::BadLength(len) => {
+                write!(f, "Game Genie codes have 6 or 8 letters, not {len}")
+            }
+            GenieError::BadLetter(letter) => {
+                write!(f, "`{letter}` is not a Game Genie letter")
+            }
+            GenieError::BadBios(len) => {
+                write!(f, "Game Genie ROM is {len} bytes instead of {BIOS_SIZE}")
+            }
+        }
+    }
+}
+
+impl Error for GenieError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code {
+    /// Always in `0x8000..=0xFFFF`
+    pub addr: u16,
+    pub value: u8,
+    /// Only replace the byte while the cartridge has this one there
+    pub compare: Option<u8>,
+}
+
+impl Code {
+    pub fn decode(text: &str) -> Result<Code, GenieError> {
+        let n = text
+            .chars()
+            .map(|letter| {
+                LETTERS
+                    .iter()
+                    .position(|&l| l == letter.to_ascii_uppercase() as u8)
+                    .map(|n| n as u16)
+                    .ok_or(GenieError::BadLetter(letter))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // The last letter holds the replacement's bit 3, and the compare byte's letters follow
+        // the sixth
+        let (value_bit, compare) = match n[..] {
+            [.., n5] if n.len() == 6 => (n5 & 8, None),
+            [.., n5, n6, n7] if n.len() == 8 => {
+                let compare = ((n7 & 7) << 4) | ((n6 & 8) << 4) | (n6 & 7) | (n5 & 8);
+                (n7 & 8, Some(compare as u8))
+            }
+            _ => return Err(GenieError::BadLength(n.len())),
+        };
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7) | value_bit;
+        Ok(Code {
+            addr,
+            value: value as u8,
+            compare,
+        })
+    }
+
+    /// The code in letters, six or eight depending on whether it has a compare byte. Bit 3 of
+    /// the third letter tells the menu how many letters to expect and is set here to match, so
+    /// a code written by hand may come back different in that letter.
+    pub fn encode(&self) -> String {
+        let (addr, value) = (self.addr, self.value as u16);
+        let compare = self.compare.map(u16::from);
+        let mut n = vec![
+            (value & 7) | ((value >> 4) & 8),
+            ((value >> 4) & 7) | ((addr >> 4) & 8),
+            ((addr >> 4) & 7) | if compare.is_some() { 8 } else { 0 },
+            ((addr >> 12) & 7) | (addr & 8),
+            (addr & 7) | ((addr >> 8) & 8),
+            ((addr >> 8) & 7) | compare.unwrap_or(value) & 8,
+        ];
+        if let Some(compare) = compare {
+            n.push((compare & 7) | ((compare >> 4) & 8));
+            n.push(((compare >> 4) & 7) | (value & 8));
+        }
+        n.into_iter().map(|n| LETTERS[n as usize] as char).collect()
+    }
+
+    /// What the CPU reads at `addr` with the cartridge holding `value` there
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        if addr == self.addr && self.compare.is_none_or(|compare| compare == value) {
+            self.value
+        } else {
+            value
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+/// The adapter, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct GameGenie {
+    /// The adapter's ROM, or none to skip the menu
+    bios: Option<Arc<[u8]>>,
+    /// Whether the menu is running rather than the game
+    menu: bool,
+    /// `$8000`
+    control: u8,
+    /// `$8001..=$800C`
+    registers: [u8; 4 * HARDWARE_CODES],
+    /// The codes the game runs with
+    codes: Vec<Code>,
+}
+
+/// The ROM is left out; the console's ROM hash covers the cartridge, not what's plugged in front
+impl Hash for GameGenie {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.menu.hash(state);
+        self.control.hash(state);
+        self.registers.hash(state);
+        self.codes.hash(state);
+    }
+}
+
+impl GameGenie {
+    /// The adapter running its menu from `bios`, a dump of its ROM
+    pub fn new(bios: &[u8]) -> Result<Self, GenieError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(GenieError::BadBios(bios.len()));
+        }
+        Ok(Self {
+            bios: Some(bios.into()),
+            menu: true,
+            control: 0,
+            registers: [0; 4 * HARDWARE_CODES],
+            codes: Vec::new(),
+        })
+    }
+
+    /// Runs the game straight away with `codes`, as many as wanted up to `u16::MAX`
+    pub fn with_codes(codes: Vec<Code>) -> Self {
+        assert!(
+            codes.len() <= u16::MAX as usize,
+            "{} Game Genie codes, more than a save state can hold",
+            codes.len()
+        );
+        Self {
+            bios: None,
+            menu: false,
+            control: 0,
+            registers: [0; 4 * HARDWARE_CODES],
+            codes,
+        }
+    }
+
+    /// Whether the adapter's own menu is running rather than the game
+    pub fn in_menu(&self) -> bool {
+        self.menu
+    }
+
+    /// The codes the game runs with; none while in the menu
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    /// Back to the menu, forgetting the codes entered there; codes given to
+    /// [`GameGenie::with_codes`] stay
+    pub(crate) fn power_on(&mut self) {
+        if self.bios.is_some() {
+            self.menu = true;
+            self.control = 0;
+            self.registers = [0; 4 * HARDWARE_CODES];
+            self.codes.clear();
+        }
+    }
+
+    /// What the CPU reads at `addr` in `0x8000..=0xFFFF`, with the cartridge holding `value`
+    pub(crate) fn read(&self, addr: u16, value: u8) -> u8 {
+        match &self.bios {
+            Some(bios) if self.menu => bios[addr as usize % BIOS_SIZE],
+            _ => self
+                .codes
+                .iter()
+                .fold(value, |read, code| code.apply(addr, read)),
+        }
+    }
+
+    /// A CPU write to `0x8000..=0xFFFF` while in the menu
+    pub(crate) fn write(&mut self, addr: u16, value: u8) {
+        match addr - 0x8000 {
+            0 => {
+                self.control = value;
+                if value & 1 == 0 {
+                    self.start_game();
+                }
+            }
+            register @ 1..=12 => self.registers[register as usize - 1] = value,
+            _ => {}
+        }
+    }
+
+    fn start_game(&mut self) {
+        self.menu = false;
+        self.codes = (0..HARDWARE_CODES)
+            .filter(|i| self.control & (0x10 << i) == 0)
+            .map(|i| {
+                let [high, low, compare, value] = [0, 1, 2, 3].map(|j| self.registers[4 * i + j]);
+                Code {
+                    addr: 0x8000 | u16::from_be_bytes([high, low]),
+                    value,
+                    compare: (self.control & (2 << i) != 0).then_some(compare),
+                }
+            })
+            .collect();
+    }
+
+    pub(crate) fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.menu);
+        w.write_u8(self.control);
+        w.write_bytes(&self.registers);
+        w.write_u16(self.codes.len() as u16);
+        for code in &self.codes {
+            w.write_u16(code.addr);
+            w.write_u8(code.value);
+            w.write_bool(code.compare.is_some());
+            w.write_u8(code.compare.unwrap_or(0));
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        let menu = r.read_bool()?;
+        let control = r.read_u8()?;
+        let registers = r.read_bytes(4 * HARDWARE_CODES)?.try_into().unwrap();
+        let mut codes = Vec::new();
+        for _ in 0..r.read_u16()? {
+            let addr = r.read_u16()?;
+            let value = r.read_u8()?;
+            let has_compare = r.read_bool()?;
+            let compare = r.read_u8()?;
+            codes.push(Code {
+                addr,
+                value,
+                compare: has_compare.then_some(compare),
+            });
+        }
+        self.menu = menu && self.bios.is_some();
+        self.control = control;
+        self.registers = registers;
+        self.codes = codes;
+        Ok(())
+    }
+}
+
+/// The pattern tables shown while the adapter's menu runs. The adapter has no CHR memory and
+/// makes each byte up from its address instead: the low four bits of the tile number light the
+/// tile's four 4x4 pixel quadrants, top left, top right, bottom left and bottom right, in color 3.
+pub(crate) fn menu_pattern_tables() -> Arc<[u8]> {
+    (0..0x2000u16)
+        .map(|addr| {
+            let tile = addr >> 4;
+            let quadrants = if addr & 7 < 4 { tile } else { tile >> 2 };
+            (if quadrants & 1 != 0 { 0xF0 } else { 0 })
+                | (if quadrants & 2 != 0 { 0x0F } else { 0 })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+    use crate::cpu::Mem;
+    use crate::rom::{Format, Mirroring, Rom};
+
+    #[test]
+    fn test_codes_round_trip() {
+        let code = Code::decode("zexpypgl").unwrap();
+        assert_eq!(code.encode(), "ZEXPYPGL");
+        assert_eq!(Code::decode(&code.encode()), Ok(code));
+        assert_eq!(code.apply(code.addr, code.compare.unwrap()), code.value);
+        assert_eq!(
+            code.apply(code.addr, !code.compare.unwrap()),
+            !code.compare.unwrap()
+        );
+        assert_eq!(Code::decode("GOSSI"), Err(GenieError::BadLength(5)));
+        assert_eq!(Code::decode("GOSSIB"), Err(GenieError::BadLetter('B')));
+    }
+
+    /// A menu that enters `code` as code 0 with the others off, then starts the game the way
+    /// the real one does: from RAM, since its own ROM disappears the moment the game starts
+    fn menu(code: Code) -> Vec<u8> {
+        let [high, low] = code.addr.to_be_bytes();
+        let control = 0x60 | if code.compare.is_some() { 0x02 } else { 0 };
+        let mut program = vec![0xa9, 0x01, 0x8d, 0x00, 0x80]; // LDA #$01; STA $8000
+        for (register, value) in [high, low, code.compare.unwrap_or(0), code.value]
+            .into_iter()
+            .enumerate()
+        {
+            program.extend([0xa9, value, 0x8d, register as u8 + 1, 0x80]);
+        }
+        // STA $8000; JMP ($FFFC), copied to $0300
+        let start = [0x8d, 0x00, 0x80, 0x6c, 0xfc, 0xff];
+        for (i, byte) in start.into_iter().enumerate() {
+            program.extend([0xa9, byte, 0x8d, i as u8, 0x03]);
+        }
+        program.extend([0xa9, control, 0x4c, 0x00, 0x03]); // LDA #control; JMP $0300
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..program.len()].copy_from_slice(&program);
+        bios[0xFFC..].copy_from_slice(&[0x00, 0xf0, 0x00, 0xf0]);
+        bios
+    }
+
+    #[test]
+    fn test_menu_passes_through_to_the_game() {
+        // LDA $9000; STA $10; loop: JMP loop
+        let mut prg_rom = vec![0xea; 0x8000];
+        prg_rom[..8].copy_from_slice(&[0xad, 0x00, 0x90, 0x85, 0x10, 0x4c, 0x05, 0x80]);
+        prg_rom[0x1000] = 0x01;
+        prg_rom[0x7FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        let rom = Rom {
+            prg_rom,
+            chr_rom: vec![0x55; 0x2000],
+            format: Format::INes,
+            mapper: 0,
+            submapper: 0,
+            board: None,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            timing: None,
+        };
+        let code = Code {
+            addr: 0x9000,
+            value: 0x07,
+            compare: Some(0x01),
+        };
+
+        let mut console = Console::new();
+        console.set_game_genie(Some(GameGenie::new(&menu(code)).unwrap()));
+        console.load_rom(&rom).unwrap();
+        assert!(console.game_genie().unwrap().in_menu());
+        assert_eq!(console.ppu().read_vram(0x0010), 0xF0);
+        console.run_frame();
+
+        let genie = console.game_genie().unwrap();
+        assert!(!genie.in_menu());
+        assert_eq!(genie.codes(), [code]);
+        assert_eq!(console.cpu().mem_peek(0x0010), code.value);
+        assert_eq!(console.ppu().read_vram(0x0010), 0x55);
+
+        let state = console.save_state();
+        console.power_cycle();
+        assert!(console.game_genie().unwrap().in_menu());
+        console.load_state(&state).unwrap();
+        assert_eq!(console.game_genie().unwrap().codes(), [code]);
+        assert_eq!(console.cpu().mem_peek(0x9000), code.value);
+
+        // More codes than fit in a byte survive a state load
+        let codes: Vec<_> = (0..300)
+            .map(|i| Code {
+                addr: 0x8000 + i,
+                value: i as u8,
+                compare: None,
+            })
+            .collect();
+        console.set_game_genie(Some(GameGenie::with_codes(codes.clone())));
+        let state = console.save_state();
+        console.set_game_genie(Some(GameGenie::with_codes(Vec::new())));
+        console.load_state(&state).unwrap();
+        assert_eq!(console.game_genie().unwrap().codes(), codes);
+    }
+}
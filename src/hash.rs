@@ -0,0 +1,153 @@
+//! Stable hashing of emulator state.
+//!
+//! [`std::collections::hash_map::DefaultHasher`] is not guaranteed to produce the same output across
+//! Rust releases, and the default [`Hasher`] integer methods use native endianness. Hashes that are
+//! compared between machines (netplay peers, CI runs, recorded movies) use [`Fnv1a`] instead, which
+//! encodes every integer little endian.
+//!
+//! [`crc32`] and [`adler32`] are here for file formats that checksum their data with them, such
+//! as BPS patches and PNG images.
+
+use std::hash::{Hash, Hasher};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hashes `value` on its own
+    pub fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+        let mut hasher = Self::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64) // same on 32 and 64 bit hosts
+    }
+}
+
+/// CRC-32 lookup table for the reflected polynomial `0xEDB88320`
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC-32 used by zip, PNG and BPS
+pub fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// The Adler-32 checksum ending a zlib stream
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before `b` could overflow
+    for block in data.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_known_vectors() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_integers_are_little_endian() {
+        let mut a = Fnv1a::new();
+        a.write_u16(0x1234);
+
+        let mut b = Fnv1a::new();
+        b.write(&[0x34, 0x12]);
+
+        assert_eq!(a.finish(), b.finish());
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&[0xFF; 100_000]), 0x149A_302C);
+    }
+}
@@ -0,0 +1,184 @@
+//! Input providers: what drives each controller port.
+//!
+//! Without one, a controller holds whatever was last set through [`Console::joypad_mut`] or
+//! [`Console::queue_input`]. An [`InputProvider`] set on a port with
+//! [`Console::set_input_provider`] takes it over instead: at the start of every frame the console
+//! asks it for the buttons to hold, so one port can be a person and the other a bot, a movie or a
+//! player across the network, with nothing knowing which is which. The providers here are:
+//!
+//! - [`HostInput`], buttons held on a keyboard or gamepad, set from any thread
+//! - [`MovieInput`], one controller of a [`Movie`]
+//! - an [`mpsc::Receiver`] of buttons, e.g. a network peer's inputs read on another thread
+//! - any `FnMut(&Console) -> JoypadButton`, for scripts and agents that look at the game first
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::cpu::Mem;
+//! use nes_emulator::input::HostInput;
+//! use nes_emulator::joypad::JoypadButton;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]); // loop: JMP loop
+//! let host = HostInput::new();
+//! console.set_input_provider(0, Some(Box::new(host.clone())));
+//! // Player 2 jumps whenever the byte at $0010 is clear
+//! let bot = |console: &Console| match console.cpu().mem_peek(0x0010) {
+//!     0 => JoypadButton::BUTTON_A,
+//!     _ => JoypadButton::empty(),
+//! };
+//! console.set_input_provider(1, Some(Box::new(bot)));
+//!
+//! host.press(JoypadButton::START);
+//! console.run_frame();
+//! assert_eq!(console.joypad(0).buttons(), JoypadButton::START);
+//! assert_eq!(console.joypad(1).buttons(), JoypadButton::BUTTON_A);
+//! ```
+//!
+//! Providers are outside the emulated machine: they aren't saved in save states or hashed, and
+//! a loaded state keeps the ones set. Inputs queued for a port with a provider are overridden by
+//! it.
+//!
+//! [`Console::joypad_mut`]: crate::console::Console::joypad_mut
+//! [`Console::queue_input`]: crate::console::Console::queue_input
+//! [`Console::set_input_provider`]: crate::console::Console::set_input_provider
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
+
+use crate::console::Console;
+use crate::joypad::JoypadButton;
+use crate::movie::Movie;
+
+/// A source of buttons for one controller port
+pub trait InputProvider: Send {
+    /// The buttons to hold for the frame about to run, with `console` as the last frame left it
+    fn poll(&mut self, console: &Console) -> JoypadButton;
+}
+
+impl<F: FnMut(&Console) -> JoypadButton + Send> InputProvider for F {
+    fn poll(&mut self, console: &Console) -> JoypadButton {
+        self(console)
+    }
+}
+
+/// Waits for each frame's buttons, so the console runs in lockstep with the sender. All buttons
+/// are released once every sender is gone.
+impl InputProvider for mpsc::Receiver<JoypadButton> {
+    fn poll(&mut self, _console: &Console) -> JoypadButton {
+        self.recv().unwrap_or_default()
+    }
+}
+
+/// Buttons held on a host device. Clones share the buttons, so keep one to update from the
+/// frontend's event loop and give a clone to the console.
+#[derive(Debug, Clone, Default)]
+pub struct HostInput(Arc<AtomicU8>);
+
+impl HostInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        JoypadButton::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, buttons: JoypadButton) {
+        self.0.store(buttons.bits(), Ordering::Relaxed);
+    }
+
+    pub fn press(&self, button: JoypadButton) {
+        self.0.fetch_or(button.bits(), Ordering::Relaxed);
+    }
+
+    pub fn release(&self, button: JoypadButton) {
+        self.0.fetch_and(!button.bits(), Ordering::Relaxed);
+    }
+}
+
+impl InputProvider for HostInput {
+    fn poll(&mut self, _console: &Console) -> JoypadButton {
+        self.buttons()
+    }
+}
+
+/// One controller of a movie, a frame per poll from the movie's first frame. The movie's resets
+/// aren't applied; queue the whole movie with [`Movie::queue`] for those.
+#[derive(Debug, Clone)]
+pub struct MovieInput {
+    movie: Arc<Movie>,
+    player: usize,
+    next: usize,
+}
+
+impl MovieInput {
+    /// Plays the controller `player` held in `movie`, releasing everything after its last frame
+    pub fn new(movie: impl Into<Arc<Movie>>, player: usize) -> Self {
+        assert!(player < 2, "no controller port {player}");
+        Self {
+            movie: movie.into(),
+            player,
+            next: 0,
+        }
+    }
+
+    /// Whether every frame of the movie has been played
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.movie.len()
+    }
+}
+
+impl InputProvider for MovieInput {
+    fn poll(&mut self, _console: &Console) -> JoypadButton {
+        let buttons = self
+            .movie
+            .frames
+            .get(self.next)
+            .map_or_else(JoypadButton::empty, |frame| frame[self.player]);
+        self.next += 1;
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_providers_drive_their_ports() {
+        let mut console = Console::new();
+        console.load(&[0x4c, 0x00, 0x80]);
+        let mut movie = Movie::new();
+        movie.push(JoypadButton::empty(), JoypadButton::UP);
+        movie.push(JoypadButton::empty(), JoypadButton::DOWN);
+        console.set_input_provider(1, Some(Box::new(MovieInput::new(movie, 1))));
+        let (peer, inputs) = mpsc::channel();
+        console.set_input_provider(0, Some(Box::new(inputs)));
+
+        let mut seen = Vec::new();
+        for buttons in [JoypadButton::BUTTON_A, JoypadButton::BUTTON_B] {
+            peer.send(buttons).unwrap();
+            console.run_frame();
+            seen.push([0, 1].map(|player| console.joypad(player).buttons()));
+        }
+        assert_eq!(
+            seen,
+            [
+                [JoypadButton::BUTTON_A, JoypadButton::UP],
+                [JoypadButton::BUTTON_B, JoypadButton::DOWN],
+            ]
+        );
+
+        // A hung up peer releases its port; the finished movie does too
+        drop(peer);
+        console.joypad_mut(0).set_buttons(JoypadButton::START);
+        console.run_frame();
+        assert_eq!(console.joypad(0).buttons(), JoypadButton::empty());
+        assert_eq!(console.joypad(1).buttons(), JoypadButton::empty());
+
+        assert!(console.take_input_provider(0).is_some());
+        console.joypad_mut(0).set_buttons(JoypadButton::START);
+        console.run_frame();
+        assert_eq!(console.joypad(0).buttons(), JoypadButton::START);
+    }
+}
@@ -0,0 +1,254 @@
+//! Session journals: what happened during a play session, frame by frame.
+//!
+//! "What exactly did I do before the glitch at frame 84121?" is hard to answer from memory. With
+//! [`Console::enable_journal`] on, the console notes its own resets, power cycles, state loads
+//! and ROM loads in a [`Journal`], and frontends add what only they know about (states saved,
+//! cheats, achievements, the player's own notes) with [`Console::note`]. Every entry is stamped
+//! with the frame it happened on.
+//!
+//! [`Journal::to_text`] writes one entry per line, tab separated, and [`Journal::parse`] reads
+//! that back, so journals can be kept next to saves and attached to bug reports. With the tabs
+//! shown as spaces:
+//!
+//! ```text
+//! 0      rom     loaded 0123456789abcdef
+//! 1830   saved   slot 1
+//! 84090  note    about to take the pipe
+//! 84121  reset
+//! ```
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::journal::EntryKind;
+//!
+//! let mut console = Console::new();
+//! console.enable_journal();
+//! console.load(&[0x4c, 0x00, 0x80]); // loop: JMP loop
+//! console.run_frame();
+//! console.note(EntryKind::Note, "looks fine so far");
+//! console.reset();
+//! let kinds: Vec<_> = console.journal().unwrap().entries().iter().map(|e| e.kind).collect();
+//! assert_eq!(kinds, [EntryKind::Note, EntryKind::Reset]);
+//! ```
+//!
+//! [`Console::enable_journal`]: crate::console::Console::enable_journal
+//! [`Console::note`]: crate::console::Console::note
+
+use std::error::Error;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    /// A soft reset, from the console
+    Reset,
+    /// A power cycle, from the console
+    PowerCycle,
+    /// A ROM loaded or reloaded, from the console
+    RomLoaded,
+    /// A save state loaded, from the console
+    StateLoaded,
+    StateSaved,
+    /// A cheat turned on or off
+    Cheat,
+    /// An achievement or trigger firing
+    Achievement,
+    /// Anything the player wrote down
+    Note,
+}
+
+impl EntryKind {
+    pub fn all() -> [EntryKind; 8] {
+        [
+            EntryKind::Reset,
+            EntryKind::PowerCycle,
+            EntryKind::RomLoaded,
+            EntryKind::StateLoaded,
+            EntryKind::StateSaved,
+            EntryKind::Cheat,
+            EntryKind::Achievement,
+            EntryKind::Note,
+        ]
+    }
+
+    /// Name used in the text format
+    pub fn name(self) -> &'static str {
+        match self {
+            EntryKind::Reset => "reset",
+            EntryKind::PowerCycle => "power",
+            EntryKind::RomLoaded => "rom",
+            EntryKind::StateLoaded => "loaded",
+            EntryKind::StateSaved => "saved",
+            EntryKind::Cheat => "cheat",
+            EntryKind::Achievement => "achievement",
+            EntryKind::Note => "note",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EntryKind> {
+        EntryKind::all()
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Entry {
+    /// The console frame it happened on, see [`Console::frame`](crate::console::Console::frame)
+    pub frame: u64,
+    pub kind: EntryKind,
+    /// Details, one line; may be empty
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalError {
+    /// A line without a frame number and kind, with its 1-based line number
+    BadLine(usize),
+    UnknownKind {
+        line: usize,
+        kind: String,
+    },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::BadLine(line) => write!(f, "line {line}: expected `frame<TAB>kind`"),
+            JournalError::UnknownKind { line, kind } => {
+                write!(f, "line {line}: unknown entry kind `{kind}`")
+            }
+        }
+    }
+}
+
+impl Error for JournalError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry. Line breaks in `text` become spaces, so every entry stays one line.
+    pub fn push(&mut self, frame: u64, kind: EntryKind, text: &str) {
+        self.entries.push(Entry {
+            frame,
+            kind,
+            text: text.replace(['\r', '\n', '\t'], " "),
+        });
+    }
+
+    /// Every entry, in the order they were added
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entries from frames `start..=end`, e.g. the few hundred frames before a glitch
+    pub fn between(&self, start: u64, end: u64) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| (start..=end).contains(&entry.frame))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// One `frame<TAB>kind<TAB>text` line per entry, the text left off when empty
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            write!(text, "{}\t{}", entry.frame, entry.kind.name()).unwrap();
+            if !entry.text.is_empty() {
+                write!(text, "\t{}", entry.text).unwrap();
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Reads a journal written by [`Journal::to_text`]. Blank lines are skipped.
+    pub fn parse(text: &str) -> Result<Self, JournalError> {
+        let mut journal = Journal::new();
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '\t');
+            let frame = fields
+                .next()
+                .and_then(|frame| frame.parse().ok())
+                .ok_or(JournalError::BadLine(number))?;
+            let kind = fields.next().ok_or(JournalError::BadLine(number))?;
+            let kind = EntryKind::from_name(kind).ok_or_else(|| JournalError::UnknownKind {
+                line: number,
+                kind: kind.to_string(),
+            })?;
+            journal.push(frame, kind, fields.next().unwrap_or(""));
+        }
+        Ok(journal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+
+    #[test]
+    fn test_console_entries_round_trip() {
+        let mut console = Console::new();
+        console.load(&[0x4c, 0x00, 0x80]);
+        console.enable_journal();
+        let state = console.save_state();
+        console.note(EntryKind::StateSaved, "slot 1");
+        console.run_frame();
+        console.run_frame();
+        console.note(EntryKind::Note, "two\nlines");
+        console.load_state(&state).unwrap();
+        console.power_cycle();
+
+        let journal = console.journal().unwrap();
+        let entries: Vec<_> = journal
+            .entries()
+            .iter()
+            .map(|entry| (entry.frame, entry.kind))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (0, EntryKind::StateSaved),
+                (2, EntryKind::Note),
+                (0, EntryKind::StateLoaded),
+                (0, EntryKind::PowerCycle),
+            ]
+        );
+        assert_eq!(journal.entries()[1].text, "two lines");
+        assert_eq!(journal.between(1, 2).count(), 1);
+
+        let text = journal.to_text();
+        assert!(text.starts_with("0\tsaved\tslot 1\n2\tnote\ttwo lines\n"));
+        assert_eq!(Journal::parse(&text).unwrap(), *journal);
+        assert_eq!(
+            Journal::parse("12\tglitch").unwrap_err().to_string(),
+            "line 1: unknown entry kind `glitch`"
+        );
+        assert_eq!(
+            Journal::parse("\nnote").unwrap_err(),
+            JournalError::BadLine(2)
+        );
+    }
+}
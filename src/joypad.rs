@@ -0,0 +1,203 @@
+//! Standard NES controller.
+//!
+//! The controller is read one button at a time through `0x4016` (player 1) and `0x4017` (player 2).
+//! Writing `1` to `0x4016` turns on strobe mode, which keeps reloading the button state; writing `0`
+//! latches it so subsequent reads shift out the buttons in order: A, B, Select, Start, Up, Down,
+//! Left, Right.
+
+use std::hash::{Hash, Hasher};
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+/// Lowercase names for each button, as used in config files and the remote control protocol
+pub const BUTTON_NAMES: [(&str, JoypadButton); 8] = [
+    ("a", JoypadButton::BUTTON_A),
+    ("b", JoypadButton::BUTTON_B),
+    ("select", JoypadButton::SELECT),
+    ("start", JoypadButton::START),
+    ("up", JoypadButton::UP),
+    ("down", JoypadButton::DOWN),
+    ("left", JoypadButton::LEFT),
+    ("right", JoypadButton::RIGHT),
+];
+
+/// A set of pressed buttons, one bit per button in the order the controller reports them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JoypadButton(u8);
+
+impl JoypadButton {
+    pub const BUTTON_A: Self = Self(0b0000_0001);
+    pub const BUTTON_B: Self = Self(0b0000_0010);
+    pub const SELECT: Self = Self(0b0000_0100);
+    pub const START: Self = Self(0b0000_1000);
+    pub const UP: Self = Self(0b0001_0000);
+    pub const DOWN: Self = Self(0b0010_0000);
+    pub const LEFT: Self = Self(0b0100_0000);
+    pub const RIGHT: Self = Self(0b1000_0000);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The button called `name` in [`BUTTON_NAMES`], ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        BUTTON_NAMES
+            .iter()
+            .find(|(button, _)| button.eq_ignore_ascii_case(name))
+            .map(|&(_, button)| button)
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for JoypadButton {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for JoypadButton {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: JoypadButton,
+    /// Buttons when strobe was last released, for input displays. Not emulated state.
+    latched: Option<JoypadButton>,
+}
+
+impl Hash for Joypad {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.strobe.hash(state);
+        self.button_index.hash(state);
+        self.button_status.hash(state);
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buttons currently held on the controller
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+
+    /// Handles a CPU write to `0x4016`
+    pub fn write(&mut self, data: u8) {
+        let was_strobing = self.strobe;
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        } else if was_strobing {
+            self.latched = Some(self.button_status);
+        }
+    }
+
+    /// The buttons the game latched the last time it released strobe, if it has since the last
+    /// [`Joypad::take_latched`]
+    pub fn latched(&self) -> Option<JoypadButton> {
+        self.latched
+    }
+
+    /// The buttons the game latched the last time it released strobe, clearing the record
+    pub fn take_latched(&mut self) -> Option<JoypadButton> {
+        self.latched.take()
+    }
+
+    /// Handles a CPU read of this controller's port, shifting out the next button
+    pub fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1; // official controllers report 1 once all buttons are shifted out
+        }
+        let response = (self.button_status.bits() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.strobe);
+        w.write_u8(self.button_index);
+        w.write_u8(self.button_status.bits());
+    }
+
+    pub fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        self.strobe = r.read_bool()?;
+        self.button_index = r.read_u8()?;
+        self.button_status = JoypadButton::from_bits(r.read_u8()?);
+        Ok(())
+    }
+
+    /// Returns what the next [`Joypad::read`] would without advancing the shift register
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.button_status.bits() >> self.button_index) & 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strobe_mode_repeats_button_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::BUTTON_A);
+        joypad.write(1);
+
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1); // strobe keeps reporting button A
+        }
+    }
+
+    #[test]
+    fn test_buttons_shift_out_in_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::RIGHT | JoypadButton::SELECT | JoypadButton::BUTTON_A);
+        joypad.write(1);
+        joypad.write(0);
+
+        let reads: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(reads, vec![1, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(joypad.read(), 1); // past the last button
+    }
+}
@@ -0,0 +1,153 @@
+//! Measuring input latency in frames.
+//!
+//! A [`LatencyProbe`] notes when the host pressed a button, then watches the frames that follow
+//! for two things: the first poll that latched the button, and the first picture that differs
+//! from the one on screen at the press. Together they give the emulated part of end-to-end
+//! latency, as a speedrunner's lag test would, minus the host's display.
+//!
+//! Any change on screen counts as the reaction, so measure on a still screen, such as a menu where
+//! the button moves a cursor.
+
+use crate::console::Console;
+use crate::hash::Fnv1a;
+use crate::joypad::JoypadButton;
+
+/// Frames to wait for a reaction before giving up on a press
+pub const TIMEOUT_FRAMES: u64 = 30;
+
+/// One measurement, counted in frames from the press
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Latency {
+    /// Until the game latched the button; 1 means the very next frame polled it
+    pub polled: u64,
+    /// Until the picture changed
+    pub shown: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Press {
+    frame: u64,
+    player: usize,
+    buttons: JoypadButton,
+    /// The picture on screen when the button went down
+    picture: u64,
+    polled: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LatencyProbe {
+    press: Option<Press>,
+}
+
+impl LatencyProbe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a measurement: `buttons` went down on `player`'s controller before the next frame.
+    /// Ignored while a measurement is already running.
+    pub fn press(&mut self, console: &Console, player: usize, buttons: JoypadButton) {
+        if self.press.is_none() {
+            self.press = Some(Press {
+                frame: console.frame(),
+                player,
+                buttons,
+                picture: picture_hash(console),
+                polled: None,
+            });
+        }
+    }
+
+    pub fn is_measuring(&self) -> bool {
+        self.press.is_some()
+    }
+
+    /// Checks the frame just run, returning the measurement once the picture reacts. Gives up
+    /// after [`TIMEOUT_FRAMES`], or if a state load or rewind took the console back before the
+    /// press.
+    pub fn end_frame(&mut self, console: &Console) -> Option<Latency> {
+        let press = self.press.as_mut()?;
+        let Some(frames) = console.frame().checked_sub(press.frame) else {
+            self.press = None;
+            return None;
+        };
+        let latched = console.latched_input(press.player);
+        if press.polled.is_none() && latched.is_some_and(|held| held.contains(press.buttons)) {
+            press.polled = Some(frames);
+        }
+        if let Some(polled) = press.polled {
+            if picture_hash(console) != press.picture {
+                self.press = None;
+                return Some(Latency {
+                    polled,
+                    shown: frames,
+                });
+            }
+        }
+        if frames >= TIMEOUT_FRAMES {
+            self.press = None;
+        }
+        None
+    }
+}
+
+fn picture_hash(console: &Console) -> u64 {
+    Fnv1a::hash_of(&console.frame_ref().indices().pixels[..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_measures_poll_and_picture() {
+        let mut program = vec![0; 0x8000];
+        // Each frame's NMI polls, and once A has been seen turns the backdrop red the frame after
+        #[rustfmt::skip]
+        program[..0x2b].copy_from_slice(&[
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80; STA $2000, enabling NMI
+            0x4c, 0x05, 0x80,             // JMP $8005
+            0xa5, 0x10,                   // NMI: LDA $10
+            0xf0, 0x0f,                   // BEQ poll
+            0xa9, 0x3f, 0x8d, 0x06, 0x20, // PPUADDR = $3F00
+            0xa9, 0x00, 0x8d, 0x06, 0x20,
+            0xa9, 0x16, 0x8d, 0x07, 0x20, // PPUDATA = red
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // poll: LDA #1; STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #0; STA $4016
+            0xad, 0x16, 0x40,             // LDA $4016
+            0x85, 0x10,                   // STA $10
+            0x40,                         // RTI
+        ]);
+        program[0x7FFA..0x7FFC].copy_from_slice(&[0x08, 0x80]);
+
+        let mut console = Console::new();
+        console.load(&program);
+        for _ in 0..3 {
+            console.run_frame();
+        }
+        let mut probe = LatencyProbe::new();
+        probe.press(&console, 0, JoypadButton::BUTTON_A);
+        console.joypad_mut(0).set_buttons(JoypadButton::BUTTON_A);
+        let mut latency = None;
+        for _ in 0..5 {
+            console.run_frame();
+            latency = latency.or(probe.end_frame(&console));
+        }
+        assert_eq!(
+            latency,
+            Some(Latency {
+                polled: 1,
+                shown: 2
+            })
+        );
+        assert!(!probe.is_measuring());
+
+        // Going back before the press drops the measurement
+        let before = console.save_state();
+        console.run_frame();
+        probe.press(&console, 0, JoypadButton::BUTTON_A);
+        console.load_state(&before).unwrap();
+        assert_eq!(probe.end_frame(&console), None);
+        assert!(!probe.is_measuring());
+    }
+}
@@ -1 +1,96 @@
+//! An NES emulator as a library, with a small headless frontend in the binary.
+//!
+//! The default build is only the emulation core and the std-only tooling around it; nothing
+//! outside the standard library is compiled in. Everything that pulls in a dependency or serves
+//! outside programs is a Cargo feature:
+//!
+//! | Feature | Adds | Dependencies |
+//! | :--- | :--- | :--- |
+//! | `zip` | zip archives in [`rom::Rom::from_path`], crash dumps as one zip | `zip` |
+//! | `sevenz` | 7z archives in [`rom::Rom::from_path`] | `sevenz-rust` |
+//! | `zstd` | compressed save states and rewind history | `zstd` |
+//! | `midi` | `notes::NoteTracker::to_midi` | |
+//! | `remote` | the `remote` TCP control server | `serde_json` |
+//! | `tui-video` | `tui_video`, frames drawn in a terminal, and the frontend's `--tui` | |
+//! | `full` | all of the above | |
+//!
+//! There is no mutable global state: every [`console::Console`] owns its whole machine, so a
+//! process can run any number of them side by side, on one thread or several.
+//!
+//! The modules fall into three groups:
+//!
+//! - the machine: [`console`] (start here), [`cpu`], [`opcodes`], [`bus`], [`ppu`], [`apu`],
+//!   [`joypad`], [`rom`], [`mapper`], [`romdb`], [`rng`], [`savestate`], [`frame`],
+//!   [`video_timing`] and [`blend`]
+//! - embedding it: [`builder`], [`shared`], [`watchdog`], [`sram`], [`metrics`], [`crash`],
+//!   [`rewind`], [`input`], [`osd`], [`crosshair`], [`journal`], [`feedback`], [`accessibility`],
+//!   [`triggers`], [`livesplit`], [`practice`], [`movie`], [`patch`], [`game_genie`], [`png`],
+//!   [`thumbnail`], [`time_stretch`], [`frame_diff`] and [`hash`]
+//! - debugging and research tools: [`diagnostics`], [`debug_info`], [`ram_map`], [`ram_search`],
+//!   [`ram_watch`], [`latency`], [`bcd`], [`tas`], [`tilemap`], [`timing_overlay`], [`ripper`],
+//!   [`chr_banks`], [`notes`], [`apu_log`], [`vgm`], [`capture`], [`timeline`], [`test_rom`],
+//!   [`self_test`] and [`compare`]
+
+pub mod accessibility;
+pub mod apu;
+pub mod apu_log;
+pub mod bcd;
+pub mod blend;
+pub mod builder;
+pub mod bus;
+pub mod capture;
+pub mod chr_banks;
+pub mod compare;
+pub mod console;
 pub mod cpu;
+pub mod crash;
+pub mod crosshair;
+pub mod debug_info;
+pub mod diagnostics;
+pub mod feedback;
+pub mod frame;
+pub mod frame_diff;
+pub mod game_genie;
+pub mod hash;
+pub mod input;
+pub mod journal;
+pub mod joypad;
+pub mod latency;
+pub mod livesplit;
+pub mod mapper;
+pub mod metrics;
+pub mod movie;
+pub mod notes;
+pub mod opcodes;
+pub mod osd;
+pub mod patch;
+pub mod png;
+pub mod ppu;
+pub mod practice;
+pub mod ram_map;
+pub mod ram_search;
+pub mod ram_watch;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod rewind;
+pub mod ripper;
+pub mod rng;
+pub mod rom;
+pub mod romdb;
+pub mod savestate;
+pub mod self_test;
+pub mod shared;
+pub mod sram;
+pub mod tas;
+pub mod test_rom;
+pub mod thumbnail;
+pub mod tilemap;
+pub mod time_stretch;
+pub mod timeline;
+pub mod timing_overlay;
+pub mod triggers;
+#[cfg(feature = "tui-video")]
+pub mod tui_video;
+pub mod vgm;
+pub mod video_timing;
+pub mod watchdog;
@@ -0,0 +1,206 @@
+//! Auto-splitting for LiveSplit, driven by [RAM triggers](crate::triggers).
+//!
+//! Speedrunners normally run a separate memory reading tool next to the emulator to split their
+//! timer. An [`AutoSplitter`] does it from inside instead: it watches a trigger file and turns the
+//! triggers that fire into [`Signal`]s, which a [`LiveSplit`] connection sends to LiveSplit's TCP
+//! server (Control > Start TCP Server, port 16834 by default).
+//!
+//! A trigger named `start` starts the timer and one named `reset` resets it. Every other trigger
+//! is a split, so a file for a two level game could be:
+//!
+//! ```text
+//! start: when $0770 == 1 and $0772 == 3
+//! level 1: when $0760 == 1 for 2 frames
+//! level 2: when $0760 == 2 for 2 frames
+//! reset: when $0770 == 0
+//! ```
+//!
+//! Splits only count while the timer runs, so a level trigger firing on the title screen's demo
+//! doesn't start a run. Without a `start` trigger the first split starts it, as it does when
+//! splitting by hand.
+//!
+//! ```
+//! use nes_emulator::bus::Bus;
+//! use nes_emulator::cpu::Mem;
+//! use nes_emulator::livesplit::{AutoSplitter, LiveSplit, Signal};
+//! use nes_emulator::triggers::TriggerSet;
+//!
+//! let triggers = TriggerSet::parse("start: when $10 == 1\nsplit: when $11 == 1")?;
+//! let mut splitter = AutoSplitter::new(triggers);
+//! let mut ram = Bus::new();
+//! ram.mem_write(0x10, 1);
+//! let mut livesplit = LiveSplit::new(Vec::new());
+//! for signal in splitter.check(&ram) {
+//!     livesplit.send(signal)?;
+//! }
+//! assert_eq!(livesplit.get_ref(), b"starttimer\r\n");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::cpu::Mem;
+use crate::triggers::TriggerSet;
+
+/// LiveSplit's default server port
+pub const DEFAULT_PORT: u16 = 16834;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    Start,
+    Split,
+    Reset,
+}
+
+impl Signal {
+    /// The LiveSplit server command for the signal
+    pub fn command(self) -> &'static str {
+        match self {
+            Signal::Start => "starttimer",
+            Signal::Split => "split",
+            Signal::Reset => "reset",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoSplitter {
+    triggers: TriggerSet,
+    /// Whether any trigger is named `start`
+    has_start: bool,
+    running: bool,
+    /// Splits since the timer started
+    splits: u32,
+}
+
+impl AutoSplitter {
+    pub fn new(triggers: TriggerSet) -> Self {
+        let has_start = triggers.triggers().any(|trigger| trigger.name == "start");
+        Self {
+            triggers,
+            has_start,
+            running: false,
+            splits: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn splits(&self) -> u32 {
+        self.splits
+    }
+
+    /// Checks the triggers against the frame just run, see [`TriggerSet::check`], and returns
+    /// the signals to send, in the order the triggers are listed
+    pub fn check(&mut self, mem: &impl Mem) -> Vec<Signal> {
+        let mut signals = Vec::new();
+        for trigger in self.triggers.check(mem) {
+            let signal = match trigger.name.as_str() {
+                "start" if !self.running => Signal::Start,
+                "reset" if self.running => Signal::Reset,
+                "start" | "reset" => continue,
+                _ if self.running || !self.has_start => Signal::Split,
+                _ => continue,
+            };
+            match signal {
+                Signal::Start => self.running = true,
+                Signal::Split => {
+                    self.running = true;
+                    self.splits += 1;
+                }
+                Signal::Reset => {
+                    self.running = false;
+                    self.splits = 0;
+                }
+            }
+            signals.push(signal);
+        }
+        signals
+    }
+
+    /// Forgets trigger progress, e.g. after loading a state. The timer keeps its state.
+    pub fn reset_triggers(&mut self) {
+        self.triggers.reset();
+    }
+}
+
+/// A connection to a LiveSplit server
+#[derive(Debug)]
+pub struct LiveSplit<W: Write = TcpStream> {
+    writer: W,
+}
+
+impl LiveSplit {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<W: Write> LiveSplit<W> {
+    /// Sends commands to `writer`, for testing or relaying
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn send(&mut self, signal: Signal) -> io::Result<()> {
+        write!(self.writer, "{}\r\n", signal.command())?;
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_signals_follow_the_timer() {
+        let triggers = TriggerSet::parse(
+            "start: when $10 == 1
+             level: when $11 increases
+             reset: when $10 == 0",
+        )
+        .unwrap();
+        let mut splitter = AutoSplitter::new(triggers);
+        let mut ram = Bus::new();
+        let mut signals = Vec::new();
+        // A level change before the start, two during the run, then one after the reset
+        for (started, level) in [
+            (0, 0),
+            (0, 1),
+            (1, 1),
+            (1, 2),
+            (1, 2),
+            (1, 3),
+            (0, 3),
+            (0, 4),
+        ] {
+            ram.mem_write(0x10, started);
+            ram.mem_write(0x11, level);
+            signals.extend(splitter.check(&ram));
+        }
+        assert_eq!(
+            signals,
+            [Signal::Start, Signal::Split, Signal::Split, Signal::Reset]
+        );
+        assert!(!splitter.is_running());
+
+        let mut livesplit = LiveSplit::new(Vec::new());
+        for signal in signals {
+            livesplit.send(signal).unwrap();
+        }
+        assert_eq!(
+            livesplit.get_ref(),
+            b"starttimer\r\nsplit\r\nsplit\r\nreset\r\n"
+        );
+    }
+}
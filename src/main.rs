@@ -1,3 +1,501 @@
-fn main() {
-    println!("Hello, world!");
+// Key events only arrive once there is a renderer to deliver them, so parts of the frontend are
+// unused by the headless loop for now
+#[allow(dead_code)]
+mod frontend;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use frontend::bindings::Bindings;
+use frontend::browser::{self, Recent};
+use frontend::config::{parse_bool, Config};
+use frontend::game::GameSettings;
+use frontend::macros::Macros;
+use frontend::session::Session;
+use frontend::watch::{self, RomWatcher};
+use nes_emulator::accessibility::ColorVision;
+use nes_emulator::builder::{BuildProblem, ConsoleBuilder};
+use nes_emulator::console::Console;
+use nes_emulator::crash::{self, CrashBundle};
+use nes_emulator::debug_info::DebugInfo;
+use nes_emulator::livesplit::{self, AutoSplitter, LiveSplit};
+use nes_emulator::metrics::{self, Metrics};
+use nes_emulator::patch;
+use nes_emulator::rng::RamInit;
+use nes_emulator::rom::{self, Rom};
+use nes_emulator::self_test;
+use nes_emulator::sram::{Autosave, FlushPolicy};
+use nes_emulator::triggers::TriggerSet;
+
+const USAGE: &str =
+    "usage: nes_emulator [--config FILE] [--frames N] [--seed N] [--remote ADDR] [--metrics ADDR] [--watch] [--tui] [--self-test] [ROM]";
+
+#[derive(Debug, Default)]
+struct Args {
+    rom: Option<PathBuf>,
+    config: Option<PathBuf>,
+    /// Stop after this many frames instead of running until the program halts
+    frames: Option<u64>,
+    /// Seed for the console's randomness, to reproduce an earlier run
+    seed: Option<u64>,
+    /// Hand the console to a remote control server listening here instead of running it
+    remote: Option<String>,
+    /// Serve Prometheus metrics over HTTP here while running
+    metrics: Option<String>,
+    /// Reload the ROM whenever the file changes
+    watch: bool,
+    /// Draw each frame in the terminal
+    tui: bool,
+    /// Run the built-in test suite instead of a game
+    self_test: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    parsed.config = Some(args.next().ok_or(USAGE)?.into());
+                }
+                "--frames" => {
+                    let frames = args.next().ok_or(USAGE)?;
+                    parsed.frames = Some(
+                        frames
+                            .parse()
+                            .map_err(|_| format!("--frames: `{frames}` is not a number"))?,
+                    );
+                }
+                "--seed" => {
+                    let seed = args.next().ok_or(USAGE)?;
+                    parsed.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("--seed: `{seed}` is not a number"))?,
+                    );
+                }
+                "--remote" => {
+                    parsed.remote = Some(args.next().ok_or(USAGE)?);
+                }
+                "--metrics" => {
+                    parsed.metrics = Some(args.next().ok_or(USAGE)?);
+                }
+                "--watch" => parsed.watch = true,
+                "--tui" => parsed.tui = true,
+                "--self-test" => parsed.self_test = true,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}\n{USAGE}")),
+                _ if parsed.rom.is_none() => parsed.rom = Some(arg.into()),
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse(env::args().skip(1))?;
+    if args.self_test {
+        return run_self_test();
+    }
+
+    let config_dir = match &args.config {
+        Some(path) => path.parent().map(PathBuf::from),
+        None => Config::default_dir(),
+    };
+    let config_path = match &args.config {
+        Some(path) => Some(path.clone()),
+        None => config_dir.as_ref().map(|dir| dir.join("config.ini")),
+    };
+    let config = match (&args.config, &config_dir) {
+        (Some(path), _) => Config::load(path)?,
+        (None, Some(dir)) => Config::load(&dir.join("config.ini"))?,
+        (None, None) => Config::default(),
+    };
+    let bindings = Bindings::from_config(&config)?;
+    for conflict in bindings.conflicts() {
+        eprintln!("warning: {conflict}");
+    }
+    let mut recent = match &config_dir {
+        Some(dir) => Recent::load(dir.join("recent.txt"))?,
+        None => Recent::default(),
+    };
+
+    let rom_path = match args.rom {
+        Some(path) => path,
+        None => {
+            let roms = match config.rom_dir() {
+                Some(dir) => {
+                    browser::scan(&dir).map_err(|err| format!("{}: {err}", dir.display()))?
+                }
+                None => Vec::new(),
+            };
+            match browser::choose(recent.games(), &roms, io::stdin().lock(), io::stdout())? {
+                Some(path) => path,
+                None => return Ok(()),
+            }
+        }
+    };
+
+    let rom = read_rom(&rom_path)?;
+    let developer_warnings = config
+        .get("frontend", "developer_warnings")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    let random_ram = config
+        .get("frontend", "random_ram")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    // Random RAM without a seed picks a fresh one each run, printed so the run can be repeated
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None if random_ram => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            let seed = now.map_or(0, |time| time.as_nanos() as u64);
+            eprintln!("Random seed {seed} (rerun with --seed {seed} to repeat this run)");
+            seed
+        }
+        None => 0,
+    };
+
+    let data_dir = config_dir.unwrap_or_default();
+    let game = GameSettings::from_config(&config, rom.hash())?;
+    for key in &game.unsupported {
+        eprintln!(
+            "warning: [{}] {key} is not supported yet",
+            GameSettings::section(rom.hash())
+        );
+    }
+    let rom_hash = rom.hash();
+    let battery = rom.battery;
+    let mut builder = ConsoleBuilder::new()
+        .rom(rom)
+        .seed(seed)
+        .diagnostics(developer_warnings);
+    if random_ram {
+        builder = builder.ram_init(RamInit::Random);
+    }
+    if let Some(path) = &game.palette {
+        builder = builder.palette_file(data_dir.join(path));
+    }
+    if let Some(region) = game.region {
+        builder = builder.region(region);
+    }
+    if let Some(path) = config.get("frontend", "game_genie") {
+        builder = builder.game_genie_file(data_dir.join(path));
+    }
+    let mut console = builder.build().map_err(|err| {
+        let problems: Vec<String> = err
+            .problems
+            .iter()
+            .map(|problem| match problem {
+                BuildProblem::Rom { path: None, error } => {
+                    format!("{}: {error}", rom_path.display())
+                }
+                problem => problem.to_string(),
+            })
+            .collect();
+        problems.join("\n")
+    })?;
+    if let Some(genie) = game.game_genie() {
+        console.set_game_genie(Some(genie));
+    }
+    recent.add(&rom_path);
+    recent.save()?;
+    eprintln!("Loaded {} (hash {rom_hash:016x})", rom_path.display());
+    if let Some(mismatch) = console.take_region_mismatch() {
+        eprintln!(
+            "warning: {mismatch} (set by [{}] region)",
+            GameSettings::section(rom_hash)
+        );
+    }
+
+    let name = rom_path
+        .file_stem()
+        .map_or("game".into(), |stem| stem.to_string_lossy());
+    let mut session = Session::new(
+        console,
+        bindings,
+        &name,
+        data_dir.join("states"),
+        data_dir.join("screenshots"),
+    );
+    session.set_music_dir(data_dir.join("music"));
+    let show_hud = config
+        .get("frontend", "show_hud")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    session.set_hud_enabled(game.show_hud.unwrap_or(show_hud));
+    session.set_rumble(game.rumble.clone());
+    session.set_macros(Macros::from_config(&config)?);
+    if let Some(path) = &game.triggers {
+        let path = data_dir.join(path);
+        let triggers = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| TriggerSet::parse(&text).map_err(|err| err.to_string()))
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        session.set_triggers(triggers);
+    }
+    if let Some(path) = &game.autosplit {
+        let path = data_dir.join(path);
+        let triggers = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| TriggerSet::parse(&text).map_err(|err| err.to_string()))
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        let addr = config.get("frontend", "livesplit").map_or_else(
+            || format!("localhost:{}", livesplit::DEFAULT_PORT),
+            str::to_string,
+        );
+        // Splitting is optional, so a LiveSplit that isn't running only gets a warning
+        match LiveSplit::connect(&addr) {
+            Ok(livesplit) => session.set_autosplit(AutoSplitter::new(triggers), livesplit),
+            Err(err) => eprintln!("Not auto-splitting, can't reach LiveSplit at {addr}: {err}"),
+        }
+    }
+    if let Some(value) = config.get("frontend", "input_lead") {
+        let scanlines = value.parse().map_err(|_| {
+            format!("[frontend] input_lead: `{value}` is not a number of scanlines")
+        })?;
+        session.set_input_lead(Some(scanlines));
+    }
+    let measure_latency = config
+        .get("frontend", "measure_latency")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    session.set_latency_probe(measure_latency);
+    if let Some(value) = config.get("frontend", "color_vision") {
+        let vision = ColorVision::parse(value).ok_or_else(|| {
+            format!("[frontend] color_vision: `{value}` is not normal, protanopia, deuteranopia or tritanopia")
+        })?;
+        session.set_color_vision(vision);
+    }
+    let reduce_flashing = config
+        .get("frontend", "reduce_flashing")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    session.set_flash_filter_enabled(reduce_flashing);
+    if let Some(value) = config.get("frontend", "frame_blend") {
+        let persistence = value
+            .parse()
+            .ok()
+            .filter(|persistence| (0.0..=1.0).contains(persistence))
+            .ok_or_else(|| {
+                format!("[frontend] frame_blend: `{value}` is not a number from 0 to 1")
+            })?;
+        session.set_frame_blend(Some(persistence));
+    }
+    let time_stretch = config
+        .get("frontend", "time_stretch")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    session.set_time_stretch(time_stretch);
+    let debugger = config
+        .get("frontend", "debugger")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    session.set_debugger_enabled(debugger);
+    // ld65's debug file, if the game was built with one next to it
+    let dbg_path = rom_path.with_extension("dbg");
+    if let Ok(text) = fs::read_to_string(&dbg_path) {
+        match DebugInfo::parse(&text) {
+            Ok(info) => session.set_debug_info(Some(info)),
+            Err(err) => eprintln!("warning: {}: {err}", dbg_path.display()),
+        }
+    }
+    if battery {
+        let policy = match config.get("frontend", "sram_flush") {
+            Some(value) => FlushPolicy::parse(value).ok_or_else(|| {
+                format!("[frontend] sram_flush: `{value}` is not immediate, idle or exit")
+            })?,
+            None => FlushPolicy::default(),
+        };
+        let path = data_dir.join("saves").join(format!("{name}.sav"));
+        session
+            .set_autosave(Autosave::new(&path, policy))
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    if let Some(addr) = &args.metrics {
+        let listener = TcpListener::bind(addr).map_err(|err| format!("--metrics {addr}: {err}"))?;
+        eprintln!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        let metrics = Metrics::new();
+        session.set_metrics(Some(metrics.clone()));
+        thread::spawn(move || metrics::serve_http(&metrics, &listener));
+    }
+    if let Some(addr) = &args.remote {
+        return serve_remote(&mut session.console, addr);
+    }
+    let crash_dumps = config
+        .get("frontend", "crash_dumps")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    if crash_dumps {
+        session
+            .console
+            .enable_instruction_history(crash::DEFAULT_HISTORY_LEN);
+    }
+    let journal = config
+        .get("frontend", "journal")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    if journal {
+        session.console.enable_journal();
+    }
+    let mut watcher = args
+        .watch
+        .then(|| RomWatcher::new(&rom_path, watch::POLL_INTERVAL));
+    let reload_keeps_state = config
+        .get("frontend", "reload_keeps_state")
+        .and_then(parse_bool)
+        .unwrap_or(false);
+    let mut tui = args.tui.then(terminal_video).transpose()?;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        while !session.console.is_halted()
+            && args.frames.is_none_or(|n| session.console.frame() < n)
+        {
+            if let Some(watcher) = &mut watcher {
+                if watcher.poll(Instant::now()) {
+                    let path = watcher.path();
+                    let reloaded = read_rom(path).and_then(|rom| {
+                        session
+                            .reload(&rom, reload_keeps_state)
+                            .map_err(|err| format!("{}: {err}", path.display()).into())
+                    });
+                    match reloaded {
+                        Ok(()) => eprintln!("Reloaded {}", path.display()),
+                        Err(err) => eprintln!("warning: {err}"),
+                    }
+                }
+            }
+            session.tick();
+            if let Some(draw) = &mut tui {
+                draw(&session.console);
+            }
+            for diagnostic in session.console.take_diagnostics() {
+                eprintln!("warning: {diagnostic}");
+            }
+        }
+    }));
+    if let Err(err) = session.flush_autosave() {
+        eprintln!("warning: couldn't write battery save: {err}");
+    }
+    if let Some(journal) = session.console.journal() {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let dir = data_dir.join("journals");
+        let path = dir.join(format!("{name}-{started}.txt"));
+        match fs::create_dir_all(&dir).and_then(|()| fs::write(&path, journal.to_text())) {
+            Ok(()) => eprintln!("Wrote session journal {}", path.display()),
+            Err(err) => eprintln!("warning: couldn't write session journal: {err}"),
+        }
+    }
+    let crash_reason = match &run {
+        Err(_) => Some("emulator panicked"),
+        Ok(()) if session.console.is_halted() => Some("CPU halted"),
+        Ok(()) => None,
+    };
+    if let (true, Some(reason)) = (crash_dumps, crash_reason) {
+        let mut bundle = CrashBundle::capture(&session.console, reason);
+        if let Some(text) = config_path.and_then(|path| fs::read_to_string(path).ok()) {
+            bundle = bundle.with_config(&text);
+        }
+        match bundle.write_to(&data_dir.join("crashes")) {
+            Ok(path) => eprintln!("Wrote crash dump {}", path.display()),
+            Err(err) => eprintln!("warning: couldn't write crash dump: {err}"),
+        }
+    }
+    if let Err(panic) = run {
+        panic::resume_unwind(panic);
+    }
+    println!("Ran {} frames", session.console.frame());
+    Ok(())
+}
+
+/// Prints the built-in suite's results, failing if any test did
+fn run_self_test() -> Result<(), Box<dyn Error>> {
+    let results = self_test::run_all();
+    print!("{}", self_test::matrix(&results));
+    let failed = results
+        .iter()
+        .filter(|(_, report)| !report.passed())
+        .count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} self-tests failed", results.len()).into());
+    }
+    Ok(())
+}
+
+/// Reads the ROM at `path`, applying a soft patch next to it if there is one
+fn read_rom(path: &Path) -> Result<Rom, Box<dyn Error>> {
+    let mut data =
+        rom::read_file(path, None).map_err(|err| format!("{}: {err}", path.display()))?;
+    if let Some(patch_path) = patch::soft_patch_path(path) {
+        let patched = fs::read(&patch_path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|p| Ok(patch::apply(&data, &p)?))
+            .map_err(|err| format!("{}: {err}", patch_path.display()))?;
+        data = patched;
+        eprintln!("Applied {}", patch_path.display());
+    }
+    Ok(Rom::new(&data).map_err(|err| format!("{}: {err}", path.display()))?)
+}
+
+#[cfg(feature = "remote")]
+fn serve_remote(console: &mut Console, addr: &str) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).map_err(|err| format!("--remote {addr}: {err}"))?;
+    eprintln!("Listening for remote control on {}", listener.local_addr()?);
+    nes_emulator::remote::serve(console, &listener)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "remote"))]
+fn serve_remote(_console: &mut Console, _addr: &str) -> Result<(), Box<dyn Error>> {
+    Err("--remote: built without the `remote` feature".into())
+}
+
+/// Clears the terminal and returns a function drawing the console's last frame over it, in
+/// 24-bit color if `COLORTERM` says the terminal has it
+#[cfg(feature = "tui-video")]
+fn terminal_video() -> Result<impl FnMut(&Console), Box<dyn Error>> {
+    use std::io::Write;
+
+    use nes_emulator::tui_video::{self, ColorMode};
+
+    let mode = match env::var("COLORTERM").as_deref() {
+        Ok("truecolor" | "24bit") => ColorMode::TrueColor,
+        _ => ColorMode::Ansi256,
+    };
+    print!("\x1b[2J");
+    Ok(move |console: &Console| {
+        let frame = console.frame_ref();
+        let text = tui_video::render(frame.indices(), console.palette(), tui_video::COLUMNS, mode);
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "{}{text}", tui_video::HOME);
+        let _ = stdout.flush();
+    })
+}
+
+#[cfg(not(feature = "tui-video"))]
+fn terminal_video() -> Result<fn(&Console), Box<dyn Error>> {
+    Err("--tui: built without the `tui-video` feature".into())
 }
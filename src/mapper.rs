@@ -0,0 +1,666 @@
+//! Cartridge boards supplied from outside the crate.
+//!
+//! The console runs NROM boards on its own. Any other board, from the common ones to homebrew
+//! and bootleg oddities, can be added without touching the emulator by implementing [`Mapper`]
+//! and registering it in the console's [`MapperRegistry`] by iNES mapper number, or by UNIF board
+//! name, before the ROM is loaded. Registered mappers take precedence over the built-in ones.
+//!
+//! Memory is one flat space, so a mapper switches banks by copying them in: the [`Cartridge`] it
+//! is handed copies PRG ROM banks into `0x8000..=0xFFFF` and CHR ROM banks into the pattern
+//! tables. The mapper sees every CPU write to `0x8000..=0xFFFF`, which no longer reach memory.
+//!
+//! The expansion area at `0x4020..=0x5FFF` belongs to the board as well, where the MMC5, the FDS
+//! and many unlicensed boards put registers and extra RAM. Boards that leave it alone needn't do
+//! anything: reads of it float, returning the high byte of the address as the last byte left on
+//! the data bus, and writes are dropped.
+//!
+//! Boards with interrupts, such as scanline or cycle counters, count in [`Mapper::tick`] and hold
+//! the CPU's IRQ line through [`Mapper::irq_pending`].
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::mapper::{Cartridge, Mapper};
+//! use nes_emulator::rom::{Format, Mirroring, Rom};
+//! use nes_emulator::savestate::{ChunkReader, ChunkWriter, StateError};
+//!
+//! /// UxROM: a switchable 16KB bank at 0x8000, the last bank fixed at 0xC000
+//! #[derive(Clone, Default)]
+//! struct UxRom {
+//!     bank: u8,
+//! }
+//!
+//! impl Mapper for UxRom {
+//!     fn power_on(&mut self, cart: &mut Cartridge<'_>) {
+//!         self.bank = 0;
+//!         self.map(cart);
+//!     }
+//!
+//!     fn write(&mut self, cart: &mut Cartridge<'_>, _addr: u16, value: u8) {
+//!         self.bank = value;
+//!         self.map(cart);
+//!     }
+//!
+//!     fn save_state(&self, w: &mut ChunkWriter) {
+//!         w.write_u8(self.bank);
+//!     }
+//!
+//!     fn load_state(
+//!         &mut self,
+//!         cart: &mut Cartridge<'_>,
+//!         r: &mut ChunkReader,
+//!     ) -> Result<(), StateError> {
+//!         self.bank = r.read_u8()?;
+//!         self.map(cart);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl UxRom {
+//!     fn map(&self, cart: &mut Cartridge<'_>) {
+//!         let last = cart.prg_banks(0x4000) - 1;
+//!         cart.map_prg(0x8000, 0x4000, self.bank as usize);
+//!         cart.map_prg(0xC000, 0x4000, last);
+//!     }
+//! }
+//!
+//! let mut console = Console::new();
+//! console.mappers_mut().register(2, |_rom| Box::new(UxRom::default()));
+//!
+//! // Two banks; the fixed one jumps to itself
+//! let mut prg_rom = vec![0; 0x8000];
+//! prg_rom[0x4000..0x4003].copy_from_slice(&[0x4c, 0x00, 0xC0]);
+//! prg_rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xC0]);
+//! let rom = Rom {
+//!     prg_rom,
+//!     chr_rom: Vec::new(),
+//!     format: Format::INes,
+//!     mapper: 2,
+//!     submapper: 0,
+//!     board: None,
+//!     screen_mirroring: Mirroring::Vertical,
+//!     battery: false,
+//!     timing: None,
+//! };
+//! console.load_rom(&rom).unwrap();
+//! assert_eq!(console.cpu().registers().pc, 0xC000);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::ppu::Ppu;
+use crate::rom::{Mirroring, Rom, RomError};
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+/// Where the mapped PRG ROM starts
+const PRG_ROM_START: usize = 0x8000;
+/// Size of the pattern tables CHR ROM banks are mapped into
+const CHR_WINDOW_SIZE: usize = 0x2000;
+/// [`Cartridge::map_prg`] records where in PRG ROM each 4KB of `0x8000..=0xFFFF` comes from
+pub(crate) const PRG_SLOT_SIZE: usize = 0x1000;
+pub(crate) const PRG_SLOTS: usize = 8;
+
+/// A cartridge board's bank switching logic
+pub trait Mapper: MapperClone + Send {
+    /// Maps the banks the board starts with, when the ROM is loaded and on every power cycle.
+    /// Until then the first 32KB of PRG ROM and 8KB of CHR ROM are mapped.
+    fn power_on(&mut self, cart: &mut Cartridge<'_>);
+
+    /// A CPU write to `0x8000..=0xFFFF`
+    fn write(&mut self, cart: &mut Cartridge<'_>, addr: u16, value: u8);
+
+    /// A CPU read of the expansion area, `0x4020..=0x5FFF`. `None`, the default, is open bus.
+    fn read_expansion(&mut self, cart: &mut Cartridge<'_>, addr: u16) -> Option<u8> {
+        let _ = cart;
+        self.peek_expansion(addr)
+    }
+
+    /// What [`Mapper::read_expansion`] would return, without its side effects, for debuggers
+    fn peek_expansion(&self, addr: u16) -> Option<u8> {
+        let _ = addr;
+        None
+    }
+
+    /// A CPU write to the expansion area, `0x4020..=0x5FFF`. Ignored by default.
+    fn write_expansion(&mut self, cart: &mut Cartridge<'_>, addr: u16, value: u8) {
+        let _ = (cart, addr, value);
+    }
+
+    /// `cpu_cycles` CPU cycles have passed, for boards that count them, e.g. for an IRQ timer.
+    /// Ignored by default.
+    fn tick(&mut self, cpu_cycles: u64) {
+        let _ = cpu_cycles;
+    }
+
+    /// Whether the board is holding the CPU's IRQ line, until the game acknowledges it the way
+    /// the board expects. `false`, the default, for boards without IRQs.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Writes the board's registers to a save-state chunk
+    fn save_state(&self, w: &mut ChunkWriter);
+
+    /// Reads back what [`Mapper::save_state`] wrote and maps the banks the registers select
+    /// again. CHR ROM isn't part of save states, and the PRG ROM in them may be from an older
+    /// build of the game.
+    fn load_state(
+        &mut self,
+        cart: &mut Cartridge<'_>,
+        r: &mut ChunkReader,
+    ) -> Result<(), StateError>;
+}
+
+/// Lets the console be cloned with its mapper. Implemented for every `Mapper` that is `Clone`.
+pub trait MapperClone {
+    fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<M: Mapper + Clone + 'static> MapperClone for M {
+    fn clone_box(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+/// Makes a board's mapper for a ROM, e.g. to pick a variant by [`Rom::submapper`]
+pub type MapperFactory = fn(&Rom) -> Box<dyn Mapper>;
+
+/// What a [`Mapper`] can do to the console: switch banks and mirroring
+pub struct Cartridge<'a> {
+    pub(crate) prg_rom: &'a [u8],
+    pub(crate) chr_rom: &'a [u8],
+    pub(crate) memory: &'a mut [u8],
+    pub(crate) ppu: &'a mut Ppu,
+    /// The PRG ROM offset mapped at each 4KB slot, for
+    /// [`Bus::bank_address`](crate::bus::Bus::bank_address)
+    pub(crate) prg_map: &'a mut [usize; PRG_SLOTS],
+}
+
+impl Cartridge<'_> {
+    pub fn prg_rom(&self) -> &[u8] {
+        self.prg_rom
+    }
+
+    /// Empty for boards with CHR RAM
+    pub fn chr_rom(&self) -> &[u8] {
+        self.chr_rom
+    }
+
+    /// How many `size` byte banks the PRG ROM holds, at least one
+    pub fn prg_banks(&self, size: usize) -> usize {
+        (self.prg_rom.len() / size).max(1)
+    }
+
+    /// How many `size` byte banks the CHR ROM holds, at least one
+    pub fn chr_banks(&self, size: usize) -> usize {
+        (self.chr_rom.len() / size).max(1)
+    }
+
+    /// Copies PRG ROM bank `bank`, counted in `size` byte banks, into the CPU addresses from
+    /// `addr`. Banks past the end wrap around, as the unconnected address lines do on hardware.
+    pub fn map_prg(&mut self, addr: u16, size: usize, bank: usize) {
+        let start = addr as usize;
+        assert!(
+            start >= PRG_ROM_START && start + size <= self.memory.len(),
+            "PRG bank at {addr:#06x} is outside 0x8000..=0xFFFF"
+        );
+        copy_bank(
+            self.prg_rom,
+            size,
+            bank,
+            &mut self.memory[start..start + size],
+        );
+        if self.prg_rom.is_empty() {
+            return;
+        }
+        let first = (start - PRG_ROM_START).div_ceil(PRG_SLOT_SIZE);
+        let last = (start + size - PRG_ROM_START) / PRG_SLOT_SIZE;
+        for slot in first..last {
+            let into_bank = PRG_ROM_START + slot * PRG_SLOT_SIZE - start;
+            self.prg_map[slot] =
+                bank.wrapping_mul(size).wrapping_add(into_bank) % self.prg_rom.len();
+        }
+    }
+
+    /// Copies CHR ROM bank `bank`, counted in `size` byte banks, into the pattern tables from
+    /// `addr`. Does nothing on boards with CHR RAM.
+    pub fn map_chr(&mut self, addr: u16, size: usize, bank: usize) {
+        if self.chr_rom.is_empty() {
+            return;
+        }
+        assert!(
+            addr as usize + size <= CHR_WINDOW_SIZE,
+            "CHR bank at {addr:#06x} is outside 0x0000..=0x1FFF"
+        );
+        let mut data = [0; CHR_WINDOW_SIZE];
+        copy_bank(self.chr_rom, size, bank, &mut data[..size]);
+        self.ppu.map_chr(addr, &data[..size]);
+        self.ppu.record_chr_switch(addr, size, bank);
+    }
+
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.ppu.set_mirroring(mirroring);
+    }
+}
+
+pub(crate) fn copy_bank(rom: &[u8], size: usize, bank: usize, out: &mut [u8]) {
+    if rom.is_empty() {
+        return;
+    }
+    let start = bank.wrapping_mul(size);
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = rom[start.wrapping_add(i) % rom.len()];
+    }
+}
+
+/// The mapper a loaded ROM runs with, and the ROM it maps from
+#[derive(Clone)]
+pub(crate) struct Board {
+    pub(crate) mapper: Box<dyn Mapper>,
+    pub(crate) prg_rom: Arc<[u8]>,
+    pub(crate) chr_rom: Arc<[u8]>,
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Mappers registered by number and by UNIF board name
+#[derive(Debug, Clone, Default)]
+pub struct MapperRegistry {
+    numbers: HashMap<u16, MapperFactory>,
+    boards: HashMap<String, MapperFactory>,
+}
+
+impl MapperRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs ROMs with iNES or NES 2.0 mapper number `mapper` with the mapper `factory` makes.
+    /// UNIF boards with a known number match too.
+    pub fn register(&mut self, mapper: u16, factory: MapperFactory) {
+        self.numbers.insert(mapper, factory);
+    }
+
+    /// Runs UNIF ROMs naming `board` with the mapper `factory` makes. Names match without the
+    /// `NES-`, `HVC-` or `UNL-` prefix and in any case, and before the mapper number.
+    pub fn register_board(&mut self, board: &str, factory: MapperFactory) {
+        self.boards.insert(board_key(board), factory);
+    }
+
+    /// The registered factory for `rom`'s board, if any
+    pub fn get(&self, rom: &Rom) -> Option<MapperFactory> {
+        rom.board
+            .as_deref()
+            .and_then(|board| self.boards.get(&board_key(board)))
+            .or_else(|| self.numbers.get(&rom.mapper))
+            .copied()
+    }
+
+    /// Whether the console can run `rom`, on its own or with a registered mapper
+    pub fn supports(&self, rom: &Rom) -> bool {
+        self.get(rom).is_some() || rom.is_supported()
+    }
+
+    /// [`MapperRegistry::supports`], as the error to report
+    pub fn check(&self, rom: &Rom) -> Result<(), RomError> {
+        if self.supports(rom) {
+            Ok(())
+        } else {
+            Err(rom.unsupported_error())
+        }
+    }
+}
+
+fn board_key(board: &str) -> String {
+    let name = ["NES-", "HVC-", "UNL-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    name.to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+    use crate::cpu::Mem;
+    use crate::rom::{Format, NO_MAPPER};
+    use crate::timeline::{EventKind, IrqSource};
+
+    /// CNROM: 8KB CHR banks picked by any write, and a register to show up in states
+    #[derive(Clone, Default)]
+    struct CnRom {
+        bank: u8,
+    }
+
+    impl Mapper for CnRom {
+        fn power_on(&mut self, cart: &mut Cartridge<'_>) {
+            self.bank = 0;
+            cart.map_chr(0, 0x2000, 0);
+        }
+
+        fn write(&mut self, cart: &mut Cartridge<'_>, _addr: u16, value: u8) {
+            self.bank = value;
+            cart.map_chr(0, 0x2000, value as usize);
+            cart.set_mirroring(Mirroring::Horizontal);
+        }
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_u8(self.bank);
+        }
+
+        fn load_state(
+            &mut self,
+            cart: &mut Cartridge<'_>,
+            r: &mut ChunkReader,
+        ) -> Result<(), StateError> {
+            self.bank = r.read_u8()?;
+            cart.map_chr(0, 0x2000, self.bank as usize);
+            Ok(())
+        }
+    }
+
+    fn cnrom(board: Option<&str>) -> Rom {
+        #[rustfmt::skip]
+        let mut prg_rom = [
+            0xa9, 0x01,       // LDA #1
+            0x8d, 0x00, 0x80, // STA $8000
+            0x4c, 0x05, 0x80, // loop: JMP loop
+        ].to_vec();
+        prg_rom.resize(0x4000, 0);
+        prg_rom[0x3FFD] = 0x80;
+        let chr_rom = [[0x11; 0x2000], [0x22; 0x2000]].concat();
+        Rom {
+            prg_rom,
+            chr_rom,
+            format: Format::INes,
+            mapper: if board.is_some() { NO_MAPPER } else { 3 },
+            submapper: 0,
+            board: board.map(str::to_string),
+            screen_mirroring: Mirroring::Vertical,
+            battery: false,
+            timing: None,
+        }
+    }
+
+    #[test]
+    fn test_registered_mapper_switches_banks() {
+        let mut console = Console::new();
+        let rom = cnrom(None);
+        assert_eq!(
+            console.load_rom(&rom).unwrap_err().to_string(),
+            "mapper 3 is not supported"
+        );
+        console
+            .mappers_mut()
+            .register(3, |_rom| Box::new(CnRom::default()));
+        console.load_rom(&rom).unwrap();
+        assert_eq!(console.ppu().read_vram(0x0000), 0x11);
+
+        let state = console.save_state();
+        console.run_frame();
+        assert_eq!(console.ppu().read_vram(0x1FFF), 0x22);
+        // The write went to the board, not over the ROM
+        assert_eq!(console.cpu().mem_peek(0x8000), 0xa9);
+
+        let hash = console.state_hash();
+        console.load_state(&state).unwrap();
+        assert_eq!(console.ppu().read_vram(0x0000), 0x11);
+        assert_ne!(console.state_hash(), hash);
+        console.run_frame();
+        assert_eq!(console.state_hash(), hash);
+
+        console.power_cycle();
+        assert_eq!(console.ppu().read_vram(0x0000), 0x11);
+    }
+
+    /// UxROM: a 16KB PRG bank at `0x8000` picked by any write, the last one fixed at `0xC000`
+    #[derive(Clone, Default)]
+    struct UxRom {
+        bank: u8,
+    }
+
+    impl UxRom {
+        fn map(&self, cart: &mut Cartridge<'_>) {
+            let last = cart.prg_banks(0x4000) - 1;
+            cart.map_prg(0x8000, 0x4000, self.bank as usize);
+            cart.map_prg(0xC000, 0x4000, last);
+        }
+    }
+
+    impl Mapper for UxRom {
+        fn power_on(&mut self, cart: &mut Cartridge<'_>) {
+            self.bank = 0;
+            self.map(cart);
+        }
+
+        fn write(&mut self, cart: &mut Cartridge<'_>, _addr: u16, value: u8) {
+            self.bank = value;
+            self.map(cart);
+        }
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_u8(self.bank);
+        }
+
+        fn load_state(
+            &mut self,
+            cart: &mut Cartridge<'_>,
+            r: &mut ChunkReader,
+        ) -> Result<(), StateError> {
+            self.bank = r.read_u8()?;
+            self.map(cart);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_bank_address_follows_switched_banks() {
+        // Four banks; the fixed one jumps to itself
+        let mut prg_rom = vec![0; 0x10000];
+        prg_rom[0xC000..0xC003].copy_from_slice(&[0x4c, 0x00, 0xC0]);
+        prg_rom[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0xC0]);
+        let rom = Rom {
+            prg_rom,
+            chr_rom: Vec::new(),
+            format: Format::INes,
+            mapper: 2,
+            submapper: 0,
+            board: None,
+            screen_mirroring: Mirroring::Vertical,
+            battery: false,
+            timing: None,
+        };
+        let mut console = Console::new();
+        console
+            .mappers_mut()
+            .register(2, |_rom| Box::new(UxRom::default()));
+        console.load_rom(&rom).unwrap();
+        let bank_address = |console: &Console, addr| console.cpu().bus().bank_address(addr);
+        assert_eq!(bank_address(&console, 0x8123).to_string(), "00:0123");
+        assert_eq!(bank_address(&console, 0xC000).to_string(), "03:0000");
+
+        let state = console.save_state();
+        console.cpu_mut().mem_write(0x8000, 2);
+        assert_eq!(bank_address(&console, 0x8123).to_string(), "02:0123");
+        assert_eq!(bank_address(&console, 0xFFFF).to_string(), "03:3FFF");
+        console.load_state(&state).unwrap();
+        assert_eq!(bank_address(&console, 0x8123).to_string(), "00:0123");
+    }
+
+    /// An IRQ timer: a write starts it counting down that many CPU cycles, and a write of zero
+    /// stops it and acknowledges its IRQ
+    #[derive(Clone, Default)]
+    struct IrqTimer {
+        remaining: u64,
+        irq: bool,
+    }
+
+    impl Mapper for IrqTimer {
+        fn power_on(&mut self, _cart: &mut Cartridge<'_>) {
+            *self = Self::default();
+        }
+
+        fn write(&mut self, _cart: &mut Cartridge<'_>, _addr: u16, value: u8) {
+            self.remaining = value as u64;
+            self.irq = false;
+        }
+
+        fn tick(&mut self, cpu_cycles: u64) {
+            if self.remaining > 0 {
+                self.remaining = self.remaining.saturating_sub(cpu_cycles);
+                self.irq = self.remaining == 0;
+            }
+        }
+
+        fn irq_pending(&self) -> bool {
+            self.irq
+        }
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_u64(self.remaining);
+            w.write_bool(self.irq);
+        }
+
+        fn load_state(
+            &mut self,
+            _cart: &mut Cartridge<'_>,
+            r: &mut ChunkReader,
+        ) -> Result<(), StateError> {
+            self.remaining = r.read_u64()?;
+            self.irq = r.read_bool()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_board_irq_reaches_the_cpu() {
+        let mut prg_rom = vec![0; 0x4000];
+        #[rustfmt::skip]
+        prg_rom[..0x16].copy_from_slice(&[
+            0xa9, 0x40, 0x8d, 0x17, 0x40, // inhibit the frame counter's IRQ
+            0x58,                         // CLI
+            0xa9, 0x20, 0x8d, 0x00, 0x80, // start the timer at 32 cycles
+            0x4c, 0x0b, 0x80,             // spin
+            0xe6, 0x10,                   // IRQ: INC $10
+            0xa9, 0x00, 0x8d, 0x00, 0x80, // acknowledge
+            0x40,                         // RTI
+        ]);
+        prg_rom[0x3FFC..].copy_from_slice(&[0x00, 0x80, 0x0e, 0x80]);
+        let rom = Rom {
+            prg_rom,
+            chr_rom: Vec::new(),
+            format: Format::INes,
+            mapper: 69,
+            submapper: 0,
+            board: None,
+            screen_mirroring: Mirroring::Vertical,
+            battery: false,
+            timing: None,
+        };
+        let mut console = Console::new();
+        console
+            .mappers_mut()
+            .register(69, |_rom| Box::new(IrqTimer::default()));
+        console.load_rom(&rom).unwrap();
+        console.track_interrupts(true);
+        console.run_frame();
+
+        assert_eq!(console.cpu().mem_peek(0x10), 1);
+        let timeline = console.interrupt_timeline().unwrap();
+        let irqs: Vec<_> = timeline
+            .events()
+            .iter()
+            .map(|event| event.kind)
+            .filter(|kind| {
+                !matches!(
+                    kind,
+                    EventKind::NmiAsserted | EventKind::NmiServiced | EventKind::OamDma { .. }
+                )
+            })
+            .collect();
+        assert_eq!(
+            irqs,
+            [
+                EventKind::IrqAsserted(IrqSource::Mapper),
+                EventKind::IrqServiced,
+                EventKind::IrqCleared(IrqSource::Mapper),
+            ]
+        );
+        assert!(timeline.irq_latency().unwrap() < 8);
+    }
+
+    /// Eight bytes of RAM at `0x5000`, and a register at `0x4100` switching the CHR bank
+    #[derive(Clone, Default)]
+    struct ExpansionBoard {
+        ram: [u8; 8],
+    }
+
+    impl Mapper for ExpansionBoard {
+        fn power_on(&mut self, cart: &mut Cartridge<'_>) {
+            cart.map_chr(0, 0x2000, 0);
+        }
+
+        fn write(&mut self, _cart: &mut Cartridge<'_>, _addr: u16, _value: u8) {}
+
+        fn peek_expansion(&self, addr: u16) -> Option<u8> {
+            let offset = addr.checked_sub(0x5000)? as usize;
+            self.ram.get(offset).copied()
+        }
+
+        fn write_expansion(&mut self, cart: &mut Cartridge<'_>, addr: u16, value: u8) {
+            match addr {
+                0x4100 => cart.map_chr(0, 0x2000, value as usize),
+                0x5000..=0x5007 => self.ram[addr as usize - 0x5000] = value,
+                _ => {}
+            }
+        }
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_bytes(&self.ram);
+        }
+
+        fn load_state(
+            &mut self,
+            _cart: &mut Cartridge<'_>,
+            r: &mut ChunkReader,
+        ) -> Result<(), StateError> {
+            self.ram.copy_from_slice(r.read_bytes(8)?);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_expansion_area_reaches_the_board() {
+        let mut console = Console::new();
+        console
+            .mappers_mut()
+            .register(3, |_rom| Box::new(ExpansionBoard::default()));
+        console.load_rom(&cnrom(None)).unwrap();
+        let cpu = console.cpu_mut();
+        cpu.mem_write(0x5003, 0x42);
+        cpu.mem_write(0x4100, 1);
+        assert_eq!(cpu.mem_read(0x5003), 0x42);
+        // Nothing answers: the bus floats
+        assert_eq!(cpu.mem_read(0x5008), 0x50);
+        assert_eq!(cpu.mem_peek(0x4800), 0x48);
+        assert_eq!(console.ppu().read_vram(0x0000), 0x22);
+    }
+
+    #[test]
+    fn test_boards_match_by_name() {
+        let mut registry = MapperRegistry::new();
+        let rom = cnrom(Some("UNL-Homebrew"));
+        assert_eq!(
+            registry.check(&rom).unwrap_err().to_string(),
+            "unknown UNIF board \"UNL-Homebrew\""
+        );
+        registry.register_board("homebrew", |_rom| Box::new(CnRom::default()));
+        assert!(registry.supports(&rom));
+        assert!(!registry.supports(&cnrom(None)));
+    }
+}
@@ -0,0 +1,288 @@
+//! Counters and gauges for emulators running as a long-lived service, in the Prometheus text
+//! format.
+//!
+//! [`Metrics`] is a cheap to clone handle: the thread running the console records into it while
+//! another reads [`Metrics::snapshot`] or answers scrapes with [`serve_http`]. Recording only
+//! touches atomics, apart from a lock taken once per emulated frame to update the FPS window.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::metrics::Metrics;
+//!
+//! let metrics = Metrics::new();
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! console.run_frame();
+//! metrics.record_frame(console.frame_stats());
+//! assert_eq!(metrics.snapshot().frames, 1);
+//! assert!(metrics.to_prometheus().contains("\nnes_frames_total 1\n"));
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::console::FrameStats;
+
+/// How long frames are counted before the FPS gauge updates
+pub const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// The values at one moment
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Frames emulated, including ones rewound later
+    pub frames: u64,
+    /// CPU cycles emulated
+    pub cycles: u64,
+    /// Host time spent emulating, per [`FrameStats::total`]
+    pub emulation_time: Duration,
+    /// Frames emulated per second of wall time over the last full [`FPS_WINDOW`]
+    pub fps: f64,
+    /// Audio samples that never reached the output device
+    pub dropped_audio_samples: u64,
+    /// Frames stepped back through rewind history
+    pub rollbacks: u64,
+    /// Time since the handle was created
+    pub uptime: Duration,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    frames: u64,
+    fps: f64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    started: Instant,
+    frames: AtomicU64,
+    cycles: AtomicU64,
+    emulation_nanos: AtomicU64,
+    dropped_audio_samples: AtomicU64,
+    rollbacks: AtomicU64,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            inner: Arc::new(Inner {
+                started: now,
+                frames: AtomicU64::new(0),
+                cycles: AtomicU64::new(0),
+                emulation_nanos: AtomicU64::new(0),
+                dropped_audio_samples: AtomicU64::new(0),
+                rollbacks: AtomicU64::new(0),
+                window: Mutex::new(Window {
+                    start: now,
+                    frames: 0,
+                    fps: 0.0,
+                }),
+            }),
+        }
+    }
+
+    /// Counts one emulated frame, from [`Console::frame_stats`](crate::console::Console::frame_stats)
+    pub fn record_frame(&self, stats: FrameStats) {
+        self.record_frame_at(stats, Instant::now());
+    }
+
+    fn record_frame_at(&self, stats: FrameStats, now: Instant) {
+        let inner = &self.inner;
+        inner.frames.fetch_add(1, Ordering::Relaxed);
+        inner.cycles.fetch_add(stats.cycles, Ordering::Relaxed);
+        let nanos = stats.total().as_nanos() as u64;
+        inner.emulation_nanos.fetch_add(nanos, Ordering::Relaxed);
+
+        let mut window = inner.window.lock().unwrap();
+        window.frames += 1;
+        let elapsed = now.saturating_duration_since(window.start);
+        if elapsed >= FPS_WINDOW {
+            window.fps = window.frames as f64 / elapsed.as_secs_f64();
+            window.start = now;
+            window.frames = 0;
+        }
+    }
+
+    /// Counts `samples` audio samples thrown away, e.g. because the output queue was full
+    pub fn record_dropped_audio(&self, samples: u64) {
+        self.inner
+            .dropped_audio_samples
+            .fetch_add(samples, Ordering::Relaxed);
+    }
+
+    /// Counts one frame stepped back by [`Console::rewind`](crate::console::Console::rewind)
+    pub fn record_rollback(&self) {
+        self.inner.rollbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = &self.inner;
+        MetricsSnapshot {
+            frames: inner.frames.load(Ordering::Relaxed),
+            cycles: inner.cycles.load(Ordering::Relaxed),
+            emulation_time: Duration::from_nanos(inner.emulation_nanos.load(Ordering::Relaxed)),
+            fps: inner.window.lock().unwrap().fps,
+            dropped_audio_samples: inner.dropped_audio_samples.load(Ordering::Relaxed),
+            rollbacks: inner.rollbacks.load(Ordering::Relaxed),
+            uptime: inner.started.elapsed(),
+        }
+    }
+
+    /// The current values in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let metrics: [(&str, &str, &str, f64); 7] = [
+            (
+                "nes_frames_total",
+                "counter",
+                "Frames emulated",
+                snapshot.frames as f64,
+            ),
+            (
+                "nes_cpu_cycles_total",
+                "counter",
+                "CPU cycles emulated",
+                snapshot.cycles as f64,
+            ),
+            (
+                "nes_emulation_seconds_total",
+                "counter",
+                "Host time spent emulating frames",
+                snapshot.emulation_time.as_secs_f64(),
+            ),
+            (
+                "nes_emulation_fps",
+                "gauge",
+                "Frames emulated per second of wall time",
+                snapshot.fps,
+            ),
+            (
+                "nes_dropped_audio_samples_total",
+                "counter",
+                "Audio samples that never reached the output device",
+                snapshot.dropped_audio_samples as f64,
+            ),
+            (
+                "nes_rollbacks_total",
+                "counter",
+                "Frames stepped back through rewind history",
+                snapshot.rollbacks as f64,
+            ),
+            (
+                "nes_uptime_seconds",
+                "gauge",
+                "Time since the instance started",
+                snapshot.uptime.as_secs_f64(),
+            ),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            writeln!(text, "# HELP {name} {help}").unwrap();
+            writeln!(text, "# TYPE {name} {kind}").unwrap();
+            writeln!(text, "{name} {value}").unwrap();
+        }
+        text
+    }
+}
+
+/// Answers HTTP requests for `/metrics` on `listener` with [`Metrics::to_prometheus`], forever.
+/// Each connection gets one response and is closed; anything other than `GET /metrics` is a 404.
+pub fn serve_http(metrics: &Metrics, listener: &TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        // Read up to the blank line ending the headers, so closing doesn't reset the connection
+        // under a client still sending them
+        let mut lines = BufReader::new(&stream).lines();
+        let Some(Ok(request_line)) = lines.next() else {
+            continue;
+        };
+        for line in lines.by_ref() {
+            if line.map_or(true, |line| line.is_empty()) {
+                break;
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", metrics.to_prometheus()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+        // A client hanging up early is its problem, not a reason to stop serving
+        let _ = write!(
+            stream,
+            "HTTP/1.0 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::thread;
+
+    #[test]
+    fn test_records_and_serves() {
+        let metrics = Metrics::new();
+        let start = metrics.inner.window.lock().unwrap().start;
+        let stats = FrameStats {
+            cycles: 29781,
+            cpu: Duration::from_millis(2),
+            ..FrameStats::default()
+        };
+        for i in 1..=120 {
+            metrics.record_frame_at(stats, start + FPS_WINDOW * i / 60);
+        }
+        metrics.record_rollback();
+        metrics.record_dropped_audio(735);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.frames, 120);
+        assert_eq!(snapshot.cycles, 120 * 29781);
+        assert_eq!(snapshot.emulation_time, Duration::from_millis(240));
+        assert_eq!(snapshot.fps, 60.0);
+        assert_eq!(
+            (snapshot.rollbacks, snapshot.dropped_audio_samples),
+            (1, 735)
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = metrics.clone();
+        thread::spawn(move || serve_http(&server, &listener));
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\n# TYPE nes_emulation_fps gauge\nnes_emulation_fps 60\n"));
+        assert!(response.contains("\nnes_rollbacks_total 1\n"));
+        assert!(get("/").starts_with("HTTP/1.0 404"));
+    }
+}
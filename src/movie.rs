@@ -0,0 +1,336 @@
+//! Input movies.
+//!
+//! A [`Movie`] is the controller input for every frame of a run, stored in the text format FCEUX
+//! uses for `.fm2` files so movies can be recorded there and played back here. Playing a movie on a
+//! freshly loaded ROM reproduces the run exactly, which makes movies end-to-end regression tests:
+//! [`play`] runs one for a number of frames and returns a [`Checkpoint`] of hashes to compare
+//! against a known good run.
+//!
+//! An `.fm2` file is a header of `key value` lines followed by one line per frame:
+//!
+//! ```text
+//! version 3
+//! port0 1
+//! port1 1
+//! |0|R.......|........||
+//! |0|.......A|........||
+//! ```
+//!
+//! The first field holds commands, then one field per controller with the buttons in the order
+//! `RLDUTSBA` (Right, Left, Down, Up, sTart, Select, B, A). A `.` or space is a released button,
+//! anything else a pressed one. The commands supported are `1`, a soft reset, and `2`, a power
+//! cycle, both taking effect at the start of their frame before its input; see [`ResetKind`].
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hasher;
+
+use crate::console::{Console, ResetKind};
+use crate::cpu::Mem;
+use crate::hash::Fnv1a;
+use crate::joypad::JoypadButton;
+use crate::watchdog::{StopReason, Watchdog};
+
+/// `.fm2` button columns, left to right
+const FM2_BUTTONS: [JoypadButton; 8] = [
+    JoypadButton::RIGHT,
+    JoypadButton::LEFT,
+    JoypadButton::DOWN,
+    JoypadButton::UP,
+    JoypadButton::START,
+    JoypadButton::SELECT,
+    JoypadButton::BUTTON_B,
+    JoypadButton::BUTTON_A,
+];
+
+/// `.fm2` command bits
+const SOFT_RESET: u32 = 1;
+const POWER_CYCLE: u32 = 2;
+
+/// Bytes of internal RAM hashed into [`Checkpoint::ram_hash`]
+const RAM_SIZE: u16 = 0x0800;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// An input line that isn't `|commands|port0|port1|...`, with its 1-based line number
+    BadInputLine(usize),
+    /// A frame uses commands (e.g. disk swaps) that playback doesn't support
+    UnsupportedCommand { line: usize, commands: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadInputLine(line) => write!(f, "line {line}: malformed input line"),
+            MovieError::UnsupportedCommand { line, commands } => {
+                write!(f, "line {line}: unsupported movie command {commands}")
+            }
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    /// Header `key value` pairs in file order
+    pub header: Vec<(String, String)>,
+    /// Buttons held by both controllers, one entry per frame
+    pub frames: Vec<[JoypadButton; 2]>,
+    /// Resets keyed by the frame they start, applied before its input
+    pub resets: BTreeMap<usize, ResetKind>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let Some(input) = line.strip_prefix('|') else {
+                if let Some((key, value)) = line.trim().split_once(' ') {
+                    movie
+                        .header
+                        .push((key.to_string(), value.trim().to_string()));
+                } else if !line.trim().is_empty() {
+                    movie.header.push((line.trim().to_string(), String::new()));
+                }
+                continue;
+            };
+
+            let mut fields = input.split('|');
+            let commands = fields
+                .next()
+                .and_then(|c| c.trim().parse::<u32>().ok())
+                .ok_or(MovieError::BadInputLine(line_number))?;
+            if commands & !(SOFT_RESET | POWER_CYCLE) != 0 {
+                return Err(MovieError::UnsupportedCommand {
+                    line: line_number,
+                    commands,
+                });
+            }
+            // A power cycle resets everything a soft reset would
+            if commands & POWER_CYCLE != 0 {
+                movie.resets.insert(movie.frames.len(), ResetKind::Power);
+            } else if commands & SOFT_RESET != 0 {
+                movie.resets.insert(movie.frames.len(), ResetKind::Soft);
+            }
+
+            let mut frame = [JoypadButton::empty(); 2];
+            for pad in frame.iter_mut() {
+                let field = fields.next().unwrap_or("");
+                if field.is_empty() {
+                    continue;
+                }
+                if field.chars().count() != FM2_BUTTONS.len() {
+                    return Err(MovieError::BadInputLine(line_number));
+                }
+                for (c, button) in field.chars().zip(FM2_BUTTONS) {
+                    if c != '.' && c != ' ' {
+                        pad.insert(button);
+                    }
+                }
+            }
+            movie.frames.push(frame);
+        }
+        Ok(movie)
+    }
+
+    /// The movie in `.fm2` text form
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.header {
+            out.push_str(key);
+            if !value.is_empty() {
+                out.push(' ');
+                out.push_str(value);
+            }
+            out.push('\n');
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            let commands = match self.resets.get(&i) {
+                None => 0,
+                Some(ResetKind::Soft) => SOFT_RESET,
+                Some(ResetKind::Power) => POWER_CYCLE,
+            };
+            out.push('|');
+            out.push_str(&commands.to_string());
+            out.push('|');
+            for pad in frame {
+                for (button, c) in FM2_BUTTONS.iter().zip("RLDUTSBA".chars()) {
+                    out.push(if pad.contains(*button) { c } else { '.' });
+                }
+                out.push('|');
+            }
+            out.push_str("|\n");
+        }
+        out
+    }
+
+    /// Number of frames of input
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Appends a frame of input, e.g. while recording
+    pub fn push(&mut self, player1: JoypadButton, player2: JoypadButton) {
+        self.frames.push([player1, player2]);
+    }
+
+    /// Records a reset at the start of the next frame pushed
+    pub fn push_reset(&mut self, kind: ResetKind) {
+        self.resets.insert(self.frames.len(), kind);
+    }
+
+    /// Queues the whole movie on `console`, starting with its next frame
+    pub fn queue(&self, console: &mut Console) {
+        let start = console.frame();
+        for (&i, &kind) in &self.resets {
+            console.queue_reset(start + i as u64, kind);
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            for (player, buttons) in frame.iter().enumerate() {
+                console.queue_input(start + i as u64, player, *buttons);
+            }
+        }
+    }
+}
+
+/// Hashes summarizing a console at one frame, for comparing runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Checkpoint {
+    /// Number of frames run
+    pub frame: u64,
+    /// [`Console::state_hash`]
+    pub state_hash: u64,
+    /// Hash of the 2KB of internal RAM
+    pub ram_hash: u64,
+    /// Hash of the palette indices of the last completed frame
+    pub frame_hash: u64,
+}
+
+impl Checkpoint {
+    pub fn capture(console: &Console) -> Self {
+        let mut ram = Fnv1a::new();
+        for addr in 0..RAM_SIZE {
+            ram.write_u8(console.cpu().mem_peek(addr));
+        }
+        let mut frame = Fnv1a::new();
+        frame.write(&console.frame_ref().indices().pixels);
+
+        Self {
+            frame: console.frame(),
+            state_hash: console.state_hash(),
+            ram_hash: ram.finish(),
+            frame_hash: frame.finish(),
+        }
+    }
+}
+
+/// Plays `movie` on `console` for `frames` frames and captures the result. Frames past the end of
+/// the movie keep the last input held.
+pub fn play(console: &mut Console, movie: &Movie, frames: u64) -> Checkpoint {
+    movie.queue(console);
+    for _ in 0..frames {
+        console.run_frame();
+    }
+    Checkpoint::capture(console)
+}
+
+/// Plays `movie` like [`play`], stopping once `watchdog` says to. The rest of the movie's input
+/// stays queued, so running on plays it out.
+pub fn play_watched(
+    console: &mut Console,
+    movie: &Movie,
+    frames: u64,
+    watchdog: &mut Watchdog,
+) -> Result<Checkpoint, StopReason> {
+    movie.queue(console);
+    for _ in 0..frames {
+        console.run_frame_watched(watchdog)?;
+    }
+    Ok(Checkpoint::capture(console))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fm2() {
+        let movie = Movie::parse(
+            "version 3\n\
+             comment author someone\n\
+             |0|R......A|........||\n\
+             |0|...UT...|.L......||\n\
+             |0|||\n",
+        )
+        .unwrap();
+
+        assert_eq!(movie.header[1], ("comment".into(), "author someone".into()));
+        assert_eq!(movie.len(), 3);
+        assert_eq!(
+            movie.frames[0][0],
+            JoypadButton::RIGHT | JoypadButton::BUTTON_A
+        );
+        assert_eq!(movie.frames[1][0], JoypadButton::UP | JoypadButton::START);
+        assert_eq!(movie.frames[1][1], JoypadButton::LEFT);
+        assert_eq!(movie.frames[2], [JoypadButton::empty(); 2]);
+        assert_eq!(Movie::parse(&movie.to_fm2()).unwrap().frames, movie.frames);
+    }
+
+    #[test]
+    fn test_resets() {
+        let movie = Movie::parse("|0|||\n|1|||\n|0|||\n|2|||\n|0|||\n").unwrap();
+        assert_eq!(
+            movie.resets,
+            BTreeMap::from([(1, ResetKind::Soft), (3, ResetKind::Power)])
+        );
+        assert_eq!(Movie::parse(&movie.to_fm2()).unwrap(), movie);
+
+        // Counts boots in $11, then spins
+        #[rustfmt::skip]
+        let program = [
+            0xe6, 0x11,       // INC $11
+            0x4c, 0x02, 0x80, // loop: JMP loop
+        ];
+        let mut console = Console::new();
+        console.load(&program);
+        movie.queue(&mut console);
+        let boots: Vec<_> = (0..movie.len())
+            .map(|_| {
+                console.run_frame();
+                console.cpu().mem_peek(0x0011)
+            })
+            .collect();
+        // The power cycle clears RAM
+        assert_eq!(boots, [1, 2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_rejects_bad_lines() {
+        assert_eq!(
+            Movie::parse("|x|........||"),
+            Err(MovieError::BadInputLine(1))
+        );
+        assert_eq!(
+            Movie::parse("version 3\n|0|RLD|"),
+            Err(MovieError::BadInputLine(2))
+        );
+        assert_eq!(
+            Movie::parse("|4|........||"),
+            Err(MovieError::UnsupportedCommand {
+                line: 1,
+                commands: 4
+            })
+        );
+    }
+}
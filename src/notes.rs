@@ -0,0 +1,280 @@
+//! Note transcription: turning what the APU plays into note on and note off events.
+//!
+//! A [`NoteTracker`] looks at each channel's [`Tone`] once per frame. A channel starting to sound,
+//! changing pitch, or jumping up in volume (a restarted envelope) starts a note; going silent or
+//! moving to the next note ends it. Pitches are rounded to the nearest MIDI note, so slides and
+//! vibrato come out as runs of short notes.
+//!
+//! The events can be written as a plain text log with [`NoteTracker::to_log`], or, with the
+//! `midi` feature, as a standard MIDI file with [`NoteTracker::to_midi`].
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::notes::NoteTracker;
+//!
+//! let mut console = Console::new();
+//! #[rustfmt::skip]
+//! console.load(&[
+//!     0xa9, 0x01, 0x8d, 0x15, 0x40, // enable pulse 1
+//!     0xa9, 0x9f, 0x8d, 0x00, 0x40, // 50% duty, constant volume 15
+//!     0xa9, 0xfd, 0x8d, 0x02, 0x40, // A4
+//!     0xa9, 0x08, 0x8d, 0x03, 0x40, // start it
+//!     0x4c, 0x14, 0x80,             // spin
+//! ]);
+//! let mut notes = NoteTracker::new();
+//! for _ in 0..30 {
+//!     console.run_frame();
+//!     notes.sample(console.frame(), console.apu());
+//! }
+//! notes.finish(console.frame());
+//! assert_eq!(notes.events()[0].note, 69);
+//! println!("{}", notes.to_log());
+//! ```
+
+use std::fmt::Write;
+
+use crate::apu::{Apu, Channel, Tone};
+
+/// Channels that play notes, in the order events for one frame are reported
+const TONAL: [Channel; 4] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+];
+/// The General MIDI drum kit note noise period setting 0 maps to, ending at 15 on 35
+const NOISE_BASE_NOTE: u8 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteEvent {
+    pub frame: u64,
+    pub channel: Channel,
+    /// MIDI note number, 69 being A4. Noise is mapped onto drum notes by its period setting.
+    pub note: u8,
+    /// MIDI velocity `1..=127` for note on, 0 for note off
+    pub velocity: u8,
+}
+
+impl NoteEvent {
+    pub fn is_on(&self) -> bool {
+        self.velocity > 0
+    }
+}
+
+/// The nearest MIDI note to `frequency` Hz
+pub fn midi_note(frequency: f32) -> u8 {
+    (69.0 + 12.0 * (frequency / 440.0).log2())
+        .round()
+        .clamp(0.0, 127.0) as u8
+}
+
+/// Name of a MIDI note in scientific pitch notation, like `C#4`
+pub fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    format!("{}{}", NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Playing {
+    note: u8,
+    volume: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoteTracker {
+    /// Note sounding on each of [`TONAL`]
+    playing: [Option<Playing>; 4],
+    events: Vec<NoteEvent>,
+}
+
+impl NoteTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks at what `apu` is playing as of `frame`, recording any notes that started or ended
+    pub fn sample(&mut self, frame: u64, apu: &Apu) {
+        for (i, channel) in TONAL.into_iter().enumerate() {
+            let now = apu.tone(channel).map(|tone| Playing {
+                note: Self::note(channel, tone),
+                volume: tone.volume,
+            });
+            let retrigger = match (self.playing[i], now) {
+                (Some(before), Some(now)) => now.note != before.note || now.volume > before.volume,
+                (before, now) => before.is_some() || now.is_some(),
+            };
+            if !retrigger {
+                self.playing[i] = now;
+                continue;
+            }
+            if let Some(before) = self.playing[i] {
+                self.push(frame, channel, before.note, 0);
+            }
+            if let Some(now) = now {
+                self.push(frame, channel, now.note, Self::velocity(now.volume));
+            }
+            self.playing[i] = now;
+        }
+    }
+
+    /// Ends every note still sounding as of `frame`, e.g. when the recording stops
+    pub fn finish(&mut self, frame: u64) {
+        for (i, channel) in TONAL.into_iter().enumerate() {
+            if let Some(before) = self.playing[i].take() {
+                self.push(frame, channel, before.note, 0);
+            }
+        }
+    }
+
+    /// Every event so far, in frame order
+    pub fn events(&self) -> &[NoteEvent] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// One line per event: frame, channel, `on` or `off`, note number and name, and velocity
+    pub fn to_log(&self) -> String {
+        let mut log = String::from("# frame channel event note name velocity\n");
+        for event in &self.events {
+            writeln!(
+                log,
+                "{} {:?} {} {} {} {}",
+                event.frame,
+                event.channel,
+                if event.is_on() { "on" } else { "off" },
+                event.note,
+                note_name(event.note),
+                event.velocity
+            )
+            .unwrap();
+        }
+        log
+    }
+
+    /// The events as a type 0 standard MIDI file, one tick per frame at `frames_per_second`.
+    /// Pulse 1, pulse 2 and triangle play on MIDI channels 1 to 3, and noise on the drum channel.
+    #[cfg(feature = "midi")]
+    pub fn to_midi(&self, frames_per_second: f64) -> Vec<u8> {
+        /// Ticks per quarter note; with the tempo below a quarter note lasts 60 frames
+        const DIVISION: u16 = 60;
+        let tempo = (DIVISION as f64 / frames_per_second * 1_000_000.0).round() as u32;
+
+        let mut track = vec![0x00, 0xFF, 0x51, 0x03];
+        track.extend_from_slice(&tempo.to_be_bytes()[1..]);
+        let mut last_frame = self.events.first().map_or(0, |event| event.frame);
+        for event in &self.events {
+            write_var_len(&mut track, (event.frame - last_frame) as u32);
+            last_frame = event.frame;
+            let channel = match event.channel {
+                Channel::Pulse1 => 0,
+                Channel::Pulse2 => 1,
+                Channel::Triangle => 2,
+                _ => 9,
+            };
+            let status = if event.is_on() { 0x90 } else { 0x80 };
+            track.extend_from_slice(&[status | channel, event.note, event.velocity]);
+        }
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut midi = b"MThd".to_vec();
+        midi.extend_from_slice(&6u32.to_be_bytes());
+        for value in [0, 1, DIVISION] {
+            midi.extend_from_slice(&value.to_be_bytes());
+        }
+        midi.extend_from_slice(b"MTrk");
+        midi.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        midi.extend_from_slice(&track);
+        midi
+    }
+
+    fn note(channel: Channel, tone: Tone) -> u8 {
+        match channel {
+            Channel::Noise => NOISE_BASE_NOTE - tone.period as u8,
+            _ => midi_note(tone.frequency),
+        }
+    }
+
+    fn velocity(volume: u8) -> u8 {
+        (volume as u16 * 127 / 15).max(1) as u8
+    }
+
+    fn push(&mut self, frame: u64, channel: Channel, note: u8, velocity: u8) {
+        self.events.push(NoteEvent {
+            frame,
+            channel,
+            note,
+            velocity,
+        });
+    }
+}
+
+/// Appends `value` as a MIDI variable length quantity: 7 bits per byte, most significant first,
+/// with the top bit set on all but the last
+#[cfg(feature = "midi")]
+fn write_var_len(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notes_start_and_end() {
+        assert_eq!(midi_note(440.0), 69);
+        assert_eq!(note_name(61), "C#4");
+
+        let memory = [0; 0x10000];
+        let mut apu = Apu::new();
+        let mut notes = NoteTracker::new();
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0b1001_1111);
+        apu.write_register(0x4002, 0xFD); // A4
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.tick(1000, &memory);
+        notes.sample(0, &apu);
+        notes.sample(1, &apu); // still playing: nothing new
+
+        apu.write_register(0x4002, 0x7E); // an octave up, A5
+        notes.sample(2, &apu);
+        apu.write_register(0x4015, 0x00);
+        notes.sample(3, &apu);
+
+        let event = |frame, note, velocity| NoteEvent {
+            frame,
+            channel: Channel::Pulse1,
+            note,
+            velocity,
+        };
+        assert_eq!(
+            notes.events(),
+            [
+                event(0, 69, 127),
+                event(2, 69, 0),
+                event(2, 81, 127),
+                event(3, 81, 0)
+            ]
+        );
+        assert!(notes.to_log().contains("\n2 Pulse1 on 81 A5 127\n"));
+
+        #[cfg(feature = "midi")]
+        {
+            let midi = notes.to_midi(60.0);
+            assert_eq!(&midi[..4], b"MThd");
+            // two frames later pulse 1 lets go of A4, and the track ends
+            assert!(midi.windows(4).any(|bytes| bytes == [0x02, 0x80, 69, 0]));
+            assert_eq!(&midi[midi.len() - 3..], [0xFF, 0x2F, 0x00]);
+        }
+    }
+}
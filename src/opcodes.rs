@@ -0,0 +1,297 @@
+//! The 6502 opcode table.
+//!
+//! Each of the 151 official opcodes maps to its mnemonic, length in bytes, base cycle count, and
+//! [`AddressingMode`]. Reads that cross a page boundary and taken branches cost extra cycles on top
+//! of the base count; the CPU adds those as it executes.
+//!
+//! The 65C02 has its own table: the official opcodes, the instructions it added, and `NOP`s of
+//! varying length for every opcode it leaves undefined.
+
+use crate::cpu::{AddressingMode, CpuVariant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpCode {
+    pub code: u8,
+    pub mnemonic: &'static str,
+    /// Instruction length including the opcode byte
+    pub len: u8,
+    pub cycles: u8,
+    pub mode: AddressingMode,
+}
+
+impl OpCode {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        Self {
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
+        }
+    }
+}
+
+pub const CPU_OPS_CODES: [OpCode; 151] = [
+    OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7D, "ADC", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x79, "ADC", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x61, "ADC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x71, "ADC", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3D, "AND", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x39, "AND", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x21, "AND", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x31, "AND", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative),
+    OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative),
+    OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative),
+    OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative),
+    OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::Relative),
+    OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative),
+    OpCode::new(0x00, "BRK", 1, 7, AddressingMode::Implied),
+    OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative),
+    OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative),
+    OpCode::new(0x18, "CLC", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x58, "CLI", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xDD, "CMP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xD9, "CMP", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xD1, "CMP", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xE0, "CPX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xCC, "CPY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x88, "DEY", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x5D, "EOR", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x59, "EOR", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x41, "EOR", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x51, "EOR", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xFE, "INC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xE8, "INX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xC8, "INY", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
+    OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect),
+    OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xB1, "LDA", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBE, "LDX", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1D, "ORA", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x19, "ORA", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x01, "ORA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x11, "ORA", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0x48, "PHA", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x08, "PHP", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x68, "PLA", 1, 4, AddressingMode::Implied),
+    OpCode::new(0x28, "PLP", 1, 4, AddressingMode::Implied),
+    OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x40, "RTI", 1, 6, AddressingMode::Implied),
+    OpCode::new(0x60, "RTS", 1, 6, AddressingMode::Implied),
+    OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xFD, "SBC", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0x38, "SEC", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xF8, "SED", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x78, "SEI", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9D, "STA", 3, 5, AddressingMode::AbsoluteX),
+    OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY),
+    OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::Implied),
+    OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::Implied),
+    OpCode::new(0x98, "TYA", 1, 2, AddressingMode::Implied),
+];
+
+/// [`CPU_OPS_CODES`] indexed by opcode byte
+pub static OPCODES_MAP: [Option<OpCode>; 256] = {
+    let mut map = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        map[CPU_OPS_CODES[i].code as usize] = Some(CPU_OPS_CODES[i]);
+        i += 1;
+    }
+    map
+};
+
+/// Opcodes the 65C02 adds or changes
+pub const CMOS_OPS_CODES: [OpCode; 28] = [
+    OpCode::new(0x72, "ADC", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x32, "AND", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x89, "BIT", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x34, "BIT", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x3C, "BIT", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x80, "BRA", 2, 2, AddressingMode::Relative),
+    OpCode::new(0xD2, "CMP", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x3A, "DEC", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x52, "EOR", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x1A, "INC", 1, 2, AddressingMode::Accumulator),
+    OpCode::new(0x6C, "JMP", 3, 6, AddressingMode::Indirect),
+    OpCode::new(0x7C, "JMP", 3, 6, AddressingMode::AbsoluteIndexedIndirect),
+    OpCode::new(0xB2, "LDA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x12, "ORA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0xDA, "PHX", 1, 3, AddressingMode::Implied),
+    OpCode::new(0x5A, "PHY", 1, 3, AddressingMode::Implied),
+    OpCode::new(0xFA, "PLX", 1, 4, AddressingMode::Implied),
+    OpCode::new(0x7A, "PLY", 1, 4, AddressingMode::Implied),
+    OpCode::new(0xF2, "SBC", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x92, "STA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x64, "STZ", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x74, "STZ", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x9C, "STZ", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9E, "STZ", 3, 5, AddressingMode::AbsoluteX),
+    OpCode::new(0x14, "TRB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x1C, "TRB", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x04, "TSB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x0C, "TSB", 3, 6, AddressingMode::Absolute),
+];
+
+/// The `NOP` the 65C02 executes for an opcode it leaves undefined. Most take one byte and one
+/// cycle; the rest skip an operand like the instructions they sit among.
+const fn cmos_nop(code: u8) -> OpCode {
+    let (len, cycles) = match code {
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => (2, 2),
+        0x44 => (2, 3),
+        0x54 | 0xD4 | 0xF4 => (2, 4),
+        0x5C => (3, 8),
+        0xDC | 0xFC => (3, 4),
+        _ => (1, 1),
+    };
+    let mode = if len == 1 {
+        AddressingMode::Implied
+    } else {
+        AddressingMode::Immediate
+    };
+    OpCode::new(code, "NOP", len, cycles, mode)
+}
+
+pub static CMOS_OPCODES_MAP: [OpCode; 256] = {
+    let mut map = [cmos_nop(0); 256];
+    let mut code = 0;
+    while code < 256 {
+        map[code] = match OPCODES_MAP[code] {
+            Some(opcode) => opcode,
+            None => cmos_nop(code as u8),
+        };
+        code += 1;
+    }
+    let mut i = 0;
+    while i < CMOS_OPS_CODES.len() {
+        map[CMOS_OPS_CODES[i].code as usize] = CMOS_OPS_CODES[i];
+        i += 1;
+    }
+    map
+};
+
+/// The opcode for `code`, or `None` for the unofficial opcodes
+pub fn lookup(code: u8) -> Option<&'static OpCode> {
+    OPCODES_MAP[code as usize].as_ref()
+}
+
+/// The unofficial opcodes that jam an NMOS 6502, also called `KIL` or `HLT`. The CPU stops
+/// fetching instructions and ignores interrupts until it's reset. The 65C02 runs them as `NOP`s
+/// or its `(zp)` instructions instead.
+pub const JAM_OPCODES: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
+
+/// Whether `code` jams a CPU of `variant`
+pub fn is_jam(variant: CpuVariant, code: u8) -> bool {
+    variant != CpuVariant::Cmos65C02 && JAM_OPCODES.contains(&code)
+}
+
+/// The opcode for `code` on `variant`, or `None` if it isn't implemented
+pub fn lookup_for(variant: CpuVariant, code: u8) -> Option<&'static OpCode> {
+    match variant {
+        CpuVariant::Cmos65C02 => Some(&CMOS_OPCODES_MAP[code as usize]),
+        CpuVariant::Ricoh2A03 | CpuVariant::Ricoh2A07 | CpuVariant::Nmos6502 => lookup(code),
+    }
+}
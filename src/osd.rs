@@ -0,0 +1,209 @@
+//! On-screen display.
+//!
+//! [`Osd`] keeps short-lived text messages ("State saved to slot 2") plus a persistent status
+//! block, and draws them onto a finished frame with a built-in 5x7 font. It works on either the
+//! palette indices of a [`Frame`] or on pixels already converted to a [`PixelFormat`], so every
+//! frontend, including headless captures, can use it.
+//!
+//! Messages stack in the bottom left corner, newest last; the status lines sit right aligned in the
+//! top right. Text is drawn in white with a black drop shadow. Lower case letters are shown as upper
+//! case and characters the font lacks as `?`.
+
+mod font;
+
+use std::collections::VecDeque;
+
+use crate::frame::{Frame, Palette, PixelFormat, HEIGHT, WIDTH};
+
+/// Ticks a message stays up by default, 3 seconds at 60 ticks per second
+pub const DEFAULT_DURATION: u32 = 180;
+/// Messages shown at once; showing another drops the oldest
+pub const MAX_MESSAGES: usize = 4;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// Glyph plus spacing and room for the shadow
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+const MARGIN: usize = 4;
+
+const TEXT_COLOR: u8 = 0x30;
+const SHADOW_COLOR: u8 = 0x0F;
+
+struct Message {
+    text: String,
+    remaining: u32,
+}
+
+#[derive(Default)]
+pub struct Osd {
+    messages: VecDeque<Message>,
+    status: String,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows `text` for [`DEFAULT_DURATION`] ticks
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.show_for(text, DEFAULT_DURATION);
+    }
+
+    pub fn show_for(&mut self, text: impl Into<String>, ticks: u32) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(Message {
+            text: text.into(),
+            remaining: ticks,
+        });
+    }
+
+    /// Sets the persistent top right text, e.g. an FPS counter, one line per `\n`. An empty string
+    /// hides it.
+    pub fn set_status(&mut self, text: &str) {
+        self.status.clear();
+        self.status.push_str(text);
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// Messages currently shown, oldest first
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().map(|m| m.text.as_str())
+    }
+
+    /// Removes all messages; the status stays
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// Ages messages by one tick, dropping the expired ones. Call once per displayed frame.
+    pub fn tick(&mut self) {
+        for message in self.messages.iter_mut() {
+            message.remaining = message.remaining.saturating_sub(1);
+        }
+        self.messages.retain(|m| m.remaining > 0);
+    }
+
+    /// Draws onto the palette indices of `frame`
+    pub fn draw(&self, frame: &mut Frame) {
+        self.render(|x, y, index| frame.set_pixel(x, y, index));
+    }
+
+    /// Draws onto `out`, a whole frame of pixels in `format`
+    pub fn draw_pixels(&self, format: PixelFormat, palette: &Palette, out: &mut [u8]) {
+        assert_eq!(out.len(), format.frame_len(), "output buffer size");
+        let size = format.bytes_per_pixel();
+        self.render(|x, y, index| {
+            palette.write_color(index, format, &mut out[(y * WIDTH + x) * size..])
+        });
+    }
+
+    fn render(&self, mut plot: impl FnMut(usize, usize, u8)) {
+        for (i, line) in self.status.lines().enumerate() {
+            let x = WIDTH.saturating_sub(MARGIN + text_width(line));
+            draw_text(x, MARGIN + i * LINE_HEIGHT, line, &mut plot);
+        }
+
+        let bottom = HEIGHT - MARGIN - LINE_HEIGHT;
+        for (i, message) in self.messages.iter().rev().enumerate() {
+            let Some(y) = bottom.checked_sub(i * LINE_HEIGHT) else {
+                break;
+            };
+            draw_text(MARGIN, y, &message.text, &mut plot);
+        }
+    }
+}
+
+/// Width in pixels of `text` drawn with the OSD font
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * CELL_WIDTH
+}
+
+/// Draws `text` with its top left corner at (`x`, `y`), calling `plot` with the position and
+/// palette index of every lit pixel. Text running off the frame is clipped.
+pub fn draw_text(x: usize, y: usize, text: &str, mut plot: impl FnMut(usize, usize, u8)) {
+    for (offset, color) in [(1, SHADOW_COLOR), (0, TEXT_COLOR)] {
+        for (i, c) in text.chars().enumerate() {
+            let rows = glyph(c);
+            let left = x + i * CELL_WIDTH + offset;
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    let (px, py) = (left + col, y + row + offset);
+                    if bits & (0b10000 >> col) != 0 && px < WIDTH && py < HEIGHT {
+                        plot(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> &'static [u8; 7] {
+    let c = c.to_ascii_uppercase();
+    let index = match c {
+        ' '..='_' => c as u8 - font::FIRST,
+        _ => b'?' - font::FIRST,
+    };
+    &font::GLYPHS[index as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_draws_text_with_shadow() {
+        let mut frame = Frame::new();
+        draw_text(10, 20, "I", |x, y, index| frame.set_pixel(x, y, index));
+
+        // The top bar of `I` spans columns 1-3 of the glyph
+        assert_eq!(frame.get_pixel(11, 20), TEXT_COLOR);
+        assert_eq!(frame.get_pixel(13, 20), TEXT_COLOR);
+        assert_eq!(frame.get_pixel(14, 21), SHADOW_COLOR);
+        assert_eq!(frame.get_pixel(10, 20), 0);
+        assert_eq!(glyph('i'), glyph('I'));
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+
+    #[test]
+    fn test_messages_expire_and_stack() {
+        let mut osd = Osd::new();
+        osd.show_for("first", 1);
+        for i in 0..MAX_MESSAGES {
+            osd.show(format!("message {i}"));
+        }
+        assert_eq!(osd.messages().count(), MAX_MESSAGES);
+        assert_eq!(osd.messages().next(), Some("message 0"));
+
+        osd.show_for("short", 2);
+        osd.tick();
+        assert!(osd.messages().any(|m| m == "short"));
+        osd.tick();
+        assert!(!osd.messages().any(|m| m == "short"));
+    }
+
+    #[test]
+    fn test_draw_pixels_matches_indices() {
+        let mut osd = Osd::new();
+        osd.show("State saved to slot 2");
+        osd.set_status("60 FPS");
+
+        let mut frame = Frame::new();
+        osd.draw(&mut frame);
+        let mut pixels = Frame::new().to_pixels(PixelFormat::Rgba8888, &Palette::default());
+        osd.draw_pixels(PixelFormat::Rgba8888, &Palette::default(), &mut pixels);
+
+        assert_eq!(
+            pixels,
+            frame.to_pixels(PixelFormat::Rgba8888, &Palette::default())
+        );
+        assert!(frame.pixels[..WIDTH * 16].contains(&TEXT_COLOR)); // status line
+        assert!(frame.pixels[WIDTH * (HEIGHT - 16)..].contains(&TEXT_COLOR)); // message
+    }
+}
@@ -0,0 +1,351 @@
+//! ROM patches.
+//!
+//! Translations and ROM hacks are usually distributed as patches against the original ROM file.
+//! [`apply`] takes the raw bytes of a ROM file (header included) and an IPS or BPS patch, and
+//! returns the patched file to pass to [`Rom::new`](crate::rom::Rom::new):
+//!
+//! | Format | |
+//! | :--- | :--- |
+//! | IPS | records that overwrite or fill byte ranges, with no way to check the source |
+//! | BPS | copy instructions, with CRC-32s of the source, patched ROM and patch itself |
+//!
+//! Frontends soft-patch by convention: a patch saved next to the ROM with the same name
+//! (`game.ips` or `game.bps` for `game.nes`) is applied every time the ROM loads, leaving the ROM
+//! file untouched. [`soft_patch_path`] finds it.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::hash::crc32;
+
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_TAG: &[u8] = b"BPS1";
+/// Source, target and patch CRC-32s at the end of a BPS patch
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// Patch file extensions looked for next to a ROM, in order
+pub const SOFT_PATCH_EXTENSIONS: [&str; 2] = ["ips", "bps"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ips,
+    Bps,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with the IPS or BPS tag
+    UnknownFormat,
+    /// The patch ends in the middle of a record
+    Truncated,
+    /// The patch's own checksum doesn't match, or it copies from outside the ROM
+    Corrupt,
+    /// A BPS patch made for a different ROM, with the CRC-32s it expects and found
+    WrongSource { expected: u32, found: u32 },
+    /// The patched ROM doesn't match the checksum the BPS patch promises
+    BadOutput,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::Corrupt => write!(f, "patch is corrupt"),
+            PatchError::WrongSource { expected, found } => write!(
+                f,
+                "patch is for a ROM with CRC-32 {expected:08x}, this one is {found:08x}"
+            ),
+            PatchError::BadOutput => write!(f, "patched ROM fails the patch's checksum"),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+impl Format {
+    /// Detects the format from the patch's tag
+    pub fn detect(patch: &[u8]) -> Option<Format> {
+        if patch.starts_with(IPS_TAG) {
+            Some(Format::Ips)
+        } else if patch.starts_with(BPS_TAG) {
+            Some(Format::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+/// Applies an IPS or BPS `patch` to the ROM file `rom`
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match Format::detect(patch) {
+        Some(Format::Ips) => apply_ips(rom, patch),
+        Some(Format::Bps) => apply_bps(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+/// The soft patch for the ROM at `rom_path`, if one exists next to it
+pub fn soft_patch_path(rom_path: &Path) -> Option<PathBuf> {
+    SOFT_PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Reads big and little endian integers and BPS numbers from a patch
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(len).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(PatchError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// A big endian integer of `len` bytes
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |n, byte| (n << 8) | *byte as usize))
+    }
+
+    fn le_u32(&mut self) -> Result<u32, PatchError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// A BPS variable length number: 7 bits per byte, low bits first, with the top bit marking the
+    /// last byte. Each continuation also adds one, so every number has a single encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let (mut n, mut shift) = (0usize, 1usize);
+        loop {
+            let byte = self.bytes(1)?[0] as usize;
+            n = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| n.checked_add(bits))
+                .ok_or(PatchError::Corrupt)?;
+            if byte & 0x80 != 0 {
+                return Ok(n);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Corrupt)?;
+            n = n.checked_add(shift).ok_or(PatchError::Corrupt)?;
+        }
+    }
+}
+
+/// Applies an IPS patch: `PATCH`, then records of a 3 byte offset and 2 byte length followed by
+/// that many bytes, or by a 2 byte count and 1 byte fill value when the length is 0. `EOF` ends
+/// the records and may be followed by a 3 byte size to truncate the file to.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(IPS_TAG) {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut out = rom.to_vec();
+    let mut r = Reader::new(patch, IPS_TAG.len());
+    loop {
+        let tag = r.bytes(3)?;
+        if tag == IPS_EOF {
+            break;
+        }
+        let offset = tag.iter().fold(0, |n, byte| (n << 8) | *byte as usize);
+        let (len, fill) = match r.be(2)? {
+            0 => (r.be(2)?, Some(r.bytes(1)?[0])),
+            len => (len, None),
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match fill {
+            Some(value) => out[offset..offset + len].fill(value),
+            None => out[offset..offset + len].copy_from_slice(r.bytes(len)?),
+        }
+    }
+    if let Ok(size) = r.be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+/// Applies a BPS patch, checking the CRC-32s of the source, the patch and the result
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(BPS_TAG) {
+        return Err(PatchError::UnknownFormat);
+    }
+    if patch.len() < BPS_TAG.len() + BPS_FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let actions_end = patch.len() - BPS_FOOTER_SIZE;
+    let mut footer = Reader::new(patch, actions_end);
+    let (source_crc, target_crc, patch_crc) =
+        (footer.le_u32()?, footer.le_u32()?, footer.le_u32()?);
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(PatchError::Corrupt);
+    }
+    let found = crc32(rom);
+    if found != source_crc {
+        return Err(PatchError::WrongSource {
+            expected: source_crc,
+            found,
+        });
+    }
+
+    let mut r = Reader::new(&patch[..actions_end], BPS_TAG.len());
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    let metadata_size = r.number()?;
+    r.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(PatchError::Corrupt);
+    }
+
+    let mut out = Vec::with_capacity(rom.len());
+    let (mut source_pos, mut target_pos) = (0usize, 0usize);
+    while r.pos < actions_end {
+        let action = r.number()?;
+        let len = (action >> 2) + 1;
+        if len > target_size - out.len() {
+            return Err(PatchError::Corrupt);
+        }
+        match action & 3 {
+            // SourceRead: the source bytes at the same offset as the output
+            0 => {
+                let start = out.len();
+                let bytes = rom.get(start..).and_then(|rest| rest.get(..len));
+                out.extend_from_slice(bytes.ok_or(PatchError::Corrupt)?);
+            }
+            // TargetRead: bytes from the patch
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // SourceCopy and TargetCopy: bytes from a relative offset in the source or output
+            kind => {
+                let pos = if kind == 2 {
+                    &mut source_pos
+                } else {
+                    &mut target_pos
+                };
+                let delta = r.number()?;
+                *pos = if delta & 1 != 0 {
+                    pos.checked_sub(delta >> 1)
+                } else {
+                    pos.checked_add(delta >> 1)
+                }
+                .ok_or(PatchError::Corrupt)?;
+                if kind == 2 {
+                    let bytes = rom.get(*pos..).and_then(|rest| rest.get(..len));
+                    out.extend_from_slice(bytes.ok_or(PatchError::Corrupt)?);
+                } else {
+                    if *pos >= out.len() {
+                        return Err(PatchError::Corrupt);
+                    }
+                    // The copy can overlap what it's writing, repeating a pattern
+                    for i in *pos..*pos + len {
+                        out.push(out[i]);
+                    }
+                }
+                *pos += len;
+            }
+        }
+    }
+
+    if out.len() != target_size || crc32(&out) != target_crc {
+        return Err(PatchError::BadOutput);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ips() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]); // 2 bytes at 1
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xCC]); // 3 x 0xCC at 6
+        patch.extend_from_slice(b"EOF");
+
+        let patched = apply(&[0; 4], &patch).unwrap();
+        assert_eq!(patched, [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC]);
+
+        // A size after EOF truncates
+        patch.extend_from_slice(&[0x00, 0x00, 0x02]);
+        assert_eq!(apply(&[0; 4], &patch).unwrap(), [0, 0xAA]);
+
+        assert_eq!(
+            apply(&[0; 4], b"PATCH\0\0\x01\0\x02\xAA"),
+            Err(PatchError::Truncated)
+        );
+        assert_eq!(apply(&[0; 4], b"PACTH"), Err(PatchError::UnknownFormat));
+    }
+
+    /// Writes `n` as a BPS number
+    fn number(out: &mut Vec<u8>, mut n: usize) {
+        loop {
+            let bits = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | bits);
+                return;
+            }
+            out.push(bits);
+            n -= 1;
+        }
+    }
+
+    /// A BPS patch turning `source` into `target` with the given actions
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, source.len());
+        number(&mut patch, target.len());
+        number(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"ABCDEFGH";
+        let target = b"ABCDxyxyxyGHAB";
+        let mut actions = Vec::new();
+        number(&mut actions, (4 - 1) << 2); // SourceRead "ABCD"
+        number(&mut actions, ((2 - 1) << 2) | 1); // TargetRead "xy"
+        actions.extend_from_slice(b"xy");
+        number(&mut actions, ((4 - 1) << 2) | 3); // TargetCopy "xyxy" from 4
+        number(&mut actions, 4 << 1);
+        number(&mut actions, ((2 - 1) << 2) | 2); // SourceCopy "GH" from 6
+        number(&mut actions, 6 << 1);
+        number(&mut actions, ((2 - 1) << 2) | 2); // SourceCopy "AB" from 8 - 8
+        number(&mut actions, (8 << 1) | 1);
+        let patch = bps(source, target, &actions);
+
+        assert_eq!(apply(source, &patch).unwrap(), target);
+        assert_eq!(
+            apply(b"ABCDEFGX", &patch),
+            Err(PatchError::WrongSource {
+                expected: crc32(source),
+                found: crc32(b"ABCDEFGX")
+            })
+        );
+
+        let mut damaged = patch.clone();
+        damaged[6] ^= 1;
+        assert_eq!(apply(source, &damaged), Err(PatchError::Corrupt));
+
+        let wrong_target = bps(source, b"ABCDxyxyxyGHAC", &actions);
+        assert_eq!(apply(source, &wrong_target), Err(PatchError::BadOutput));
+    }
+}
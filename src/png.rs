@@ -0,0 +1,95 @@
+//! Minimal PNG encoding, for images exported to artists and documentation.
+//!
+//! [`encode_rgba`] writes 8-bit RGBA images with the pixel data in uncompressed (stored) deflate
+//! blocks. The files are bigger than a real encoder's, but any PNG reader opens them and no
+//! compression library is needed. Recompress them with an optimizer if size matters.
+
+use crate::hash::{adler32, crc32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const COLOR_TYPE_RGBA: u8 = 6;
+/// Largest payload of one stored deflate block
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes a `width` x `height` image of packed `R, G, B, A` bytes, row major from the top left
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    assert!(width > 0 && height > 0, "PNG images can't be empty");
+    let row_len = width as usize * 4;
+    assert_eq!(rgba.len(), row_len * height as usize, "image size mismatch");
+
+    // Every row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in rgba.chunks_exact(row_len) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(tag);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data`, which must not be empty, in a zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK);
+    let mut out = Vec::with_capacity(2 + data.len() + blocks * 5 + 4);
+    // Deflate with a 32KB window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_rgba() {
+        let rgba: Vec<u8> = (0..300 * 70 * 4).map(|i| (i % 251) as u8).collect();
+        let png = encode_rgba(300, 70, &rgba);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 1, 44, 0, 0, 0, 70]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // Undo the stored blocks and check the rows came through intact
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let zlib = &png[41..41 + idat_len];
+        let mut raw = Vec::new();
+        let mut rest = &zlib[2..zlib.len() - 4];
+        while !rest.is_empty() {
+            let len = u16::from_le_bytes([rest[1], rest[2]]) as usize;
+            raw.extend_from_slice(&rest[5..5 + len]);
+            rest = &rest[5 + len..];
+        }
+        assert_eq!(raw.len(), 70 * (1 + 300 * 4));
+        assert_eq!(raw[0], 0);
+        assert_eq!(raw[1..1201], rgba[..1200]);
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&raw).to_be_bytes());
+    }
+}
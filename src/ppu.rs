@@ -0,0 +1,1103 @@
+//! The picture processing unit.
+//!
+//! The CPU talks to the [`Ppu`] through eight registers at `0x2000..=0x2007`, mirrored through
+//! `0x3FFF`:
+//!
+//! | Address | Register | |
+//! | :--- | :--- | :--- |
+//! | `0x2000` | PPUCTRL | base nametable, VRAM increment, pattern tables, sprite size, NMI enable |
+//! | `0x2001` | PPUMASK | greyscale, left edge clipping, background and sprite enable |
+//! | `0x2002` | PPUSTATUS | vblank, sprite 0 hit and sprite overflow; reading clears vblank |
+//! | `0x2003` | OAMADDR | OAM address for OAMDATA |
+//! | `0x2004` | OAMDATA | reads and writes OAM |
+//! | `0x2005` | PPUSCROLL | X then Y scroll |
+//! | `0x2006` | PPUADDR | high then low byte of the VRAM address |
+//! | `0x2007` | PPUDATA | reads and writes VRAM at the address, then increments it |
+//!
+//! Writing a page number to `0x4014` (OAM DMA, handled by the [`Bus`](crate::bus::Bus)) copies
+//! that page of CPU memory into OAM.
+//!
+//! #### PPU Memory Map
+//!
+//! |  | Start | End |
+//! | ---:  | :---: | :---: |
+//! | **Pattern tables** (CHR ROM or RAM) | `0x0000` | `0x2000` |
+//! | **Nametables** (2KB of VRAM, mirrored by the cartridge) | `0x2000` | `0x3F00` |
+//! | **Palettes** | `0x3F00` | `0x4000` |
+//!
+//! Debug viewers that redraw what changed, rather than decoding all of VRAM every frame, can
+//! turn on [`Ppu::track_changes`] and collect the [`VramChanges`] after each frame. Likewise
+//! [`Ppu::track_timing`] records when sprite 0 hit, NMI and register writes happened, for the
+//! [timing overlay](crate::timing_overlay), and [`Ppu::track_chr_switches`] when the board
+//! switched CHR banks, for the [CHR bank inspector](crate::chr_banks).
+//!
+//! Timing is kept per dot so vblank, NMI and sprite 0 hit land on the right CPU cycle, but the
+//! picture is drawn all at once by [`Ppu::render`] at the end of each frame. Changes made
+//! mid-frame (split scrolling, palette swaps) only show up in the next frame.
+
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::frame::{Frame, HEIGHT, WIDTH};
+use crate::rom::Mirroring;
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+const CHR_RAM_SIZE: usize = 0x2000;
+/// Enough VRAM for four screen boards; the rest use the first 2KB
+const VRAM_SIZE: usize = 0x1000;
+const DOTS_PER_SCANLINE: u16 = 341;
+const VBLANK_SCANLINE: u16 = 241;
+/// Scanlines per frame, including the pre-render line
+const NTSC_SCANLINES: u16 = 262;
+const PAL_SCANLINES: u16 = 312;
+/// PPU dots per CPU cycle, in fifths
+const NTSC_DOTS_PER_CYCLE: u64 = 15;
+const PAL_DOTS_PER_CYCLE: u64 = 16;
+
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITES_8X16: u8 = 0b0010_0000;
+const CTRL_NMI: u8 = 0b1000_0000;
+
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+/// Show the background in the leftmost 8 pixels; games clear it to hide scrolling seams
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+/// Show sprites in the leftmost 8 pixels, so they can slide in from the edge
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+/// Sprites drawn on one scanline before the rest are dropped
+const SPRITES_PER_LINE: usize = 8;
+
+/// What [`Ppu::render`] draws, for debugging. Turning a layer off hides it without changing any
+/// emulated state, unlike a game clearing its PPUMASK bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Background,
+    Sprites,
+}
+
+/// Something [`Ppu::track_timing`] records, and where the PPU was when it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimingEvent {
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: TimingKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimingKind {
+    SpriteZeroHit,
+    /// The PPU raised an NMI, at vblank or when PPUCTRL enabled it during vblank
+    Nmi,
+    /// A CPU write to register `0x2000 + (addr & 7)`
+    RegisterWrite {
+        addr: u16,
+        value: u8,
+    },
+    /// An OAM DMA copy through `0x4014`
+    OamDma,
+}
+
+/// A CHR bank a board copied into the pattern tables, recorded by [`Ppu::track_chr_switches`]
+/// with where the PPU was when it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChrSwitch {
+    pub scanline: u16,
+    pub dot: u16,
+    /// Where in the pattern tables the bank went
+    pub addr: u16,
+    /// Bytes, e.g. `0x400` for a 1KB bank
+    pub size: usize,
+    /// Bank number in units of `size`, as the board picked it; numbers past the end of CHR ROM
+    /// wrap around
+    pub bank: usize,
+}
+
+/// Pattern table tiles, nametable bytes, palette entries and sprites written since the last
+/// [`Ppu::take_changes`]. Nametable writes show up at every address mirroring the byte written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VramChanges {
+    /// One bit per 16 byte tile in `0x0000..0x2000`
+    tiles: [u64; 8],
+    /// One bit per byte in `0x2000..0x3000`
+    nametables: [u64; 64],
+    palette: u32,
+    sprites: u64,
+}
+
+impl Default for VramChanges {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl VramChanges {
+    pub fn none() -> Self {
+        Self {
+            tiles: [0; 8],
+            nametables: [0; 64],
+            palette: 0,
+            sprites: 0,
+        }
+    }
+
+    /// Everything changed, as after loading a cartridge or a save state
+    pub fn all() -> Self {
+        Self {
+            tiles: [!0; 8],
+            nametables: [!0; 64],
+            palette: !0,
+            sprites: !0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::none()
+    }
+
+    /// Tile numbers (`0..512`, tile `n` at `n * 16`) whose pattern data changed
+    pub fn tiles(&self) -> impl Iterator<Item = usize> + '_ {
+        set_bits(&self.tiles)
+    }
+
+    /// Nametable and attribute addresses (`0x2000..0x3000`) whose byte changed
+    pub fn nametable_addrs(&self) -> impl Iterator<Item = u16> + '_ {
+        set_bits(&self.nametables).map(|i| 0x2000 + i as u16)
+    }
+
+    /// Palette RAM entries (`0..32`) that changed
+    pub fn palette_entries(&self) -> impl Iterator<Item = usize> {
+        word_bits(self.palette as u64)
+    }
+
+    /// OAM sprite numbers (`0..64`) whose entry changed
+    pub fn sprites(&self) -> impl Iterator<Item = usize> {
+        word_bits(self.sprites)
+    }
+}
+
+fn set_bit(words: &mut [u64], bit: usize) {
+    words[bit / 64] |= 1 << (bit % 64);
+}
+
+fn word_bits(word: u64) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |bit| word & (1 << bit) != 0)
+}
+
+fn set_bits(words: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    words
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &word)| word_bits(word).map(move |bit| i * 64 + bit))
+}
+
+#[derive(Clone)]
+pub struct Ppu {
+    /// Pattern tables: the cartridge's CHR ROM, or 8KB of CHR RAM for boards without one
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// Pattern tables a device between the console and the cartridge shows instead of `chr`
+    chr_overlay: Option<Arc<[u8]>>,
+    mirroring: Mirroring,
+    vram: [u8; VRAM_SIZE],
+    palette: [u8; 32],
+    /// Sprite attribute memory: 64 sprites of Y, tile, attributes and X
+    pub(crate) oam: [u8; 256],
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    scroll_x: u8,
+    scroll_y: u8,
+    /// VRAM address for PPUDATA
+    addr: u16,
+    /// Whether the next PPUSCROLL or PPUADDR write is the second of the pair
+    write_latch: bool,
+    /// PPUDATA reads return the byte read by the previous one
+    read_buffer: u8,
+    /// Last value written to any register, read back from the write-only ones
+    io_latch: u8,
+    scanline: u16,
+    dot: u16,
+    /// Fifths of a dot carried over from the last [`Ppu::tick`]
+    dot_fraction: u64,
+    pal: bool,
+    nmi_pending: bool,
+    show_background: bool,
+    show_sprites: bool,
+    changes: Option<Box<VramChanges>>,
+    timing: Option<Vec<TimingEvent>>,
+    chr_switches: Option<Vec<ChrSwitch>>,
+}
+
+/// The layer toggles, change, timing and CHR switch tracking are tooling state, not part of the emulated
+/// machine. The CHR overlay is hashed with the device that set it.
+impl Hash for Ppu {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.chr.hash(state);
+        self.vram.hash(state);
+        self.palette.hash(state);
+        self.oam.hash(state);
+        self.ctrl.hash(state);
+        self.mask.hash(state);
+        self.status.hash(state);
+        self.oam_addr.hash(state);
+        self.scroll_x.hash(state);
+        self.scroll_y.hash(state);
+        self.addr.hash(state);
+        self.write_latch.hash(state);
+        self.read_buffer.hash(state);
+        self.io_latch.hash(state);
+        self.scanline.hash(state);
+        self.dot.hash(state);
+        self.dot_fraction.hash(state);
+        self.nmi_pending.hash(state);
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    /// A PPU with CHR RAM and horizontal mirroring, until a cartridge is loaded
+    pub fn new() -> Self {
+        Self {
+            chr: vec![0; CHR_RAM_SIZE],
+            chr_is_ram: true,
+            chr_overlay: None,
+            mirroring: Mirroring::Horizontal,
+            vram: [0; VRAM_SIZE],
+            palette: [0; 32],
+            oam: [0; 256],
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            scroll_x: 0,
+            scroll_y: 0,
+            addr: 0,
+            write_latch: false,
+            read_buffer: 0,
+            io_latch: 0,
+            // Frames start at vblank, so a frame's picture is complete when it ends
+            scanline: VBLANK_SCANLINE,
+            dot: 0,
+            dot_fraction: 0,
+            pal: false,
+            nmi_pending: false,
+            show_background: true,
+            show_sprites: true,
+            changes: None,
+            timing: None,
+            chr_switches: None,
+        }
+    }
+
+    /// Connects a cartridge's pattern tables and nametable mirroring. An empty `chr_rom` means
+    /// the board has 8KB of CHR RAM.
+    pub fn load_cartridge(&mut self, chr_rom: &[u8], mirroring: Mirroring) {
+        self.chr_is_ram = chr_rom.is_empty();
+        self.chr = if self.chr_is_ram {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            chr_rom.to_vec()
+        };
+        self.mirroring = mirroring;
+        self.mark_all_changed();
+    }
+
+    /// Copies a bank of CHR ROM into the pattern tables at `addr`, for boards that switch banks
+    pub(crate) fn map_chr(&mut self, addr: u16, bank: &[u8]) {
+        let start = addr as usize;
+        self.chr[start..start + bank.len()].copy_from_slice(bank);
+        if let Some(changes) = &mut self.changes {
+            for tile in start / 16..(start + bank.len()) / 16 {
+                set_bit(&mut changes.tiles, tile);
+            }
+        }
+    }
+
+    /// Shows `chr` instead of the cartridge's pattern tables, which keep their contents and take
+    /// no writes until the overlay is removed with `None`
+    pub(crate) fn set_chr_overlay(&mut self, chr: Option<Arc<[u8]>>) {
+        self.chr_overlay = chr;
+        self.mark_all_changed();
+    }
+
+    pub(crate) fn has_chr_overlay(&self) -> bool {
+        self.chr_overlay.is_some()
+    }
+
+    /// Changes the nametable mirroring, for boards that switch it
+    pub(crate) fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if self.mirroring != mirroring {
+            self.mirroring = mirroring;
+            self.mark_all_changed();
+        }
+    }
+
+    /// Puts every register and memory back in its power on state, keeping the cartridge and the
+    /// debugging toggles
+    pub fn power_on(&mut self) {
+        let mut chr = std::mem::take(&mut self.chr);
+        if self.chr_is_ram {
+            chr.fill(0);
+        }
+        *self = Self {
+            chr,
+            chr_is_ram: self.chr_is_ram,
+            mirroring: self.mirroring,
+            pal: self.pal,
+            show_background: self.show_background,
+            show_sprites: self.show_sprites,
+            changes: self.changes.take(),
+            timing: self.timing.take(),
+            chr_switches: self.chr_switches.take(),
+            ..Self::new()
+        };
+        self.mark_all_changed();
+    }
+
+    /// Starts or stops recording which parts of VRAM and OAM are written, for viewers that
+    /// redraw incrementally
+    pub fn track_changes(&mut self, enabled: bool) {
+        self.changes = enabled.then(|| Box::new(VramChanges::none()));
+    }
+
+    /// Returns what changed since the last call, or since tracking started. Everything counts as
+    /// changed after a new cartridge or save state is loaded. Empty while not tracking.
+    pub fn take_changes(&mut self) -> VramChanges {
+        match &mut self.changes {
+            Some(changes) => std::mem::take(changes.as_mut()),
+            None => VramChanges::none(),
+        }
+    }
+
+    /// Starts or stops recording [`TimingEvent`]s
+    pub fn track_timing(&mut self, enabled: bool) {
+        self.timing = enabled.then(Vec::new);
+    }
+
+    /// Returns the events since the last call, oldest first. Empty while not tracking.
+    pub fn take_timing(&mut self) -> Vec<TimingEvent> {
+        match &mut self.timing {
+            Some(events) => std::mem::take(events),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts or stops recording [`ChrSwitch`]es
+    pub fn track_chr_switches(&mut self, enabled: bool) {
+        self.chr_switches = enabled.then(Vec::new);
+    }
+
+    /// Returns the CHR bank switches since the last call, oldest first. Empty while not
+    /// tracking.
+    pub fn take_chr_switches(&mut self) -> Vec<ChrSwitch> {
+        match &mut self.chr_switches {
+            Some(switches) => std::mem::take(switches),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn record_chr_switch(&mut self, addr: u16, size: usize, bank: usize) {
+        if let Some(switches) = &mut self.chr_switches {
+            switches.push(ChrSwitch {
+                scanline: self.scanline,
+                dot: self.dot,
+                addr,
+                size,
+                bank,
+            });
+        }
+    }
+
+    fn record_timing(&mut self, kind: TimingKind) {
+        if let Some(events) = &mut self.timing {
+            events.push(TimingEvent {
+                scanline: self.scanline,
+                dot: self.dot,
+                kind,
+            });
+        }
+    }
+
+    fn mark_all_changed(&mut self) {
+        if let Some(changes) = &mut self.changes {
+            **changes = VramChanges::all();
+        }
+    }
+
+    fn mark_sprite_changed(&mut self, oam_addr: u8) {
+        if let Some(changes) = &mut self.changes {
+            changes.sprites |= 1 << (oam_addr / 4);
+        }
+    }
+
+    /// Shows or hides a layer in [`Ppu::render`]'s output; both start enabled
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        match layer {
+            Layer::Background => self.show_background = enabled,
+            Layer::Sprites => self.show_sprites = enabled,
+        }
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Background => self.show_background,
+            Layer::Sprites => self.show_sprites,
+        }
+    }
+
+    /// Switches between NTSC and PAL (2A07) timing
+    pub fn set_pal(&mut self, pal: bool) {
+        self.pal = pal;
+    }
+
+    /// Sprite attribute memory: 64 sprites of Y, tile, attributes and X
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// Whether PPUMASK has the background or sprites switched on
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Whether an NMI is raised and waiting for [`Ppu::take_nmi`]
+    pub(crate) fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    /// Whether the PPU has raised an NMI since the last call
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// Advances the PPU by `cpu_cycles` CPU cycles: 3 dots each on NTSC, 3.2 on PAL
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        let per_cycle = if self.pal {
+            PAL_DOTS_PER_CYCLE
+        } else {
+            NTSC_DOTS_PER_CYCLE
+        };
+        self.dot_fraction += cpu_cycles * per_cycle;
+        let mut dots = self.dot_fraction / 5;
+        self.dot_fraction %= 5;
+
+        let scanlines = if self.pal {
+            PAL_SCANLINES
+        } else {
+            NTSC_SCANLINES
+        };
+        while dots > 0 {
+            let step = dots.min((DOTS_PER_SCANLINE - self.dot) as u64) as u16;
+            let before = self.dot;
+            self.dot += step;
+            dots -= step as u64;
+            if self.scanline == VBLANK_SCANLINE && before < 1 && self.dot >= 1 {
+                self.status |= STATUS_VBLANK;
+                if self.ctrl & CTRL_NMI != 0 {
+                    self.nmi_pending = true;
+                    self.record_timing(TimingKind::Nmi);
+                }
+            }
+            if self.scanline == scanlines - 1 && before < 1 && self.dot >= 1 {
+                self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_OVERFLOW);
+            }
+            self.check_sprite_zero_hit();
+            if self.dot == DOTS_PER_SCANLINE {
+                self.dot = 0;
+                self.scanline = (self.scanline + 1) % scanlines;
+            }
+        }
+    }
+
+    /// Approximates sprite 0 hit as happening once the PPU passes sprite 0's top left corner with
+    /// both layers on, rather than at its first opaque pixel over opaque background. As on
+    /// hardware there's no hit in the leftmost 8 pixels while either layer is clipped there, or
+    /// at x 255, so the corner counts from the first column a hit can happen in.
+    fn check_sprite_zero_hit(&mut self) {
+        let rendering = MASK_BACKGROUND | MASK_SPRITES;
+        if self.status & STATUS_SPRITE_ZERO_HIT != 0 || self.mask & rendering != rendering {
+            return;
+        }
+        let (top, left) = (self.oam[0] as u16 + 1, self.oam[3] as u16);
+        let unclipped = MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT;
+        let left = if self.mask & unclipped == unclipped {
+            left
+        } else {
+            left.max(8)
+        };
+        if left >= (self.oam[3] as u16 + 8).min(WIDTH as u16 - 1) {
+            return;
+        }
+        let visible = (self.scanline as usize) < HEIGHT;
+        if visible && (self.scanline > top || (self.scanline == top && self.dot > left)) {
+            self.status |= STATUS_SPRITE_ZERO_HIT;
+            self.record_timing(TimingKind::SpriteZeroHit);
+        }
+    }
+
+    /// Reads register `0x2000 + (addr & 7)`, with its side effects
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        match addr & 7 {
+            2 => {
+                let value = self.peek_register(addr);
+                self.status &= !STATUS_VBLANK;
+                self.write_latch = false;
+                value
+            }
+            4 => self.oam[self.oam_addr as usize],
+            7 => {
+                let value = self.peek_register(addr);
+                let addr = self.addr;
+                self.read_buffer = match addr & 0x3FFF {
+                    // Palette reads aren't buffered, but still fill the buffer from the nametable
+                    // underneath
+                    0x3F00.. => self.read_vram(addr - 0x1000),
+                    _ => self.read_vram(addr),
+                };
+                self.increment_addr();
+                value
+            }
+            _ => self.io_latch,
+        }
+    }
+
+    /// Returns what [`Ppu::read_register`] would without side effects
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr & 7 {
+            2 => self.status | (self.io_latch & 0x1F),
+            4 => self.oam[self.oam_addr as usize],
+            7 => match self.addr & 0x3FFF {
+                0x3F00.. => self.read_vram(self.addr),
+                _ => self.read_buffer,
+            },
+            _ => self.io_latch,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.io_latch = data;
+        self.record_timing(TimingKind::RegisterWrite {
+            addr: 0x2000 + (addr & 7),
+            value: data,
+        });
+        match addr & 7 {
+            0 => {
+                // Enabling NMI during vblank raises one straight away
+                let enabling = self.ctrl & CTRL_NMI == 0 && data & CTRL_NMI != 0;
+                if enabling && self.status & STATUS_VBLANK != 0 {
+                    self.nmi_pending = true;
+                    self.record_timing(TimingKind::Nmi);
+                }
+                self.ctrl = data;
+            }
+            1 => self.mask = data,
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.mark_sprite_changed(self.oam_addr);
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => {
+                if self.write_latch {
+                    self.scroll_y = data;
+                } else {
+                    self.scroll_x = data;
+                }
+                self.write_latch = !self.write_latch;
+            }
+            6 => {
+                self.addr = if self.write_latch {
+                    (self.addr & 0xFF00) | data as u16
+                } else {
+                    ((data as u16 & 0x3F) << 8) | (self.addr & 0x00FF)
+                };
+                self.write_latch = !self.write_latch;
+            }
+            7 => {
+                self.write_vram(self.addr, data);
+                self.increment_addr();
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies a page of CPU memory into OAM, starting at OAMADDR
+    pub fn write_oam_dma(&mut self, page: &[u8; 256]) {
+        self.record_timing(TimingKind::OamDma);
+        for &byte in page {
+            self.oam[self.oam_addr as usize] = byte;
+            self.mark_sprite_changed(self.oam_addr);
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    fn increment_addr(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.addr = self.addr.wrapping_add(step) & 0x3FFF;
+    }
+
+    /// Reads PPU memory without side effects
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => match &self.chr_overlay {
+                Some(chr) => chr[addr as usize % chr.len()],
+                None => self.chr[addr as usize % self.chr.len()],
+            },
+            addr @ 0x2000..=0x3EFF => self.vram[self.nametable_index(addr)],
+            addr => self.palette[palette_index(addr)],
+        }
+    }
+
+    fn write_vram(&mut self, addr: u16, data: u8) {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => {
+                if self.chr_is_ram && self.chr_overlay.is_none() {
+                    let len = self.chr.len();
+                    self.chr[addr as usize % len] = data;
+                    if let Some(changes) = &mut self.changes {
+                        set_bit(&mut changes.tiles, addr as usize / 16);
+                    }
+                }
+            }
+            addr @ 0x2000..=0x3EFF => {
+                let index = self.nametable_index(addr);
+                self.vram[index] = data;
+                self.mark_nametable_changed(index);
+            }
+            addr => {
+                let index = palette_index(addr);
+                self.palette[index] = data & 0x3F;
+                if let Some(changes) = &mut self.changes {
+                    changes.palette |= 1 << index;
+                    // So do the sprite palette entries mirroring it
+                    if index.is_multiple_of(4) {
+                        changes.palette |= 1 << (index + 0x10);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks every nametable address that mirrors VRAM byte `index`
+    fn mark_nametable_changed(&mut self, index: usize) {
+        let offset = index % 0x400;
+        let mirrors: [bool; 4] = std::array::from_fn(|table| {
+            self.nametable_index(0x2000 + (table * 0x400 + offset) as u16) == index
+        });
+        if let Some(changes) = &mut self.changes {
+            for table in (0..4).filter(|&table| mirrors[table]) {
+                set_bit(&mut changes.nametables, table * 0x400 + offset);
+            }
+        }
+    }
+
+    /// Index into VRAM of a nametable address, after the cartridge's mirroring
+    fn nametable_index(&self, addr: u16) -> usize {
+        let addr = (addr - 0x2000) & 0x0FFF;
+        let (table, offset) = (addr / 0x400, addr % 0x400);
+        let physical = match self.mirroring {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::FourScreen => table,
+        };
+        (physical * 0x400 + offset) as usize
+    }
+
+    /// The 2 bit color of pixel (`x`, `y`) of a tile starting at `tile_addr` in the pattern tables
+    pub(crate) fn tile_pixel(&self, tile_addr: u16, x: u16, y: u16) -> u8 {
+        let lo = self.read_vram(tile_addr + y);
+        let hi = self.read_vram(tile_addr + y + 8);
+        let bit = 7 - x;
+        (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+    }
+
+    /// Draws the current picture into `frame` as palette indices
+    pub fn render(&self, frame: &mut Frame) {
+        let grey = if self.mask & MASK_GREYSCALE != 0 {
+            0x30
+        } else {
+            0x3F
+        };
+        let backdrop = self.palette[0] & grey;
+        let background = self.mask & MASK_BACKGROUND != 0 && self.show_background;
+        let sprites = self.mask & MASK_SPRITES != 0 && self.show_sprites;
+        // The first column each layer shows in
+        let clip = |bit| if self.mask & bit != 0 { 0 } else { 8 };
+        let (background_left, sprites_left) = (clip(MASK_BACKGROUND_LEFT), clip(MASK_SPRITES_LEFT));
+
+        for y in 0..HEIGHT {
+            let row = &mut frame.pixels[y * WIDTH..(y + 1) * WIDTH];
+            row.fill(backdrop);
+            let mut opaque = [false; WIDTH];
+            if background {
+                self.render_background_row(y, row, &mut opaque, grey);
+                row[..background_left].fill(backdrop);
+                opaque[..background_left].fill(false);
+            }
+            if sprites {
+                self.render_sprite_row(y, row, &opaque, grey, sprites_left);
+            }
+        }
+    }
+
+    fn render_background_row(&self, y: usize, row: &mut [u8], opaque: &mut [bool], grey: u8) {
+        let table = self.background_table();
+        let base = self.ctrl & CTRL_NAMETABLE;
+        // Scroll across the 2x2 nametables, 512x480 pixels
+        let sy = (y + self.scroll_y as usize + (base as usize >> 1) * HEIGHT) % (2 * HEIGHT);
+        let (table_y, tile_y, fine_y) = (sy / HEIGHT, (sy % HEIGHT) / 8, sy % 8);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let sx = (x + self.scroll_x as usize + (base as usize & 1) * WIDTH) % (2 * WIDTH);
+            let (table_x, tile_x, fine_x) = (sx / WIDTH, (sx % WIDTH) / 8, sx % 8);
+            let nametable = 0x2000 + (table_y * 2 + table_x) as u16 * 0x400;
+
+            let tile = self.read_vram(nametable + (tile_y * 32 + tile_x) as u16) as u16;
+            let color = self.tile_pixel(table + tile * 16, fine_x as u16, fine_y as u16);
+            if color == 0 {
+                continue;
+            }
+            let palette = self.tile_palette(nametable, tile_x, tile_y);
+            *pixel = self.palette[(palette * 4 + color) as usize] & grey;
+            opaque[x] = true;
+        }
+    }
+
+    /// Draws the sprites on line `y` from column `first` on
+    fn render_sprite_row(
+        &self,
+        y: usize,
+        row: &mut [u8],
+        background: &[bool],
+        grey: u8,
+        first: usize,
+    ) {
+        let height = self.sprite_height();
+        // The lowest numbered sprite with an opaque pixel wins, whatever its priority
+        let mut drawn = [false; WIDTH];
+        let sprites = self.oam.chunks_exact(4).filter(|sprite| {
+            let top = sprite[0] as usize + 1;
+            (top..top + height).contains(&y)
+        });
+        for sprite in sprites.take(SPRITES_PER_LINE) {
+            let sprite: &[u8; 4] = sprite.try_into().unwrap();
+            let (top, attributes, left) = (sprite[0] as usize + 1, sprite[2], sprite[3]);
+            let line = (y - top) as u16;
+            let behind = attributes & 0x20 != 0;
+            let palette = 0x10 + (attributes & 0b11) as usize * 4;
+
+            for column in 0..8u16 {
+                let x = left as usize + column as usize;
+                if x < first || x >= WIDTH || drawn[x] {
+                    continue;
+                }
+                let color = self.sprite_pixel(sprite, column, line);
+                if color == 0 {
+                    continue;
+                }
+                drawn[x] = true;
+                if !(behind && background[x]) {
+                    row[x] = self.palette[palette + color as usize] & grey;
+                }
+            }
+        }
+    }
+
+    /// Address of the pattern table background tiles come from, `0x0000` or `0x1000`
+    pub fn background_table(&self) -> u16 {
+        if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        }
+    }
+
+    /// The background palette (0-3) the attribute table gives tile (`tile_x`, `tile_y`) of the
+    /// nametable at `nametable`
+    pub fn tile_palette(&self, nametable: u16, tile_x: usize, tile_y: usize) -> u8 {
+        let attribute = self.read_vram(nametable + 0x3C0 + (tile_y / 4 * 8 + tile_x / 4) as u16);
+        let shift = (tile_y % 4 / 2) * 4 + (tile_x % 4 / 2) * 2;
+        (attribute >> shift) & 0b11
+    }
+
+    /// Sprite height in pixels, 8 or 16 as set in PPUCTRL
+    pub fn sprite_height(&self) -> usize {
+        if self.ctrl & CTRL_SPRITES_8X16 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The 2 bit color of pixel (`column`, `line`) of a sprite's OAM entry, counted from its top
+    /// left corner on screen after flipping
+    pub(crate) fn sprite_pixel(&self, sprite: &[u8; 4], column: u16, line: u16) -> u8 {
+        let (tile, attributes) = (sprite[1], sprite[2]);
+        let height = self.sprite_height() as u16;
+        let line = if attributes & 0x80 != 0 {
+            height - 1 - line
+        } else {
+            line
+        };
+        let column = if attributes & 0x40 != 0 {
+            7 - column
+        } else {
+            column
+        };
+        let tile_addr = if height == 16 {
+            // Bit 0 picks the pattern table, the rest the top tile of the pair
+            (tile as u16 & 1) * 0x1000 + ((tile & 0xFE) as u16 + line / 8) * 16
+        } else if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+            0x1000 + tile as u16 * 16
+        } else {
+            tile as u16 * 16
+        };
+        self.tile_pixel(tile_addr, column, line % 8)
+    }
+
+    /// Writes all emulated PPU state to a save-state chunk. CHR ROM comes from the cartridge and
+    /// isn't saved; CHR RAM is.
+    pub fn save_state(&self, w: &mut ChunkWriter) {
+        if self.chr_is_ram {
+            w.write_bytes(&self.chr);
+        }
+        w.write_bytes(&self.vram);
+        w.write_bytes(&self.palette);
+        w.write_bytes(&self.oam);
+        for value in [
+            self.ctrl,
+            self.mask,
+            self.status,
+            self.oam_addr,
+            self.scroll_x,
+            self.scroll_y,
+            self.read_buffer,
+            self.io_latch,
+            self.dot_fraction as u8,
+        ] {
+            w.write_u8(value);
+        }
+        w.write_u16(self.addr);
+        w.write_bool(self.write_latch);
+        w.write_u16(self.scanline);
+        w.write_u16(self.dot);
+        w.write_bool(self.nmi_pending);
+    }
+
+    pub fn load_state(&mut self, r: &mut ChunkReader) -> Result<(), StateError> {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr.copy_from_slice(r.read_bytes(len)?);
+        }
+        self.vram.copy_from_slice(r.read_bytes(VRAM_SIZE)?);
+        self.palette.copy_from_slice(r.read_bytes(32)?);
+        self.oam.copy_from_slice(r.read_bytes(256)?);
+        self.ctrl = r.read_u8()?;
+        self.mask = r.read_u8()?;
+        self.status = r.read_u8()?;
+        self.oam_addr = r.read_u8()?;
+        self.scroll_x = r.read_u8()?;
+        self.scroll_y = r.read_u8()?;
+        self.read_buffer = r.read_u8()?;
+        self.io_latch = r.read_u8()?;
+        self.dot_fraction = r.read_u8()? as u64;
+        self.addr = r.read_u16()?;
+        self.write_latch = r.read_bool()?;
+        self.scanline = r.read_u16()?;
+        self.dot = r.read_u16()?;
+        self.nmi_pending = r.read_bool()?;
+        self.mark_all_changed();
+        Ok(())
+    }
+}
+
+/// Index into palette RAM; the sprite palettes' first entries mirror the background ones
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index >= 0x10 && index.is_multiple_of(4) {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hash::Fnv1a;
+
+    fn set_addr(ppu: &mut Ppu, addr: u16) {
+        ppu.write_register(0x2006, (addr >> 8) as u8);
+        ppu.write_register(0x2006, addr as u8);
+    }
+
+    fn write(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        set_addr(ppu, addr);
+        for &byte in data {
+            ppu.write_register(0x2007, byte);
+        }
+    }
+
+    #[test]
+    fn test_ppudata_reads_are_buffered() {
+        let mut ppu = Ppu::new();
+        write(&mut ppu, 0x2108, &[0xAA, 0xBB]);
+        write(&mut ppu, 0x3F00, &[0x0F]);
+        // Sprite palette 0's first entry mirrors the backdrop
+        write(&mut ppu, 0x3F10, &[0x21]);
+
+        set_addr(&mut ppu, 0x2108);
+        ppu.read_register(0x2007); // stale buffer
+        assert_eq!(ppu.read_register(0x2007), 0xAA);
+        assert_eq!(ppu.read_register(0x2007), 0xBB);
+
+        set_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_register(0x2007), 0x21);
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(0x2000, CTRL_NMI);
+        ppu.tick(1);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, STATUS_VBLANK);
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, 0);
+
+        // Vblank comes back 89342 dots later, 29780.67 CPU cycles
+        ppu.tick(29779);
+        assert!(!ppu.take_nmi());
+        ppu.tick(1);
+        assert!(ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_change_tracking() {
+        let mut ppu = Ppu::new();
+        ppu.track_changes(true);
+        ppu.load_cartridge(&[], Mirroring::Vertical);
+        assert_eq!(ppu.take_changes(), VramChanges::all());
+
+        write(&mut ppu, 0x0031, &[0xFF]);
+        write(&mut ppu, 0x2005, &[0x01]);
+        write(&mut ppu, 0x3F10, &[0x0F, 0x16]);
+        ppu.write_register(0x2003, 5);
+        ppu.write_register(0x2004, 0x42);
+
+        let changes = ppu.take_changes();
+        assert_eq!(changes.tiles().collect::<Vec<_>>(), [3]);
+        assert_eq!(
+            changes.nametable_addrs().collect::<Vec<_>>(),
+            [0x2005, 0x2805]
+        );
+        assert_eq!(
+            changes.palette_entries().collect::<Vec<_>>(),
+            [0x00, 0x10, 0x11]
+        );
+        assert_eq!(changes.sprites().collect::<Vec<_>>(), [1]);
+        assert!(ppu.take_changes().is_empty());
+    }
+
+    #[test]
+    fn test_layer_toggles() {
+        let mut ppu = Ppu::new();
+        // Tile 1 is solid color 1
+        write(&mut ppu, 0x0010, &[0xFF; 8]);
+        write(&mut ppu, 0x2000, &[0x01]);
+        write(&mut ppu, 0x3F00, &[0x0F, 0x16]);
+        write(&mut ppu, 0x3F11, &[0x2A]);
+        // Sprite 0 at (100, 100)
+        ppu.oam[..4].copy_from_slice(&[99, 0x01, 0x00, 100]);
+        ppu.write_register(
+            0x2001,
+            MASK_BACKGROUND | MASK_SPRITES | MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT,
+        );
+
+        let mut frame = Frame::new();
+        ppu.render(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), 0x16);
+        assert_eq!(frame.get_pixel(8, 0), 0x0F);
+        assert_eq!(frame.get_pixel(100, 100), 0x2A);
+
+        let hash = Fnv1a::hash_of(&ppu);
+        ppu.set_layer_enabled(Layer::Background, false);
+        ppu.render(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), 0x0F);
+        assert_eq!(frame.get_pixel(100, 100), 0x2A);
+
+        ppu.set_layer_enabled(Layer::Background, true);
+        ppu.set_layer_enabled(Layer::Sprites, false);
+        ppu.render(&mut frame);
+        assert_eq!(frame.get_pixel(0, 0), 0x16);
+        assert_eq!(frame.get_pixel(100, 100), 0x0F);
+        assert!(!ppu.layer_enabled(Layer::Sprites));
+        assert_eq!(Fnv1a::hash_of(&ppu), hash);
+    }
+
+    #[test]
+    fn test_left_edge_clipping() {
+        let mut ppu = Ppu::new();
+        write(&mut ppu, 0x0010, &[0xFF; 8]);
+        write(&mut ppu, 0x2000, &[0x01, 0x01]);
+        write(&mut ppu, 0x3F00, &[0x0F, 0x16]);
+        write(&mut ppu, 0x3F11, &[0x2A]);
+        // Sprite 0 at (4, 100), half in the clipped columns
+        ppu.oam[..4].copy_from_slice(&[99, 0x01, 0x00, 4]);
+        ppu.write_register(0x2001, MASK_BACKGROUND | MASK_SPRITES);
+
+        let mut frame = Frame::new();
+        ppu.render(&mut frame);
+        assert_eq!(frame.get_pixel(7, 0), 0x0F);
+        assert_eq!(frame.get_pixel(8, 0), 0x16);
+        assert_eq!(frame.get_pixel(7, 100), 0x0F);
+        assert_eq!(frame.get_pixel(8, 100), 0x2A);
+
+        // The hit waits for column 8, and can't happen at all for a sprite entirely left of it
+        let run_to = |ppu: &mut Ppu, line, dot| {
+            while ppu.scanline() != line || ppu.dot() < dot {
+                ppu.tick(1);
+            }
+        };
+        run_to(&mut ppu, 100, 6);
+        assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+        run_to(&mut ppu, 100, 10);
+        assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+
+        let mut ppu = Ppu::new();
+        ppu.oam[..4].copy_from_slice(&[99, 0x01, 0x00, 0]);
+        ppu.write_register(0x2001, MASK_BACKGROUND | MASK_SPRITES | MASK_SPRITES_LEFT);
+        run_to(&mut ppu, 120, 0);
+        assert_eq!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+        ppu.write_register(0x2001, 0x1E);
+        ppu.tick(1);
+        assert_ne!(ppu.peek_register(0x2002) & STATUS_SPRITE_ZERO_HIT, 0);
+    }
+}
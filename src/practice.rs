@@ -0,0 +1,194 @@
+//! Practice mode: replaying one section of a game over and over.
+//!
+//! A [`PracticeLoop`] keeps a savepoint and watches a RAM value for the player dying, the same
+//! [`Filter`]s a cheat search uses read against a [`RamField`]. On a death it loads the savepoint
+//! straight back. [`Preset`]s are written into RAM every time the savepoint loads, for things like
+//! infinite lives or a fixed power up, so a frontend doesn't need a script per game.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::practice::PracticeLoop;
+//! use nes_emulator::ram_map::{RamField, Width};
+//! use nes_emulator::ram_search::Filter;
+//!
+//! let lives = RamField::new(0x075A, Width::U8);
+//! let mut practice = PracticeLoop::new()
+//!     .death_when(lives.clone(), Filter::Decreased)
+//!     .preset(lives, 9);
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]); // loop: JMP loop
+//! practice.set_savepoint(&mut console);
+//! console.run_frame();
+//! if let Some(death) = practice.end_frame(&mut console)? {
+//!     println!("attempt {} lasted {} frames", death.attempt, death.frames);
+//! }
+//! # Ok::<(), nes_emulator::savestate::StateError>(())
+//! ```
+
+use crate::console::Console;
+use crate::ram_map::RamField;
+use crate::ram_search::Filter;
+use crate::savestate::StateError;
+
+/// A value written into RAM each time the savepoint loads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub field: RamField,
+    pub value: i64,
+}
+
+/// The player died and the savepoint was loaded again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Death {
+    /// Attempts since the savepoint was set, counting this one
+    pub attempt: u32,
+    /// Frames the attempt lasted
+    pub frames: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PracticeLoop {
+    death: Option<(RamField, Filter)>,
+    presets: Vec<Preset>,
+    savepoint: Option<Vec<u8>>,
+    /// Frame number the savepoint was taken at
+    start: u64,
+    /// The watched value at the end of the previous frame
+    previous: Option<i64>,
+    attempts: u32,
+}
+
+impl PracticeLoop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a death whenever `field` passes `filter` between one frame and the next, e.g. lives
+    /// [`Filter::Decreased`], or the player state [`Filter::Equals`] the dying animation
+    pub fn death_when(mut self, field: RamField, filter: Filter) -> Self {
+        self.death = Some((field, filter));
+        self
+    }
+
+    /// Writes `value` to `field` whenever the savepoint is set or loaded
+    pub fn preset(mut self, field: RamField, value: i64) -> Self {
+        self.presets.push(Preset { field, value });
+        self
+    }
+
+    pub fn presets(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    /// Applies the presets and saves the state to come back to, starting the attempt count over
+    pub fn set_savepoint(&mut self, console: &mut Console) {
+        self.apply_presets(console);
+        self.savepoint = Some(console.save_state());
+        self.start = console.frame();
+        self.previous = None;
+        self.attempts = 0;
+    }
+
+    pub fn savepoint(&self) -> Option<&[u8]> {
+        self.savepoint.as_deref()
+    }
+
+    pub fn clear_savepoint(&mut self) {
+        self.savepoint = None;
+        self.attempts = 0;
+    }
+
+    /// Attempts that ended in a death or [`PracticeLoop::restart`] since the savepoint was set
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Checks the frame just run for a death, loading the savepoint if there was one. Call after
+    /// each [`Console::run_frame`]. Does nothing without a savepoint or a death condition.
+    pub fn end_frame(&mut self, console: &mut Console) -> Result<Option<Death>, StateError> {
+        let (Some((field, filter)), Some(_)) = (&self.death, &self.savepoint) else {
+            return Ok(None);
+        };
+        let current = field.read(console.cpu());
+        let previous = self.previous.replace(current).unwrap_or(current);
+        if !filter.matches(previous, current) {
+            return Ok(None);
+        }
+        self.restart(console)
+    }
+
+    /// Ends the attempt and loads the savepoint, as a death would. Returns `None` without a
+    /// savepoint, and fails if it can't be loaded, e.g. because another ROM is in.
+    pub fn restart(&mut self, console: &mut Console) -> Result<Option<Death>, StateError> {
+        let Some(savepoint) = self.savepoint.as_deref() else {
+            return Ok(None);
+        };
+        let frames = console.frame().saturating_sub(self.start);
+        console.load_state(savepoint)?;
+        self.apply_presets(console);
+        self.previous = None;
+        self.attempts += 1;
+        Ok(Some(Death {
+            attempt: self.attempts,
+            frames,
+        }))
+    }
+
+    fn apply_presets(&self, console: &mut Console) {
+        for preset in &self.presets {
+            preset.field.write(console.cpu_mut(), preset.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ram_map::Width;
+
+    #[test]
+    fn test_reloads_on_death() {
+        let mut program = vec![0; 0x8000];
+        // Each frame's NMI counts frames in $10, and loses a life from $20 on the third
+        #[rustfmt::skip]
+        program[..0x14].copy_from_slice(&[
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80; STA $2000, enabling NMI
+            0x4c, 0x05, 0x80,             // JMP $8005
+            0xe6, 0x10,                   // NMI: INC $10
+            0xa5, 0x10,                   // LDA $10
+            0xc9, 0x03,                   // CMP #3
+            0xd0, 0x02,                   // BNE done
+            0xc6, 0x20,                   // DEC $20
+            0x40,                         // done: RTI
+            0x00,
+        ]);
+        program[0x7FFA..0x7FFC].copy_from_slice(&[0x08, 0x80]);
+
+        let mut console = Console::new();
+        console.load(&program);
+        let lives = RamField::new(0x0020, Width::U8);
+        let mut practice = PracticeLoop::new()
+            .death_when(lives.clone(), Filter::Decreased)
+            .preset(lives.clone(), 3);
+        assert_eq!(practice.end_frame(&mut console).unwrap(), None);
+        practice.set_savepoint(&mut console);
+        let counter = RamField::new(0x0010, Width::U8);
+        let start = counter.read(console.cpu());
+
+        let mut deaths = Vec::new();
+        for _ in 0..8 {
+            console.run_frame();
+            if let Some(death) = practice.end_frame(&mut console).unwrap() {
+                assert_eq!(counter.read(console.cpu()), start);
+                assert_eq!(lives.read(console.cpu()), 3);
+                deaths.push(death);
+            }
+        }
+        let frames = deaths[0].frames;
+        assert_eq!(
+            deaths,
+            [Death { attempt: 1, frames }, Death { attempt: 2, frames }]
+        );
+        assert_eq!(practice.attempts(), 2);
+    }
+}
@@ -0,0 +1,198 @@
+//! Named RAM fields for bots, scripts, and RL environments.
+//!
+//! A [`RamMap`] describes the interesting parts of a game's RAM (player position, lives, score, ...)
+//! once, so tooling can read a structured snapshot each frame instead of hand rolling peek offsets.
+//!
+//! ```
+//! use nes_emulator::ram_map::{RamField, RamMap, Width};
+//!
+//! let mut map = RamMap::new();
+//! map.register("lives", RamField::new(0x075A, Width::U8).description("Lives remaining"));
+//! map.register("speed", RamField::new(0x0057, Width::U8).signed());
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::cpu::Mem;
+
+/// Number of bytes a [`RamField`] spans. Multi-byte fields are read little endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    U8,
+    U16,
+    U32,
+}
+
+impl Width {
+    pub fn bytes(self) -> u16 {
+        match self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+        }
+    }
+}
+
+/// A single named value living in RAM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamField {
+    pub address: u16,
+    pub width: Width,
+    pub signed: bool,
+    pub description: String,
+}
+
+impl RamField {
+    /// An unsigned field of `width` bytes starting at `address`
+    pub fn new(address: u16, width: Width) -> Self {
+        Self {
+            address,
+            width,
+            signed: false,
+            description: String::new(),
+        }
+    }
+
+    /// Interpret the field as two's complement
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    /// Reads the current value of the field from `mem` without disturbing it
+    pub fn read(&self, mem: &impl Mem) -> i64 {
+        let bytes = self.width.bytes();
+        let mut value = 0u64;
+        for i in (0..bytes).rev() {
+            value = (value << 8) | mem.mem_peek(self.address.wrapping_add(i)) as u64;
+        }
+
+        if self.signed {
+            let shift = 64 - 8 * bytes as u32;
+            ((value << shift) as i64) >> shift // sign extend from the top bit of the field
+        } else {
+            value as i64
+        }
+    }
+
+    /// Writes `value` to the field in `mem`, keeping its low bytes if it doesn't fit
+    pub fn write(&self, mem: &mut impl Mem, value: i64) {
+        for i in 0..self.width.bytes() {
+            mem.mem_write(self.address.wrapping_add(i), (value >> (8 * i)) as u8);
+        }
+    }
+}
+
+/// A per game collection of [`RamField`]s keyed by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RamMap {
+    fields: BTreeMap<String, RamField>,
+}
+
+impl RamMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a field, replacing any field previously registered under `name`
+    pub fn register(&mut self, name: &str, field: RamField) {
+        self.fields.insert(name.to_string(), field);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<RamField> {
+        self.fields.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RamField> {
+        self.fields.get(name)
+    }
+
+    /// Iterates over the registered fields in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RamField)> {
+        self.fields
+            .iter()
+            .map(|(name, field)| (name.as_str(), field))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Reads every registered field from `mem`
+    pub fn observations(&self, mem: &impl Mem) -> HashMap<String, i64> {
+        self.fields
+            .iter()
+            .map(|(name, field)| (name.clone(), field.read(mem)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_read_unsigned_fields() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0010, 0xFE);
+        cpu.mem_write_u16(0x0020, 0x1234);
+
+        assert_eq!(RamField::new(0x0010, Width::U8).read(&cpu), 0xFE);
+        assert_eq!(RamField::new(0x0020, Width::U16).read(&cpu), 0x1234); // little endian
+    }
+
+    #[test]
+    fn test_read_signed_fields() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0010, 0xFE);
+        cpu.mem_write_u16(0x0020, 0x8000);
+        cpu.mem_write_u16(0x0030, 0xFFFF);
+        cpu.mem_write_u16(0x0032, 0xFFFF);
+
+        assert_eq!(RamField::new(0x0010, Width::U8).signed().read(&cpu), -2);
+        assert_eq!(
+            RamField::new(0x0020, Width::U16).signed().read(&cpu),
+            -32768
+        );
+        assert_eq!(RamField::new(0x0030, Width::U32).signed().read(&cpu), -1);
+    }
+
+    #[test]
+    fn test_write_fields() {
+        let mut cpu = CPU::new();
+        RamField::new(0x0020, Width::U16).write(&mut cpu, 0x1234);
+        RamField::new(0x0030, Width::U8)
+            .signed()
+            .write(&mut cpu, -2);
+
+        assert_eq!(cpu.mem_peek(0x0020), 0x34);
+        assert_eq!(cpu.mem_peek(0x0021), 0x12);
+        assert_eq!(cpu.mem_peek(0x0030), 0xFE);
+    }
+
+    #[test]
+    fn test_observations() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x075A, 3);
+        cpu.mem_write(0x0057, 0xF0);
+
+        let mut map = RamMap::new();
+        map.register("lives", RamField::new(0x075A, Width::U8));
+        map.register("speed", RamField::new(0x0057, Width::U8).signed());
+
+        let observations = map.observations(&cpu);
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations["lives"], 3);
+        assert_eq!(observations["speed"], -16);
+    }
+}
@@ -0,0 +1,224 @@
+//! Cheat search: finding where a game keeps a value by watching how RAM changes.
+//!
+//! A [`RamSearch`] starts with every address in internal RAM as a candidate. Each
+//! [`RamSearch::filter`] compares RAM now against the snapshot taken by the previous step and
+//! keeps only the candidates that match, so a few rounds of "lose a life, filter
+//! [`Filter::Decreased`]" narrow thousands of addresses down to the one holding the lives counter.
+//!
+//! | Filter | Keeps values that |
+//! | :--- | :--- |
+//! | [`Filter::Equals`] | are now exactly this |
+//! | [`Filter::Changed`] / [`Filter::Unchanged`] | differ from / match the snapshot |
+//! | [`Filter::Increased`] / [`Filter::Decreased`] | went up / down since the snapshot |
+//! | [`Filter::ChangedBy`] | moved by exactly this much since the snapshot |
+//!
+//! ```
+//! use nes_emulator::bus::Bus;
+//! use nes_emulator::cpu::Mem;
+//! use nes_emulator::ram_search::{Filter, RamSearch, SearchWidth};
+//!
+//! let mut ram = Bus::new();
+//! ram.mem_write(0x0075, 3); // lives
+//! let mut search = RamSearch::new(SearchWidth::U8, &ram);
+//! ram.mem_write(0x0075, 2);
+//! search.filter(&ram, Filter::Decreased);
+//! search.filter(&ram, Filter::Equals(2));
+//! assert_eq!(search.results(&ram)[0].addr, 0x0075);
+//! ```
+
+use std::ops::Range;
+
+use crate::bcd;
+use crate::cpu::Mem;
+
+/// Internal RAM, where nearly every game keeps its state
+const NES_RAM: Range<u16> = 0x0000..0x0800;
+
+/// How the bytes at a candidate address are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchWidth {
+    U8,
+    /// Two bytes, little endian
+    U16,
+    /// One byte of packed BCD (see [`bcd`]), `0x42` meaning 42. Bytes that aren't valid BCD never
+    /// match.
+    Bcd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Equals(i64),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    /// Changed by exactly this amount, negative for decreases
+    ChangedBy(i64),
+}
+
+impl Filter {
+    /// Whether a value that went from `previous` to `current` passes
+    pub fn matches(self, previous: i64, current: i64) -> bool {
+        match self {
+            Filter::Equals(value) => current == value,
+            Filter::Changed => current != previous,
+            Filter::Unchanged => current == previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::ChangedBy(delta) => current - previous == delta,
+        }
+    }
+}
+
+/// A candidate left by the search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchResult {
+    pub addr: u16,
+    /// Value now
+    pub value: i64,
+    /// Value in the snapshot the next filter compares against
+    pub previous: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamSearch {
+    width: SearchWidth,
+    range: Range<u16>,
+    /// Addresses still matching every filter so far, in order
+    candidates: Vec<u16>,
+    /// The whole range as of the last snapshot
+    snapshot: Vec<u8>,
+}
+
+impl RamSearch {
+    /// A search over internal RAM (`0x0000..0x0800`) starting from its contents in `mem`
+    pub fn new(width: SearchWidth, mem: &impl Mem) -> Self {
+        Self::with_range(width, NES_RAM, mem)
+    }
+
+    /// A search over `range`, e.g. `0x6000..0x8000` for cartridge RAM
+    pub fn with_range(width: SearchWidth, range: Range<u16>, mem: &impl Mem) -> Self {
+        let mut search = Self {
+            width,
+            range,
+            candidates: Vec::new(),
+            snapshot: Vec::new(),
+        };
+        search.reset(mem);
+        search
+    }
+
+    pub fn width(&self) -> SearchWidth {
+        self.width
+    }
+
+    /// Makes every address a candidate again and snapshots `mem`
+    pub fn reset(&mut self, mem: &impl Mem) {
+        self.snapshot(mem);
+        let span = match self.width {
+            SearchWidth::U16 => 2,
+            SearchWidth::U8 | SearchWidth::Bcd => 1,
+        };
+        let end = self.range.end.saturating_sub(span - 1);
+        self.candidates = (self.range.start..end)
+            .filter(|&addr| value_at(self.width, self.range.start, &self.snapshot, addr).is_some())
+            .collect();
+    }
+
+    /// Replaces the snapshot the next filter compares against, keeping the candidates
+    pub fn snapshot(&mut self, mem: &impl Mem) {
+        self.snapshot = self.range.clone().map(|addr| mem.mem_peek(addr)).collect();
+    }
+
+    /// Keeps only the candidates whose value in `mem` passes `filter` against the snapshot, then
+    /// snapshots `mem`. Returns the number of candidates left.
+    pub fn filter(&mut self, mem: &impl Mem, filter: Filter) -> usize {
+        let previous = std::mem::take(&mut self.snapshot);
+        self.snapshot(mem);
+        let current = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            match (
+                value_at(self.width, self.range.start, &previous, addr),
+                value_at(self.width, self.range.start, current, addr),
+            ) {
+                (Some(previous), Some(current)) => filter.matches(previous, current),
+                _ => false,
+            }
+        });
+        self.candidates.len()
+    }
+
+    /// The candidates left, with their values in `mem` now
+    pub fn results(&self, mem: &impl Mem) -> Vec<SearchResult> {
+        let current: Vec<u8> = self.range.clone().map(|addr| mem.mem_peek(addr)).collect();
+        self.candidates
+            .iter()
+            .map(|&addr| SearchResult {
+                addr,
+                value: value_at(self.width, self.range.start, &current, addr).unwrap_or(-1),
+                previous: value_at(self.width, self.range.start, &self.snapshot, addr)
+                    .unwrap_or(-1),
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+/// The value at `addr` in a copy of the range starting at `start`, or `None` if it can't be read
+/// as `width`
+fn value_at(width: SearchWidth, start: u16, bytes: &[u8], addr: u16) -> Option<i64> {
+    let i = (addr - start) as usize;
+    match width {
+        SearchWidth::U8 => Some(bytes[i] as i64),
+        SearchWidth::U16 => Some(u16::from_le_bytes([bytes[i], *bytes.get(i + 1)?]) as i64),
+        SearchWidth::Bcd => bcd::decode(&bytes[i..=i]).map(|value| value as i64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_filters_narrow_candidates() {
+        let mut ram = Bus::new();
+        ram.mem_write(0x0010, 0x09);
+        let mut bcd = RamSearch::new(SearchWidth::Bcd, &ram);
+        // A BCD counter going from 09 to 10 only increases by 1 when read as BCD
+        ram.mem_write(0x0010, 0x10);
+        assert_eq!(bcd.filter(&ram, Filter::ChangedBy(1)), 1);
+        ram.mem_write(0x0010, 0x11);
+        assert_eq!(
+            bcd.results(&ram),
+            [SearchResult {
+                addr: 0x0010,
+                value: 11,
+                previous: 10
+            }]
+        );
+
+        ram.mem_write_u16(0x0020, 0x01FF);
+        let mut word = RamSearch::new(SearchWidth::U16, &ram);
+        assert_eq!(word.len(), 0x7FF);
+        ram.mem_write_u16(0x0020, 0x0200);
+        assert_eq!(word.filter(&ram, Filter::Increased), 2); // 0x0020 and 0x0021
+        assert_eq!(word.filter(&ram, Filter::Unchanged), 2);
+        assert_eq!(word.filter(&ram, Filter::Equals(0x0200)), 1);
+        assert_eq!(word.results(&ram)[0].addr, 0x0020);
+
+        bcd.reset(&ram);
+        assert_eq!(bcd.len(), 0x800);
+        ram.mem_write(0x0010, 0x12);
+        ram.mem_write(0x0030, 0xAB);
+        // 0x0030 changed too, but isn't BCD any more
+        assert_eq!(bcd.filter(&ram, Filter::Changed), 1);
+    }
+}
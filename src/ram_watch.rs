@@ -0,0 +1,174 @@
+//! A RAM watch list: labelled addresses sampled once per frame, with what changed.
+//!
+//! Frontends register the addresses they want to show, call [`RamWatch::sample`] after each
+//! frame, and draw [`RamWatch::entries`], highlighting those [`WatchEntry::changed`] in the last
+//! sample or fading with [`WatchEntry::samples_since_change`].
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::ram_map::Width;
+//! use nes_emulator::ram_watch::{RamWatch, WatchKind};
+//!
+//! let mut console = Console::new();
+//! console.load(&[0xe6, 0x10, 0x4c, 0x00, 0x80]); // loop: INC $10; JMP loop
+//! let mut watch = RamWatch::new();
+//! watch.add("counter", 0x0010, WatchKind::Unsigned(Width::U8));
+//! watch.add("score", 0x07DD, WatchKind::Bcd(3));
+//! console.run_frame();
+//! watch.sample(console.cpu());
+//! console.run_frame();
+//! watch.sample(console.cpu());
+//! let changed: Vec<_> = watch.changed().map(|entry| entry.label.as_str()).collect();
+//! assert_eq!(changed, ["counter"]);
+//! ```
+
+use crate::bcd;
+use crate::cpu::Mem;
+use crate::ram_map::{RamField, Width};
+
+/// How a watched value is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Unsigned(Width),
+    /// Two's complement
+    Signed(Width),
+    /// Packed BCD spanning this many bytes, see [`bcd`]
+    Bcd(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub label: String,
+    pub address: u16,
+    pub kind: WatchKind,
+    /// Value in the last sample, `None` before the first or if it wasn't valid BCD
+    pub value: Option<i64>,
+    /// Value in the sample before that
+    pub previous: Option<i64>,
+    since_change: Option<u64>,
+    sampled: bool,
+}
+
+impl WatchEntry {
+    /// Whether the value changed in the last sample. The first sample an entry sees never counts
+    /// as a change.
+    pub fn changed(&self) -> bool {
+        self.since_change == Some(0)
+    }
+
+    /// Samples since the value last changed, `Some(0)` if it just did, or `None` if it never has
+    pub fn samples_since_change(&self) -> Option<u64> {
+        self.since_change
+    }
+
+    fn read(&self, mem: &impl Mem) -> Option<i64> {
+        match self.kind {
+            WatchKind::Unsigned(width) => Some(RamField::new(self.address, width).read(mem)),
+            WatchKind::Signed(width) => Some(RamField::new(self.address, width).signed().read(mem)),
+            WatchKind::Bcd(bytes) => {
+                bcd::read_bcd(mem, self.address, bytes).map(|value| value as i64)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RamWatch {
+    entries: Vec<WatchEntry>,
+}
+
+impl RamWatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watches `address` under `label`, returning the entry's index
+    pub fn add(&mut self, label: &str, address: u16, kind: WatchKind) -> usize {
+        self.entries.push(WatchEntry {
+            label: label.to_string(),
+            address,
+            kind,
+            value: None,
+            previous: None,
+            since_change: None,
+            sampled: false,
+        });
+        self.entries.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> WatchEntry {
+        self.entries.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The entries in the order they were added
+    pub fn entries(&self) -> &[WatchEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reads every entry from `mem` without disturbing it, noting which changed
+    pub fn sample(&mut self, mem: &impl Mem) {
+        for entry in &mut self.entries {
+            let value = entry.read(mem);
+            entry.since_change = if entry.sampled && value != entry.value {
+                Some(0)
+            } else {
+                entry.since_change.map(|n| n + 1)
+            };
+            entry.sampled = true;
+            entry.previous = std::mem::replace(&mut entry.value, value);
+        }
+    }
+
+    /// The entries whose value changed in the last sample
+    pub fn changed(&self) -> impl Iterator<Item = &WatchEntry> {
+        self.entries.iter().filter(|entry| entry.changed())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_sample_tracks_changes() {
+        let mut ram = Bus::new();
+        let mut watch = RamWatch::new();
+        watch.add("x", 0x0010, WatchKind::Signed(Width::U8));
+        watch.add("score", 0x0020, WatchKind::Bcd(2));
+        ram.mem_write(0x0010, 0xFF);
+        watch.sample(&ram);
+        assert_eq!(watch.changed().count(), 0);
+        assert_eq!(watch.entries()[0].value, Some(-1));
+
+        bcd::write_bcd(&mut ram, 0x0020, 2, 150);
+        watch.sample(&ram);
+        let score = &watch.entries()[1];
+        assert_eq!((score.previous, score.value), (Some(0), Some(150)));
+        assert!(score.changed());
+
+        watch.sample(&ram);
+        watch.sample(&ram);
+        assert_eq!(watch.changed().count(), 0);
+        assert_eq!(watch.entries()[1].samples_since_change(), Some(2));
+        assert_eq!(watch.entries()[0].samples_since_change(), None);
+
+        // Turning invalid is a change too
+        ram.mem_write(0x0021, 0xFF);
+        watch.sample(&ram);
+        assert_eq!(watch.entries()[1].value, None);
+        assert!(watch.entries()[1].changed());
+    }
+}
@@ -0,0 +1,341 @@
+//! A TCP remote control server, for driving the emulator from tools in any language.
+//!
+//! Clients connect to [`serve`] and send one JSON object per line; each gets one JSON object back
+//! on a line of its own. Every request has a `"cmd"`, and an `"id"` which, if present, is echoed in
+//! the response. Responses carry `"ok": true` and the command's results, or `"ok": false` and an
+//! `"error"` message.
+//!
+//! | Command | Arguments | Result |
+//! | :--- | :--- | :--- |
+//! | `load_rom` | `path` | `hash`, the ROM's hash as hex |
+//! | `run_frames` | `frames` (default 1) | `frame`, the next frame number |
+//! | `peek` | `addr`, `len` (default 1, at most 65536) | `bytes` read without side effects |
+//! | `poke` | `addr`, `bytes` | |
+//! | `press` | `player` (default 0), `buttons` such as `["a", "start"]` | |
+//! | `frame_png` | | `png`, the last frame as base64 PNG, and its `frame` number |
+//! | `save_state` | | `state` as base64 |
+//! | `load_state` | `state` as base64 | |
+//! | `shutdown` | | stops the server after responding |
+//!
+//! Buttons stay held until the next `press`, so `{"cmd":"press","buttons":[]}` lets go.
+//!
+//! ```text
+//! > {"cmd":"run_frames","frames":60,"id":1}
+//! < {"frame":60,"id":1,"ok":true}
+//! > {"cmd":"peek","addr":117}
+//! < {"bytes":[3],"ok":true}
+//! ```
+//!
+//! The server handles one client at a time and has no authentication: bind it to localhost. A
+//! client that sends something other than UTF-8 lines or hangs up is dropped, and the server goes
+//! on to the next.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use serde_json::{json, Map, Value};
+
+use crate::console::Console;
+use crate::cpu::Mem;
+use crate::frame::{PixelFormat, HEIGHT, WIDTH};
+use crate::joypad::JoypadButton;
+use crate::png;
+use crate::rom::Rom;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Accepts clients on `listener` one after another, running their commands on `console`, until
+/// one sends `shutdown`
+pub fn serve(console: &mut Console, listener: &TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        // A client's I/O errors end its session, not the server
+        let Ok(stream) = stream else {
+            continue;
+        };
+        if let Ok(true) = serve_client(console, stream) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Runs one client's commands until it hangs up, returning whether it sent `shutdown`
+fn serve_client(console: &mut Console, stream: TcpStream) -> io::Result<bool> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shutdown) = respond(console, &line);
+        writeln!(writer, "{response}")?;
+        if shutdown {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Runs the command in one request line, returning the response line
+pub fn handle_request(console: &mut Console, line: &str) -> String {
+    respond(console, line).0
+}
+
+fn respond(console: &mut Console, line: &str) -> (String, bool) {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return (failure(None, format!("bad request: {err}")), false),
+    };
+    let id = request.get("id").cloned();
+    let cmd = request.get("cmd").and_then(Value::as_str).unwrap_or("");
+    let response = match run(console, cmd, &request) {
+        Ok(mut results) => {
+            results.insert("ok".into(), true.into());
+            if let Some(id) = id {
+                results.insert("id".into(), id);
+            }
+            Value::Object(results).to_string()
+        }
+        Err(err) => failure(id, err),
+    };
+    (response, cmd == "shutdown")
+}
+
+fn failure(id: Option<Value>, error: String) -> String {
+    let mut response = json!({ "ok": false, "error": error });
+    if let Some(id) = id {
+        response["id"] = id;
+    }
+    response.to_string()
+}
+
+fn run(console: &mut Console, cmd: &str, request: &Value) -> Result<Map<String, Value>, String> {
+    let mut results = Map::new();
+    match cmd {
+        "load_rom" => {
+            let path = string_arg(request, "path")?;
+            let rom = Rom::from_path(path).map_err(|err| format!("{path}: {err}"))?;
+            console.load_rom(&rom).map_err(|err| err.to_string())?;
+            results.insert("hash".into(), format!("{:016x}", rom.hash()).into());
+        }
+        "run_frames" => {
+            let frames = optional_arg(request, "frames", 1)?;
+            for _ in 0..frames {
+                console.run_frame();
+            }
+            results.insert("frame".into(), console.frame().into());
+        }
+        "peek" => {
+            let addr = u16_arg(request, "addr")?;
+            let len = optional_arg(request, "len", 1)?;
+            if len > 0x10000 {
+                return Err("`len` must be at most 65536".into());
+            }
+            let bytes: Vec<u8> = (0..len)
+                .map(|i| console.cpu().mem_peek(addr.wrapping_add(i as u16)))
+                .collect();
+            results.insert("bytes".into(), bytes.into());
+        }
+        "poke" => {
+            let addr = u16_arg(request, "addr")?;
+            let bytes = request
+                .get("bytes")
+                .and_then(Value::as_array)
+                .ok_or("missing `bytes`")?;
+            for (i, byte) in bytes.iter().enumerate() {
+                let byte = byte
+                    .as_u64()
+                    .and_then(|byte| u8::try_from(byte).ok())
+                    .ok_or("`bytes` must be numbers 0-255")?;
+                console
+                    .cpu_mut()
+                    .mem_write(addr.wrapping_add(i as u16), byte);
+            }
+        }
+        "press" => {
+            let player = optional_arg(request, "player", 0)? as usize;
+            if player > 1 {
+                return Err(format!("no controller port {player}"));
+            }
+            let mut buttons = JoypadButton::empty();
+            let names = request
+                .get("buttons")
+                .and_then(Value::as_array)
+                .ok_or("missing `buttons`")?;
+            for name in names {
+                let name = name.as_str().unwrap_or("");
+                buttons |= JoypadButton::from_name(name)
+                    .ok_or_else(|| format!("unknown button `{name}`"))?;
+            }
+            console.joypad_mut(player).set_buttons(buttons);
+        }
+        "frame_png" => {
+            let frame = console.frame_ref();
+            let rgba = frame
+                .indices()
+                .to_pixels(PixelFormat::Rgba8888, console.palette());
+            let png = png::encode_rgba(WIDTH as u32, HEIGHT as u32, &rgba);
+            results.insert("png".into(), encode_base64(&png).into());
+            results.insert("frame".into(), frame.number().into());
+        }
+        "save_state" => {
+            results.insert("state".into(), encode_base64(&console.save_state()).into());
+        }
+        "load_state" => {
+            let state = decode_base64(string_arg(request, "state")?)
+                .ok_or("`state` is not valid base64")?;
+            console.load_state(&state).map_err(|err| err.to_string())?;
+        }
+        "shutdown" => {}
+        "" => return Err("missing `cmd`".into()),
+        _ => return Err(format!("unknown command `{cmd}`")),
+    }
+    Ok(results)
+}
+
+fn string_arg<'a>(request: &'a Value, name: &str) -> Result<&'a str, String> {
+    request
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("missing `{name}`"))
+}
+
+fn optional_arg(request: &Value, name: &str, default: u64) -> Result<u64, String> {
+    match request.get(name) {
+        None => Ok(default),
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| format!("`{name}` must be a number")),
+    }
+}
+
+fn u16_arg(request: &Value, name: &str) -> Result<u16, String> {
+    request
+        .get(name)
+        .and_then(Value::as_u64)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("`{name}` must be an address 0-65535"))
+}
+
+/// Standard base64 with padding
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(group >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut group, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        group = (group << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((group >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_commands() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob"] {
+            assert_eq!(decode_base64(&encode_base64(data)).unwrap(), data);
+        }
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+
+        let mut console = Console::new();
+        console.load(&[0xe6, 0x10, 0x4c, 0x00, 0x80]); // loop: INC $10; JMP loop
+        let mut request = |line: &str| -> Value {
+            serde_json::from_str(&handle_request(&mut console, line)).unwrap()
+        };
+        let saved = request(r#"{"cmd":"save_state"}"#);
+        let response = request(r#"{"cmd":"run_frames","frames":2,"id":"a"}"#);
+        assert_eq!(response, json!({ "ok": true, "frame": 2, "id": "a" }));
+        let counter = request(r#"{"cmd":"peek","addr":16}"#)["bytes"][0].clone();
+        assert_ne!(counter, 0);
+
+        assert_eq!(
+            request(r#"{"cmd":"poke","addr":32,"bytes":[1,2]}"#)["ok"],
+            true
+        );
+        assert_eq!(
+            request(r#"{"cmd":"peek","addr":32,"len":2}"#)["bytes"],
+            json!([1, 2])
+        );
+        let load = json!({ "cmd": "load_state", "state": saved["state"] }).to_string();
+        assert_eq!(request(&load)["ok"], true);
+        assert_eq!(request(r#"{"cmd":"peek","addr":32}"#)["bytes"], json!([0]));
+
+        assert_eq!(
+            request(r#"{"cmd":"press","buttons":["a","start"]}"#)["ok"],
+            true
+        );
+        assert_eq!(
+            request(r#"{"cmd":"press","buttons":["turbo"]}"#)["error"],
+            "unknown button `turbo`"
+        );
+        let png = request(r#"{"cmd":"frame_png"}"#)["png"].clone();
+        assert_eq!(&decode_base64(png.as_str().unwrap()).unwrap()[1..4], b"PNG");
+        assert_eq!(request("nonsense")["ok"], false);
+
+        assert_eq!(
+            console.joypad(0).buttons(),
+            JoypadButton::BUTTON_A | JoypadButton::START
+        );
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut console = Console::new();
+            console.load(&[0x4c, 0x00, 0x80]);
+            serve(&mut console, &listener).unwrap();
+            console.frame()
+        });
+
+        // Not UTF-8, which only ends this client's session
+        let mut bad = TcpStream::connect(addr).unwrap();
+        bad.write_all(b"\xff\xfe\n").unwrap();
+        let mut rest = String::new();
+        BufReader::new(bad).read_line(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        writeln!(stream, r#"{{"cmd":"peek","addr":0,"len":65537}}"#).unwrap();
+        writeln!(stream, r#"{{"cmd":"run_frames","frames":3}}"#).unwrap();
+        writeln!(stream, r#"{{"cmd":"shutdown"}}"#).unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(
+            lines.next().unwrap().unwrap(),
+            r#"{"error":"`len` must be at most 65536","ok":false}"#
+        );
+        assert_eq!(lines.next().unwrap().unwrap(), r#"{"frame":3,"ok":true}"#);
+        assert_eq!(lines.next().unwrap().unwrap(), r#"{"ok":true}"#);
+        assert_eq!(server.join().unwrap(), 3);
+    }
+}
@@ -0,0 +1,260 @@
+//! Rewind history.
+//!
+//! [`Rewind`] is a ring buffer of save states. Every `keyframe_interval` states a full keyframe is
+//! stored; the states in between are stored as deltas against that keyframe. Consecutive frames
+//! differ in a handful of RAM bytes, so a delta is the XOR of the two states with runs of zeros
+//! collapsed, usually a few dozen bytes instead of a full state. With the `zstd` feature keyframes
+//! and deltas are additionally zstd compressed.
+
+use std::collections::VecDeque;
+
+/// A keyframe and the deltas that depend on it
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+pub struct Rewind {
+    capacity: usize,
+    keyframe_interval: usize,
+    groups: VecDeque<Group>,
+    len: usize,
+    /// Unpacked keyframe of the newest group, so pushing a delta doesn't have to unpack it
+    last_keyframe: Vec<u8>,
+}
+
+impl Rewind {
+    /// Keeps roughly the last `capacity` states, with a keyframe every `keyframe_interval` states.
+    /// The interval is capped at half the capacity, so dropping the oldest group never takes more
+    /// than half the history with it.
+    pub fn new(capacity: usize, keyframe_interval: usize) -> Self {
+        assert!(
+            keyframe_interval > 0,
+            "keyframe interval must be at least 1"
+        );
+        Self {
+            capacity,
+            keyframe_interval: keyframe_interval.min(capacity / 2).max(1),
+            groups: VecDeque::new(),
+            len: 0,
+            last_keyframe: Vec::new(),
+        }
+    }
+
+    /// Number of states that can be rewound through
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes used by the stored history
+    pub fn compressed_size(&self) -> usize {
+        self.groups
+            .iter()
+            .map(|g| g.keyframe.len() + g.deltas.iter().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.len = 0;
+        self.last_keyframe.clear();
+    }
+
+    /// Records `state` as the newest entry, dropping the oldest keyframe group once over capacity
+    pub fn push(&mut self, state: &[u8]) {
+        match self.groups.back_mut() {
+            Some(group) if group.deltas.len() + 1 < self.keyframe_interval => {
+                group
+                    .deltas
+                    .push(pack(&encode_delta(&self.last_keyframe, state)));
+            }
+            _ => {
+                self.groups.push_back(Group {
+                    keyframe: pack(state),
+                    deltas: Vec::new(),
+                });
+                self.last_keyframe.clear();
+                self.last_keyframe.extend_from_slice(state);
+            }
+        }
+        self.len += 1;
+
+        // The newest group holds the state just pushed, so it always stays
+        while self.len > self.capacity && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.len -= 1 + group.deltas.len();
+        }
+    }
+
+    /// Removes and returns the newest state
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+        self.len -= 1;
+        match group.deltas.pop() {
+            Some(delta) => Some(apply_delta(&self.last_keyframe, &unpack(&delta))),
+            None => {
+                let state = std::mem::take(&mut self.last_keyframe);
+                self.groups.pop_back();
+                if let Some(group) = self.groups.back() {
+                    self.last_keyframe = unpack(&group.keyframe);
+                }
+                Some(state)
+            }
+        }
+    }
+}
+
+/// Encodes `state` as the XOR against `base`, written as alternating (zero run, literal run)
+/// pairs. Each run length is a LEB128 varint, and literal runs are followed by their bytes. Bytes
+/// past the end of `base` are XORed against zero.
+fn encode_delta(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| state[i] ^ base.get(i).copied().unwrap_or(0);
+
+    let mut out = Vec::new();
+    write_varint(&mut out, state.len());
+    let mut i = 0;
+    while i < state.len() {
+        let zeros_start = i;
+        while i < state.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < state.len() && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(xor));
+    }
+    out
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut state: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + literals] {
+            state[i] ^= byte;
+            i += 1;
+        }
+        pos += literals;
+    }
+    state
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn pack(data: &[u8]) -> Vec<u8> {
+    zstd::bulk::compress(data, 1).expect("zstd compression of an in-memory buffer")
+}
+
+#[cfg(feature = "zstd")]
+fn unpack(data: &[u8]) -> Vec<u8> {
+    zstd::stream::decode_all(data).expect("rewind entries are written by pack")
+}
+
+#[cfg(not(feature = "zstd"))]
+fn pack(data: &[u8]) -> Vec<u8> {
+    data.to_vec()
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unpack(data: &[u8]) -> Vec<u8> {
+    data.to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(seed: u8) -> Vec<u8> {
+        let mut state = vec![0u8; 0x1000];
+        state[0x10] = seed;
+        state[0x800] = seed.wrapping_mul(3);
+        state
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = state(1);
+        let mut next = state(2);
+        next.extend_from_slice(&[7, 0, 9]); // longer than the base
+
+        let delta = encode_delta(&base, &next);
+        assert!(delta.len() < 32);
+        assert_eq!(apply_delta(&base, &delta), next);
+        assert_eq!(apply_delta(&next, &encode_delta(&next, &base)), base);
+    }
+
+    #[test]
+    fn test_pop_returns_newest_first() {
+        let mut rewind = Rewind::new(100, 4);
+        for seed in 0..10 {
+            rewind.push(&state(seed));
+        }
+        assert_eq!(rewind.len(), 10);
+
+        for seed in (0..10).rev() {
+            assert_eq!(rewind.pop(), Some(state(seed)));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest_groups() {
+        let mut rewind = Rewind::new(10, 4);
+        for seed in 0..20 {
+            rewind.push(&state(seed));
+        }
+
+        assert!(rewind.len() <= 10);
+        assert!(rewind.compressed_size() < 4 * 0x1000); // keyframes plus small deltas
+        assert_eq!(rewind.pop(), Some(state(19)));
+
+        // Less history than a keyframe interval still keeps the newest states
+        let mut rewind = Rewind::new(10, 60);
+        for seed in 0..11 {
+            rewind.push(&state(seed));
+        }
+        assert!((5..=10).contains(&rewind.len()));
+        assert_eq!(rewind.pop(), Some(state(10)));
+        let mut rewind = Rewind::new(1, 60);
+        rewind.push(&state(0));
+        rewind.push(&state(1));
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.pop(), Some(state(1)));
+    }
+}
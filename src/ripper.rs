@@ -0,0 +1,191 @@
+//! Tile and sprite sheets ripped from the PPU, for artists, ROM hackers and documentation.
+//!
+//! | Sheet | Size | |
+//! | :--- | :--- | :--- |
+//! | [`pattern_table`] | 128x128 | the 256 tiles of one pattern table in a 16x16 grid, in one palette |
+//! | [`background_tileset`] | 128x512 | the background pattern table four times, once per background palette |
+//! | [`sprites`] | 64x64, or 64x128 for 8x16 sprites | the 64 sprites in OAM in an 8x8 grid, each in its own palette and flipped as on screen |
+//!
+//! Colors come from the palette RAM the game has set up, looked up in a [`Palette`]. Sprite
+//! sheets leave color 0 transparent; tile sheets draw it in the backdrop color.
+//!
+//! ```no_run
+//! use nes_emulator::console::Console;
+//! use nes_emulator::ripper;
+//! use nes_emulator::rom::Rom;
+//!
+//! let mut console = Console::new();
+//! console.load_rom(&Rom::from_path("game.nes")?)?;
+//! for _ in 0..120 {
+//!     console.run_frame();
+//! }
+//! ripper::pattern_table(console.ppu(), 0, 0, console.palette()).save_png("tiles.png")?;
+//! ripper::sprites(console.ppu(), console.palette()).save_png("sprites.png")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::frame::Palette;
+use crate::png;
+use crate::ppu::Ppu;
+
+pub(crate) const TILES_PER_ROW: usize = 16;
+const SPRITES_PER_ROW: usize = 8;
+const PALETTE_RAM: u16 = 0x3F00;
+
+/// A ripped image as RGBA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sheet {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+}
+
+impl Sheet {
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            rgba: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Packed `R, G, B, A` bytes, row major from the top left
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        self.rgba[i..i + 4].try_into().unwrap()
+    }
+
+    pub(crate) fn set_color(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        let i = (y * self.width + x) * 4;
+        self.rgba[i..i + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_rgba(self.width as u32, self.height as u32, &self.rgba)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+}
+
+/// The color of entry `color` of PPU palette `palette` (0-3 background, 4-7 sprites)
+pub(crate) fn palette_color(ppu: &Ppu, colors: &Palette, palette: u8, color: u8) -> (u8, u8, u8) {
+    let entry = ppu.read_vram(PALETTE_RAM + (palette as u16 & 7) * 4 + color as u16);
+    colors.rgb(entry)
+}
+
+/// Pattern table `table` (0 at `0x0000`, 1 at `0x1000`) drawn in PPU palette `palette`
+pub fn pattern_table(ppu: &Ppu, table: u8, palette: u8, colors: &Palette) -> Sheet {
+    let base = (table as u16 & 1) * 0x1000;
+    let size = TILES_PER_ROW * 8;
+    let mut sheet = Sheet::new(size, size);
+    for tile in 0..256 {
+        let (left, top) = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let color = ppu.tile_pixel(base + tile as u16 * 16, x as u16, y as u16);
+                let rgb = palette_color(ppu, colors, palette, color);
+                sheet.set_color(left + x, top + y, rgb);
+            }
+        }
+    }
+    sheet
+}
+
+/// The pattern table the background uses, in each of the four background palettes from top to
+/// bottom. Tile `n` in palette `p` is tile `p * 256 + n` of the sheet, as used by
+/// [`Tilemap::to_tmx`](crate::tilemap::Tilemap::to_tmx).
+pub fn background_tileset(ppu: &Ppu, colors: &Palette) -> Sheet {
+    let table = (ppu.background_table() / 0x1000) as u8;
+    let mut tileset = Sheet::new(TILES_PER_ROW * 8, TILES_PER_ROW * 8 * 4);
+    for palette in 0..4 {
+        let sheet = pattern_table(ppu, table, palette, colors);
+        let start = palette as usize * sheet.rgba.len();
+        tileset.rgba[start..start + sheet.rgba.len()].copy_from_slice(&sheet.rgba);
+    }
+    tileset
+}
+
+/// The sprites in OAM as the PPU would draw them this frame, in OAM order, with color 0
+/// transparent
+pub fn sprites(ppu: &Ppu, colors: &Palette) -> Sheet {
+    let height = ppu.sprite_height();
+    let mut sheet = Sheet::new(SPRITES_PER_ROW * 8, 64 / SPRITES_PER_ROW * height);
+    for (i, sprite) in ppu.oam.chunks_exact(4).enumerate() {
+        let sprite: &[u8; 4] = sprite.try_into().unwrap();
+        let (left, top) = (i % SPRITES_PER_ROW * 8, i / SPRITES_PER_ROW * height);
+        let palette = 4 + (sprite[2] & 0b11);
+        for y in 0..height {
+            for x in 0..8 {
+                let color = ppu.sprite_pixel(sprite, x as u16, y as u16);
+                if color != 0 {
+                    let rgb = palette_color(ppu, colors, palette, color);
+                    sheet.set_color(left + x, top + y, rgb);
+                }
+            }
+        }
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sheets() {
+        let colors = Palette::default();
+        let mut ppu = Ppu::new();
+        let mut write = |addr: u16, data: &[u8]| {
+            ppu.write_register(0x2006, (addr >> 8) as u8);
+            ppu.write_register(0x2006, addr as u8);
+            for &byte in data {
+                ppu.write_register(0x2007, byte);
+            }
+        };
+        // Tile 1: the top row in color 1, the left column in color 2, and color 3 where they meet
+        write(0x0010, &[0xFF, 0, 0, 0, 0, 0, 0, 0]);
+        write(0x0018, &[0x80; 8]);
+        write(0x3F00, &[0x0F, 0x16, 0x2A]);
+        write(0x3F19, &[0x21, 0x30]);
+        // Sprite 1 is tile 1 in palette 6, flipped horizontally
+        ppu.oam[4..8].copy_from_slice(&[0, 0x01, 0x42, 0]);
+
+        let tiles = pattern_table(&ppu, 0, 0, &colors);
+        assert_eq!((tiles.width(), tiles.height()), (128, 128));
+        let rgba = |index| {
+            let (r, g, b) = colors.rgb(index);
+            [r, g, b, 0xFF]
+        };
+        assert_eq!(tiles.get_pixel(0, 0), rgba(0x0F));
+        assert_eq!(tiles.get_pixel(9, 0), rgba(0x16));
+        assert_eq!(tiles.get_pixel(8, 0), rgba(0x00)); // both planes set: color 3
+        assert_eq!(tiles.get_pixel(8, 1), rgba(0x2A));
+
+        let sprites = sprites(&ppu, &colors);
+        assert_eq!((sprites.width(), sprites.height()), (64, 64));
+        assert_eq!(sprites.get_pixel(0, 0), [0; 4]); // sprite 0 is tile 0, all transparent
+        assert_eq!(sprites.get_pixel(8, 0), rgba(0x21));
+        assert_eq!(sprites.get_pixel(15, 1), rgba(0x30));
+        assert_eq!(sprites.get_pixel(8, 1), [0; 4]);
+
+        assert_eq!(&sprites.to_png()[1..4], b"PNG");
+    }
+}
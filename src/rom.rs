@@ -0,0 +1,834 @@
+//! Cartridge images in the iNES, NES 2.0 and UNIF formats.
+//!
+//! ### iNES
+//!
+//! | Bytes | |
+//! | :--- | :--- |
+//! | 0-3 | `NES\x1A` |
+//! | 4 | PRG ROM size in 16KB units |
+//! | 5 | CHR ROM size in 8KB units (0 means the board uses CHR RAM) |
+//! | 6 | Flags 6: mirroring, battery, trainer, four screen, mapper low nibble |
+//! | 7 | Flags 7: NES 2.0 identifier in bits 2-3, mapper high nibble |
+//! | 8 | Unused here |
+//! | 9 | Bit 0 set for PAL cartridges, trusted only when bytes 12-15 are zero |
+//! | 10-15 | Unused here |
+//!
+//! The header is followed by an optional 512 byte trainer, the PRG ROM, then the CHR ROM.
+//!
+//! ### NES 2.0
+//!
+//! An iNES header with bits 2-3 of flags 7 set to `10`. It extends the iNES fields:
+//!
+//! | Bytes | |
+//! | :--- | :--- |
+//! | 8 | Mapper bits 8-11 in the low nibble, submapper in the high nibble |
+//! | 9 | PRG ROM size bits 8-11 in the low nibble, CHR ROM size bits 8-11 in the high nibble |
+//! | 10-11 | RAM sizes, unused here |
+//! | 12 | Timing in bits 0-1: NTSC, PAL, multi-region or Dendy |
+//! | 13-15 | Console type and the like, unused here |
+//!
+//! When a size's high nibble is `0xF`, its low byte is instead `EEEEEEMM` and the size is
+//! `2^E * (MM * 2 + 1)` bytes.
+//!
+//! [`Rom::to_nes2`] writes a ROM back out with a NES 2.0 header, and [`Header`] reads what a header
+//! claims without loading the file, for checking it against what's really there.
+//!
+//! ### UNIF
+//!
+//! A 32 byte header starting with `UNIF`, then chunks of a 4 byte ID, a little endian `u32`
+//! length and the data. The chunks used here are `MAPR` (board name), `PRG0`-`PRGF` and
+//! `CHR0`-`CHRF` (ROM data, concatenated in order), `MIRR` (mirroring), `BATR` (battery) and
+//! `TVCI` (timing).
+//! UNIF names boards instead of numbering mappers. Boards in `UNIF_BOARDS` get their iNES number;
+//! any other board gets [`NO_MAPPER`] and can only run once a [`Mapper`](crate::mapper::Mapper)
+//! is registered for its name.
+//!
+//! ### Archives
+//!
+//! [`Rom::from_path`] also opens zip archives (with the `zip` feature) and 7z archives
+//! (with the `sevenz` feature), recognized by their contents rather than their extension. It loads
+//! the first entry with one of the [`ARCHIVE_ROM_EXTENSIONS`]; [`Rom::from_archive_entry`] picks
+//! one by name instead.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+
+use crate::console::Region;
+use crate::hash::Fnv1a;
+
+const NES_TAG: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const UNIF_TAG: [u8; 4] = *b"UNIF";
+const HEADER_SIZE: usize = 16;
+const UNIF_HEADER_SIZE: usize = 32;
+const TRAINER_SIZE: usize = 512;
+const ZIP_TAG: [u8; 4] = *b"PK\x03\x04";
+const SEVENZ_TAG: [u8; 6] = *b"7z\xBC\xAF\x27\x1C";
+
+/// Extensions of the archive entries [`Rom::from_path`] considers ROMs, in any case
+pub const ARCHIVE_ROM_EXTENSIONS: &[&str] = &["nes", "unf", "unif", "fds", "nsf"];
+/// Largest archive entry [`read_file`] unpacks, well past the biggest multicarts
+pub const MAX_ROM_SIZE: u64 = 64 * 1024 * 1024;
+pub const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+pub const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+
+/// Mappers the console can run without a [registered](crate::mapper) one
+pub const SUPPORTED_MAPPERS: &[u16] = &[0];
+
+/// [`Rom::mapper`] of UNIF boards with no known iNES number
+pub const NO_MAPPER: u16 = u16::MAX;
+
+/// UNIF board names, without their `NES-`/`HVC-`/`UNL-` prefix, and the iNES mapper they match
+const UNIF_BOARDS: &[(&str, u16)] = &[
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("SAROM", 1),
+    ("SBROM", 1),
+    ("SCROM", 1),
+    ("SEROM", 1),
+    ("SGROM", 1),
+    ("SKROM", 1),
+    ("SLROM", 1),
+    ("SNROM", 1),
+    ("SOROM", 1),
+    ("SUROM", 1),
+    ("SXROM", 1),
+    ("UNROM", 2),
+    ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4),
+    ("TEROM", 4),
+    ("TFROM", 4),
+    ("TGROM", 4),
+    ("TKROM", 4),
+    ("TLROM", 4),
+    ("TSROM", 4),
+    ("TVROM", 4),
+    ("AMROM", 7),
+    ("ANROM", 7),
+    ("AOROM", 7),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    INes,
+    Nes2,
+    Unif,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+/// The TV systems a cartridge is made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    /// Runs on either
+    MultiRegion,
+    /// The Dendy and other Famiclones: 50Hz with a faster CPU than PAL consoles
+    Dendy,
+}
+
+impl Timing {
+    /// The region the cartridge has to run at, the nearest there is for a Dendy game, or `None`
+    /// if either will do
+    pub fn region(self) -> Option<Region> {
+        match self {
+            Timing::Ntsc => Some(Region::Ntsc),
+            Timing::Pal | Timing::Dendy => Some(Region::Pal),
+            Timing::MultiRegion => None,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Timing {
+        match bits & 0b11 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The file doesn't start with the iNES or UNIF tag
+    UnknownFormat,
+    /// The file is shorter than its header says
+    Truncated,
+    UnsupportedMapper(u16),
+    /// A UNIF file names a board with no known or registered mapper, or none at all
+    UnknownBoard(String),
+    /// A zip or 7z archive that couldn't be read, or whose format support isn't compiled in
+    Archive(String),
+    /// An archive without a ROM entry, or without the one asked for
+    NotInArchive(Option<String>),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "{err}"),
+            RomError::UnknownFormat => write!(f, "not an iNES, NES 2.0 or UNIF file"),
+            RomError::Truncated => write!(f, "file is shorter than its header says"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {mapper} is not supported"),
+            RomError::UnknownBoard(board) if board.is_empty() => {
+                write!(f, "UNIF file has no board name")
+            }
+            RomError::UnknownBoard(board) => write!(f, "unknown UNIF board {board:?}"),
+            RomError::Archive(err) => write!(f, "bad archive: {err}"),
+            RomError::NotInArchive(None) => write!(f, "archive has no ROM in it"),
+            RomError::NotInArchive(Some(name)) => write!(f, "archive has no entry {name:?}"),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub format: Format,
+    pub mapper: u16,
+    /// NES 2.0 submapper, 0 for other formats
+    pub submapper: u8,
+    /// UNIF board name
+    pub board: Option<String>,
+    pub screen_mirroring: Mirroring,
+    /// The cartridge has battery backed save RAM
+    pub battery: bool,
+    /// What the header says about the TV system, `None` if it doesn't say
+    pub timing: Option<Timing>,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.starts_with(&UNIF_TAG) {
+            return Rom::from_unif(raw);
+        }
+        let header = Header::parse(raw)?;
+        let prg_rom_start = header.data_start();
+        let chr_rom_start = header
+            .prg_rom_size
+            .and_then(|size| prg_rom_start.checked_add(size))
+            .ok_or(RomError::Truncated)?;
+        let end = header
+            .chr_rom_size
+            .and_then(|size| chr_rom_start.checked_add(size))
+            .ok_or(RomError::Truncated)?;
+        if raw.len() < end {
+            return Err(RomError::Truncated);
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..end].to_vec(),
+            format: header.format,
+            mapper: header.mapper,
+            submapper: header.submapper,
+            board: None,
+            screen_mirroring: header.screen_mirroring,
+            battery: header.battery,
+            timing: header.timing,
+        })
+    }
+
+    fn from_unif(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < UNIF_HEADER_SIZE {
+            return Err(RomError::Truncated);
+        }
+
+        let mut prg: [&[u8]; 16] = [&[]; 16];
+        let mut chr: [&[u8]; 16] = [&[]; 16];
+        let mut board = String::new();
+        let mut screen_mirroring = Mirroring::Horizontal;
+        let mut battery = false;
+        let mut timing = None;
+
+        let mut chunks = &raw[UNIF_HEADER_SIZE..];
+        while !chunks.is_empty() {
+            let (id, rest) = chunks.split_at_checked(4).ok_or(RomError::Truncated)?;
+            let (len, rest) = rest.split_at_checked(4).ok_or(RomError::Truncated)?;
+            let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            let (data, rest) = rest.split_at_checked(len).ok_or(RomError::Truncated)?;
+            chunks = rest;
+
+            match id {
+                b"MAPR" => {
+                    let name = data.split(|b| *b == 0).next().unwrap_or(&[]);
+                    board = String::from_utf8_lossy(name).trim().to_string();
+                }
+                // Single screen and mapper controlled mirroring are left to the mapper
+                b"MIRR" => {
+                    screen_mirroring = match data.first() {
+                        Some(1) => Mirroring::Vertical,
+                        Some(4) => Mirroring::FourScreen,
+                        _ => Mirroring::Horizontal,
+                    }
+                }
+                b"BATR" => battery = true,
+                b"TVCI" => {
+                    timing = data.first().map(|&tv| match tv {
+                        0 => Timing::Ntsc,
+                        1 => Timing::Pal,
+                        _ => Timing::MultiRegion,
+                    })
+                }
+                [b'P', b'R', b'G', n] | [b'C', b'H', b'R', n] => {
+                    if let Some(bank) = (*n as char).to_digit(16) {
+                        let banks = if id[0] == b'P' { &mut prg } else { &mut chr };
+                        banks[bank as usize] = data;
+                    }
+                }
+                // Names, dumper info, checksums and the like
+                _ => {}
+            }
+        }
+
+        let mapper = unif_mapper(&board).unwrap_or(NO_MAPPER);
+        Ok(Rom {
+            prg_rom: prg.concat(),
+            chr_rom: chr.concat(),
+            format: Format::Unif,
+            mapper,
+            submapper: 0,
+            board: Some(board),
+            screen_mirroring,
+            battery,
+            timing,
+        })
+    }
+
+    /// Loads a ROM file, or the first ROM in a zip or 7z archive
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Rom, RomError> {
+        Rom::new(&read_file(path, None)?)
+    }
+
+    /// Loads the entry called `entry` from a zip or 7z archive
+    pub fn from_archive_entry(path: impl AsRef<Path>, entry: &str) -> Result<Rom, RomError> {
+        Rom::new(&read_file(path, Some(entry))?)
+    }
+
+    /// Hash of the PRG and CHR data, identifying the game regardless of header differences
+    pub fn hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write(&self.prg_rom);
+        hasher.write(&self.chr_rom);
+        hasher.finish()
+    }
+
+    /// Whether the console runs the board on its own; see [`MapperRegistry::supports`] for
+    /// registered boards
+    ///
+    /// [`MapperRegistry::supports`]: crate::mapper::MapperRegistry::supports
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_MAPPERS.contains(&self.mapper)
+    }
+
+    /// The error for a board the console can't run
+    pub fn unsupported_error(&self) -> RomError {
+        match &self.board {
+            Some(board) if self.mapper == NO_MAPPER => RomError::UnknownBoard(board.clone()),
+            _ => RomError::UnsupportedMapper(self.mapper),
+        }
+    }
+
+    /// The ROM as a NES 2.0 file, e.g. to save a repaired header. PRG RAM, CHR RAM and timing
+    /// are written as an 8KB NROM-style board would have them: 8KB of battery backed PRG RAM
+    /// with a battery, and 8KB of CHR RAM without CHR ROM. A ROM whose timing isn't known is
+    /// written as NTSC. `None` if a ROM size can't be
+    /// written in a NES 2.0 header, or the board has no mapper number.
+    pub fn to_nes2(&self) -> Option<Vec<u8>> {
+        if self.mapper == NO_MAPPER {
+            return None;
+        }
+        let (prg_lsb, prg_msb) = nes2_size_fields(self.prg_rom.len(), PRG_ROM_PAGE_SIZE)?;
+        let (chr_lsb, chr_msb) = nes2_size_fields(self.chr_rom.len(), CHR_ROM_PAGE_SIZE)?;
+        let mirroring = match self.screen_mirroring {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 0b1,
+            Mirroring::FourScreen => 0b1000,
+        };
+        let mut raw = Vec::with_capacity(HEADER_SIZE + self.prg_rom.len() + self.chr_rom.len());
+        raw.extend_from_slice(&NES_TAG);
+        raw.extend_from_slice(&[
+            prg_lsb,
+            chr_lsb,
+            ((self.mapper as u8 & 0x0F) << 4) | ((self.battery as u8) << 1) | mirroring,
+            (self.mapper as u8 & 0xF0) | 0b1000,
+            (self.submapper << 4) | ((self.mapper >> 8) as u8 & 0x0F),
+            (chr_msb << 4) | prg_msb,
+            // PRG NVRAM size in the high nibble, as 64 << n bytes
+            if self.battery { 7 << 4 } else { 0 },
+            // CHR RAM size, likewise in the low nibble
+            if self.chr_rom.is_empty() { 7 } else { 0 },
+            self.timing.map_or(0, |timing| timing as u8),
+            0,
+            0,
+            0,
+        ]);
+        raw.extend_from_slice(&self.prg_rom);
+        raw.extend_from_slice(&self.chr_rom);
+        Some(raw)
+    }
+}
+
+/// What an iNES or NES 2.0 header claims about the file it starts, before checking the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub format: Format,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    /// A 512 byte trainer comes before PRG ROM
+    pub trainer: bool,
+    pub timing: Option<Timing>,
+    /// In bytes, `None` if it doesn't fit in memory
+    pub prg_rom_size: Option<usize>,
+    pub chr_rom_size: Option<usize>,
+}
+
+impl Header {
+    pub fn parse(raw: &[u8]) -> Result<Header, RomError> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err(RomError::UnknownFormat);
+        }
+
+        let nes2 = raw[7] & 0b1100 == 0b1000;
+        let mut mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        // Old tools wrote their names into bytes 7-15, so only a clean iNES header's PAL flag
+        // means anything
+        let timing = if nes2 {
+            Some(Timing::from_bits(raw[12]))
+        } else if raw[12..16] == [0; 4] && raw[9] & 1 == 1 {
+            Some(Timing::Pal)
+        } else {
+            None
+        };
+
+        let (prg_rom_size, chr_rom_size, submapper) = if nes2 {
+            mapper |= ((raw[8] & 0x0F) as u16) << 8;
+            let prg = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+            let chr = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+            (prg, chr, raw[8] >> 4)
+        } else {
+            let prg = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+            let chr = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+            (Some(prg), Some(chr), 0)
+        };
+
+        Ok(Header {
+            format: if nes2 { Format::Nes2 } else { Format::INes },
+            mapper,
+            submapper,
+            screen_mirroring,
+            battery: raw[6] & 0b10 != 0,
+            trainer: raw[6] & 0b100 != 0,
+            timing,
+            prg_rom_size,
+            chr_rom_size,
+        })
+    }
+
+    /// Offset of PRG ROM in the file
+    pub fn data_start(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+    }
+}
+
+/// Decodes a NES 2.0 ROM size from its low byte and high nibble, in bytes. `None` if it doesn't
+/// fit in memory.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)?
+            .checked_mul(multiplier)
+    } else {
+        Some((((msb as usize) << 8) | lsb as usize) * unit)
+    }
+}
+
+/// Encodes a ROM size as a NES 2.0 low byte and high nibble, the inverse of [`nes2_rom_size`]
+fn nes2_size_fields(size: usize, unit: usize) -> Option<(u8, u8)> {
+    let units = size / unit;
+    if size.is_multiple_of(unit) && units < 0xF00 {
+        return Some((units as u8, (units >> 8) as u8));
+    }
+    // 2^E * (MM * 2 + 1), with the multiplier odd
+    let exponent = size.trailing_zeros().min(63);
+    let multiplier = size >> exponent;
+    (size > 0 && multiplier <= 7)
+        .then_some((((exponent as u8) << 2) | (multiplier as u8 >> 1), 0x0F))
+}
+
+/// Reads a ROM file, unpacking the ROM first if it's an archive: the entry called `entry`, or the
+/// first one with a ROM extension. Frontends that patch or inspect the raw file use this in place
+/// of [`fs::read`].
+pub fn read_file(path: impl AsRef<Path>, entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    let data = fs::read(path)?;
+    if data.starts_with(&ZIP_TAG) {
+        read_zip(&data, entry)
+    } else if data.starts_with(&SEVENZ_TAG) {
+        read_7z(&data, entry)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Whether an archive entry is the one asked for, or a ROM when none was
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn is_wanted_entry(name: &str, entry: Option<&str>) -> bool {
+    match entry {
+        Some(entry) => name == entry,
+        None => Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ARCHIVE_ROM_EXTENSIONS
+                    .iter()
+                    .any(|rom| rom.eq_ignore_ascii_case(ext))
+            }),
+    }
+}
+
+/// Reads an archive entry of up to `limit` bytes. The size the archive claims for it is checked
+/// first, but only what actually comes out counts, so a wrong size can't make it allocate more.
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+fn read_entry(reader: impl io::Read, claimed: u64, limit: u64) -> Result<Vec<u8>, RomError> {
+    use std::io::Read;
+
+    let too_large = || RomError::Archive(format!("ROM is larger than {limit} bytes"));
+    if claimed > limit {
+        return Err(too_large());
+    }
+    let mut out = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut out)?;
+    if out.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "zip")]
+fn read_zip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    let archive_error = |err: zip::result::ZipError| RomError::Archive(err.to_string());
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(data)).map_err(archive_error)?;
+    let index = archive
+        .file_names()
+        .position(|name| is_wanted_entry(name, entry));
+    let index = index.ok_or_else(|| RomError::NotInArchive(entry.map(String::from)))?;
+    let file = archive.by_index(index).map_err(archive_error)?;
+    let claimed = file.size();
+    read_entry(file, claimed, MAX_ROM_SIZE)
+}
+
+#[cfg(not(feature = "zip"))]
+fn read_zip(_data: &[u8], _entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    Err(RomError::Archive("zip support is not compiled in".into()))
+}
+
+#[cfg(feature = "sevenz")]
+fn read_7z(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    use sevenz_rust::{Password, SevenZReader};
+
+    let archive_error = |err: sevenz_rust::Error| RomError::Archive(err.to_string());
+    let mut archive =
+        SevenZReader::new(io::Cursor::new(data), data.len() as u64, Password::empty())
+            .map_err(archive_error)?;
+    let name = archive
+        .archive()
+        .files
+        .iter()
+        .find(|file| !file.is_directory() && is_wanted_entry(file.name(), entry))
+        .map(|file| file.name().to_string())
+        .ok_or_else(|| RomError::NotInArchive(entry.map(String::from)))?;
+
+    // Entries in a solid archive are compressed as one stream, so the ones before the ROM still
+    // have to be read through
+    let mut out = None;
+    archive
+        .for_each_entries(|file, reader| {
+            if out.is_some() {
+                return Ok(false);
+            }
+            if file.name() == name {
+                out = Some(read_entry(reader, file.size(), MAX_ROM_SIZE));
+                return Ok(false);
+            }
+            io::copy(reader, &mut io::sink())?;
+            Ok(true)
+        })
+        .map_err(archive_error)?;
+    out.unwrap_or(Err(RomError::NotInArchive(Some(name))))
+}
+
+#[cfg(not(feature = "sevenz"))]
+fn read_7z(_data: &[u8], _entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    Err(RomError::Archive("7z support is not compiled in".into()))
+}
+
+fn unif_mapper(board: &str) -> Option<u16> {
+    let name = ["NES-", "HVC-", "UNL-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    UNIF_BOARDS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, mapper)| *mapper)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Builds an iNES image with `prg_pages` 16KB PRG banks filled with `prg_fill`
+    pub fn ines(prg_pages: u8, chr_pages: u8, flags6: u8, flags7: u8, prg_fill: u8) -> Vec<u8> {
+        let mut raw = vec![b'N', b'E', b'S', 0x1A, prg_pages, chr_pages, flags6, flags7];
+        raw.resize(HEADER_SIZE, 0);
+        raw.resize(raw.len() + prg_pages as usize * PRG_ROM_PAGE_SIZE, prg_fill);
+        raw.resize(raw.len() + chr_pages as usize * CHR_ROM_PAGE_SIZE, 0);
+        raw
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let rom = Rom::new(&ines(2, 1, 0b0100_0011, 0b0001_0000, 0xEA)).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.mapper, 0x14);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert!(!rom.is_supported());
+        assert_eq!(rom.timing, None);
+
+        let mut raw = ines(1, 0, 0, 0, 0xEA);
+        raw[9] = 1;
+        assert_eq!(Rom::new(&raw).unwrap().timing, Some(Timing::Pal));
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(Rom::new(&raw).unwrap().timing, None);
+    }
+
+    #[test]
+    fn test_skips_trainer() {
+        let mut raw = ines(1, 0, 0b0000_0100, 0, 0xEA);
+        raw.splice(HEADER_SIZE..HEADER_SIZE, [0xFF; TRAINER_SIZE]);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert!(rom.prg_rom.iter().all(|b| *b == 0xEA));
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        assert!(matches!(
+            Rom::new(b"not a rom"),
+            Err(RomError::UnknownFormat)
+        ));
+
+        let raw = ines(2, 0, 0, 0, 0);
+        assert!(matches!(
+            Rom::new(&raw[..raw.len() - 1]),
+            Err(RomError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_parse_nes2_header() {
+        let mut raw = ines(1, 0, 0b0001_0001, 0b0010_1000, 0xEA);
+        raw[8] = 0x31; // submapper 3, mapper bit 8
+        raw[9] = 0xF0; // CHR size in exponent form: 2^2 * 3 bytes
+        raw[5] = 0b0000_1001;
+        raw[12] = 3;
+        raw.extend_from_slice(&[0xCC; 12]);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.format, Format::Nes2);
+        assert_eq!(rom.timing, Some(Timing::Dendy));
+        assert_eq!(rom.mapper, 0x121);
+        assert_eq!(rom.submapper, 3);
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom, [0xCC; 12]);
+    }
+
+    #[test]
+    fn test_rejects_huge_nes2_sizes() {
+        let mut raw = ines(0, 0, 0, 0b1000, 0);
+        raw[4] = 0b1111_1111; // 2^63 * 7
+        raw[9] = 0x0F;
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated)));
+    }
+
+    #[test]
+    fn test_to_nes2_round_trips() {
+        let mut rom = Rom::new(&ines(2, 0, 0b0001_0011, 0, 0xEA)).unwrap();
+        rom.mapper = 0x1A4;
+        rom.submapper = 2;
+        rom.timing = Some(Timing::Pal);
+        let raw = rom.to_nes2().unwrap();
+        assert_eq!(raw[10..13], [0x70, 0x07, 1]);
+        let again = Rom::new(&raw).unwrap();
+        assert_eq!(again.format, Format::Nes2);
+        assert_eq!(
+            Rom {
+                format: rom.format,
+                ..again
+            },
+            rom
+        );
+
+        rom.chr_rom = vec![0xCC; 12];
+        assert_eq!(
+            Rom::new(&rom.to_nes2().unwrap()).unwrap().chr_rom,
+            rom.chr_rom
+        );
+        rom.chr_rom = vec![0; 9];
+        assert_eq!(rom.to_nes2(), None);
+    }
+
+    /// A UNIF chunk
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn test_parse_unif() {
+        let mut raw = b"UNIF".to_vec();
+        raw.resize(UNIF_HEADER_SIZE, 0);
+        raw.extend(chunk(b"MAPR", b"NES-NROM-256\0"));
+        raw.extend(chunk(b"PRG1", &[2; 4]));
+        raw.extend(chunk(b"PRG0", &[1; 4]));
+        raw.extend(chunk(b"MIRR", &[1]));
+        raw.extend(chunk(b"NAME", b"Test\0"));
+        raw.extend(chunk(b"TVCI", &[2]));
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.format, Format::Unif);
+        assert_eq!(rom.board.as_deref(), Some("NES-NROM-256"));
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.prg_rom, [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.battery);
+        assert_eq!(rom.timing, Some(Timing::MultiRegion));
+
+        raw.extend(chunk(b"CHR0", &[0; 8]));
+        raw.pop();
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated)));
+
+        let mut raw = b"UNIF".to_vec();
+        raw.resize(UNIF_HEADER_SIZE, 0);
+        raw.extend(chunk(b"MAPR", b"UNL-SOMETHING"));
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, NO_MAPPER);
+        assert_eq!(
+            rom.unsupported_error().to_string(),
+            "unknown UNIF board \"UNL-SOMETHING\""
+        );
+    }
+
+    /// Writes `data` to a file in the temp dir unique to this test run
+    #[cfg(any(feature = "zip", feature = "sevenz"))]
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("nes_emulator_{}_{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_load_from_zip() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        zip.start_file("readme.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"not a ROM").unwrap();
+        for (name, fill) in [("Game (U).NES", 0xEA), ("Game (E).nes", 0x60)] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(&ines(1, 0, 0, 0, fill)).unwrap();
+        }
+        let path = temp_file("game.zip", &zip.finish().unwrap().into_inner());
+
+        assert_eq!(Rom::from_path(&path).unwrap().prg_rom[0], 0xEA);
+        let rom = Rom::from_archive_entry(&path, "Game (E).nes").unwrap();
+        assert_eq!(rom.prg_rom[0], 0x60);
+        assert!(matches!(
+            Rom::from_archive_entry(&path, "missing.nes"),
+            Err(RomError::NotInArchive(Some(_)))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(any(feature = "zip", feature = "sevenz"))]
+    #[test]
+    fn test_archive_entries_are_capped() {
+        let data = [0xEA; 32];
+        assert_eq!(read_entry(&data[..], 32, 32).unwrap(), data);
+        // Claiming too much, or claiming little and holding more
+        for claimed in [64, 16] {
+            let err = read_entry(&data[..], claimed, 16).unwrap_err();
+            assert_eq!(err.to_string(), "bad archive: ROM is larger than 16 bytes");
+        }
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn test_load_from_7z() {
+        use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+
+        let mut archive = SevenZWriter::new(io::Cursor::new(Vec::new())).unwrap();
+        for (name, data) in [
+            ("readme.txt", b"not a ROM".to_vec()),
+            ("game.nes", ines(1, 0, 0, 0, 0xEA)),
+        ] {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.into();
+            entry.has_stream = true;
+            archive
+                .push_archive_entry(entry, Some(data.as_slice()))
+                .unwrap();
+        }
+        let path = temp_file("game.7z", &archive.finish().unwrap().into_inner());
+
+        assert_eq!(Rom::from_path(&path).unwrap().prg_rom[0], 0xEA);
+        fs::remove_file(path).unwrap();
+    }
+}
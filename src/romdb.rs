@@ -0,0 +1,482 @@
+//! A database of known cartridges, for fixing bad headers.
+//!
+//! Many dumps in circulation have iNES headers with the wrong mapper, mirroring or ROM sizes, and
+//! misbehave in every emulator. The ROM data itself is usually fine, so a [`RomDb`] keyed by
+//! [`Rom::hash`] knows what the header should say, and [`repair`] rewrites it, reporting each
+//! [`Fix`]. Save the result with [`Rom::to_nes2`].
+//!
+//! No cartridges are built in; a database is a text file with one cartridge per line:
+//!
+//! ```text
+//! # hash            mapper  mirroring  battery  prg  chr  [timing]  name
+//! 8c5a2e8f1bd0c4a7  0       vertical   no       32   8              Some Homebrew
+//! 0123456789abcdef  4.1     horizontal yes      256  128  pal       Another Game
+//! ```
+//!
+//! The mapper may carry a NES 2.0 submapper after a dot. Mirroring is `horizontal`, `vertical` or
+//! `four`, and the ROM sizes are in KB, PRG in 16KB steps and CHR in 8KB steps. An optional
+//! `ntsc`, `pal`, `multi` or `dendy` after the sizes says which TV system the cartridge is made
+//! for. The name is the rest of the line. Blank lines and lines starting with `#` are skipped.
+//!
+//! ```
+//! use nes_emulator::rom::Rom;
+//! use nes_emulator::romdb::{self, Fix, RomDb};
+//!
+//! // A 16KB NROM game whose header says 32KB and mapper 1
+//! let mut raw = vec![b'N', b'E', b'S', 0x1A, 2, 0, 0x10, 0];
+//! raw.resize(16 + 0x4000, 0xEA);
+//! let hash = Rom::new(&[&raw[..4], &[1, 0, 0, 0], &raw[8..]].concat()).unwrap().hash();
+//!
+//! let db = RomDb::parse(&format!("{hash:016x} 0 horizontal no 16 0 Some Homebrew")).unwrap();
+//! let repaired = romdb::repair(&raw, &db).unwrap();
+//! assert_eq!(repaired.name, "Some Homebrew");
+//! assert!(repaired.fixes.contains(&Fix::Mapper { from: (1, 0), to: (0, 0) }));
+//! let fixed = repaired.rom.to_nes2().unwrap();
+//! assert_eq!(Rom::new(&fixed).unwrap().mapper, 0);
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hasher;
+
+use crate::hash::Fnv1a;
+use crate::rom::{
+    Format, Header, Mirroring, Rom, RomError, Timing, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomDbError {
+    /// A line with too few columns, with its 1-based line number
+    BadLine(usize),
+    /// A column that doesn't parse, or a ROM size that isn't a whole number of banks
+    BadField { line: usize, field: &'static str },
+}
+
+impl fmt::Display for RomDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomDbError::BadLine(line) => write!(f, "line {line}: malformed entry"),
+            RomDbError::BadField { line, field } => write!(f, "line {line}: bad `{field}`"),
+        }
+    }
+}
+
+impl Error for RomDbError {}
+
+/// What a cartridge's header should say
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartInfo {
+    pub name: String,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    /// In bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    /// `None` when the database doesn't say
+    pub timing: Option<Timing>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RomDb {
+    carts: HashMap<u64, CartInfo>,
+}
+
+impl RomDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a database in the text format above
+    pub fn parse(text: &str) -> Result<Self, RomDbError> {
+        let mut db = RomDb::new();
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut rest = line;
+            let mut column = |field| {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let (value, after) = rest.split_at(end);
+                rest = after.trim_start();
+                if value.is_empty() {
+                    return Err(RomDbError::BadLine(number));
+                }
+                Ok((
+                    value,
+                    RomDbError::BadField {
+                        line: number,
+                        field,
+                    },
+                ))
+            };
+
+            let (hash, bad) = column("hash")?;
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| bad)?;
+            let (mapper, bad) = column("mapper")?;
+            let (mapper, submapper) = mapper.split_once('.').unwrap_or((mapper, "0"));
+            let mapper = mapper.parse().map_err(|_| bad.clone())?;
+            let submapper = submapper.parse().ok().filter(|&n| n < 16).ok_or(bad)?;
+            let (mirroring, bad) = column("mirroring")?;
+            let screen_mirroring = match mirroring {
+                "horizontal" => Mirroring::Horizontal,
+                "vertical" => Mirroring::Vertical,
+                "four" => Mirroring::FourScreen,
+                _ => return Err(bad),
+            };
+            let (battery, bad) = column("battery")?;
+            let battery = match battery {
+                "yes" => true,
+                "no" => false,
+                _ => return Err(bad),
+            };
+            let (prg, bad) = column("prg")?;
+            let prg_rom_size = kilobytes(prg, PRG_ROM_PAGE_SIZE).ok_or(bad)?;
+            let (chr, bad) = column("chr")?;
+            let chr_rom_size = kilobytes(chr, CHR_ROM_PAGE_SIZE).ok_or(bad)?;
+            let (first, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let timing = match first {
+                "ntsc" => Some(Timing::Ntsc),
+                "pal" => Some(Timing::Pal),
+                "multi" => Some(Timing::MultiRegion),
+                "dendy" => Some(Timing::Dendy),
+                _ => None,
+            };
+            if timing.is_some() {
+                rest = after.trim_start();
+            }
+            let name = rest.to_string();
+
+            db.insert(
+                hash,
+                CartInfo {
+                    name,
+                    mapper,
+                    submapper,
+                    screen_mirroring,
+                    battery,
+                    prg_rom_size,
+                    chr_rom_size,
+                    timing,
+                },
+            );
+        }
+        Ok(db)
+    }
+
+    /// Adds a cartridge, replacing any entry with the same hash
+    pub fn insert(&mut self, hash: u64, cart: CartInfo) {
+        self.carts.insert(hash, cart);
+    }
+
+    /// The cartridge whose PRG and CHR ROM have this [`Rom::hash`]
+    pub fn get(&self, hash: u64) -> Option<&CartInfo> {
+        self.carts.get(&hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.carts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.carts.is_empty()
+    }
+}
+
+/// A size in KB that has to be a whole number of `bank`s, in bytes
+fn kilobytes(text: &str, bank: usize) -> Option<usize> {
+    let bytes = text.parse::<usize>().ok()?.checked_mul(1024)?;
+    bytes.is_multiple_of(bank).then_some(bytes)
+}
+
+/// One thing [`repair`] changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fix {
+    /// Mapper and submapper
+    Mapper {
+        from: (u16, u8),
+        to: (u16, u8),
+    },
+    Mirroring {
+        from: Mirroring,
+        to: Mirroring,
+    },
+    Battery {
+        to: bool,
+    },
+    /// In bytes, `from` being `None` when the header's size doesn't fit in memory
+    PrgRomSize {
+        from: Option<usize>,
+        to: usize,
+    },
+    ChrRomSize {
+        from: Option<usize>,
+        to: usize,
+    },
+    /// Bytes after the ROM data, dropped
+    TrailingData(usize),
+    Timing {
+        from: Option<Timing>,
+        to: Timing,
+    },
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size =
+            |size: Option<usize>| size.map_or("?".into(), |size| format!("{}KB", size / 1024));
+        match self {
+            Fix::Mapper { from, to } => {
+                write!(f, "mapper {}.{} -> {}.{}", from.0, from.1, to.0, to.1)
+            }
+            Fix::Mirroring { from, to } => write!(f, "mirroring {from:?} -> {to:?}"),
+            Fix::Battery { to: true } => write!(f, "battery added"),
+            Fix::Battery { to: false } => write!(f, "battery removed"),
+            Fix::PrgRomSize { from, to } => {
+                write!(f, "PRG ROM {} -> {}", size(*from), size(Some(*to)))
+            }
+            Fix::ChrRomSize { from, to } => {
+                write!(f, "CHR ROM {} -> {}", size(*from), size(Some(*to)))
+            }
+            Fix::TrailingData(bytes) => write!(f, "{bytes} bytes after the ROM data dropped"),
+            Fix::Timing { from: None, to } => write!(f, "timing set to {to:?}"),
+            Fix::Timing {
+                from: Some(from),
+                to,
+            } => write!(f, "timing {from:?} -> {to:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repaired {
+    pub name: String,
+    /// The ROM as the database describes it
+    pub rom: Rom,
+    /// What was wrong with the header; empty if it was right
+    pub fixes: Vec<Fix>,
+}
+
+#[derive(Debug)]
+pub enum RepairError {
+    /// Not an image with a header to repair
+    Rom(RomError),
+    /// The ROM data isn't in the database, with the hash it was looked up by
+    Unknown(u64),
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepairError::Rom(err) => write!(f, "{err}"),
+            RepairError::Unknown(hash) => write!(f, "ROM {hash:016x} is not in the database"),
+        }
+    }
+}
+
+impl Error for RepairError {}
+
+impl From<RomError> for RepairError {
+    fn from(err: RomError) -> Self {
+        RepairError::Rom(err)
+    }
+}
+
+/// Looks up the ROM data in `raw`, an iNES, NES 2.0 or UNIF image, and rebuilds the ROM as `db`
+/// describes it. The data is everything after the header (and trainer), or, if that isn't
+/// known, only as much as the header says, to drop junk appended to the dump. Trainers are
+/// dropped; no database game needs one.
+pub fn repair(raw: &[u8], db: &RomDb) -> Result<Repaired, RepairError> {
+    let (header, data) = match Header::parse(raw) {
+        Ok(header) => (
+            header,
+            raw.get(header.data_start()..).unwrap_or(&[]).to_vec(),
+        ),
+        Err(RomError::UnknownFormat) => {
+            let rom = Rom::new(raw)?;
+            let header = Header {
+                format: Format::Unif,
+                mapper: rom.mapper,
+                submapper: rom.submapper,
+                screen_mirroring: rom.screen_mirroring,
+                battery: rom.battery,
+                trainer: false,
+                timing: rom.timing,
+                prg_rom_size: Some(rom.prg_rom.len()),
+                chr_rom_size: Some(rom.chr_rom.len()),
+            };
+            (header, [rom.prg_rom, rom.chr_rom].concat())
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let declared = header
+        .prg_rom_size
+        .zip(header.chr_rom_size)
+        .and_then(|(prg, chr)| prg.checked_add(chr))
+        .filter(|&len| len < data.len());
+    let mut candidates = vec![data.len()];
+    candidates.extend(declared);
+    let (len, cart) = candidates
+        .iter()
+        .find_map(|&len| db.get(hash_of(&data[..len])).map(|cart| (len, cart)))
+        .ok_or_else(|| RepairError::Unknown(hash_of(&data)))?;
+
+    let mut fixes = Vec::new();
+    let wanted = (cart.mapper, cart.submapper);
+    if (header.mapper, header.submapper) != wanted {
+        fixes.push(Fix::Mapper {
+            from: (header.mapper, header.submapper),
+            to: wanted,
+        });
+    }
+    if header.screen_mirroring != cart.screen_mirroring {
+        fixes.push(Fix::Mirroring {
+            from: header.screen_mirroring,
+            to: cart.screen_mirroring,
+        });
+    }
+    if header.battery != cart.battery {
+        fixes.push(Fix::Battery { to: cart.battery });
+    }
+    if header.prg_rom_size != Some(cart.prg_rom_size) {
+        fixes.push(Fix::PrgRomSize {
+            from: header.prg_rom_size,
+            to: cart.prg_rom_size,
+        });
+    }
+    if header.chr_rom_size != Some(cart.chr_rom_size) {
+        fixes.push(Fix::ChrRomSize {
+            from: header.chr_rom_size,
+            to: cart.chr_rom_size,
+        });
+    }
+    if len < data.len() {
+        fixes.push(Fix::TrailingData(data.len() - len));
+    }
+    if let Some(timing) = cart.timing.filter(|&timing| header.timing != Some(timing)) {
+        fixes.push(Fix::Timing {
+            from: header.timing,
+            to: timing,
+        });
+    }
+
+    // The database's sizes add up to the data's length, or the hash wouldn't have matched
+    let (prg, chr) = data[..len].split_at(cart.prg_rom_size.min(len));
+    let rom = Rom {
+        prg_rom: prg.to_vec(),
+        chr_rom: chr.to_vec(),
+        format: Format::Nes2,
+        mapper: cart.mapper,
+        submapper: cart.submapper,
+        board: None,
+        screen_mirroring: cart.screen_mirroring,
+        battery: cart.battery,
+        timing: cart.timing.or(header.timing),
+    };
+    Ok(Repaired {
+        name: cart.name.clone(),
+        rom,
+        fixes,
+    })
+}
+
+/// [`Rom::hash`] of PRG and CHR ROM stored back to back
+fn hash_of(data: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test::ines;
+
+    #[test]
+    fn test_repairs_bad_header() {
+        let good = Rom::new(&ines(1, 1, 0b0000_0001, 0, 0xEA)).unwrap();
+        let db = RomDb::parse(&format!(
+            "# hash mapper mirroring battery prg chr name\n\
+             \n\
+             {:016x}  0.0  vertical  no  16  8  Test Cart\n",
+            good.hash()
+        ))
+        .unwrap();
+        assert_eq!(db.len(), 1);
+
+        // Wrong mapper, mirroring, battery and bank split, with junk on the end
+        let mut raw = ines(1, 1, 0b0001_0010, 0, 0xEA);
+        raw[4] = 0;
+        raw[5] = 3;
+        raw.extend_from_slice(&[0xFF; 100]);
+        let repaired = repair(&raw, &db).unwrap();
+        assert_eq!(repaired.name, "Test Cart");
+        assert_eq!(
+            repaired.fixes,
+            [
+                Fix::Mapper {
+                    from: (1, 0),
+                    to: (0, 0)
+                },
+                Fix::Mirroring {
+                    from: Mirroring::Horizontal,
+                    to: Mirroring::Vertical
+                },
+                Fix::Battery { to: false },
+                Fix::PrgRomSize {
+                    from: Some(0),
+                    to: 0x4000
+                },
+                Fix::ChrRomSize {
+                    from: Some(0x6000),
+                    to: 0x2000
+                },
+                Fix::TrailingData(100),
+            ]
+        );
+        assert_eq!(repaired.fixes[4].to_string(), "CHR ROM 24KB -> 8KB");
+        let fixed = Rom::new(&repaired.rom.to_nes2().unwrap()).unwrap();
+        assert_eq!(fixed.hash(), good.hash());
+        assert_eq!(fixed.screen_mirroring, Mirroring::Vertical);
+
+        let clean = repair(&ines(1, 1, 0b0000_0001, 0, 0xEA), &db).unwrap();
+        assert_eq!(clean.fixes, []);
+
+        assert!(matches!(
+            repair(&ines(1, 1, 0, 0, 0x00), &db),
+            Err(RepairError::Unknown(_))
+        ));
+        assert_eq!(
+            RomDb::parse("0 0 vertical no 12 8").unwrap_err(),
+            RomDbError::BadField {
+                line: 1,
+                field: "prg"
+            }
+        );
+        assert_eq!(
+            RomDb::parse("\n0 0 vertical").unwrap_err(),
+            RomDbError::BadLine(2)
+        );
+
+        let db = RomDb::parse(&format!(
+            "{:016x} 0 vertical no 16 8 pal Test Cart (E)",
+            good.hash()
+        ))
+        .unwrap();
+        let repaired = repair(&ines(1, 1, 0b0000_0001, 0, 0xEA), &db).unwrap();
+        assert_eq!(repaired.name, "Test Cart (E)");
+        assert_eq!(
+            repaired.fixes,
+            [Fix::Timing {
+                from: None,
+                to: Timing::Pal
+            }]
+        );
+        assert_eq!(repaired.rom.timing, Some(Timing::Pal));
+    }
+}
@@ -0,0 +1,511 @@
+//! Save-state binary format.
+//!
+//! A save state is a small header followed by tagged chunks:
+//!
+//! | Field | Size | |
+//! | :--- | :---: | :--- |
+//! | Magic | 4 | `NESS` |
+//! | Version | 2 | Little endian [`FORMAT_VERSION`] the state was written with |
+//! | Chunks | .. | Repeated until the end of the data |
+//!
+//! Each chunk is a 4 byte ASCII tag, a little endian `u32` length, and that many bytes of payload.
+//! Readers skip chunks with tags they don't know, so newer crate versions can add chunks without
+//! breaking older readers. Changes to the layout of an existing chunk bump [`FORMAT_VERSION`] and
+//! add a step to [`migrate`], so states written by older crate versions keep loading.
+//!
+//! States hold only the machine itself, never how the console is being run: diagnostics,
+//! instruction history, the journal, rewind, the pixel format, the palette and the flash filter
+//! are all left out, so a state loads into a console set up any other way and carries on exactly
+//! as it would have. Emulation has a single accuracy level, so there is nothing to reconcile
+//! between settings yet; a setting that changed how the machine runs would reconcile its state
+//! on load, next to [`migrate`].
+//!
+//! With the `zstd` feature, [`compress`] turns a state into the compressed form: the same header
+//! with the magic `NESZ`, followed by the zstd compressed chunk stream. [`SaveState::parse`] accepts
+//! both forms.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::game_genie;
+use crate::thumbnail::Thumbnail;
+
+pub type Tag = [u8; 4];
+
+/// Frame counter and the hash of the loaded ROM, read by [`state_info`]
+pub const META: Tag = *b"META";
+pub const CPU: Tag = *b"CPU ";
+pub const RAM: Tag = *b"RAM ";
+pub const JOYPADS: Tag = *b"JOYP";
+/// PPU registers and memory; states from before the PPU existed lack it
+pub const PPU: Tag = *b"PPU ";
+/// APU channels and frame counter; states from before the APU existed lack it
+pub const APU: Tag = *b"APU ";
+/// State of the console's [`Rng`](crate::rng::Rng); states from before it existed lack it
+pub const RNG: Tag = *b"RNG ";
+/// Optional [`Thumbnail`] of the frame on screen when the state was saved
+pub const THUMBNAIL: Tag = *b"THMB";
+/// Registers of a [registered mapper](crate::mapper), for boards that have one
+pub const MAPPER: Tag = *b"MAPR";
+/// Registers and codes of a plugged in [Game Genie](crate::game_genie)
+pub const GAME_GENIE: Tag = *b"GENI";
+
+const MAGIC: &[u8; 4] = b"NESS";
+const MAGIC_ZSTD: &[u8; 4] = b"NESZ";
+
+/// Version written by this crate
+pub const FORMAT_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the save-state magic
+    BadMagic,
+    /// Written with a version this crate can't read or migrate
+    UnsupportedVersion(u16),
+    /// The data ended in the middle of a header, chunk, or field
+    Truncated,
+    MissingChunk(Tag),
+    /// The state is compressed and the `zstd` feature is disabled, or the compressed data is corrupt
+    Compression,
+    /// The state was saved while a different ROM was loaded
+    RomMismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MissingChunk(tag) => write!(
+                f,
+                "save state is missing the {} chunk",
+                String::from_utf8_lossy(tag).trim_end()
+            ),
+            StateError::Compression => write!(f, "save state could not be decompressed"),
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for ROM {found:016x}, but ROM {expected:016x} is loaded"
+            ),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Summary of a save state that can be read without loading it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateInfo {
+    pub version: u16,
+    pub rom_hash: u64,
+    pub frame: u64,
+    thumbnail: Option<Thumbnail>,
+}
+
+impl StateInfo {
+    /// Preview of the screen when the state was saved, if the state has one
+    pub fn thumbnail(&self) -> Option<&Thumbnail> {
+        self.thumbnail.as_ref()
+    }
+}
+
+/// Reads the ROM hash, frame count, and thumbnail out of a save state without loading it
+pub fn state_info(data: &[u8]) -> Result<StateInfo, StateError> {
+    let state = SaveState::parse(data)?;
+    let mut meta = state.chunk(META)?;
+    let thumbnail = match state.chunk(THUMBNAIL) {
+        Ok(mut chunk) => Some(Thumbnail::load(&mut chunk)?),
+        Err(_) => None,
+    };
+    Ok(StateInfo {
+        version: state.version,
+        rom_hash: meta.read_u64()?,
+        frame: meta.read_u64()?,
+        thumbnail,
+    })
+}
+
+/// A parsed save state: its version and raw chunks in file order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub version: u16,
+    pub chunks: Vec<(Tag, Vec<u8>)>,
+}
+
+impl SaveState {
+    pub fn new() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Splits `data` into chunks without interpreting them
+    pub fn parse(data: &[u8]) -> Result<Self, StateError> {
+        if data.len() < 6 {
+            let prefix = &data[..data.len().min(4)];
+            return Err(
+                if MAGIC.starts_with(prefix) || MAGIC_ZSTD.starts_with(prefix) {
+                    StateError::Truncated
+                } else {
+                    StateError::BadMagic
+                },
+            );
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        match &data[0..4] {
+            magic if magic == MAGIC => Self::parse_chunks(version, &data[6..]),
+            magic if magic == MAGIC_ZSTD => Self::parse_chunks(version, &decompress(&data[6..])?),
+            _ => Err(StateError::BadMagic),
+        }
+    }
+
+    fn parse_chunks(version: u16, mut rest: &[u8]) -> Result<Self, StateError> {
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(StateError::Truncated);
+            }
+            let tag: Tag = rest[0..4].try_into().unwrap();
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            // A length near `u32::MAX` overflows on 32-bit targets
+            let end = 8usize.checked_add(len).ok_or(StateError::Truncated)?;
+            let payload = rest.get(8..end).ok_or(StateError::Truncated)?;
+            chunks.push((tag, payload.to_vec()));
+            rest = &rest[end..];
+        }
+
+        Ok(Self { version, chunks })
+    }
+
+    /// Starts a chunk, returning a writer for its payload
+    pub fn add_chunk(&mut self, tag: Tag) -> ChunkWriter<'_> {
+        self.chunks.push((tag, Vec::new()));
+        ChunkWriter {
+            data: &mut self.chunks.last_mut().unwrap().1,
+        }
+    }
+
+    /// Reader over the first chunk tagged `tag`
+    pub fn chunk(&self, tag: Tag) -> Result<ChunkReader<'_>, StateError> {
+        self.chunks
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, data)| ChunkReader { data })
+            .ok_or(StateError::MissingChunk(tag))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = 6 + self.chunks.iter().map(|(_, d)| 8 + d.len()).sum::<usize>();
+        let mut out = Vec::with_capacity(len);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        for (tag, data) in &self.chunks {
+            out.extend_from_slice(tag);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        out
+    }
+}
+
+/// Compresses a plain save state, leaving already compressed states as they are.
+///
+/// Compressed states typically shrink from tens of KB to a few KB, since most of the address space
+/// is zeros or repeated data.
+#[cfg(feature = "zstd")]
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    if data.len() < 6 || &data[0..4] != MAGIC {
+        return SaveState::parse(data).map(|_| data.to_vec());
+    }
+    let mut out = MAGIC_ZSTD.to_vec();
+    out.extend_from_slice(&data[4..6]);
+    out.extend(zstd::bulk::compress(&data[6..], 3).map_err(|_| StateError::Compression)?);
+    Ok(out)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    zstd::stream::decode_all(data).map_err(|_| StateError::Compression)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_data: &[u8]) -> Result<Vec<u8>, StateError> {
+    Err(StateError::Compression)
+}
+
+impl Default for SaveState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Upgrades `state` in place to [`FORMAT_VERSION`].
+///
+/// When a chunk layout changes, the previous version gets an arm here that rewrites its chunks into
+/// the layout of the next version and bumps `state.version`, looping so a state from any older
+/// release walks the steps in order.
+pub fn migrate(state: &mut SaveState) -> Result<(), StateError> {
+    loop {
+        match state.version {
+            FORMAT_VERSION => return Ok(()),
+            // Version 2 appended the Y register and stack pointer to the CPU chunk
+            1 => {
+                let (_, cpu) = state
+                    .chunks
+                    .iter_mut()
+                    .find(|(tag, _)| *tag == CPU)
+                    .ok_or(StateError::MissingChunk(CPU))?;
+                cpu.extend_from_slice(&[0x00, 0xFD]);
+                state.version = 2;
+            }
+            // Version 3 widened the Game Genie's code count, after its registers, to a u16
+            2 => {
+                let count_at = 2 + 4 * game_genie::HARDWARE_CODES;
+                if let Some((_, genie)) =
+                    state.chunks.iter_mut().find(|(tag, _)| *tag == GAME_GENIE)
+                {
+                    if genie.len() <= count_at {
+                        return Err(StateError::Truncated);
+                    }
+                    genie.insert(count_at + 1, 0);
+                }
+                state.version = 3;
+            }
+            version => return Err(StateError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Writes a plain save state straight into a caller provided buffer, so saving every frame (e.g.
+/// for rewind) can reuse one allocation
+pub struct StateWriter<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> StateWriter<'a> {
+    /// Clears `out` and writes the header
+    pub fn new(out: &'a mut Vec<u8>) -> Self {
+        out.clear();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        Self { out }
+    }
+
+    /// Appends a chunk whose payload is written by `write`
+    pub fn chunk(&mut self, tag: Tag, write: impl FnOnce(&mut ChunkWriter)) {
+        self.out.extend_from_slice(&tag);
+        let len_at = self.out.len();
+        self.out.extend_from_slice(&[0; 4]);
+
+        write(&mut ChunkWriter { data: self.out });
+
+        let len = (self.out.len() - len_at - 4) as u32;
+        self.out[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+/// Appends little endian fields to a chunk payload
+pub struct ChunkWriter<'a> {
+    data: &'a mut Vec<u8>,
+}
+
+impl ChunkWriter<'_> {
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Reads little endian fields from a chunk payload in the order they were written
+pub struct ChunkReader<'a> {
+    data: &'a [u8],
+}
+
+impl ChunkReader<'_> {
+    pub fn read_bytes(&mut self, len: usize) -> Result<&[u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_state() -> SaveState {
+        let mut state = SaveState::new();
+        let mut meta = state.add_chunk(META);
+        meta.write_u64(0xDEAD_BEEF);
+        meta.write_u64(120);
+        state.add_chunk(RAM).write_bytes(&[1, 2, 3]);
+        state
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = sample_state();
+        let parsed = SaveState::parse(&state.to_bytes()).unwrap();
+
+        assert_eq!(parsed, state);
+        assert_eq!(
+            parsed.chunk(RAM).unwrap().read_bytes(3).unwrap(),
+            &[1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_state_writer_matches_save_state() {
+        let mut out = vec![0xAA; 3]; // stale contents are cleared
+        let mut writer = StateWriter::new(&mut out);
+        writer.chunk(META, |meta| {
+            meta.write_u64(0xDEAD_BEEF);
+            meta.write_u64(120);
+        });
+        writer.chunk(RAM, |ram| ram.write_bytes(&[1, 2, 3]));
+
+        assert_eq!(out, sample_state().to_bytes());
+    }
+
+    #[test]
+    fn test_state_info() {
+        let info = state_info(&sample_state().to_bytes()).unwrap();
+        assert_eq!(
+            info,
+            StateInfo {
+                version: FORMAT_VERSION,
+                rom_hash: 0xDEAD_BEEF,
+                frame: 120,
+                thumbnail: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_chunks_are_skipped() {
+        let mut state = SaveState::new();
+        state.add_chunk(*b"NEW!").write_bytes(&[9; 16]); // chunk from a future crate version
+        let mut meta = state.add_chunk(META);
+        meta.write_u64(1);
+        meta.write_u64(2);
+
+        let info = state_info(&state.to_bytes()).unwrap();
+        assert_eq!(info.frame, 2);
+    }
+
+    #[test]
+    fn test_rejects_bad_data() {
+        let bytes = sample_state().to_bytes();
+
+        assert_eq!(SaveState::parse(b"nope nope"), Err(StateError::BadMagic));
+        assert_eq!(
+            SaveState::parse(&bytes[..bytes.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        let mut huge = b"NESS\x02\x00META".to_vec();
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(SaveState::parse(&huge), Err(StateError::Truncated));
+        assert_eq!(
+            state_info(&SaveState::new().to_bytes()),
+            Err(StateError::MissingChunk(META))
+        );
+
+        let mut future = sample_state();
+        future.version = FORMAT_VERSION + 1;
+        assert_eq!(
+            migrate(&mut future),
+            Err(StateError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn test_migrates_version_1_cpu_chunk() {
+        let mut state = SaveState::new();
+        state.version = 1;
+        let mut cpu = state.add_chunk(CPU);
+        cpu.write_u8(0x12); // A
+        cpu.write_u8(0x34); // X
+        cpu.write_u8(0x80); // status
+        cpu.write_u16(0x8003);
+        cpu.write_u64(99);
+
+        migrate(&mut state).unwrap();
+        assert_eq!(state.version, FORMAT_VERSION);
+        let mut cpu = state.chunk(CPU).unwrap();
+        cpu.read_bytes(13).unwrap();
+        assert_eq!(cpu.read_u8(), Ok(0x00)); // Y
+        assert_eq!(cpu.read_u8(), Ok(0xFD)); // stack pointer
+    }
+
+    #[test]
+    fn test_migrates_version_2_game_genie_chunk() {
+        let mut state = SaveState::new();
+        state.version = 2;
+        let mut genie = state.add_chunk(GAME_GENIE);
+        genie.write_bytes(&[0; 2 + 4 * game_genie::HARDWARE_CODES]);
+        genie.write_u8(1); // codes
+        genie.write_bytes(&[0x00, 0x90, 0x42, 0x00, 0x00]);
+
+        migrate(&mut state).unwrap();
+        let mut genie = state.chunk(GAME_GENIE).unwrap();
+        genie
+            .read_bytes(2 + 4 * game_genie::HARDWARE_CODES)
+            .unwrap();
+        assert_eq!(genie.read_u16(), Ok(1));
+        assert_eq!(genie.read_u16(), Ok(0x9000));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compressed_round_trip() {
+        let mut state = sample_state();
+        state.add_chunk(*b"ZERO").write_bytes(&[0; 0x10000]);
+        let plain = state.to_bytes();
+
+        let compressed = compress(&plain).unwrap();
+        assert!(compressed.len() < plain.len() / 100);
+        assert_eq!(SaveState::parse(&compressed).unwrap(), state);
+        assert_eq!(compress(&compressed).unwrap(), compressed); // already compressed
+    }
+}
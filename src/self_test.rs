@@ -0,0 +1,409 @@
+//! A built-in suite of tiny CPU, PPU and APU test programs, for checking a build on a platform.
+//!
+//! Packagers and users on an unusual target want to know the emulator works there before they
+//! trust it with a game. [`run_all`] runs [`SUITE`], a set of programs small enough to embed,
+//! each checking one behavior games rely on and reporting through the [test ROM
+//! mailbox](crate::test_rom), and [`matrix`] lays the results out one per line. The frontend's
+//! `--self-test` prints it and exits with a failure status if any test failed.
+//!
+//! ```
+//! use nes_emulator::self_test;
+//!
+//! let results = self_test::run_all();
+//! assert!(results.iter().all(|(_, report)| report.passed()));
+//! print!("{}", self_test::matrix(&results));
+//! ```
+//!
+//! The programs are plain 6502 written for this suite, listed with their assembly. They're smoke
+//! tests, not a replacement for the community's accuracy test ROMs: each runs in a fresh
+//! console for at most [`MAX_FRAMES`] frames.
+
+use std::fmt;
+
+use crate::console::Console;
+use crate::test_rom::{self, Report};
+
+/// Frames a test gets to report a result
+pub const MAX_FRAMES: u64 = 10;
+
+/// The part of the console a test checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Area {
+    Cpu,
+    Ppu,
+    Apu,
+}
+
+impl fmt::Display for Area {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Area::Cpu => "cpu",
+            Area::Ppu => "ppu",
+            Area::Apu => "apu",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SelfTest {
+    pub name: &'static str,
+    pub area: Area,
+    /// What passing shows
+    pub checks: &'static str,
+    /// Loaded at `0x8000`
+    pub program: &'static [u8],
+    /// Where in `program` the NMI handler starts, for tests that take one
+    pub nmi: Option<u16>,
+}
+
+impl SelfTest {
+    /// Runs the test in a fresh console
+    pub fn run(&self) -> Report {
+        let mut console = Console::new();
+        console.load(self.program);
+        if let Some(handler) = self.nmi {
+            let vector = (0x8000 + handler).to_le_bytes();
+            console.cpu_mut().bus_mut().load(0xFFFA, &vector);
+        }
+        test_rom::run(&mut console, MAX_FRAMES).expect("a fresh console has the mailbox free")
+    }
+}
+
+/// Runs every test in [`SUITE`], in order
+pub fn run_all() -> Vec<(&'static SelfTest, Report)> {
+    SUITE.iter().map(|test| (test, test.run())).collect()
+}
+
+/// A line per test with its area, name and `pass` or what went wrong, then how many passed
+pub fn matrix(results: &[(&SelfTest, Report)]) -> String {
+    let width = results
+        .iter()
+        .map(|(test, _)| test.name.len())
+        .max()
+        .unwrap_or(0);
+    let mut text = String::new();
+    for (test, report) in results {
+        let result = if report.passed() {
+            "pass".to_string()
+        } else {
+            format!("FAIL {}", report.to_string().replace('\n', "\n     "))
+        };
+        text += &format!("{}  {:width$}  {result}\n", test.area, test.name);
+    }
+    let passed = results.iter().filter(|(_, report)| report.passed()).count();
+    text += &format!("{passed} of {} passed\n", results.len());
+    text
+}
+
+/// Each program ends by writing its result to the mailbox and spinning
+#[rustfmt::skip]
+pub const SUITE: &[SelfTest] = &[
+    SelfTest {
+        name: "adc_overflow",
+        area: Area::Cpu,
+        checks: "ADC sets overflow and negative going from $7F to $80",
+        program: &[
+            0x18,             // CLC
+            0xa9, 0x7f,       // LDA #$7F
+            0x69, 0x01,       // ADC #$01
+            0x85, 0x00,       // STA $00
+            0x08,             // PHP
+            0x68,             // PLA
+            0x29, 0xc3,       // AND #$C3 ; N, V, Z and C
+            0xa2, 0xc0,       // LDX #$C0
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect N and V
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa5, 0x00,       // LDA $00
+            0xa2, 0x80,       // LDX #$80
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $80
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x22, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "sbc_borrow",
+        area: Area::Cpu,
+        checks: "SBC borrows through the carry going below $00",
+        program: &[
+            0x38,             // SEC
+            0xa9, 0x00,       // LDA #$00
+            0xe9, 0x01,       // SBC #$01
+            0x85, 0x00,       // STA $00
+            0x08,             // PHP
+            0x68,             // PLA
+            0x29, 0xc3,       // AND #$C3 ; N, V, Z and C
+            0xa2, 0x80,       // LDX #$80
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect N, borrowed
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa5, 0x00,       // LDA $00
+            0xa2, 0xff,       // LDX #$FF
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $FF
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x22, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "no_decimal_mode",
+        area: Area::Cpu,
+        checks: "ADC ignores the decimal flag, as the 2A03 has no BCD",
+        program: &[
+            0xf8,             // SED
+            0x18,             // CLC
+            0xa9, 0x09,       // LDA #$09
+            0x69, 0x01,       // ADC #$01
+            0xd8,             // CLD
+            0xa2, 0x0a,       // LDX #$0A
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect binary $0A, not BCD $10
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x14, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "stack",
+        area: Area::Cpu,
+        checks: "PHA, JSR and RTS move the stack pointer down from $FF and back",
+        program: &[
+            0xa2, 0xff,       // LDX #$FF
+            0x9a,             // TXS
+            0xa9, 0x5a,       // LDA #$5A
+            0x48,             // PHA
+            0x20, 0x1a, 0x80, // JSR sub
+            0x68,             // PLA
+            0xa2, 0x5a,       // LDX #$5A
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect what was pushed
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x17, 0x80, // spin: JMP spin
+            0xba,             // sub: TSX
+            0x8a,             // TXA
+            0xa2, 0xfc,       // LDX #$FC
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect the return address pushed below it
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0x60,             // RTS
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "indirect",
+        area: Area::Cpu,
+        checks: "(zp),Y and (zp,X) addressing find their pointers",
+        program: &[
+            0xa9, 0x00,       // LDA #$00
+            0x85, 0x10,       // STA $10
+            0xa9, 0x03,       // LDA #$03
+            0x85, 0x11,       // STA $11 ; pointer to $0300 at $10
+            0xa9, 0x37,       // LDA #$37
+            0x8d, 0x00, 0x03, // STA $0300
+            0xa9, 0x42,       // LDA #$42
+            0x8d, 0x05, 0x03, // STA $0305
+            0xa0, 0x05,       // LDY #$05
+            0xb1, 0x10,       // LDA ($10),Y
+            0xa2, 0x42,       // LDX #$42
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $0305
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa2, 0x04,       // LDX #$04
+            0xa1, 0x0c,       // LDA ($0C,X)
+            0xa2, 0x37,       // LDX #$37
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $0300
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x2f, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "jmp_indirect_wrap",
+        area: Area::Cpu,
+        checks: "JMP ($xxFF) takes its high byte from the start of the same page",
+        program: &[
+            0xa9, 0x17,       // LDA #<target
+            0x8d, 0xff, 0x03, // STA $03FF
+            0xa9, 0x80,       // LDA #>target
+            0x8d, 0x00, 0x03, // STA $0300 ; the high byte comes from the start of the page
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x00, 0x04, // STA $0400 ; not from the next page
+            0x6c, 0xff, 0x03, // JMP ($03FF)
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; fail
+            0xa9, 0x00,       // target: LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x1c, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "vblank_flag",
+        area: Area::Ppu,
+        checks: "PPUSTATUS sets bit 7 in vblank and clears it when read",
+        program: &[
+            0x2c, 0x02, 0x20, // wait: BIT $2002
+            0x10, 0xfb,       // BPL wait
+            0xad, 0x02, 0x20, // LDA $2002
+            0x29, 0x80,       // AND #$80
+            0xa2, 0x00,       // LDX #$00
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect cleared by the read before
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x17, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "vram_read_buffer",
+        area: Area::Ppu,
+        checks: "PPUDATA reads lag a byte behind, except for palettes",
+        program: &[
+            0x2c, 0x02, 0x20, // vblank1: BIT $2002
+            0x10, 0xfb,       // BPL vblank1
+            0x2c, 0x02, 0x20, // vblank2: BIT $2002
+            0x10, 0xfb,       // BPL vblank2 ; the PPU takes writes after the second
+            0xa9, 0x21,       // LDA #$21
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0xab,       // LDA #$AB
+            0x8d, 0x07, 0x20, // STA $2007
+            0xa9, 0xcd,       // LDA #$CD
+            0x8d, 0x07, 0x20, // STA $2007 ; $AB $CD at $2100
+            0xa9, 0x21,       // LDA #$21
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x06, 0x20, // STA $2006
+            0xad, 0x07, 0x20, // LDA $2007 ; the stale buffer
+            0xad, 0x07, 0x20, // LDA $2007
+            0xa2, 0xab,       // LDX #$AB
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $2100 a read late
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xad, 0x07, 0x20, // LDA $2007
+            0xa2, 0xcd,       // LDX #$CD
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect $2101
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x3f,       // LDA #$3F
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x2a,       // LDA #$2A
+            0x8d, 0x07, 0x20, // STA $2007
+            0xa9, 0x3f,       // LDA #$3F
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0x06, 0x20, // STA $2006
+            0xad, 0x07, 0x20, // LDA $2007
+            0xa2, 0x2a,       // LDX #$2A
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect palette reads straight away
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x6a, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "nmi",
+        area: Area::Ppu,
+        checks: "PPUCTRL bit 7 raises an NMI at vblank",
+        program: &[
+            0xa9, 0x00,       // LDA #$00
+            0x85, 0x00,       // STA $00
+            0xa9, 0x80,       // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000 ; NMI at vblank
+            0xa5, 0x00,       // wait: LDA $00
+            0xf0, 0xfc,       // BEQ wait
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x12, 0x80, // spin: JMP spin
+            0xe6, 0x00,       // nmi: INC $00
+            0x40,             // RTI
+        ],
+        nmi: Some(0x0015),
+    },
+    SelfTest {
+        name: "length_counter",
+        area: Area::Apu,
+        checks: "loading pulse 1's length counter shows in $4015, and disabling it clears it",
+        program: &[
+            0xa9, 0x01,       // LDA #$01
+            0x8d, 0x15, 0x40, // STA $4015 ; enable pulse 1
+            0xa9, 0x10,       // LDA #$10
+            0x8d, 0x00, 0x40, // STA $4000
+            0xa9, 0x08,       // LDA #$08
+            0x8d, 0x03, 0x40, // STA $4003 ; load its length counter
+            0xad, 0x15, 0x40, // LDA $4015
+            0x29, 0x01,       // AND #$01
+            0xa2, 0x01,       // LDX #$01
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect pulse 1 playing
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x15, 0x40, // STA $4015
+            0xad, 0x15, 0x40, // LDA $4015
+            0x29, 0x01,       // AND #$01
+            0xa2, 0x00,       // LDX #$00
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect disabling clears it
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x33, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+    SelfTest {
+        name: "frame_irq",
+        area: Area::Apu,
+        checks: "the 4-step frame counter raises its interrupt flag, and reading $4015 clears it",
+        program: &[
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0x17, 0x40, // STA $4017 ; 4-step sequence with its interrupt
+            0xad, 0x15, 0x40, // wait: LDA $4015
+            0x29, 0x40,       // AND #$40
+            0xf0, 0xf9,       // BEQ wait
+            0xad, 0x15, 0x40, // LDA $4015
+            0x29, 0x40,       // AND #$40
+            0xa2, 0x00,       // LDX #$00
+            0x8e, 0xf2, 0x5f, // STX $5FF2 ; expect acknowledged by the read before
+            0x8d, 0xf3, 0x5f, // STA $5FF3 ; check A
+            0xa9, 0x00,       // LDA #$00
+            0x8d, 0xf0, 0x5f, // STA $5FF0 ; pass
+            0x4c, 0x1e, 0x80, // spin: JMP spin
+        ],
+        nmi: None,
+    },
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failures_show_in_the_matrix() {
+        let results = run_all();
+        assert_eq!(results.len(), SUITE.len());
+        for (test, report) in &results {
+            assert!(report.passed(), "{}: {report}", test.name);
+        }
+
+        // A program failing with code 1 in place of the first test
+        let broken = SelfTest {
+            program: &[0xa9, 0x01, 0x8d, 0xf0, 0x5f, 0x4c, 0x05, 0x80],
+            ..SUITE[0]
+        };
+        let results = [(&SUITE[1], SUITE[1].run()), (&broken, broken.run())];
+        assert_eq!(
+            matrix(&results),
+            "cpu  sbc_borrow    pass\n\
+             cpu  adc_overflow  FAIL failed with code 1\n\
+             1 of 2 passed\n"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! A console shared between threads: one runs emulation while others show frames and send input.
+//!
+//! [`SharedConsole`] is a cheap to clone handle holding the console behind its own lock, plus a
+//! copy of the last completed frame and the controller input behind separate ones, so the parts a
+//! UI thread touches every frame never wait for the console:
+//!
+//! | Call | Locks | Waits for a running frame |
+//! | :--- | :--- | :--- |
+//! | [`SharedConsole::run_frame`] | console, then frame briefly to publish | — |
+//! | [`SharedConsole::with_console`] | console | yes |
+//! | [`SharedConsole::with_frame`] | frame | no, only for the copy at the end of one |
+//! | [`SharedConsole::set_buttons`] | none | no |
+//!
+//! Locks are only ever taken in that order, console before frame, and never held once a call
+//! returns. Don't call back into the handle from inside the closures given to
+//! [`SharedConsole::with_console`] or [`SharedConsole::with_frame`]: the lock they hold isn't
+//! reentrant, so that deadlocks.
+//!
+//! ```
+//! use std::thread;
+//! use nes_emulator::console::Console;
+//! use nes_emulator::joypad::JoypadButton;
+//! use nes_emulator::shared::SharedConsole;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! let shared = SharedConsole::new(console);
+//!
+//! let emulation = shared.clone();
+//! let runner = thread::spawn(move || {
+//!     for _ in 0..10 {
+//!         emulation.run_frame();
+//!     }
+//! });
+//! shared.set_buttons(0, JoypadButton::START);
+//! let _shown = shared.with_frame(|frame| frame.number());
+//! runner.join().unwrap();
+//! assert_eq!(shared.with_frame(|frame| frame.number()), 9);
+//! ```
+
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::console::Console;
+use crate::frame::{Frame, FrameInfo, FrameRef, PixelFormat};
+use crate::joypad::JoypadButton;
+
+/// Set in [`Shared::input`] when new buttons are waiting, with the buttons in the low byte
+const INPUT_PENDING: u16 = 0x100;
+
+/// The last completed frame, copied out of the console
+#[derive(Debug, Clone)]
+struct Published {
+    indices: Frame,
+    pixels: Vec<u8>,
+    format: PixelFormat,
+    info: FrameInfo,
+}
+
+struct Shared {
+    console: Mutex<Console>,
+    frame: Mutex<Published>,
+    input: [AtomicU16; 2],
+}
+
+#[derive(Clone)]
+pub struct SharedConsole {
+    inner: Arc<Shared>,
+}
+
+impl SharedConsole {
+    pub fn new(console: Console) -> Self {
+        let published = Self::publish(&console);
+        Self {
+            inner: Arc::new(Shared {
+                console: Mutex::new(console),
+                frame: Mutex::new(published),
+                input: [AtomicU16::new(0), AtomicU16::new(0)],
+            }),
+        }
+    }
+
+    /// Runs one frame with the latest buttons from [`SharedConsole::set_buttons`] and publishes the
+    /// picture for [`SharedConsole::with_frame`]
+    pub fn run_frame(&self) {
+        let mut console = self.lock_console();
+        for (player, input) in self.inner.input.iter().enumerate() {
+            let input = input.swap(0, Ordering::Acquire);
+            if input & INPUT_PENDING != 0 {
+                console
+                    .joypad_mut(player)
+                    .set_buttons(JoypadButton::from_bits(input as u8));
+            }
+        }
+        console.run_frame();
+
+        let frame = console.frame_ref();
+        let mut published = self.lock_frame();
+        published.indices.clone_from(frame.indices());
+        published.pixels.clear();
+        published.pixels.extend_from_slice(frame.pixels());
+        published.format = frame.format();
+        published.info = frame.info();
+    }
+
+    /// Calls `f` with the console locked, e.g. to load a ROM or a save state. Waits for a frame
+    /// in progress on another thread to finish.
+    pub fn with_console<R>(&self, f: impl FnOnce(&mut Console) -> R) -> R {
+        f(&mut self.lock_console())
+    }
+
+    /// Calls `f` with the last completed frame. Doesn't wait for emulation, apart from the moment
+    /// a finished frame is copied in.
+    pub fn with_frame<R>(&self, f: impl FnOnce(FrameRef) -> R) -> R {
+        let published = self.lock_frame();
+        f(FrameRef::new(
+            &published.indices,
+            &published.pixels,
+            published.format,
+            published.info,
+        ))
+    }
+
+    /// Holds `buttons` on controller `player` from the start of the next frame. Only the latest
+    /// call before a frame counts.
+    pub fn set_buttons(&self, player: usize, buttons: JoypadButton) {
+        assert!(player < 2, "no controller port {player}");
+        self.inner.input[player].store(INPUT_PENDING | buttons.bits() as u16, Ordering::Release);
+    }
+
+    fn publish(console: &Console) -> Published {
+        let frame = console.frame_ref();
+        Published {
+            indices: frame.indices().clone(),
+            pixels: frame.pixels().to_vec(),
+            format: frame.format(),
+            info: frame.info(),
+        }
+    }
+
+    /// A panic on another thread while it held the lock leaves the console mid frame, which is no
+    /// worse than stopping it there, so poisoning is ignored
+    fn lock_console(&self) -> MutexGuard<'_, Console> {
+        self.inner
+            .console
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_frame(&self) -> MutexGuard<'_, Published> {
+        self.inner
+            .frame
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use std::thread;
+
+    #[test]
+    fn test_frames_are_readable_while_emulation_runs() {
+        let mut console = Console::new();
+        // loop: LDA #1; STA $4016; LDA #0; STA $4016; LDA $4016; STA $10; JMP loop
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40,
+            0xad, 0x16, 0x40, 0x85, 0x10, 0x4c, 0x00, 0x80,
+        ]);
+        let shared = SharedConsole::new(console);
+        let emulation = shared.clone();
+        let runner = thread::spawn(move || {
+            for _ in 0..30 {
+                emulation.run_frame();
+            }
+        });
+        let mut last = 0;
+        while !runner.is_finished() {
+            let number = shared.with_frame(|frame| {
+                assert_eq!(frame.pixels().len(), frame.format().frame_len());
+                frame.number()
+            });
+            assert!(number >= last);
+            last = number;
+        }
+        runner.join().unwrap();
+        assert_eq!(shared.with_frame(|frame| frame.number()), 29);
+
+        shared.set_buttons(0, JoypadButton::BUTTON_A);
+        shared.run_frame();
+        let a_pressed = shared.with_console(|console| console.cpu().bus.mem_peek(0x10));
+        assert_eq!(a_pressed & 1, 1);
+    }
+}
@@ -0,0 +1,278 @@
+//! Battery saves: keeping a cartridge's save RAM on disk.
+//!
+//! [`Autosave`] loads a game's `.sav` file into save RAM, then watches the game write to it and
+//! writes the file back according to a [`FlushPolicy`]. Each flush is reported as a [`Flushed`]
+//! event so a frontend can show a saving indicator, and the recent writes are kept in an audit
+//! log for debugging saves that go wrong.
+//!
+//! Files are written to a temporary name and renamed into place, so a crash mid-write leaves the
+//! previous save intact.
+//!
+//! ```no_run
+//! use nes_emulator::console::Console;
+//! use nes_emulator::sram::{Autosave, FlushPolicy};
+//!
+//! let mut console = Console::new();
+//! let mut autosave = Autosave::new("game.sav", FlushPolicy::default());
+//! autosave.attach(&mut console)?;
+//! loop {
+//!     console.run_frame();
+//!     if let Some(flushed) = autosave.end_frame(&mut console)? {
+//!         println!("saved {} writes", flushed.writes);
+//!     }
+//! #   break;
+//! }
+//! autosave.flush(&console)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::console::Console;
+
+/// Frames without a save RAM write before [`FlushPolicy::Idle`] writes the file, half a second
+pub const DEFAULT_IDLE_FRAMES: u32 = 30;
+/// Writes kept in the audit log
+pub const AUDIT_LEN: usize = 4096;
+
+/// When [`Autosave`] writes save RAM to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// At the end of every frame the game wrote save RAM in
+    Immediate,
+    /// Once the game has gone `frames` frames without writing, so a save routine that writes
+    /// over several frames is flushed once, complete
+    Idle { frames: u32 },
+    /// Only on [`Autosave::flush`], e.g. when the frontend exits
+    OnExit,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy::Idle {
+            frames: DEFAULT_IDLE_FRAMES,
+        }
+    }
+}
+
+impl FlushPolicy {
+    /// Parses `immediate`, `idle` or `exit`
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "immediate" => Some(FlushPolicy::Immediate),
+            "idle" => Some(FlushPolicy::default()),
+            "exit" => Some(FlushPolicy::OnExit),
+            _ => None,
+        }
+    }
+}
+
+/// One write to save RAM, and the frame it happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AuditEntry {
+    pub frame: u64,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// Save RAM was written to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flushed {
+    /// The console's frame number at the flush
+    pub frame: u64,
+    /// Writes to save RAM since the previous flush
+    pub writes: usize,
+}
+
+#[derive(Debug)]
+pub struct Autosave {
+    path: PathBuf,
+    policy: FlushPolicy,
+    audit: VecDeque<AuditEntry>,
+    /// Writes not on disk yet
+    unsaved: usize,
+    /// Frame number of the last write
+    last_write: u64,
+}
+
+impl Autosave {
+    pub fn new(path: impl AsRef<Path>, policy: FlushPolicy) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            policy,
+            audit: VecDeque::new(),
+            unsaved: 0,
+            last_write: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the save file into `console`'s save RAM, if there is one, and starts logging its
+    /// writes. Call after loading the ROM. Returns whether a save was loaded.
+    pub fn attach(&mut self, console: &mut Console) -> io::Result<bool> {
+        let bus = console.cpu_mut().bus_mut();
+        bus.track_sram_writes(true);
+        match fs::read(&self.path) {
+            Ok(data) => {
+                bus.load_sram(&data);
+                Ok(true)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Collects the frame's save RAM writes and flushes if the policy says so. Call after each
+    /// [`Console::run_frame`], or after each batch of them.
+    pub fn end_frame(&mut self, console: &mut Console) -> io::Result<Option<Flushed>> {
+        let frame = console.frame();
+        // Loading an earlier state or rewinding restarts the idle count from there
+        self.last_write = self.last_write.min(frame);
+        let writes = console.cpu_mut().bus_mut().take_sram_writes();
+        if !writes.is_empty() {
+            self.unsaved += writes.len();
+            self.last_write = frame;
+        }
+        for write in writes {
+            if self.audit.len() == AUDIT_LEN {
+                self.audit.pop_front();
+            }
+            self.audit.push_back(AuditEntry {
+                frame,
+                addr: write.addr,
+                value: write.value,
+            });
+        }
+        let due = match self.policy {
+            FlushPolicy::Immediate => true,
+            FlushPolicy::Idle { frames } => frame - self.last_write >= frames as u64,
+            FlushPolicy::OnExit => false,
+        };
+        if due {
+            self.flush(console)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Writes save RAM to disk now if anything is unsaved
+    pub fn flush(&mut self, console: &Console) -> io::Result<Option<Flushed>> {
+        if self.unsaved == 0 {
+            return Ok(None);
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, console.cpu().bus().sram())?;
+        fs::rename(&temp, &self.path)?;
+        let flushed = Flushed {
+            frame: console.frame(),
+            writes: std::mem::take(&mut self.unsaved),
+        };
+        Ok(Some(flushed))
+    }
+
+    /// Whether the game has written save RAM that isn't on disk yet
+    pub fn is_dirty(&self) -> bool {
+        self.unsaved > 0
+    }
+
+    /// The last [`AUDIT_LEN`] writes to save RAM, oldest first
+    pub fn audit(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.audit.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Increments the first byte of save RAM twice, then spins
+    const PROGRAM: &[u8] = &[
+        0xee, 0x00, 0x60, // INC $6000
+        0xa5, 0x10, // loop: LDA $10
+        0xd0, 0xfc, // BNE loop
+        0xe6, 0x10, // INC $10
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+
+    #[test]
+    fn test_flush_policies() {
+        let dir = std::env::temp_dir().join(format!("nes_emulator_sram_{}", std::process::id()));
+        let path = dir.join("game.sav");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut console = Console::new();
+        console.load(PROGRAM);
+        let mut autosave = Autosave::new(&path, FlushPolicy::Idle { frames: 2 });
+        assert!(!autosave.attach(&mut console).unwrap());
+        console.run_frame();
+        assert_eq!(autosave.end_frame(&mut console).unwrap(), None);
+        assert!(autosave.is_dirty());
+        console.run_frame();
+        assert_eq!(autosave.end_frame(&mut console).unwrap(), None);
+        console.run_frame();
+        // INC writes twice, the old value and then the new one
+        assert_eq!(
+            autosave.end_frame(&mut console).unwrap(),
+            Some(Flushed {
+                frame: 3,
+                writes: 4
+            })
+        );
+        assert!(!autosave.is_dirty());
+        assert_eq!(fs::read(&path).unwrap()[0], 2);
+        let frames: Vec<_> = autosave.audit().map(|entry| entry.frame).collect();
+        assert_eq!(frames, [1; 4]);
+
+        let mut restored = Console::new();
+        restored.load(PROGRAM);
+        let mut again = Autosave::new(&path, FlushPolicy::OnExit);
+        assert!(again.attach(&mut restored).unwrap());
+        assert_eq!(restored.cpu().bus().sram()[0], 2);
+        restored.run_frame();
+        assert_eq!(again.end_frame(&mut restored).unwrap(), None);
+        assert!(again.flush(&restored).unwrap().is_some());
+        assert_eq!(fs::read(&path).unwrap()[0], 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_loading_an_earlier_state_while_dirty() {
+        let dir =
+            std::env::temp_dir().join(format!("nes_emulator_sram_back_{}", std::process::id()));
+        let path = dir.join("game.sav");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut console = Console::new();
+        console.load(PROGRAM);
+        let mut autosave = Autosave::new(&path, FlushPolicy::Idle { frames: 2 });
+        autosave.attach(&mut console).unwrap();
+        let start = console.save_state();
+        for _ in 0..2 {
+            console.run_frame();
+            assert_eq!(autosave.end_frame(&mut console).unwrap(), None);
+        }
+        assert!(autosave.is_dirty());
+
+        console.load_state(&start).unwrap();
+        assert_eq!(autosave.end_frame(&mut console).unwrap(), None);
+        assert!(autosave.is_dirty());
+        // The program writes again the frame after the load, and the flush waits 2 frames more
+        for _ in 0..2 {
+            console.run_frame();
+            assert_eq!(autosave.end_frame(&mut console).unwrap(), None);
+        }
+        console.run_frame();
+        assert!(autosave.end_frame(&mut console).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
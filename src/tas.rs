@@ -0,0 +1,284 @@
+//! Frame by frame movie authoring, the primitives behind a TAS editor.
+//!
+//! A [`Tas`] owns a [`Console`] and the [`Movie`] being written for it, and keeps the two in step:
+//! the console has always run exactly the movie's input up to [`Tas::frame`].
+//!
+//! - [`Tas::advance`] runs one frame using the movie's input for it, extending the movie with
+//!   empty input at its end.
+//! - [`Tas::set_input`] edits any frame, and [`Tas::set_reset`] adds or removes a reset or power
+//!   cycle. Editing a frame that has already run rewinds the console to it, so the edit takes
+//!   effect; that counts as a re-record.
+//! - [`Tas::seek`] jumps to any frame, loading the nearest save-state anchor at or before it and
+//!   replaying from there. Anchors come from [`Tas::add_anchor`] and, optionally, automatically
+//!   every [`Tas::set_anchor_interval`] frames. The frame the movie starts at is always one.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::joypad::JoypadButton;
+//! use nes_emulator::tas::Tas;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]); // JMP $8000
+//! let mut tas = Tas::new(console);
+//! for _ in 0..10 {
+//!     tas.advance();
+//! }
+//! // Press A on frame 3 after the fact: the console rewinds to frame 3
+//! tas.set_input(3, 0, JoypadButton::BUTTON_A);
+//! assert_eq!(tas.frame(), 3);
+//! assert_eq!(tas.rerecords(), 1);
+//! tas.seek(10);
+//! assert_eq!(tas.movie().frames[3][0], JoypadButton::BUTTON_A);
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::console::{Console, ResetKind};
+use crate::joypad::JoypadButton;
+use crate::movie::Movie;
+
+/// `.fm2` header key FCEUX keeps the re-record count under
+const RERECORD_KEY: &str = "rerecordCount";
+
+pub struct Tas {
+    console: Console,
+    movie: Movie,
+    /// Console frame the movie's first frame runs on
+    start: u64,
+    /// Save states taken at the start of the movie frame they're keyed by
+    anchors: BTreeMap<u64, Vec<u8>>,
+    anchor_interval: Option<u64>,
+    rerecords: u64,
+}
+
+impl Tas {
+    /// Starts an empty movie from the console's current frame
+    pub fn new(console: Console) -> Self {
+        Self::with_movie(console, Movie::new())
+    }
+
+    /// Continues authoring `movie`, which starts at the console's current frame. The re-record
+    /// count picks up from the movie's header.
+    pub fn with_movie(console: Console, movie: Movie) -> Self {
+        let rerecords = movie
+            .header
+            .iter()
+            .find(|(key, _)| key == RERECORD_KEY)
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0);
+        let start = console.frame();
+        let mut anchors = BTreeMap::new();
+        anchors.insert(0, console.save_state());
+        Self {
+            console,
+            movie,
+            start,
+            anchors,
+            anchor_interval: None,
+            rerecords,
+        }
+    }
+
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    /// The movie so far, with the re-record count in its header
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Gives back the console and the movie
+    pub fn into_parts(self) -> (Console, Movie) {
+        (self.console, self.movie)
+    }
+
+    /// Movie frame the next [`Tas::advance`] runs
+    pub fn frame(&self) -> u64 {
+        self.console.frame() - self.start
+    }
+
+    /// Times the run has been rewound to change its input
+    pub fn rerecords(&self) -> u64 {
+        self.rerecords
+    }
+
+    /// Buttons `player` holds on movie frame `frame`; empty past the end of the movie
+    pub fn input(&self, frame: u64, player: usize) -> JoypadButton {
+        self.movie
+            .frames
+            .get(frame as usize)
+            .map_or(JoypadButton::empty(), |input| input[player])
+    }
+
+    /// Sets the buttons `player` holds on movie frame `frame`, extending the movie with empty
+    /// input if it's past the end. Changing a frame that has already run rewinds to it.
+    pub fn set_input(&mut self, frame: u64, player: usize, buttons: JoypadButton) {
+        assert!(player < 2, "no controller port {player}");
+        if self.input(frame, player) == buttons {
+            return;
+        }
+        let index = frame as usize;
+        if self.movie.frames.len() <= index {
+            self.movie
+                .frames
+                .resize(index + 1, [JoypadButton::empty(); 2]);
+        }
+        self.movie.frames[index][player] = buttons;
+        self.edited(frame);
+    }
+
+    /// Resets or power cycles the console at the start of movie frame `frame`, or with `None`
+    /// doesn't. Changing a frame that has already run rewinds to it.
+    pub fn set_reset(&mut self, frame: u64, reset: Option<ResetKind>) {
+        let index = frame as usize;
+        if self.movie.resets.get(&index).copied() == reset {
+            return;
+        }
+        match reset {
+            Some(kind) => self.movie.resets.insert(index, kind),
+            None => self.movie.resets.remove(&index),
+        };
+        self.edited(frame);
+    }
+
+    fn edited(&mut self, frame: u64) {
+        // Anchors after the edit were taken on the old input
+        self.anchors.retain(|&anchor, _| anchor <= frame);
+        if frame < self.frame() {
+            self.seek(frame);
+            self.count_rerecord();
+        }
+    }
+
+    /// Runs one frame of the movie, appending an empty frame of input at its end
+    pub fn advance(&mut self) {
+        let frame = self.frame();
+        if self.movie.frames.len() <= frame as usize {
+            self.movie
+                .push(JoypadButton::empty(), JoypadButton::empty());
+        }
+        if let Some(&kind) = self.movie.resets.get(&(frame as usize)) {
+            self.console.apply_reset(kind);
+        }
+        let input = self.movie.frames[frame as usize];
+        for (player, buttons) in input.into_iter().enumerate() {
+            self.console.joypad_mut(player).set_buttons(buttons);
+        }
+        self.console.run_frame();
+
+        let frame = self.frame();
+        if self
+            .anchor_interval
+            .is_some_and(|n| frame.is_multiple_of(n))
+        {
+            self.add_anchor();
+        }
+    }
+
+    /// Moves to the start of movie frame `frame`, replaying from the nearest anchor when it's
+    /// behind. Frames past the end of the movie run with empty input, extending it.
+    pub fn seek(&mut self, frame: u64) {
+        if frame < self.frame() || self.nearest_anchor(frame) > self.frame() {
+            let anchor = self.nearest_anchor(frame);
+            self.console
+                .load_state(&self.anchors[&anchor])
+                .expect("anchors are states of the loaded ROM");
+        }
+        while self.frame() < frame {
+            self.advance();
+        }
+    }
+
+    /// Saves an anchor at the current frame for [`Tas::seek`] to start from
+    pub fn add_anchor(&mut self) {
+        self.anchors.insert(self.frame(), self.console.save_state());
+    }
+
+    /// Frames with an anchor, in order
+    pub fn anchors(&self) -> impl Iterator<Item = u64> + '_ {
+        self.anchors.keys().copied()
+    }
+
+    /// Adds an anchor every `frames` frames while advancing, or stops when `None`
+    pub fn set_anchor_interval(&mut self, frames: Option<u64>) {
+        self.anchor_interval = frames.filter(|&n| n > 0);
+    }
+
+    fn nearest_anchor(&self, frame: u64) -> u64 {
+        // The movie's first frame always has one
+        *self.anchors.range(..=frame).next_back().unwrap().0
+    }
+
+    fn count_rerecord(&mut self) {
+        self.rerecords += 1;
+        let count = self.rerecords.to_string();
+        match self
+            .movie
+            .header
+            .iter_mut()
+            .find(|(key, _)| key == RERECORD_KEY)
+        {
+            Some((_, value)) => *value = count,
+            None => self.movie.header.push((RERECORD_KEY.to_string(), count)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+
+    /// Keeps adding controller 1's A button into $00, so $00 depends on which frames held it
+    fn console() -> Console {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x01,       // loop: LDA #$01
+            0x8d, 0x16, 0x40, //       STA $4016
+            0xa9, 0x00,       //       LDA #$00
+            0x8d, 0x16, 0x40, //       STA $4016
+            0xad, 0x16, 0x40, //       LDA $4016
+            0x29, 0x01,       //       AND #$01
+            0x65, 0x00,       //       ADC $00
+            0x85, 0x00,       //       STA $00
+            0x4c, 0x00, 0x80, //       JMP loop
+        ]);
+        console
+    }
+
+    #[test]
+    fn test_editing_the_past_replays_it() {
+        let mut tas = Tas::new(console());
+        tas.set_anchor_interval(Some(4));
+        tas.set_input(2, 0, JoypadButton::BUTTON_A);
+        tas.seek(10);
+        let once = tas.console().cpu().mem_peek(0x0000);
+        assert!(once > 0);
+        assert_eq!(tas.anchors().collect::<Vec<_>>(), [0, 4, 8]);
+        assert_eq!(tas.rerecords(), 0);
+
+        // Holding A on frame 6 as well drops the anchor taken on the old input
+        tas.set_input(6, 0, JoypadButton::BUTTON_A);
+        assert_eq!(tas.frame(), 6);
+        assert_eq!(tas.anchors().collect::<Vec<_>>(), [0, 4]);
+        tas.seek(10);
+        let twice = tas.console().cpu().mem_peek(0x0000);
+        assert!(twice > once);
+
+        // The same edits made live reach the same state
+        let mut fresh = Tas::new(console());
+        assert!(fresh.movie().is_empty());
+        let (movie, header) = (tas.movie().frames.clone(), tas.movie().header.clone());
+        for (frame, input) in movie.iter().enumerate() {
+            fresh.set_input(frame as u64, 0, input[0]);
+        }
+        fresh.seek(10);
+        assert_eq!(fresh.console().state_hash(), tas.console().state_hash());
+        assert_eq!(header, [("rerecordCount".to_string(), "1".to_string())]);
+
+        let (_, movie) = tas.into_parts();
+        assert_eq!(Tas::with_movie(console(), movie).rerecords(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! A mailbox for homebrew test ROMs to report results to the host, and helpers to assert on them.
+//!
+//! Growing an accuracy test suite means writing small ROMs that check one behavior each. Instead
+//! of drawing a result on screen for someone to read, a ROM following this convention writes it
+//! to four registers in the expansion range, which [`run`] attaches as a
+//! [`Device`]:
+//!
+//! | Address | Name | Writes |
+//! | :--- | :--- | :--- |
+//! | `$5FF0` | [`RESULT`] | ends the test: `$00` passes, anything else fails with that code |
+//! | `$5FF1` | [`PRINT`] | appends a character to the message, e.g. what was being tested |
+//! | `$5FF2` | [`EXPECT`] | sets the value the next write to `ACTUAL` should be |
+//! | `$5FF3` | [`ACTUAL`] | checks the value against `EXPECT`, recording a failure if they differ |
+//!
+//! An assertion is then two stores, `LDA #expected; STA $5FF2; LDA value; STA $5FF3`, and a failed
+//! one doesn't stop the ROM, so one run reports every mismatch. Reads of the registers read
+//! memory as usual.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::test_rom::{self, Verdict};
+//!
+//! let mut console = Console::new();
+//! #[rustfmt::skip]
+//! console.load(&[
+//!     0xa9, 0x4f, 0x8d, 0xf1, 0x5f, // print "O"
+//!     0xa9, 0x02, 0x8d, 0xf2, 0x5f, // expect 2
+//!     0xa9, 0x01, 0x0a,             // LDA #1; ASL A
+//!     0x8d, 0xf3, 0x5f,             // check A
+//!     0xa9, 0x00, 0x8d, 0xf0, 0x5f, // pass
+//!     0x4c, 0x15, 0x80,             // spin
+//! ]);
+//! let report = test_rom::run(&mut console, 10)?;
+//! assert_eq!(report.verdict, Verdict::Passed);
+//! assert_eq!(report.message, "O");
+//! assert!(report.passed());
+//! # Ok::<(), nes_emulator::bus::DeviceError>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{Device, DeviceError};
+use crate::console::Console;
+
+pub const RESULT: u16 = 0x5FF0;
+pub const PRINT: u16 = 0x5FF1;
+pub const EXPECT: u16 = 0x5FF2;
+pub const ACTUAL: u16 = 0x5FF3;
+
+/// How a test ROM run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    Passed,
+    /// The ROM wrote this code to [`RESULT`]
+    Failed(u8),
+    /// No result within the frames allowed
+    TimedOut,
+    /// The CPU stopped on an unofficial opcode or a halting `BRK` before a result
+    Halted,
+}
+
+/// A write to [`ACTUAL`] that didn't match [`EXPECT`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AssertionFailure {
+    /// How many assertions came before it
+    pub index: u32,
+    pub expected: u8,
+    pub actual: u8,
+    /// The frame it happened in
+    pub frame: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub verdict: Verdict,
+    /// Everything written to [`PRINT`]
+    pub message: String,
+    pub assertions: u32,
+    pub failures: Vec<AssertionFailure>,
+    /// The frame the run ended on
+    pub frame: u64,
+}
+
+impl Report {
+    /// Whether the ROM passed without any failed assertion
+    pub fn passed(&self) -> bool {
+        self.verdict == Verdict::Passed && self.failures.is_empty()
+    }
+}
+
+/// `passed`, or what went wrong, then the message and the failed assertions one per line
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.verdict {
+            Verdict::Passed if self.failures.is_empty() => write!(f, "passed")?,
+            Verdict::Passed => write!(f, "passed with failed assertions")?,
+            Verdict::Failed(code) => write!(f, "failed with code {code}")?,
+            Verdict::TimedOut => write!(f, "no result by frame {}", self.frame)?,
+            Verdict::Halted => write!(f, "halted at frame {}", self.frame)?,
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message.trim_end())?;
+        }
+        for failure in &self.failures {
+            write!(
+                f,
+                "\nassertion {} in frame {}: expected ${:02X}, got ${:02X}",
+                failure.index, failure.frame, failure.expected, failure.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Report {}
+
+/// The [`Device`] behind the mailbox registers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mailbox {
+    result: Option<u8>,
+    message: String,
+    expected: u8,
+    assertions: u32,
+    failures: Vec<AssertionFailure>,
+    /// Stamped on failures; kept up to date by [`run`]
+    frame: u64,
+}
+
+impl Device for Mailbox {
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            RESULT => self.result = Some(value),
+            PRINT => self.message.push(value as char),
+            EXPECT => self.expected = value,
+            ACTUAL => {
+                if value != self.expected {
+                    self.failures.push(AssertionFailure {
+                        index: self.assertions,
+                        expected: self.expected,
+                        actual: value,
+                        frame: self.frame,
+                    });
+                }
+                self.assertions += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Attaches a [`Mailbox`] to `console` and runs it until the ROM writes a result, the CPU
+/// halts, or `max_frames` frames have passed. Call it once per console: a second call can't
+/// attach another mailbox over the first.
+pub fn run(console: &mut Console, max_frames: u64) -> Result<Report, DeviceError> {
+    let mailbox = Arc::new(Mutex::new(Mailbox::default()));
+    console
+        .cpu_mut()
+        .bus_mut()
+        .attach_device(RESULT..=ACTUAL, mailbox.clone())?;
+
+    let mut verdict = Verdict::TimedOut;
+    for _ in 0..max_frames {
+        mailbox.lock().unwrap().frame = console.frame();
+        console.run_frame();
+        if let Some(code) = mailbox.lock().unwrap().result {
+            verdict = match code {
+                0 => Verdict::Passed,
+                code => Verdict::Failed(code),
+            };
+            break;
+        }
+        if console.is_halted() {
+            verdict = Verdict::Halted;
+            break;
+        }
+    }
+
+    let mailbox = std::mem::take(&mut *mailbox.lock().unwrap());
+    Ok(Report {
+        verdict,
+        message: mailbox.message,
+        assertions: mailbox.assertions,
+        failures: mailbox.failures,
+        frame: console.frame(),
+    })
+}
+
+/// [`run`], panicking with the report unless the ROM passed without a failed assertion
+#[track_caller]
+pub fn assert_passes(console: &mut Console, max_frames: u64) {
+    let report = run(console, max_frames).expect("the mailbox range is free");
+    assert!(report.passed(), "{report}");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_failures() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x05, 0x8d, 0xf2, 0x5f, // expect 5
+            0xa9, 0x06, 0x8d, 0xf3, 0x5f, // got 6
+            0x8d, 0xf2, 0x5f,             // expect 6
+            0x8d, 0xf3, 0x5f,             // got 6
+            0xa9, 0x58, 0x8d, 0xf1, 0x5f, // print "X"
+            0xa9, 0x03, 0x8d, 0xf0, 0x5f, // fail with code 3
+            0x4c, 0x1a, 0x80,             // spin
+        ]);
+        let report = run(&mut console, 10).unwrap();
+        assert_eq!(report.verdict, Verdict::Failed(3));
+        assert_eq!(report.assertions, 2);
+        assert_eq!(
+            report.to_string(),
+            "failed with code 3: X\nassertion 0 in frame 0: expected $05, got $06"
+        );
+        assert!(run(&mut console, 1).is_err());
+
+        let mut console = Console::new();
+        console.load(&[0x4c, 0x00, 0x80]);
+        assert_eq!(run(&mut console, 3).unwrap().verdict, Verdict::TimedOut);
+        let mut console = Console::new();
+        console.set_halt_on_brk(true);
+        console.load(&[0x00]);
+        assert_eq!(run(&mut console, 3).unwrap().verdict, Verdict::Halted);
+    }
+}
@@ -0,0 +1,85 @@
+//! Small previews of a frame, stored in save states so frontends can show visual save slots.
+//!
+//! A [`Thumbnail`] is the frame scaled down by half to 128x120, each pixel the average of a 2x2
+//! block, stored as packed RGB888.
+
+use crate::frame::{self, Frame, Palette};
+use crate::savestate::{ChunkReader, ChunkWriter, StateError};
+
+pub const WIDTH: usize = frame::WIDTH / 2;
+pub const HEIGHT: usize = frame::HEIGHT / 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    rgb: Vec<u8>,
+}
+
+impl Thumbnail {
+    pub fn from_frame(frame: &Frame, palette: &Palette) -> Self {
+        let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for_each_pixel(frame, palette, |pixel| rgb.extend_from_slice(&pixel));
+        Self { rgb }
+    }
+
+    /// Packed `R, G, B` bytes, row major from the top left
+    pub fn rgb(&self) -> &[u8] {
+        &self.rgb
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let i = (y * WIDTH + x) * 3;
+        (self.rgb[i], self.rgb[i + 1], self.rgb[i + 2])
+    }
+
+    /// Writes the thumbnail of `frame` to a save-state chunk without building it in memory first
+    pub fn save_frame(frame: &Frame, palette: &Palette, w: &mut ChunkWriter) {
+        for_each_pixel(frame, palette, |pixel| w.write_bytes(&pixel));
+    }
+
+    pub fn load(r: &mut ChunkReader) -> Result<Self, StateError> {
+        Ok(Self {
+            rgb: r.read_bytes(WIDTH * HEIGHT * 3)?.to_vec(),
+        })
+    }
+}
+
+/// Calls `f` with every thumbnail pixel in order
+fn for_each_pixel(frame: &Frame, palette: &Palette, mut f: impl FnMut([u8; 3])) {
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let mut sum = [0u16; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (r, g, b) = palette.rgb(frame.get_pixel(x * 2 + dx, y * 2 + dy));
+                sum[0] += r as u16;
+                sum[1] += g as u16;
+                sum[2] += b as u16;
+            }
+            f(sum.map(|channel| ((channel + 2) / 4) as u8)); // round to nearest
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::SYSTEM_PALETTE;
+
+    #[test]
+    fn test_averages_2x2_blocks() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x30); // white
+        frame.set_pixel(1, 0, 0x30);
+        frame.set_pixel(0, 1, 0x0D); // black
+        frame.set_pixel(1, 1, 0x0D);
+        frame.set_pixel(2, 0, 0x0D); // block (1, 0) is all black
+        frame.set_pixel(3, 0, 0x0D);
+        frame.set_pixel(2, 1, 0x0D);
+        frame.set_pixel(3, 1, 0x0D);
+
+        let thumbnail = Thumbnail::from_frame(&frame, &SYSTEM_PALETTE);
+        assert_eq!(thumbnail.rgb().len(), 128 * 120 * 3);
+        assert_eq!(thumbnail.get_pixel(0, 0), (0x80, 0x80, 0x80));
+        assert_eq!(thumbnail.get_pixel(1, 0), (0, 0, 0));
+        assert_eq!(thumbnail.get_pixel(5, 5), SYSTEM_PALETTE.rgb(0));
+    }
+}
@@ -0,0 +1,178 @@
+//! Nametables exported as tile maps, for level editors and map ripping.
+//!
+//! A [`Tilemap`] holds the four nametables as the PPU sees them after mirroring, laid out 2x2 as
+//! a 64x60 grid of tiles the way scrolling moves across them:
+//!
+//! | | |
+//! | :---: | :---: |
+//! | `0x2000` | `0x2400` |
+//! | `0x2800` | `0x2C00` |
+//!
+//! Each cell has the tile number from the nametable and the background palette from the attribute
+//! table. [`Tilemap::tiles_csv`] and [`Tilemap::palettes_csv`] write those as plain CSV grids, and
+//! [`Tilemap::to_tmx`] writes a [Tiled](https://www.mapeditor.org) map whose tileset is the image
+//! from [`ripper::background_tileset`](crate::ripper::background_tileset).
+//!
+//! ```no_run
+//! # use nes_emulator::console::Console;
+//! use nes_emulator::{ripper, tilemap::Tilemap};
+//! # let console = Console::new();
+//!
+//! ripper::background_tileset(console.ppu(), console.palette()).save_png("level.png")?;
+//! std::fs::write("level.tmx", Tilemap::from_ppu(console.ppu()).to_tmx("level.png"))?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fmt::Write;
+
+use crate::ppu::Ppu;
+
+/// Width and height in tiles of the 2x2 nametables
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 60;
+const TABLE_WIDTH: usize = 32;
+const TABLE_HEIGHT: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tilemap {
+    tiles: Vec<u8>,
+    palettes: Vec<u8>,
+}
+
+impl Tilemap {
+    /// Reads the nametables and attribute tables from PPU memory
+    pub fn from_ppu(ppu: &Ppu) -> Self {
+        let mut tiles = Vec::with_capacity(WIDTH * HEIGHT);
+        let mut palettes = Vec::with_capacity(WIDTH * HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let table = (y / TABLE_HEIGHT * 2 + x / TABLE_WIDTH) as u16;
+                let nametable = 0x2000 + table * 0x400;
+                let (tile_x, tile_y) = (x % TABLE_WIDTH, y % TABLE_HEIGHT);
+                let addr = nametable + (tile_y * TABLE_WIDTH + tile_x) as u16;
+                tiles.push(ppu.read_vram(addr));
+                palettes.push(ppu.tile_palette(nametable, tile_x, tile_y));
+            }
+        }
+        Self { tiles, palettes }
+    }
+
+    /// Tile number at (`x`, `y`) in the 64x60 grid
+    pub fn tile(&self, x: usize, y: usize) -> u8 {
+        self.tiles[y * WIDTH + x]
+    }
+
+    /// Background palette (0-3) at (`x`, `y`) in the 64x60 grid
+    pub fn palette(&self, x: usize, y: usize) -> u8 {
+        self.palettes[y * WIDTH + x]
+    }
+
+    /// Tile numbers, one row of the grid per line
+    pub fn tiles_csv(&self) -> String {
+        csv(&self.tiles, |tile| tile as u32)
+    }
+
+    /// Background palettes, one row of the grid per line
+    pub fn palettes_csv(&self) -> String {
+        csv(&self.palettes, |palette| palette as u32)
+    }
+
+    /// A Tiled TMX map with one tile layer, using `tileset_image` (a path relative to the map) as
+    /// the tileset. Cells pick tile `palette * 256 + tile` of the tileset, matching the layout of
+    /// [`ripper::background_tileset`](crate::ripper::background_tileset).
+    pub fn to_tmx(&self, tileset_image: &str) -> String {
+        let mut tmx = String::new();
+        let _ = write!(
+            tmx,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" ",
+                "width=\"{w}\" height=\"{h}\" tilewidth=\"8\" tileheight=\"8\" infinite=\"0\" ",
+                "nextlayerid=\"2\" nextobjectid=\"1\">\n",
+                " <tileset firstgid=\"1\" name=\"background\" tilewidth=\"8\" tileheight=\"8\" ",
+                "tilecount=\"1024\" columns=\"16\">\n",
+                "  <image source=\"{image}\" width=\"128\" height=\"512\"/>\n",
+                " </tileset>\n",
+                " <layer id=\"1\" name=\"nametables\" width=\"{w}\" height=\"{h}\">\n",
+                "  <data encoding=\"csv\">\n",
+            ),
+            w = WIDTH,
+            h = HEIGHT,
+            image = escape_xml(tileset_image),
+        );
+        // Global tile ids start at 1; 0 is an empty cell
+        let cells: Vec<u32> = self
+            .tiles
+            .iter()
+            .zip(&self.palettes)
+            .map(|(&tile, &palette)| palette as u32 * 256 + tile as u32 + 1)
+            .collect();
+        // Tiled wants commas between rows as well
+        let rows: Vec<String> = cells
+            .chunks(WIDTH)
+            .map(|row| join(row.iter().copied()))
+            .collect();
+        tmx.push_str(&rows.join(",\n"));
+        tmx.push_str("\n</data>\n </layer>\n</map>\n");
+        tmx
+    }
+}
+
+fn join(values: impl Iterator<Item = u32>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn csv(cells: &[u8], value: impl Fn(u8) -> u32) -> String {
+    let mut out = String::new();
+    for row in cells.chunks(WIDTH) {
+        out.push_str(&join(row.iter().map(|&cell| value(cell))));
+        out.push('\n');
+    }
+    out
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_export() {
+        let mut ppu = Ppu::new();
+        ppu.load_cartridge(&[], Mirroring::Vertical);
+        let mut write = |addr: u16, data: &[u8]| {
+            ppu.write_register(0x2006, (addr >> 8) as u8);
+            ppu.write_register(0x2006, addr as u8);
+            for &byte in data {
+                ppu.write_register(0x2007, byte);
+            }
+        };
+        write(0x2000, &[0x12, 0x34]);
+        // Palette 2 for the top right 2x2 tiles of the first 4x4 block
+        write(0x23C0, &[0b0000_1000]);
+
+        let map = Tilemap::from_ppu(&ppu);
+        assert_eq!((map.tile(0, 0), map.tile(1, 0)), (0x12, 0x34));
+        // Vertical mirroring: 0x2800 shows the same table as 0x2000
+        assert_eq!(map.tile(0, 30), 0x12);
+        assert_eq!(map.tile(32, 0), 0);
+        assert_eq!((map.palette(1, 0), map.palette(2, 0)), (0, 2));
+
+        let tiles = map.tiles_csv();
+        assert_eq!(tiles.lines().count(), HEIGHT);
+        assert!(tiles.starts_with("18,52,0,"));
+        assert!(map.palettes_csv().starts_with("0,0,2,2,0,"));
+
+        let tmx = map.to_tmx("a&b.png");
+        assert!(tmx.contains("source=\"a&amp;b.png\""));
+        assert!(tmx.contains("<data encoding=\"csv\">\n19,53,513,"));
+        assert_eq!(tmx.matches(',').count(), WIDTH * HEIGHT - 1);
+    }
+}
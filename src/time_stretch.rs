@@ -0,0 +1,187 @@
+//! Time-stretching audio, for playing it at other speeds without changing its pitch.
+//!
+//! Fast-forward runs several frames per frame shown, which leaves a frontend three bad choices
+//! for their audio: play all of it and fall behind, play it faster and turn the music into
+//! chipmunks, or drop all but one frame in each and stutter. A [`TimeStretch`] fits a run of
+//! audio into less time (or more, for slow motion) at the same pitch, by WSOLA: it cuts the input
+//! into overlapping windows spaced by the speed, nudges each to where it lines up best with the
+//! one before, and cross-fades them back together at the normal spacing. Music keeps its notes
+//! and sound effects stay recognizable, at the cost of some smearing at high speeds.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::time_stretch::TimeStretch;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! let mut stretch = TimeStretch::new(console.apu().sample_rate());
+//! let mut played = Vec::new();
+//! // Fast-forward at 4x: four frames of audio in the time of one
+//! let mut audio = Vec::new();
+//! for _ in 0..4 {
+//!     console.run_frame();
+//!     audio.extend_from_slice(console.audio_samples());
+//! }
+//! stretch.process(&audio, 4.0, &mut played);
+//! assert!(played.len() < audio.len() / 2);
+//! ```
+//!
+//! It holds back most of a window of audio, about 20 milliseconds, to line windows up.
+
+/// Windows per second of audio; each lasts 20 milliseconds
+const WINDOWS_PER_SECOND: u32 = 50;
+const MIN_WINDOW: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeStretch {
+    /// Samples in a window. Windows overlap by half, so output moves on by half a window a step.
+    window: usize,
+    /// How far either side of its place a window can move to line up with the one before
+    tolerance: usize,
+    /// Hann weights for one window, adding up to 1 where two windows overlap
+    weights: Vec<f32>,
+    /// Input not consumed yet
+    input: Vec<f32>,
+    /// Where in `input` the next window goes before lining it up
+    next: f64,
+    /// Where in `input` the audio that followed the second half of the last window starts, once
+    /// there has been one
+    continuation: Option<usize>,
+    /// The weighted second half of the last window, to add to the next one
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    /// A stretcher for audio at `sample_rate` samples per second
+    pub fn new(sample_rate: u32) -> Self {
+        let window = ((sample_rate / WINDOWS_PER_SECOND) as usize & !1).max(MIN_WINDOW);
+        let weights = (0..window)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / window as f32).cos())
+            .collect();
+        Self {
+            window,
+            tolerance: window / 4,
+            weights,
+            input: Vec::new(),
+            next: 0.0,
+            continuation: None,
+            tail: vec![0.0; window / 2],
+        }
+    }
+
+    /// Adds `input`, audio emulated at `speed` times real time, and appends to `out` as much of
+    /// it as is ready, stretched back to real time: about `input.len() / speed` samples. The
+    /// speed can change from call to call.
+    pub fn process(&mut self, input: &[f32], speed: f32, out: &mut Vec<f32>) {
+        assert!(speed > 0.0, "speed must be positive");
+        self.input.extend_from_slice(input);
+        let hop = self.window / 2;
+        loop {
+            let place = self.next.round() as usize;
+            let start = match self.continuation {
+                Some(continuation) => {
+                    if place + self.tolerance + self.window > self.input.len() {
+                        break;
+                    }
+                    self.line_up(continuation, place)
+                }
+                None if place + self.window > self.input.len() => break,
+                None => place,
+            };
+            let window = &self.input[start..start + self.window];
+            for i in 0..hop {
+                out.push(self.tail[i] + window[i] * self.weights[i]);
+                self.tail[i] = window[hop + i] * self.weights[hop + i];
+            }
+            self.continuation = Some(start + hop);
+            self.next += hop as f64 * speed as f64;
+        }
+
+        // Keep what the next window can still start from, and the continuation it lines up with
+        let needed = (self.next as usize).saturating_sub(self.tolerance);
+        let consumed = self.continuation.map_or(needed, |start| needed.min(start));
+        self.input.drain(..consumed);
+        self.next -= consumed as f64;
+        self.continuation = self.continuation.map(|start| start - consumed);
+    }
+
+    /// Where within the tolerance of `place` a window starts that best continues the audio that
+    /// followed the last window, from `continuation`
+    fn line_up(&self, continuation: usize, place: usize) -> usize {
+        let overlap = self.window / 2;
+        let target = &self.input[continuation..continuation + overlap];
+        let difference = |start: usize| -> f32 {
+            self.input[start..start + overlap]
+                .iter()
+                .zip(target)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum()
+        };
+        let mut best = place;
+        let mut best_difference = difference(place);
+        for start in place.saturating_sub(self.tolerance)..=place + self.tolerance {
+            let d = difference(start);
+            if d < best_difference {
+                (best, best_difference) = (start, d);
+            }
+        }
+        best
+    }
+
+    /// Drops the audio held back, e.g. after a pause or a state load
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.next = 0.0;
+        self.continuation = None;
+        self.tail.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RATE: u32 = 44_100;
+
+    fn tone(samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| (std::f32::consts::TAU * 441.0 * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    /// Upward zero crossings per 1000 samples, which only a change of pitch changes
+    fn crossings(samples: &[f32]) -> f32 {
+        let count = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        count as f32 * 1000.0 / samples.len() as f32
+    }
+
+    #[test]
+    fn test_stretching_keeps_the_pitch() {
+        let input = tone(RATE as usize / 2);
+        let mut stretch = TimeStretch::new(RATE);
+        let mut out = Vec::new();
+        for chunk in input.chunks(735) {
+            stretch.process(chunk, 1.0, &mut out);
+        }
+        // At normal speed it's the input, once the first window fades in
+        let hop = stretch.window / 2;
+        assert!(out.len() > input.len() - 2 * stretch.window);
+        for (i, sample) in out.iter().enumerate().skip(hop) {
+            assert!((sample - input[i]).abs() < 1e-4, "sample {i}");
+        }
+
+        for speed in [4.0, 0.5] {
+            let mut stretch = TimeStretch::new(RATE);
+            let mut out = Vec::new();
+            for chunk in input.chunks(735) {
+                stretch.process(chunk, speed, &mut out);
+            }
+            let expected = input.len() as f32 / speed;
+            assert!((out.len() as f32 - expected).abs() < expected * 0.05);
+            assert!((crossings(&out[hop..]) - crossings(&input)).abs() < 0.2);
+        }
+    }
+}
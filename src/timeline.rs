@@ -0,0 +1,341 @@
+//! Per-frame timelines of interrupts and DMA, for "why did my handler run late" problems.
+//!
+//! With [`Console::track_interrupts`] on, every frame records when the PPU asserted NMI and when
+//! the CPU got to it, when the APU's frame counter, the DMC and the board's mapper raised and
+//! dropped their interrupt flags and when the CPU took the IRQ, and how long OAM and DMC DMA
+//! stalled the CPU. [`Console::interrupt_timeline`] holds the
+//! last frame's [`Timeline`], which can be listed with [`Timeline::to_text`] or drawn as lanes
+//! across the frame with [`Timeline::draw`].
+//!
+//! Frames end after the instruction that crosses their last cycle, so vblank can start during a
+//! frame's last instruction: its NMI is then asserted at the very end of that frame, a few cycles
+//! past its nominal length, and serviced at the start of the next.
+//!
+//! IRQs are only serviced while the CPU has interrupts enabled, so a source can assert and be
+//! acknowledged with no [`EventKind::IrqServiced`] in between.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::timeline::EventKind;
+//!
+//! let mut console = Console::new();
+//! #[rustfmt::skip]
+//! console.load(&[
+//!     0xa9, 0x80, 0x8d, 0x00, 0x20, // enable NMI
+//!     0x4c, 0x05, 0x80,             // spin
+//!     0x40,                         // NMI: RTI
+//! ]);
+//! console.cpu_mut().bus_mut().load(0xFFFA, &[0x08, 0x80]);
+//! console.track_interrupts(true);
+//! console.run_frame();
+//! let timeline = console.interrupt_timeline().unwrap();
+//! let kinds: Vec<_> = timeline.events().iter().map(|event| event.kind).collect();
+//! // Enabling NMI during vblank asserts it at once
+//! assert_eq!(kinds[..2], [EventKind::NmiAsserted, EventKind::NmiServiced]);
+//! assert_eq!(timeline.nmi_latency(), Some(0));
+//! ```
+//!
+//! [`Console::track_interrupts`]: crate::console::Console::track_interrupts
+//! [`Console::interrupt_timeline`]: crate::console::Console::interrupt_timeline
+
+use std::fmt::{self, Write};
+
+use crate::bus::Bus;
+use crate::timing_overlay::NMI_COLOR;
+
+pub const IRQ_COLOR: (u8, u8, u8) = (0xFF, 0xD0, 0x00);
+pub const DMA_COLOR: (u8, u8, u8) = (0x20, 0xA0, 0xFF);
+/// Height of each lane in [`Timeline::draw`]
+pub const LANE_HEIGHT: usize = 8;
+/// NMI, IRQ and DMA
+pub const LANES: usize = 3;
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    /// The board's [`Mapper`](crate::mapper::Mapper)
+    Mapper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// The PPU started vblank, or NMI was enabled during it
+    NmiAsserted,
+    /// The CPU finished the instruction it was on and jumped to the NMI handler
+    NmiServiced,
+    IrqAsserted(IrqSource),
+    /// The flag was acknowledged or its source disabled
+    IrqCleared(IrqSource),
+    /// The CPU finished the instruction it was on and jumped to the IRQ handler
+    IrqServiced,
+    /// A write to `0x4014` stalled the CPU for `cycles`
+    OamDma {
+        cycles: u64,
+    },
+    /// DMC sample fetches stalled the CPU for `cycles`
+    DmcDma {
+        cycles: u64,
+    },
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::NmiAsserted => write!(f, "NMI asserted"),
+            EventKind::NmiServiced => write!(f, "NMI serviced"),
+            EventKind::IrqAsserted(source) => write!(f, "{source:?} IRQ asserted"),
+            EventKind::IrqCleared(source) => write!(f, "{source:?} IRQ cleared"),
+            EventKind::IrqServiced => write!(f, "IRQ serviced"),
+            EventKind::OamDma { cycles } => write!(f, "OAM DMA, {cycles} cycles"),
+            EventKind::DmcDma { cycles } => write!(f, "DMC DMA, {cycles} cycles"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimelineEvent {
+    /// CPU cycles since the frame started
+    pub cycle: u64,
+    /// Where the PPU was when the event was seen
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline {
+    events: Vec<TimelineEvent>,
+    /// CPU cycles the frame ran for
+    cycles: u64,
+    /// Whether the pending NMI has been recorded as asserted
+    nmi_asserted: bool,
+    /// Interrupt flags as of the last check, by [`IrqSource`]
+    irqs: [bool; 3],
+    /// DMC stall cycles charged to the CPU with the next instruction
+    dmc_stall: u64,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The frame's events, oldest first
+    pub fn events(&self) -> &[TimelineEvent] {
+        &self.events
+    }
+
+    /// CPU cycles the frame ran for
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Cycles from the frame's first NMI being asserted to the CPU servicing it
+    pub fn nmi_latency(&self) -> Option<u64> {
+        let asserted = self.find(EventKind::NmiAsserted, 0)?;
+        let serviced = self.find(EventKind::NmiServiced, asserted)?;
+        Some(serviced - asserted)
+    }
+
+    /// Cycles from the frame's first IRQ being asserted, by any source, to the CPU servicing it
+    pub fn irq_latency(&self) -> Option<u64> {
+        let asserted = self
+            .events
+            .iter()
+            .find(|event| matches!(event.kind, EventKind::IrqAsserted(_)))?
+            .cycle;
+        let serviced = self.find(EventKind::IrqServiced, asserted)?;
+        Some(serviced - asserted)
+    }
+
+    fn find(&self, kind: EventKind, from: u64) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|event| event.kind == kind && event.cycle >= from)
+            .map(|event| event.cycle)
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.events.clear();
+    }
+
+    pub(crate) fn end_frame(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    /// Records what changed over one instruction: `stall` is the DMA cycles charged for it, made
+    /// of the DMC's fetches from before it and any OAM DMA it started
+    pub(crate) fn after_step(&mut self, cycle: u64, stall: u64, bus: &Bus) {
+        let dmc = std::mem::take(&mut self.dmc_stall).min(stall);
+        if dmc > 0 {
+            self.push(cycle, bus, EventKind::DmcDma { cycles: dmc });
+        }
+        if stall > dmc {
+            let cycles = stall - dmc;
+            self.push(cycle, bus, EventKind::OamDma { cycles });
+        }
+        self.observe(cycle, bus);
+    }
+
+    pub(crate) fn nmi_serviced(&mut self, cycle: u64, bus: &Bus) {
+        self.nmi_asserted = false;
+        self.push(cycle, bus, EventKind::NmiServiced);
+    }
+
+    pub(crate) fn irq_serviced(&mut self, cycle: u64, bus: &Bus) {
+        self.push(cycle, bus, EventKind::IrqServiced);
+    }
+
+    /// Records interrupt lines that changed since the last check
+    pub(crate) fn observe(&mut self, cycle: u64, bus: &Bus) {
+        if bus.ppu().nmi_pending() && !self.nmi_asserted {
+            self.nmi_asserted = true;
+            self.push(cycle, bus, EventKind::NmiAsserted);
+        }
+        let flags = [bus.apu().frame_irq(), bus.apu().dmc_irq(), bus.mapper_irq()];
+        for (i, source) in [IrqSource::FrameCounter, IrqSource::Dmc, IrqSource::Mapper]
+            .into_iter()
+            .enumerate()
+        {
+            if flags[i] != self.irqs[i] {
+                let kind = if flags[i] {
+                    EventKind::IrqAsserted(source)
+                } else {
+                    EventKind::IrqCleared(source)
+                };
+                self.push(cycle, bus, kind);
+            }
+        }
+        self.irqs = flags;
+        self.dmc_stall = bus.pending_dma_cycles();
+    }
+
+    fn push(&mut self, cycle: u64, bus: &Bus, kind: EventKind) {
+        self.events.push(TimelineEvent {
+            cycle,
+            scanline: bus.ppu().scanline(),
+            dot: bus.ppu().dot(),
+            kind,
+        });
+    }
+
+    /// One `cycle scanline:dot event` line per event
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for event in &self.events {
+            writeln!(
+                text,
+                "{:>6} {:>3}:{:<3} {}",
+                event.cycle, event.scanline, event.dot, event.kind
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    /// Packed `R, G, B, A` bytes, `width` by [`LANES`] * [`LANE_HEIGHT`], with the frame's
+    /// cycles running left to right. Lanes from the top: NMI, spanning assertion to service;
+    /// IRQs, spanning assertion to acknowledgement, or to the end of the frame; and DMA stalls.
+    pub fn draw(&self, width: usize) -> Vec<u8> {
+        let mut rgba = BACKGROUND.repeat(width * LANES * LANE_HEIGHT);
+        let x_of = |cycle: u64| (cycle * width as u64 / self.cycles.max(1)) as usize;
+        let mut span = |lane: usize, start: u64, end: u64, color: (u8, u8, u8)| {
+            let (left, right) = (x_of(start), x_of(end).max(x_of(start) + 1).min(width));
+            for y in lane * LANE_HEIGHT + 1..(lane + 1) * LANE_HEIGHT - 1 {
+                for x in left..right {
+                    let i = (y * width + x) * 4;
+                    rgba[i..i + 4].copy_from_slice(&[color.0, color.1, color.2, 0xFF]);
+                }
+            }
+        };
+
+        let mut nmi = None;
+        let mut irqs = [None; 3];
+        for event in &self.events {
+            match event.kind {
+                EventKind::NmiAsserted => nmi = Some(event.cycle),
+                EventKind::NmiServiced => {
+                    let start = nmi.take().unwrap_or(0);
+                    span(0, start, event.cycle, NMI_COLOR);
+                }
+                EventKind::IrqAsserted(source) => irqs[source as usize] = Some(event.cycle),
+                EventKind::IrqCleared(source) => {
+                    let start = irqs[source as usize].take().unwrap_or(0);
+                    span(1, start, event.cycle, IRQ_COLOR);
+                }
+                EventKind::IrqServiced => {}
+                EventKind::OamDma { cycles } | EventKind::DmcDma { cycles } => {
+                    span(2, event.cycle, event.cycle + cycles, DMA_COLOR);
+                }
+            }
+        }
+        // Still pending when the frame ended
+        if let Some(start) = nmi {
+            span(0, start, self.cycles, NMI_COLOR);
+        }
+        for start in irqs.into_iter().flatten() {
+            span(1, start, self.cycles, IRQ_COLOR);
+        }
+        rgba
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+
+    #[test]
+    fn test_records_interrupts_and_dma() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x00, 0x8d, 0x17, 0x40, // 4 step frame counter, IRQs on
+            0x8d, 0x14, 0x40,             // OAM DMA from page 0
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // enable NMI
+            0x4c, 0x0d, 0x80,             // spin
+            0x40,                         // NMI: RTI
+        ]);
+        console.cpu_mut().bus_mut().load(0xFFFA, &[0x10, 0x80]);
+        console.track_interrupts(true);
+        console.run_frame();
+        let timeline = console.interrupt_timeline().unwrap();
+        let kinds: Vec<_> = timeline.events().iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::OamDma { cycles: 513 },
+                // Enabled during vblank, and serviced right after the write
+                EventKind::NmiAsserted,
+                EventKind::NmiServiced,
+            ]
+        );
+        assert_eq!(timeline.nmi_latency(), Some(0));
+
+        console.run_frame();
+        let timeline = console.interrupt_timeline().unwrap();
+        let kinds: Vec<_> = timeline.events().iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds[..3],
+            [
+                EventKind::NmiAsserted,
+                EventKind::NmiServiced,
+                EventKind::IrqAsserted(IrqSource::FrameCounter),
+            ]
+        );
+        assert_eq!(timeline.nmi_latency(), Some(3));
+        assert!(timeline.to_text().contains("FrameCounter IRQ asserted"));
+
+        let width = 100;
+        let rgba = timeline.draw(width);
+        assert_eq!(rgba.len(), width * LANES * LANE_HEIGHT * 4);
+        // The frame counter flag is never acknowledged, so its bar runs to the end
+        let i = ((LANE_HEIGHT + LANE_HEIGHT / 2) * width + width - 1) * 4;
+        assert_eq!(rgba[i..i + 3], [IRQ_COLOR.0, IRQ_COLOR.1, IRQ_COLOR.2]);
+
+        console.track_interrupts(false);
+        assert!(console.interrupt_timeline().is_none());
+    }
+}
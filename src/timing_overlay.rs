@@ -0,0 +1,195 @@
+//! Marks where timing-sensitive events happened on a copy of the picture.
+//!
+//! Split scrolling, status bars and raster effects depend on when sprite 0 hit lands and when the
+//! game writes to the PPU. Turn on [`Ppu::track_timing`](crate::ppu::Ppu::track_timing), run a
+//! frame, then pass the frame and [`Ppu::take_timing`](crate::ppu::Ppu::take_timing) to [`draw`]:
+//!
+//! | Event | Drawn as |
+//! | :--- | :--- |
+//! | sprite 0 hit | a magenta crosshair on the pixel the PPU was drawing |
+//! | NMI | red ticks at both edges of its scanline, or the bottom row once off screen |
+//! | register write on a visible scanline | its row tinted by register, the dot marked solid |
+//! | OAM DMA on a visible scanline | as a write to OAMDATA |
+//!
+//! Writes during vblank aren't mid-frame and aren't drawn. The CPU has no IRQ line yet, so there
+//! are no IRQ markers. The frame itself isn't changed.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::timing_overlay;
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! console.ppu_mut().track_timing(true);
+//! console.run_frame();
+//! let events = console.ppu_mut().take_timing();
+//! let rgba = timing_overlay::draw(console.frame_ref().indices(), console.palette(), &events);
+//! assert_eq!(rgba.len(), 256 * 240 * 4);
+//! ```
+
+use crate::frame::{Frame, Palette, PixelFormat, HEIGHT, WIDTH};
+use crate::ppu::{TimingEvent, TimingKind};
+
+pub const SPRITE_ZERO_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+pub const NMI_COLOR: (u8, u8, u8) = (0xFF, 0x20, 0x20);
+/// Tints for writes to `0x2000..=0x2007`, in register order
+pub const REGISTER_COLORS: [(u8, u8, u8); 8] = [
+    (0xFF, 0xA0, 0x00), // PPUCTRL
+    (0x00, 0xC0, 0xFF), // PPUMASK
+    (0x80, 0x80, 0x80), // PPUSTATUS, read only
+    (0xC0, 0xFF, 0x00), // OAMADDR
+    (0x00, 0xFF, 0x80), // OAMDATA
+    (0xFF, 0xFF, 0x00), // PPUSCROLL
+    (0x40, 0x60, 0xFF), // PPUADDR
+    (0xFF, 0x60, 0xA0), // PPUDATA
+];
+
+/// Pixels each arm of the sprite 0 crosshair reaches out
+const CROSSHAIR_ARM: usize = 3;
+const NMI_TICK_WIDTH: usize = 8;
+/// Out of 256, how much of a register's color goes into the rows it was written on
+const TINT_ALPHA: u16 = 80;
+
+/// Returns `frame` as RGBA8888 through `palette`, with `events` drawn over it
+pub fn draw(frame: &Frame, palette: &Palette, events: &[TimingEvent]) -> Vec<u8> {
+    let mut rgba = frame.to_pixels(PixelFormat::Rgba8888, palette);
+
+    // Tints first, so markers drawn after stay solid
+    let mut tinted = [false; HEIGHT];
+    for event in events {
+        let Some(register) = register_of(event.kind) else {
+            continue;
+        };
+        let y = event.scanline as usize;
+        if y >= HEIGHT {
+            continue;
+        }
+        if !tinted[y] {
+            tinted[y] = true;
+            for x in 0..WIDTH {
+                blend(&mut rgba, x, y, REGISTER_COLORS[register]);
+            }
+        }
+    }
+    for event in events {
+        let y = event.scanline as usize;
+        match event.kind {
+            TimingKind::SpriteZeroHit if y < HEIGHT => {
+                let x = dot_x(event.dot);
+                put(&mut rgba, x, y, SPRITE_ZERO_COLOR);
+                for arm in 2..=CROSSHAIR_ARM {
+                    put(&mut rgba, x.wrapping_sub(arm), y, SPRITE_ZERO_COLOR);
+                    put(&mut rgba, x + arm, y, SPRITE_ZERO_COLOR);
+                    put(&mut rgba, x, y.wrapping_sub(arm), SPRITE_ZERO_COLOR);
+                    put(&mut rgba, x, y + arm, SPRITE_ZERO_COLOR);
+                }
+            }
+            TimingKind::Nmi => {
+                let y = y.min(HEIGHT - 1);
+                for x in (0..NMI_TICK_WIDTH).chain(WIDTH - NMI_TICK_WIDTH..WIDTH) {
+                    put(&mut rgba, x, y, NMI_COLOR);
+                }
+            }
+            kind => {
+                if let Some(register) = register_of(kind).filter(|_| y < HEIGHT) {
+                    put(&mut rgba, dot_x(event.dot), y, REGISTER_COLORS[register]);
+                }
+            }
+        }
+    }
+    rgba
+}
+
+/// Which of the eight registers an event writes, counting OAM DMA as OAMDATA
+fn register_of(kind: TimingKind) -> Option<usize> {
+    match kind {
+        TimingKind::RegisterWrite { addr, .. } => Some((addr & 7) as usize),
+        TimingKind::OamDma => Some(4),
+        TimingKind::SpriteZeroHit | TimingKind::Nmi => None,
+    }
+}
+
+/// The column drawn at `dot`; dots 1 to 256 output pixels, the rest are pinned to the edges
+fn dot_x(dot: u16) -> usize {
+    (dot.max(1) as usize - 1).min(WIDTH - 1)
+}
+
+/// Sets a pixel, ignoring ones off the frame
+fn put(rgba: &mut [u8], x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+    if x < WIDTH && y < HEIGHT {
+        let i = (y * WIDTH + x) * 4;
+        rgba[i..i + 3].copy_from_slice(&[r, g, b]);
+    }
+}
+
+fn blend(rgba: &mut [u8], x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+    let i = (y * WIDTH + x) * 4;
+    for (channel, tint) in rgba[i..i + 3].iter_mut().zip([r, g, b]) {
+        *channel = ((*channel as u16 * (256 - TINT_ALPHA) + tint as u16 * TINT_ALPHA) / 256) as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+
+    #[test]
+    fn test_marks_sprite_zero_nmi_and_writes() {
+        let mut console = Console::new();
+        // Sprite 0 at (40, 20) and rendering on, then spin:
+        // LDA #0; STA $2003; LDA #19; STA $2004; LDA #0; STA $2004; STA $2004; LDA #40; STA $2004
+        // LDA #$18; STA $2001; JMP *
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x00, 0x8d, 0x03, 0x20, 0xa9, 0x13, 0x8d, 0x04, 0x20,
+            0xa9, 0x00, 0x8d, 0x04, 0x20, 0x8d, 0x04, 0x20, 0xa9, 0x28,
+            0x8d, 0x04, 0x20, 0xa9, 0x18, 0x8d, 0x01, 0x20, 0x4c, 0x1c,
+            0x80,
+        ]);
+        console.ppu_mut().track_timing(true);
+        console.run_frame();
+        console.ppu_mut().take_timing();
+        console.run_frame();
+        let events = console.ppu_mut().take_timing();
+        let hit = events
+            .iter()
+            .find(|event| event.kind == TimingKind::SpriteZeroHit)
+            .unwrap();
+        // Checked once per CPU instruction, so it lands a few dots past the corner
+        assert_eq!(hit.scanline, 20);
+        assert!((40..50).contains(&dot_x(hit.dot)), "{hit:?}");
+        assert!(events
+            .iter()
+            .all(|event| event.kind == TimingKind::SpriteZeroHit));
+
+        let frame = console.frame_ref().indices().clone();
+        let palette = *console.palette();
+        let plain = frame.to_pixels(PixelFormat::Rgba8888, &palette);
+        let nmi = TimingEvent {
+            scanline: 241,
+            dot: 1,
+            kind: TimingKind::Nmi,
+        };
+        let rgba = draw(&frame, &palette, &[*hit, nmi]);
+        let at = |x: usize, y: usize| &rgba[(y * WIDTH + x) * 4..][..3];
+        assert_eq!(at(dot_x(hit.dot), 20), [0xFF, 0x00, 0xFF]);
+        assert_eq!(at(0, HEIGHT - 1), [0xFF, 0x20, 0x20]);
+        assert_eq!(at(WIDTH - 1, HEIGHT - 1), [0xFF, 0x20, 0x20]);
+        // Nothing was written mid-frame, so the rest is the plain picture
+        assert_eq!(&rgba[..WIDTH * 4], &plain[..WIDTH * 4]);
+
+        let write = TimingEvent {
+            scanline: 100,
+            dot: 50,
+            kind: TimingKind::RegisterWrite {
+                addr: 0x2005,
+                value: 0,
+            },
+        };
+        let rgba = draw(&frame, &palette, &[write]);
+        assert_eq!(&rgba[(100 * WIDTH + 49) * 4..][..3], [0xFF, 0xFF, 0x00]);
+        assert_ne!(rgba[100 * WIDTH * 4..][..3], plain[100 * WIDTH * 4..][..3]);
+        assert_eq!(rgba[99 * WIDTH * 4..][..3], plain[99 * WIDTH * 4..][..3]);
+    }
+}
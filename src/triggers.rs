@@ -0,0 +1,371 @@
+//! RAM triggers: named events that fire when a game's memory meets a condition.
+//!
+//! Overlays, practice splits and stream alerts all want to know when something happens in a game
+//! ("a life was lost", "the level is 3 now"). A [`TriggerSet`] checks a list of [`Trigger`]s
+//! against RAM after every frame and reports the ones that just fired. Triggers come from a text
+//! file, one per line:
+//!
+//! ```text
+//! # name: when condition [and condition...] [for N frames]
+//! died: when $075A decreases
+//! world 3: when $075F == 2 for 2 frames
+//! fast: when $0057:s8 > 20 and $001D == 0
+//! ```
+//!
+//! A condition is an address, `$075A` or `0x075A`, optionally followed by `:u16`, `:u32` or a
+//! signed `:s8`, `:s16` or `:s32`, then one of:
+//!
+//! | Condition | Holds on frames where the value |
+//! | :--- | :--- |
+//! | `== N`, `!= N`, `< N`, `<= N`, `> N`, `>= N` | compares so with `N` |
+//! | `increases` / `decreases` / `changes` | went up / down / anywhere since the last frame |
+//! | `changes by N` | moved by exactly `N`, negative for decreases |
+//!
+//! Numbers are decimal or hex with `$` or `0x`. A trigger fires on the frame all its conditions
+//! have held for `for` frames in a row, one if it's left out, and again only after they have
+//! stopped holding.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::triggers::TriggerSet;
+//!
+//! let mut triggers = TriggerSet::parse("ticked: when $10 changes by 1")?;
+//! let mut console = Console::new();
+//! console.load(&[0xe6, 0x10, 0x4c, 0x02, 0x80]); // INC $10; loop: JMP loop
+//! triggers.check(console.cpu());
+//! console.run_frame();
+//! let fired: Vec<_> = triggers.check(console.cpu()).iter().map(|t| t.name.as_str()).collect();
+//! assert_eq!(fired, ["ticked"]);
+//! # Ok::<(), nes_emulator::triggers::TriggerError>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use crate::cpu::Mem;
+use crate::ram_map::{RamField, Width};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerError {
+    /// A line that isn't `name: when ...`, with its 1-based line number
+    BadLine(usize),
+    /// A condition that doesn't parse
+    BadCondition { line: usize, condition: String },
+    /// A `for` that isn't followed by a number of frames
+    BadDuration(usize),
+}
+
+impl fmt::Display for TriggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerError::BadLine(line) => write!(f, "line {line}: expected `name: when ...`"),
+            TriggerError::BadCondition { line, condition } => {
+                write!(f, "line {line}: bad condition `{condition}`")
+            }
+            TriggerError::BadDuration(line) => {
+                write!(f, "line {line}: expected `for N frames`")
+            }
+        }
+    }
+}
+
+impl Error for TriggerError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// What a [`Condition`] asks of its field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Test {
+    Compare(Comparison, i64),
+    Increases,
+    Decreases,
+    Changes,
+    ChangesBy(i64),
+}
+
+impl Test {
+    /// Whether a value that went from `previous` to `current` passes. Tests of a change never
+    /// pass without a previous value.
+    pub fn holds(self, previous: Option<i64>, current: i64) -> bool {
+        let changed = |check: fn(i64, i64) -> bool| previous.is_some_and(|p| check(p, current));
+        match self {
+            Test::Compare(comparison, value) => match comparison {
+                Comparison::Equal => current == value,
+                Comparison::NotEqual => current != value,
+                Comparison::Less => current < value,
+                Comparison::LessOrEqual => current <= value,
+                Comparison::Greater => current > value,
+                Comparison::GreaterOrEqual => current >= value,
+            },
+            Test::Increases => changed(|p, c| c > p),
+            Test::Decreases => changed(|p, c| c < p),
+            Test::Changes => changed(|p, c| c != p),
+            Test::ChangesBy(delta) => previous.is_some_and(|p| current - p == delta),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub field: RamField,
+    pub test: Test,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    pub name: String,
+    /// All of these have to hold
+    pub conditions: Vec<Condition>,
+    /// Frames in a row they have to hold for, at least 1
+    pub frames: u32,
+}
+
+impl Trigger {
+    /// Parses one line of the text format, `line` being its number for errors
+    fn parse(text: &str, line: usize) -> Result<Trigger, TriggerError> {
+        let (name, rest) = text.split_once(':').ok_or(TriggerError::BadLine(line))?;
+        let rest = rest
+            .trim_start()
+            .strip_prefix("when ")
+            .ok_or(TriggerError::BadLine(line))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(TriggerError::BadLine(line));
+        }
+
+        let mut tokens: Vec<&str> = rest.split_whitespace().collect();
+        let mut frames = 1;
+        if let Some(at) = tokens.iter().position(|&token| token == "for") {
+            frames = match tokens[at + 1..] {
+                [count] | [count, "frame" | "frames"] => count.parse().ok().filter(|&n| n > 0),
+                _ => None,
+            }
+            .ok_or(TriggerError::BadDuration(line))?;
+            tokens.truncate(at);
+        }
+        let conditions = tokens
+            .split(|&token| token == "and")
+            .map(|tokens| {
+                Condition::parse(tokens).ok_or_else(|| TriggerError::BadCondition {
+                    line,
+                    condition: tokens.join(" "),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Trigger {
+            name: name.to_string(),
+            conditions,
+            frames,
+        })
+    }
+}
+
+impl Condition {
+    fn parse(tokens: &[&str]) -> Option<Condition> {
+        let (&field, test) = tokens.split_first()?;
+        let (address, width) = field.split_once(':').unwrap_or((field, "u8"));
+        let (width, signed) = match width {
+            "u8" => (Width::U8, false),
+            "u16" => (Width::U16, false),
+            "u32" => (Width::U32, false),
+            "s8" => (Width::U8, true),
+            "s16" => (Width::U16, true),
+            "s32" => (Width::U32, true),
+            _ => return None,
+        };
+        let address = parse_number(address).and_then(|n| u16::try_from(n).ok())?;
+        let mut field = RamField::new(address, width);
+        if signed {
+            field = field.signed();
+        }
+        let test = match *test {
+            ["increases"] => Test::Increases,
+            ["decreases"] => Test::Decreases,
+            ["changes"] => Test::Changes,
+            ["changes", "by", delta] => Test::ChangesBy(parse_number(delta)?),
+            [op, value] => {
+                let comparison = match op {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    _ => return None,
+                };
+                Test::Compare(comparison, parse_number(value)?)
+            }
+            _ => return None,
+        };
+        Some(Condition { field, test })
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Where a trigger is up to
+#[derive(Debug, Clone, Default)]
+struct Progress {
+    /// Each condition's value last frame
+    previous: Vec<Option<i64>>,
+    /// Frames in a row the conditions have held
+    held: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TriggerSet {
+    triggers: Vec<(Trigger, Progress)>,
+}
+
+impl TriggerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses triggers in the text format above. Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn parse(text: &str) -> Result<Self, TriggerError> {
+        let mut set = TriggerSet::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            set.add(Trigger::parse(line, i + 1)?);
+        }
+        Ok(set)
+    }
+
+    pub fn add(&mut self, trigger: Trigger) {
+        let progress = Progress {
+            previous: vec![None; trigger.conditions.len()],
+            held: 0,
+        };
+        self.triggers.push((trigger, progress));
+    }
+
+    pub fn triggers(&self) -> impl Iterator<Item = &Trigger> {
+        self.triggers.iter().map(|(trigger, _)| trigger)
+    }
+
+    pub fn len(&self) -> usize {
+        self.triggers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Forgets the previous values and how long conditions have held, e.g. after loading a state
+    pub fn reset(&mut self) {
+        for (_, progress) in &mut self.triggers {
+            progress.previous.fill(None);
+            progress.held = 0;
+        }
+    }
+
+    /// Reads RAM for the frame just run and returns the triggers that fired on it. Call once
+    /// after each [`Console::run_frame`](crate::console::Console::run_frame).
+    pub fn check(&mut self, mem: &impl Mem) -> Vec<&Trigger> {
+        let mut fired = Vec::new();
+        for (trigger, progress) in &mut self.triggers {
+            let mut holds = true;
+            for (condition, previous) in trigger.conditions.iter().zip(&mut progress.previous) {
+                let current = condition.field.read(mem);
+                holds &= condition.test.holds(previous.replace(current), current);
+            }
+            progress.held = if holds { progress.held + 1 } else { 0 };
+            if progress.held == trigger.frames {
+                fired.push(&*trigger);
+            }
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn fired(set: &mut TriggerSet, ram: &Bus) -> Vec<String> {
+        set.check(ram)
+            .iter()
+            .map(|trigger| trigger.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_triggers_fire_once_per_hold() {
+        let mut set = TriggerSet::parse(
+            "# lives and level
+             died: when $075A decreases
+             level 3: when 0x0770 == 3 for 2 frames
+             both: when $075A:s8 < 0 and $0770 >= 3",
+        )
+        .unwrap();
+        assert_eq!(set.len(), 3);
+        let mut ram = Bus::new();
+        let mut frames = Vec::new();
+        // Lives going to $FF is an increase for the unsigned `died`, but -1 for `both`
+        for (lives, level) in [
+            (3, 1),
+            (2, 3),
+            (2, 3),
+            (2, 3),
+            (0xFF, 1),
+            (0xFF, 3),
+            (0xFF, 3),
+        ] {
+            ram.mem_write(0x075A, lives);
+            ram.mem_write(0x0770, level);
+            frames.push(fired(&mut set, &ram));
+        }
+        assert_eq!(
+            frames,
+            [
+                vec![],
+                vec!["died"],
+                vec!["level 3"],
+                vec![],
+                vec![],
+                vec!["both"],
+                vec!["level 3"],
+            ]
+        );
+        assert!(!Test::Decreases.holds(None, 0));
+        assert_eq!(
+            TriggerSet::parse("a: when $10 >> 3").unwrap_err(),
+            TriggerError::BadCondition {
+                line: 1,
+                condition: "$10 >> 3".to_string()
+            }
+        );
+        assert_eq!(
+            TriggerSet::parse("\na: when $10 changes for ever").unwrap_err(),
+            TriggerError::BadDuration(2)
+        );
+        assert_eq!(
+            TriggerSet::parse("$10 == 3").unwrap_err().to_string(),
+            "line 1: expected `name: when ...`"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Drawing frames in a terminal, for seeing what a ROM does on a headless server or over SSH.
+//!
+//! [`render`] shrinks a frame to a number of terminal columns and draws it with the upper half
+//! block `▀`: its foreground color is the top half of a cell and its background the bottom, so
+//! each cell shows two rows of the shrunken picture. Each half is the average color of the
+//! pixels it covers. At the default [`COLUMNS`] a cell covers 2x4 pixels and the picture keeps its
+//! shape, 128 columns by 60 lines. Terminals with 24-bit color get the palette exactly;
+//! [`ColorMode::Ansi256`] picks the nearest of the standard 256 colors for the rest.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::tui_video::{self, ColorMode};
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]);
+//! console.run_frame();
+//! let text = tui_video::render(
+//!     console.frame_ref().indices(),
+//!     console.palette(),
+//!     tui_video::COLUMNS,
+//!     ColorMode::TrueColor,
+//! );
+//! assert_eq!(text.lines().count(), 60);
+//! // Moving the cursor home first draws each frame over the last
+//! print!("{}{text}", tui_video::HOME);
+//! ```
+
+use std::fmt::Write;
+
+use crate::frame::{Frame, Palette, HEIGHT, WIDTH};
+
+/// Columns that keep the picture's shape in cells twice as tall as they are wide
+pub const COLUMNS: usize = WIDTH / 2;
+/// Moves the cursor to the top left, to draw a frame over the one before
+pub const HOME: &str = "\x1b[H";
+
+const UPPER_HALF_BLOCK: char = '▀';
+/// Levels of each channel in the 6x6x6 color cube at 256 color indices 16..=231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorMode {
+    /// 24-bit color, on terminals that set `COLORTERM=truecolor`
+    #[default]
+    TrueColor,
+    /// The xterm 256 color palette
+    Ansi256,
+}
+
+impl ColorMode {
+    /// The escape sequence setting the foreground, or the background, to `rgb`
+    fn write(self, out: &mut String, background: bool, (r, g, b): (u8, u8, u8)) {
+        let layer = if background { 48 } else { 38 };
+        let _ = match self {
+            ColorMode::TrueColor => write!(out, "\x1b[{layer};2;{r};{g};{b}m"),
+            ColorMode::Ansi256 => write!(out, "\x1b[{layer};5;{}m", ansi256((r, g, b))),
+        };
+    }
+}
+
+/// The nearest of the 256 color cube and grey ramp to `rgb`. The 16 system colors are left
+/// out, since terminals theme them.
+pub fn ansi256((r, g, b): (u8, u8, u8)) -> u8 {
+    let level = |value: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| CUBE_LEVELS[i].abs_diff(value))
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    // Greys 232..=255 run from 8 to 238 in steps of 10
+    let average = (r as u16 + g as u16 + b as u16) / 3;
+    let grey_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let grey = 8 + grey_step * 10;
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        [(cr, r), (cg, g), (cb, b)]
+            .iter()
+            .map(|&(a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    if distance((grey, grey, grey)) < distance(cube) {
+        232 + grey_step
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+/// `frame` shrunk to `columns` columns, clamped to `1..=256`, as lines of half blocks in
+/// `mode`'s colors. Every line ends by resetting the colors.
+pub fn render(frame: &Frame, palette: &Palette, columns: usize, mode: ColorMode) -> String {
+    let columns = columns.clamp(1, WIDTH);
+    // Half cells are square: a cell is as tall as two of its widths
+    let halves = (HEIGHT * columns / WIDTH).max(2) & !1;
+    let average = |column: usize, half: usize| {
+        let xs = column * WIDTH / columns..(column + 1) * WIDTH / columns;
+        let ys = half * HEIGHT / halves..(half + 1) * HEIGHT / halves;
+        let mut sum = [0u32; 3];
+        for y in ys.clone() {
+            for x in xs.clone() {
+                let (r, g, b) = palette.rgb(frame.get_pixel(x, y));
+                for (total, value) in sum.iter_mut().zip([r, g, b]) {
+                    *total += value as u32;
+                }
+            }
+        }
+        let count = (xs.len() * ys.len()) as u32;
+        let [r, g, b] = sum.map(|total| (total / count) as u8);
+        (r, g, b)
+    };
+
+    let mut out = String::new();
+    for row in 0..halves / 2 {
+        let mut colors = None;
+        for column in 0..columns {
+            let (top, bottom) = (average(column, row * 2), average(column, row * 2 + 1));
+            let last = colors.replace((top, bottom));
+            if last.map(|(top, _)| top) != Some(top) {
+                mode.write(&mut out, false, top);
+            }
+            if last.map(|(_, bottom)| bottom) != Some(bottom) {
+                mode.write(&mut out, true, bottom);
+            }
+            out.push(UPPER_HALF_BLOCK);
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_half_blocks() {
+        assert_eq!(ansi256((0xFF, 0x00, 0x00)), 196);
+        assert_eq!(ansi256((0x80, 0x80, 0x80)), 244);
+        assert_eq!(ansi256((0x00, 0x00, 0x00)), 16);
+
+        // The top left 8x8 pixels white, the rest color 0x00
+        let mut frame = Frame::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                frame.set_pixel(x, y, 0x30);
+            }
+        }
+        let palette = Palette::default();
+        let white = palette.rgb(0x30);
+        let backdrop = palette.rgb(0x00);
+        let text = render(&frame, &palette, 32, ColorMode::TrueColor);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 15);
+        // A cell is 8x16 pixels, so the first is white on top and the next is all color 0x00
+        let code = |layer, (r, g, b): (u8, u8, u8)| format!("\x1b[{layer};2;{r};{g};{b}m");
+        let first = format!(
+            "{}{}▀{}▀",
+            code(38, white),
+            code(48, backdrop),
+            code(38, backdrop)
+        );
+        assert!(lines[0].starts_with(&first), "{:?}", lines[0]);
+        // Colors are only set when they change
+        assert_eq!(
+            lines[1],
+            format!(
+                "{}{}{}\x1b[0m",
+                code(38, backdrop),
+                code(48, backdrop),
+                "▀".repeat(32)
+            )
+        );
+
+        let text = render(&frame, &palette, COLUMNS, ColorMode::Ansi256);
+        assert_eq!(text.lines().count(), 60);
+        assert!(text.starts_with(&format!("\x1b[38;5;{}m", ansi256(white))));
+    }
+}
@@ -0,0 +1,135 @@
+//! VGM files: APU register logs in the format chiptune players and hardware players replay.
+//!
+//! A VGM file is a header followed by a stream of register writes and waits counted in samples
+//! at 44.1kHz. The NES APU has been part of the format since version 1.61: writes are
+//! `B4 rr dd`, `rr` being the register's offset from `0x4000`, and the DMC reads its samples from
+//! a data block loaded at `0xC000` before playback starts. [`encode`] turns an [`ApuLog`] into
+//! one; [`Console::start_vgm`] and [`Console::stop_vgm`] do the logging as well.
+//!
+//! Files are written uncompressed. A `.vgz` is the same file gzipped, which any gzip tool does.
+//!
+//! [`Console::start_vgm`]: crate::console::Console::start_vgm
+//! [`Console::stop_vgm`]: crate::console::Console::stop_vgm
+
+use crate::apu_log::ApuLog;
+
+/// The VGM sample clock all waits are counted in
+pub const SAMPLE_RATE: u64 = 44_100;
+/// Where the DMC sample data block goes in CPU space: the DMC can only play from
+/// `0xC000..=0xFFFF`
+pub const DMC_RAM_START: u16 = 0xC000;
+
+/// Version 1.61, the first with the NES APU, in BCD
+const VERSION: u32 = 0x0000_0161;
+const HEADER_SIZE: usize = 0x100;
+/// Offset of the field holding the data offset, which counts from the field itself
+const DATA_OFFSET_FIELD: usize = 0x34;
+const NES_APU_CLOCK_FIELD: usize = 0x84;
+
+const CMD_NES_APU: u8 = 0xB4;
+const CMD_DATA_BLOCK: u8 = 0x67;
+/// Data block type for NES APU RAM, which holds DMC samples
+const BLOCK_NES_RAM: u8 = 0xC2;
+const CMD_WAIT: u8 = 0x61;
+/// `0x70..=0x7F` wait 1 to 16 samples
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+/// Encodes `log` as a VGM file. `frame_rate` is the game's frames per second, 60 or 50, and
+/// `dmc_ram` what was at [`DMC_RAM_START`] onwards when logging started, or empty for games
+/// without DMC samples.
+pub fn encode(log: &ApuLog, frame_rate: u32, dmc_ram: &[u8]) -> Vec<u8> {
+    let mut vgm = vec![0; HEADER_SIZE];
+    vgm[..4].copy_from_slice(b"Vgm ");
+    write_u32(&mut vgm, 0x08, VERSION);
+    write_u32(&mut vgm, 0x24, frame_rate);
+    write_u32(
+        &mut vgm,
+        DATA_OFFSET_FIELD,
+        (HEADER_SIZE - DATA_OFFSET_FIELD) as u32,
+    );
+    write_u32(&mut vgm, NES_APU_CLOCK_FIELD, log.cpu_hz() as u32);
+
+    if !dmc_ram.is_empty() {
+        vgm.extend_from_slice(&[CMD_DATA_BLOCK, CMD_END, BLOCK_NES_RAM]);
+        vgm.extend_from_slice(&(dmc_ram.len() as u32 + 2).to_le_bytes());
+        vgm.extend_from_slice(&DMC_RAM_START.to_le_bytes());
+        vgm.extend_from_slice(dmc_ram);
+    }
+
+    let to_samples = |cycle: u64| cycle * SAMPLE_RATE / log.cpu_hz().max(1);
+    let mut at = 0;
+    for write in log.writes() {
+        let sample = to_samples(write.cycle);
+        wait(&mut vgm, sample - at);
+        at = sample;
+        vgm.extend_from_slice(&[CMD_NES_APU, (write.addr - 0x4000) as u8, write.value]);
+    }
+    let total = to_samples(log.cycles()).max(at);
+    wait(&mut vgm, total - at);
+    vgm.push(CMD_END);
+
+    write_u32(&mut vgm, 0x18, total as u32);
+    let eof = vgm.len() as u32 - 4;
+    write_u32(&mut vgm, 0x04, eof);
+    vgm
+}
+
+fn wait(vgm: &mut Vec<u8>, mut samples: u64) {
+    while samples > 16 {
+        let chunk = samples.min(u16::MAX as u64);
+        vgm.push(CMD_WAIT);
+        vgm.extend_from_slice(&(chunk as u16).to_le_bytes());
+        samples -= chunk;
+    }
+    if samples > 0 {
+        vgm.push(CMD_WAIT_SHORT + samples as u8 - 1);
+    }
+}
+
+fn write_u32(vgm: &mut [u8], at: usize, value: u32) {
+    vgm[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console::Console;
+
+    fn read_u32(vgm: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(vgm[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut console = Console::new();
+        #[rustfmt::skip]
+        console.load(&[
+            0xa9, 0x01, 0x8d, 0x15, 0x40, // LDA #$01; STA $4015
+            0x4c, 0x05, 0x80,             // spin
+        ]);
+        console.start_vgm();
+        assert!(console.is_recording_vgm());
+        console.run_frame();
+        console.run_frame();
+        let vgm = console.stop_vgm().unwrap();
+        assert!(console.stop_vgm().is_none());
+
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(read_u32(&vgm, 0x04) as usize, vgm.len() - 4);
+        assert_eq!(read_u32(&vgm, 0x84), 1_789_773);
+        assert_eq!(read_u32(&vgm, 0x24), 60);
+        // Two frames of 29780 or so cycles each, a little under 1/30s
+        assert_eq!(read_u32(&vgm, 0x18), 1467);
+
+        let data = &vgm[HEADER_SIZE..];
+        assert_eq!(data[..3], [CMD_DATA_BLOCK, CMD_END, BLOCK_NES_RAM]);
+        assert_eq!(read_u32(data, 3), 0x4002);
+        assert_eq!(data[7..9], [0x00, 0xC0]);
+        let commands = &data[9 + 0x4000..];
+        // The write lands in the first sample, then the waits make up the rest
+        assert_eq!(commands[..3], [CMD_NES_APU, 0x15, 0x01]);
+        assert_eq!(commands[3], CMD_WAIT);
+        assert_eq!(commands[commands.len() - 1], CMD_END);
+    }
+}
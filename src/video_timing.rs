@@ -0,0 +1,204 @@
+//! The video signal the PPU generates, for driving CRTs at their native timing.
+//!
+//! Played on a real TV, the NES picture is 240 progressive lines ("240p"), a mode modern
+//! scalers tend to treat as broken interlaced video. People driving a CRT from a PC through a
+//! 15kHz capable VGA adapter, or feeding a scaler that wants the exact source timing, need the
+//! signal's geometry rather than a frame rate rounded to 60. [`VideoTiming`] describes it per
+//! [`Region`]: the dot clock, the line and frame rates, and the active area, porches and sync of
+//! each line and frame. [`VideoTiming::modeline`] writes it as an X11 modeline, and
+//! [`Console::frame_timing`] says when each emulated frame starts and how long it lasts on that
+//! clock, for presenting frames in step with it.
+//!
+//! ```
+//! use nes_emulator::console::Region;
+//! use nes_emulator::video_timing::VideoTiming;
+//!
+//! let ntsc = VideoTiming::of(Region::Ntsc);
+//! assert_eq!(ntsc.total_lines(), 262);
+//! assert!((ntsc.line_rate() - 15_745.8).abs() < 0.1);
+//! assert_eq!(
+//!     ntsc.modeline(),
+//!     "Modeline \"256x240_60.10\" 5.369318 256 276 301 341 240 245 248 262 -hsync -vsync"
+//! );
+//! ```
+//!
+//! The PPU here always draws a full 341 dots on every line, leaving out the one dot real NTSC
+//! hardware skips every other frame while rendering, so frames come out half a dot longer on
+//! average: 60.0985Hz instead of 60.0988Hz.
+//!
+//! [`Console::frame_timing`]: crate::console::Console::frame_timing
+
+use std::time::Duration;
+
+use crate::console::Region;
+use crate::frame::{HEIGHT, WIDTH};
+
+/// How one direction of the signal is divided, in dots for lines or in lines for frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The picture, background and sprites
+    pub active: u32,
+    /// From the end of the picture to sync, including the border the PPU fills with the
+    /// backdrop color
+    pub front_porch: u32,
+    pub sync: u32,
+    /// From the end of sync to the picture, including the color burst and border
+    pub back_porch: u32,
+}
+
+impl Span {
+    pub fn total(&self) -> u32 {
+        self.active + self.front_porch + self.sync + self.back_porch
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoTiming {
+    pub region: Region,
+    /// PPU dots per second: the master clock divided by 4 on NTSC, by 5 on PAL
+    pub dot_clock: f64,
+    /// Dots of one line
+    pub horizontal: Span,
+    /// Lines of one frame
+    pub vertical: Span,
+    /// Always false: both fields draw the same lines
+    pub interlaced: bool,
+    /// Width of a dot over its height on a 4:3 TV
+    pub pixel_aspect: f64,
+}
+
+impl VideoTiming {
+    pub fn of(region: Region) -> Self {
+        // 11 dots of right border and 9 of porch before sync; porch, color burst and 15 dots of
+        // left border after it
+        let horizontal = Span {
+            active: WIDTH as u32,
+            front_porch: 20,
+            sync: 25,
+            back_porch: 40,
+        };
+        match region {
+            Region::Ntsc => Self {
+                region,
+                dot_clock: 21_477_272.0 / 4.0,
+                horizontal,
+                vertical: Span {
+                    active: HEIGHT as u32,
+                    front_porch: 5,
+                    sync: 3,
+                    back_porch: 14,
+                },
+                interlaced: false,
+                pixel_aspect: 8.0 / 7.0,
+            },
+            Region::Pal => Self {
+                region,
+                dot_clock: 26_601_712.0 / 5.0,
+                horizontal,
+                vertical: Span {
+                    active: HEIGHT as u32,
+                    front_porch: 29,
+                    sync: 3,
+                    back_porch: 40,
+                },
+                interlaced: false,
+                pixel_aspect: 2_950_000.0 / 2_128_137.0,
+            },
+        }
+    }
+
+    pub fn dots_per_line(&self) -> u32 {
+        self.horizontal.total()
+    }
+
+    pub fn total_lines(&self) -> u32 {
+        self.vertical.total()
+    }
+
+    pub fn dots_per_frame(&self) -> u64 {
+        self.dots_per_line() as u64 * self.total_lines() as u64
+    }
+
+    /// Lines per second, the horizontal scan rate
+    pub fn line_rate(&self) -> f64 {
+        self.dot_clock / self.dots_per_line() as f64
+    }
+
+    /// Frames per second
+    pub fn refresh_rate(&self) -> f64 {
+        self.dot_clock / self.dots_per_frame() as f64
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.refresh_rate())
+    }
+
+    /// An X11 modeline of the signal, one dot per pixel, for a CRT driven at 15kHz
+    pub fn modeline(&self) -> String {
+        let (h, v) = (self.horizontal, self.vertical);
+        let h_sync = h.active + h.front_porch;
+        let v_sync = v.active + v.front_porch;
+        format!(
+            "Modeline \"{}x{}_{:.2}\" {:.6} {} {} {} {} {} {} {} {} -hsync -vsync{}",
+            h.active,
+            v.active,
+            self.refresh_rate(),
+            self.dot_clock / 1_000_000.0,
+            h.active,
+            h_sync,
+            h_sync + h.sync,
+            h.total(),
+            v.active,
+            v_sync,
+            v_sync + v.sync,
+            v.total(),
+            if self.interlaced { " interlace" } else { "" }
+        )
+    }
+}
+
+/// When a frame happened on the emulated video clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameTiming {
+    /// As in [`FrameRef::number`](crate::frame::FrameRef::number)
+    pub frame: u64,
+    /// From power on to the frame's first line
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+impl FrameTiming {
+    /// Frame `frame` of a signal running at `timing` since power on
+    pub fn new(timing: &VideoTiming, frame: u64) -> Self {
+        let at = |frame: u64| {
+            let nanos = frame as u128 * timing.dots_per_frame() as u128 * 1_000_000_000
+                / timing.dot_clock.round() as u128;
+            Duration::from_nanos(nanos as u64)
+        };
+        Self {
+            frame,
+            start: at(frame),
+            duration: at(frame + 1) - at(frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_region_timings() {
+        let pal = VideoTiming::of(Region::Pal);
+        assert_eq!(pal.dots_per_line(), 341);
+        assert_eq!(pal.total_lines(), 312);
+        assert!((pal.refresh_rate() - 50.007).abs() < 0.001);
+        assert!(pal.modeline().contains(" 240 269 272 312 "));
+
+        let ntsc = VideoTiming::of(Region::Ntsc);
+        assert!((ntsc.refresh_rate() - 60.0985).abs() < 0.0001);
+        let timing = FrameTiming::new(&ntsc, 60);
+        assert_eq!(timing.start.as_millis(), 998);
+        assert_eq!(timing.duration.as_micros(), 16_639);
+    }
+}
@@ -0,0 +1,130 @@
+//! Stopping a run early: cancellation from another thread, and cycle budgets.
+//!
+//! A GUI's stop button, a server's request timeout or a browser tab being closed all need to end
+//! an emulation that may be stuck in a loop, without killing the thread running it. The watched
+//! run APIs, [`Console::run_frame_watched`], [`CPU::run_watched`] and [`movie::play_watched`],
+//! take a [`Watchdog`] and check it before every instruction. It stops them with a
+//! [`StopReason`] once its [`CancelToken`] has been cancelled, or once they've spent its budget
+//! of CPU cycles. A budget stops a run at the same instruction every time, whichever machine it
+//! runs on; a cancellation stops it at whichever instruction boundary comes next.
+//!
+//! ```
+//! use nes_emulator::console::Console;
+//! use nes_emulator::watchdog::{CancelToken, StopReason, Watchdog};
+//!
+//! let mut console = Console::new();
+//! console.load(&[0x4c, 0x00, 0x80]); // loop: JMP loop
+//! let token = CancelToken::new();
+//! let mut watchdog = Watchdog::new().cancel_on(&token).max_cycles(300_000);
+//! while console.run_frame_watched(&mut watchdog).is_ok() {}
+//! assert_eq!(watchdog.spent(), 300_000);
+//!
+//! // e.g. from the UI thread
+//! token.cancel();
+//! let mut watchdog = Watchdog::new().cancel_on(&token);
+//! assert_eq!(console.run_frame_watched(&mut watchdog), Err(StopReason::Cancelled));
+//! ```
+//!
+//! [`Console::run_frame_watched`]: crate::console::Console::run_frame_watched
+//! [`CPU::run_watched`]: crate::cpu::CPU::run_watched
+//! [`movie::play_watched`]: crate::movie::play_watched
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag that stops the runs watching it. Clones share the flag, so keep one and hand a clone
+/// to the thread that may need to cancel.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag, so the token can watch the next run
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Why a watched run stopped before it was done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StopReason {
+    /// The watchdog's [`CancelToken`] was cancelled
+    Cancelled,
+    /// The run spent the watchdog's budget of cycles
+    CycleLimit,
+    /// The CPU hit a [jam opcode](crate::opcodes::JAM_OPCODES) and stopped executing
+    Jammed,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Cancelled => write!(f, "cancelled"),
+            StopReason::CycleLimit => write!(f, "ran out of cycles"),
+            StopReason::Jammed => write!(f, "CPU jammed"),
+        }
+    }
+}
+
+impl Error for StopReason {}
+
+/// What a watched run checks before each instruction. Reusing one across several runs shares
+/// its budget between them.
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    token: Option<CancelToken>,
+    max_cycles: Option<u64>,
+    spent: u64,
+}
+
+impl Watchdog {
+    /// A watchdog that never stops anything until told what to watch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops runs once `token` is cancelled
+    pub fn cancel_on(mut self, token: &CancelToken) -> Self {
+        self.token = Some(token.clone());
+        self
+    }
+
+    /// Stops runs once they've spent `cycles` CPU cycles between them, DMA stalls included
+    pub fn max_cycles(mut self, cycles: u64) -> Self {
+        self.max_cycles = Some(cycles);
+        self
+    }
+
+    /// CPU cycles the runs watched so far have spent
+    pub fn spent(&self) -> u64 {
+        self.spent
+    }
+
+    /// Why a run shouldn't go on, if it shouldn't
+    pub fn check(&self) -> Result<(), StopReason> {
+        if self.token.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(StopReason::Cancelled);
+        }
+        if self.max_cycles.is_some_and(|max| self.spent >= max) {
+            return Err(StopReason::CycleLimit);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spend(&mut self, cycles: u64) {
+        self.spent += cycles;
+    }
+}
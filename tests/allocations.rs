@@ -0,0 +1,80 @@
+//! Keeps the steady-state frame loop free of heap allocations.
+//!
+//! Allocations are counted per thread, so tests running in parallel don't see each other's.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use nes_emulator::console::Console;
+use nes_emulator::frame::PixelFormat;
+use nes_emulator::joypad::JoypadButton;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn test_run_frame_does_not_allocate() {
+    for format in [
+        PixelFormat::Rgba8888,
+        PixelFormat::Rgb565,
+        PixelFormat::Indexed,
+    ] {
+        let mut console = Console::new();
+        console.set_pixel_format(format);
+        console.load(&[0xe8; 0x7FF0]);
+        console.hold_input(5..=10, 0, JoypadButton::START);
+        console.run_frame(); // warm up
+
+        let allocations = allocations_during(|| {
+            for _ in 0..20 {
+                console.run_frame();
+                console.frame_ref();
+            }
+        });
+        assert_eq!(allocations, 0, "{format:?}");
+    }
+}
+
+#[test]
+fn test_save_state_into_reuses_buffer() {
+    let mut console = Console::new();
+    console.load(&[0xe8; 0x7FF0]);
+    let mut buffer = Vec::new();
+    console.save_state_into(&mut buffer);
+
+    let allocations = allocations_during(|| {
+        for _ in 0..5 {
+            console.run_frame();
+            console.save_state_into(&mut buffer);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
@@ -0,0 +1,185 @@
+//! Property tests for the arithmetic, compare and shift instructions.
+//!
+//! Each test runs one instruction on random registers, operands and initial status, and checks the
+//! result and the C, Z, V and N flags against a straightforward reference written with wider
+//! signed arithmetic instead of the bit tricks the CPU uses. Flags the instruction doesn't touch
+//! must keep their initial value. The decimal mode tests check BCD results and carry for valid
+//! BCD operands.
+
+use nes_emulator::cpu::{flags, CpuVariant, Mem, Registers, CPU};
+use proptest::prelude::*;
+
+/// Flags set by compares and shifts
+const NZC: u8 = flags::NEGATIVE | flags::ZERO | flags::CARRY;
+/// Flags set by ADC and SBC
+const NVZC: u8 = NZC | flags::OVERFLOW;
+
+/// Zero page address used by the memory operand forms
+const OPERAND: u16 = 0x0010;
+
+/// Runs `program`, one instruction, with A = `a` and the given status
+fn execute(program: &[u8], a: u8, status: u8) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.load(program);
+    cpu.reset();
+    cpu.set_registers(Registers {
+        a,
+        status,
+        ..cpu.registers()
+    });
+    assert!(cpu.step());
+    assert_eq!(cpu.registers().pc, 0x8000 + program.len() as u16);
+    cpu
+}
+
+/// Expected status after an instruction that set `affected` flags from the reference values
+fn expected_status(initial: u8, affected: u8, result: u8, carry: bool, overflow: bool) -> u8 {
+    let mut status = initial & !affected;
+    let mut set = |flag: u8, on: bool| {
+        if on && affected & flag != 0 {
+            status |= flag;
+        }
+    };
+    set(flags::CARRY, carry);
+    set(flags::ZERO, result == 0);
+    set(flags::OVERFLOW, overflow);
+    set(flags::NEGATIVE, result & 0x80 != 0);
+    status
+}
+
+fn reference_adc(a: u8, value: u8, carry: bool) -> (u8, bool, bool) {
+    let unsigned = a as u16 + value as u16 + carry as u16;
+    let signed = a as i8 as i16 + value as i8 as i16 + carry as i16;
+    (
+        unsigned as u8,
+        unsigned > 0xFF,
+        !(-128..=127).contains(&signed),
+    )
+}
+
+fn reference_sbc(a: u8, value: u8, carry: bool) -> (u8, bool, bool) {
+    let borrow = !carry as i16;
+    let unsigned = a as i16 - value as i16 - borrow;
+    let signed = a as i8 as i16 - value as i8 as i16 - borrow;
+    (
+        unsigned as u8,
+        unsigned >= 0,
+        !(-128..=127).contains(&signed),
+    )
+}
+
+/// Shift and rotate opcodes as (accumulator, zero page) forms, with their reference
+type Shift = (u8, u8, fn(u8, bool) -> (u8, bool));
+const SHIFTS: [Shift; 4] = [
+    // ASL
+    (0x0A, 0x06, |v, _| (v << 1, v & 0x80 != 0)),
+    // LSR
+    (0x4A, 0x46, |v, _| (v >> 1, v & 0x01 != 0)),
+    // ROL
+    (0x2A, 0x26, |v, c| ((v << 1) | c as u8, v & 0x80 != 0)),
+    // ROR
+    (0x6A, 0x66, |v, c| {
+        ((v >> 1) | (c as u8) << 7, v & 0x01 != 0)
+    }),
+];
+
+/// Packs 0 to 99 into a BCD byte
+fn bcd(n: u8) -> u8 {
+    ((n / 10) << 4) | (n % 10)
+}
+
+/// Runs `opcode #value` in decimal mode on an NMOS 6502 or a 65C02
+fn execute_decimal(cmos: bool, opcode: u8, a: u8, value: u8, carry: bool) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.set_variant(if cmos {
+        CpuVariant::Cmos65C02
+    } else {
+        CpuVariant::Nmos6502
+    });
+    cpu.load(&[opcode, value]);
+    cpu.reset();
+    cpu.set_registers(Registers {
+        a,
+        status: flags::DECIMAL | carry as u8,
+        ..cpu.registers()
+    });
+    assert!(cpu.step());
+    cpu
+}
+
+proptest! {
+    #[test]
+    fn test_adc(a: u8, value: u8, status: u8) {
+        let cpu = execute(&[0x69, value], a, status);
+        let (result, carry, overflow) = reference_adc(a, value, status & flags::CARRY != 0);
+        prop_assert_eq!(cpu.registers().a, result);
+        prop_assert_eq!(cpu.registers().status, expected_status(status, NVZC, result, carry, overflow));
+    }
+
+    #[test]
+    fn test_sbc(a: u8, value: u8, status: u8) {
+        let cpu = execute(&[0xE9, value], a, status);
+        let (result, carry, overflow) = reference_sbc(a, value, status & flags::CARRY != 0);
+        prop_assert_eq!(cpu.registers().a, result);
+        prop_assert_eq!(cpu.registers().status, expected_status(status, NVZC, result, carry, overflow));
+    }
+
+    #[test]
+    fn test_compare(register: u8, value: u8, status: u8, which in 0..3usize) {
+        // CMP, CPX, CPY immediate
+        let opcode = [0xC9, 0xE0, 0xC0][which];
+        let mut cpu = CPU::new();
+        cpu.load(&[opcode, value]);
+        cpu.reset();
+        cpu.set_registers(Registers {
+            a: register,
+            x: register,
+            y: register,
+            status,
+            ..cpu.registers()
+        });
+        prop_assert!(cpu.step());
+
+        let difference = (register as i16 - value as i16) as u8;
+        let expected = expected_status(status, NZC, difference, register >= value, false);
+        prop_assert_eq!(cpu.registers().status, expected);
+        prop_assert_eq!(cpu.registers().a, register);
+    }
+
+    #[test]
+    fn test_shifts(value: u8, status: u8, which in 0..SHIFTS.len(), memory: bool) {
+        let (accumulator, zero_page, reference) = SHIFTS[which];
+        let (result, carry) = reference(value, status & flags::CARRY != 0);
+        let cpu = if memory {
+            let mut cpu = CPU::new();
+            cpu.load(&[zero_page, OPERAND as u8]);
+            cpu.reset();
+            cpu.mem_write(OPERAND, value);
+            cpu.set_registers(Registers { status, ..cpu.registers() });
+            prop_assert!(cpu.step());
+            prop_assert_eq!(cpu.mem_peek(OPERAND), result);
+            cpu
+        } else {
+            let cpu = execute(&[accumulator], value, status);
+            prop_assert_eq!(cpu.registers().a, result);
+            cpu
+        };
+        prop_assert_eq!(cpu.registers().status, expected_status(status, NZC, result, carry, false));
+    }
+
+    #[test]
+    fn test_decimal_adc(a in 0..100u8, value in 0..100u8, carry: bool, cmos: bool) {
+        let cpu = execute_decimal(cmos, 0x69, bcd(a), bcd(value), carry);
+        let sum = a + value + carry as u8;
+        prop_assert_eq!(cpu.registers().a, bcd(sum % 100));
+        prop_assert_eq!(cpu.registers().status & flags::CARRY != 0, sum > 99);
+    }
+
+    #[test]
+    fn test_decimal_sbc(a in 0..100u8, value in 0..100u8, carry: bool, cmos: bool) {
+        let cpu = execute_decimal(cmos, 0xE9, bcd(a), bcd(value), carry);
+        let difference = a as i16 - value as i16 - !carry as i16;
+        prop_assert_eq!(cpu.registers().a, bcd(difference.rem_euclid(100) as u8));
+        prop_assert_eq!(cpu.registers().status & flags::CARRY != 0, difference >= 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! End-to-end regression tests: recorded input movies played against homebrew test ROMs.
+//!
+//! Each case loads a ROM, plays a `.fm2` movie from `tests/movies` for a fixed number of frames,
+//! and compares the resulting [`Checkpoint`] with one recorded from a known good run. A mismatch
+//! means something changed in the CPU, controller, or frame timing path. When a change is
+//! intentional, run with `BLESS_MOVIES=1` to print the new checkpoints instead of asserting, and
+//! paste them in below.
+
+use nes_emulator::console::Console;
+use nes_emulator::cpu::Mem;
+use nes_emulator::movie::{self, Checkpoint, Movie};
+use nes_emulator::rom::{Rom, PRG_ROM_PAGE_SIZE};
+
+struct MovieTest {
+    name: &'static str,
+    rom: fn() -> Vec<u8>,
+    movie: &'static str,
+    frames: u64,
+    expected: Checkpoint,
+}
+
+const TESTS: &[MovieTest] = &[MovieTest {
+    name: "input_log",
+    rom: input_log_rom,
+    movie: include_str!("movies/input_log.fm2"),
+    frames: 300,
+    expected: Checkpoint {
+        frame: 300,
+        state_hash: 0xf73cfe9e892b1aca,
+        ram_hash: 0x4ffd6009b18914ff,
+        frame_hash: 0x3fd4ebc4ab9ce325,
+    },
+}];
+
+/// Wraps a program assembled at 0x8000 in a 16KB NROM image whose vectors all point at 0x8000
+fn nrom(program: &[u8]) -> Vec<u8> {
+    let mut prg = program.to_vec();
+    prg.resize(PRG_ROM_PAGE_SIZE, 0);
+    prg[PRG_ROM_PAGE_SIZE - 6..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+    let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0];
+    raw.resize(16, 0);
+    raw.extend_from_slice(&prg);
+    raw
+}
+
+/// Polls controller 1 as fast as it can and logs what it sees to zero page:
+///
+/// | Address | |
+/// | :--- | :--- |
+/// | `$00` | buttons from the latest read, A in bit 7 |
+/// | `$01` | buttons from the previous read that differed |
+/// | `$02` | number of times the buttons changed |
+/// | `$03-$04` | 16-bit sum of every read |
+/// | `$05` | number of set bits across all changes |
+/// | `$0200-$02FF` | how many reads saw each button combination |
+///
+/// Reads aren't synced to frames, so the counts depend on exact instruction timing as well as on
+/// the input.
+#[rustfmt::skip]
+fn input_log_rom() -> Vec<u8> {
+    let mut program = vec![0u8; 0x70];
+    let mut put = |addr: usize, bytes: &[u8]| {
+        program[addr - 0x8000..addr - 0x8000 + bytes.len()].copy_from_slice(bytes)
+    };
+    put(0x8000, &[
+        0xA2, 0xFF,       //        LDX #$FF
+        0x9A,             //        TXS
+        0xA9, 0x00,       //        LDA #$00
+        0xA2, 0x00,       //        LDX #$00
+        0x95, 0x00,       // clear: STA $00,X
+        0x9D, 0x00, 0x02, //        STA $0200,X
+        0xE8,             //        INX
+        0xD0, 0xF8,       //        BNE clear
+        0x20, 0x40, 0x80, // loop:  JSR read_pad
+        0xC5, 0x01,       //        CMP $01
+        0xF0, 0x07,       //        BEQ same
+        0x85, 0x01,       //        STA $01
+        0xE6, 0x02,       //        INC $02
+        0x20, 0x60, 0x80, //        JSR count_bits
+        0x18,             // same:  CLC
+        0xA5, 0x03,       //        LDA $03
+        0x65, 0x00,       //        ADC $00
+        0x85, 0x03,       //        STA $03
+        0xA5, 0x04,       //        LDA $04
+        0x69, 0x00,       //        ADC #$00
+        0x85, 0x04,       //        STA $04
+        0xA6, 0x00,       //        LDX $00
+        0xFE, 0x00, 0x02, //        INC $0200,X
+        0x4C, 0x0F, 0x80, //        JMP loop
+    ]);
+    put(0x8040, &[
+        0xA9, 0x01,       // read_pad: LDA #$01
+        0x8D, 0x16, 0x40, //           STA $4016
+        0x4A,             //           LSR A
+        0x8D, 0x16, 0x40, //           STA $4016
+        0xA0, 0x08,       //           LDY #$08
+        0xAD, 0x16, 0x40, // bit:      LDA $4016
+        0x4A,             //           LSR A
+        0x26, 0x00,       //           ROL $00
+        0x88,             //           DEY
+        0xD0, 0xF7,       //           BNE bit
+        0xA5, 0x00,       //           LDA $00
+        0x60,             //           RTS
+    ]);
+    put(0x8060, &[
+        0xA0, 0x08,       // count_bits: LDY #$08
+        0x0A,             // next:       ASL A
+        0x90, 0x02,       //             BCC skip
+        0xE6, 0x05,       //             INC $05
+        0x88,             // skip:       DEY
+        0xD0, 0xF8,       //             BNE next
+        0x60,             //             RTS
+    ]);
+    nrom(&program)
+}
+
+#[test]
+fn test_movies() {
+    let bless = std::env::var_os("BLESS_MOVIES").is_some();
+    let mut failures = Vec::new();
+
+    for test in TESTS {
+        let rom = Rom::new(&(test.rom)()).unwrap();
+        let movie = Movie::parse(test.movie).unwrap();
+        let mut console = Console::new();
+        console.load_rom(&rom).unwrap();
+
+        let checkpoint = movie::play(&mut console, &movie, test.frames);
+        if bless {
+            println!("{}: {checkpoint:#x?}", test.name);
+        } else if checkpoint != test.expected {
+            failures.push(format!(
+                "{}: expected {:#x?}, got {checkpoint:#x?}",
+                test.name, test.expected
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_input_log_sees_the_movie() {
+    let rom = Rom::new(&input_log_rom()).unwrap();
+    let movie = Movie::parse(include_str!("movies/input_log.fm2")).unwrap();
+    let mut console = Console::new();
+    console.load_rom(&rom).unwrap();
+    movie::play(&mut console, &movie, 300);
+
+    let cpu = console.cpu();
+    assert!(!console.is_halted());
+    assert!(cpu.mem_peek(0x02) > 20); // the buttons changed many times
+    assert!(cpu.mem_peek(0x0200) > 0); // some reads saw nothing held
+    assert!(cpu.mem_peek(0x0280) > 0); // and some saw A alone
+    assert_eq!(cpu.mem_peek(0x00), 0x00); // nothing is held on the last frame
+}
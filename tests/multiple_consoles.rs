@@ -0,0 +1,84 @@
+//! Several consoles in one process don't affect each other.
+//!
+//! The crate keeps no mutable global state, so embedders can host any number of emulations side
+//! by side (a netplay relay, a tournament viewer) on one thread or spread over several. Each
+//! console here runs a different cartridge, with different input, and has to end up exactly where
+//! the same console run alone does.
+
+use std::thread;
+
+use nes_emulator::console::Console;
+use nes_emulator::joypad::JoypadButton;
+use nes_emulator::rng::RamInit;
+use nes_emulator::rom::{Rom, PRG_ROM_PAGE_SIZE};
+
+const FRAMES: u64 = 60;
+
+/// A 16KB NROM image that keeps adding controller 1 into zero page address `seed`, so both the
+/// cartridge and the input show in RAM
+#[rustfmt::skip]
+fn cartridge(seed: u8) -> Rom {
+    let mut prg = vec![0; PRG_ROM_PAGE_SIZE];
+    prg[..24].copy_from_slice(&[
+        0xa9, 0x01,       // loop: LDA #$01
+        0x8d, 0x16, 0x40, //       STA $4016
+        0xa9, 0x00,       //       LDA #$00
+        0x8d, 0x16, 0x40, //       STA $4016
+        0xad, 0x16, 0x40, //       LDA $4016
+        0x29, 0x01,       //       AND #$01
+        0x65, seed,       //       ADC seed
+        0x85, seed,       //       STA seed
+        0xe6, 0x80,       //       INC $80
+        0x4c, 0x00, 0x80, //       JMP loop
+    ]);
+    prg[PRG_ROM_PAGE_SIZE - 6..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0];
+    raw.resize(16, 0);
+    raw.extend_from_slice(&prg);
+    Rom::new(&raw).unwrap()
+}
+
+fn console(seed: u8) -> Console {
+    let mut console = Console::new();
+    console.set_seed(seed as u64);
+    console.set_ram_init(RamInit::Random);
+    console.load_rom(&cartridge(seed)).unwrap();
+    console.hold_input(0..=FRAMES, 0, JoypadButton::BUTTON_A);
+    console.hold_input(seed as u64..=seed as u64 + 10, 0, JoypadButton::empty());
+    console
+}
+
+/// State hash of console `seed` run on its own
+fn alone(seed: u8) -> u64 {
+    let mut console = console(seed);
+    for _ in 0..FRAMES {
+        console.run_frame();
+    }
+    console.state_hash()
+}
+
+#[test]
+fn test_interleaved_consoles_match_running_alone() {
+    let seeds = [1, 2, 3];
+    let expected = seeds.map(alone);
+    assert!(expected[0] != expected[1] && expected[1] != expected[2]);
+
+    let mut consoles: Vec<_> = seeds.into_iter().map(console).collect();
+    for _ in 0..FRAMES {
+        for console in &mut consoles {
+            console.run_frame();
+        }
+    }
+    let hashes: Vec<_> = consoles.iter().map(Console::state_hash).collect();
+    assert_eq!(hashes, expected);
+}
+
+#[test]
+fn test_consoles_on_threads_match_running_alone() {
+    let seeds = [4, 5, 6, 7];
+    let hashes = thread::scope(|scope| {
+        let handles = seeds.map(|seed| scope.spawn(move || alone(seed)));
+        handles.map(|handle| handle.join().unwrap())
+    });
+    assert_eq!(hashes, seeds.map(alone));
+}
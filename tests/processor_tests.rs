@@ -0,0 +1,230 @@
+//! Runner for the [ProcessorTests](https://github.com/SingleStepTests/ProcessorTests) 6502 suites.
+//!
+//! Every case sets up the registers and memory, executes one instruction, and compares the final
+//! registers, memory and the exact sequence of bus reads and writes, one per cycle. The suites are
+//! too big to bundle, so this runs a small sample from `tests/processor_tests` plus, when
+//! `PROCESSOR_TESTS` points at a checkout's `nes6502/v1` directory, every `.json` file in it:
+//!
+//! ```text
+//! PROCESSOR_TESTS=../ProcessorTests/nes6502/v1 cargo test --features processor-tests --test processor_tests
+//! ```
+//!
+//! The `6502/v1` suites, which include decimal mode, run against [`CpuVariant::Nmos6502`] when
+//! `PROCESSOR_TESTS_CHIP=6502` is set as well.
+//!
+//! Skipped cases:
+//!
+//! - Unofficial opcodes, which the CPU doesn't implement
+//! - Cases touching the PPU registers at `0x2000..=0x3FFF`, the APU registers and OAM DMA at
+//!   `0x4000..=0x4015`, or the controller ports at `0x4016` and `0x4017`, which aren't plain memory
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nes_emulator::bus::BusAccess;
+use nes_emulator::cpu::{CpuVariant, Mem, Registers, CPU};
+use nes_emulator::opcodes;
+use serde_json::Value;
+
+/// Failures printed per file before summarizing the rest
+const MAX_REPORTED: usize = 5;
+
+const IO_PORTS: [u16; 2] = [0x4016, 0x4017];
+const PPU_REGISTERS: std::ops::RangeInclusive<u16> = 0x2000..=0x3FFF;
+/// APU registers, with OAM DMA at `0x4014` among them
+const APU_REGISTERS: std::ops::RangeInclusive<u16> = 0x4000..=0x4015;
+
+fn is_io(addr: u16) -> bool {
+    PPU_REGISTERS.contains(&addr) || APU_REGISTERS.contains(&addr) || IO_PORTS.contains(&addr)
+}
+
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn parse(value: &Value) -> State {
+        let field = |name: &str| value[name].as_u64().unwrap_or_else(|| panic!("no {name}"));
+        let ram = value["ram"]
+            .as_array()
+            .expect("no ram")
+            .iter()
+            .map(|entry| {
+                (
+                    entry[0].as_u64().unwrap() as u16,
+                    entry[1].as_u64().unwrap() as u8,
+                )
+            })
+            .collect();
+        State {
+            pc: field("pc") as u16,
+            s: field("s") as u8,
+            a: field("a") as u8,
+            x: field("x") as u8,
+            y: field("y") as u8,
+            p: field("p") as u8,
+            ram,
+        }
+    }
+}
+
+fn parse_cycles(value: &Value) -> Vec<BusAccess> {
+    value
+        .as_array()
+        .expect("no cycles")
+        .iter()
+        .map(|cycle| BusAccess {
+            addr: cycle[0].as_u64().unwrap() as u16,
+            value: cycle[1].as_u64().unwrap() as u8,
+            write: cycle[2] == "write",
+        })
+        .collect()
+}
+
+/// Runs one case, returning what differed
+fn run_case(
+    variant: CpuVariant,
+    initial: &State,
+    expected: &State,
+    cycles: &[BusAccess],
+) -> Vec<String> {
+    let mut cpu = CPU::new();
+    cpu.set_variant(variant);
+    for &(addr, value) in &initial.ram {
+        cpu.bus_mut().load(addr, &[value]);
+    }
+    cpu.set_registers(Registers {
+        a: initial.a,
+        x: initial.x,
+        y: initial.y,
+        status: initial.p,
+        sp: initial.s,
+        pc: initial.pc,
+    });
+
+    cpu.bus_mut().start_trace();
+    cpu.step();
+    let trace = cpu.bus_mut().take_trace();
+    let registers = cpu.registers();
+
+    let mut diffs = Vec::new();
+    let mut check = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            diffs.push(format!("{name} {actual:#x}, expected {expected:#x}"));
+        }
+    };
+    check("pc", registers.pc, expected.pc);
+    check("s", registers.sp as u16, expected.s as u16);
+    check("a", registers.a as u16, expected.a as u16);
+    check("x", registers.x as u16, expected.x as u16);
+    check("y", registers.y as u16, expected.y as u16);
+    check("p", registers.status as u16, expected.p as u16);
+    check("cycles", cpu.cycles() as u16, cycles.len() as u16);
+    for &(addr, value) in &expected.ram {
+        check(
+            &format!("[{addr:#06x}]"),
+            cpu.mem_peek(addr) as u16,
+            value as u16,
+        );
+    }
+    let mismatch = (0..trace.len().max(cycles.len())).find(|&i| trace.get(i) != cycles.get(i));
+    if let Some(i) = mismatch {
+        diffs.push(format!(
+            "cycle {i} {}, expected {}",
+            describe(trace.get(i)),
+            describe(cycles.get(i))
+        ));
+    }
+    diffs
+}
+
+fn describe(access: Option<&BusAccess>) -> String {
+    match access {
+        Some(BusAccess { addr, value, write }) => {
+            let kind = if *write { "write" } else { "read" };
+            format!("{kind} {value:#04x} at {addr:#06x}")
+        }
+        None => "nothing".to_string(),
+    }
+}
+
+/// Runs every case in a suite file, returning the number run and the failure messages
+fn run_file(path: &Path, variant: CpuVariant) -> (usize, Vec<String>) {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+    let cases: Vec<Value> = serde_json::from_str(&text).unwrap();
+
+    let mut run = 0;
+    let mut failures = Vec::new();
+    for case in &cases {
+        let initial = State::parse(&case["initial"]);
+        let expected = State::parse(&case["final"]);
+        let cycles = parse_cycles(&case["cycles"]);
+
+        let opcode = initial
+            .ram
+            .iter()
+            .find(|(addr, _)| *addr == initial.pc)
+            .map_or(0, |(_, value)| *value);
+        let touches_io = initial.ram.iter().any(|&(addr, _)| is_io(addr))
+            || cycles.iter().any(|cycle| is_io(cycle.addr));
+        if opcodes::lookup_for(variant, opcode).is_none() || touches_io {
+            continue;
+        }
+
+        run += 1;
+        let diffs = run_case(variant, &initial, &expected, &cycles);
+        if !diffs.is_empty() {
+            failures.push(format!("{}: {}", case["name"], diffs.join("; ")));
+        }
+    }
+    (run, failures)
+}
+
+fn suite_files() -> Vec<PathBuf> {
+    let mut files =
+        vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/processor_tests/sample.json")];
+    if let Some(dir) = std::env::var_os("PROCESSOR_TESTS") {
+        let mut suite: Vec<_> = fs::read_dir(&dir)
+            .unwrap_or_else(|err| panic!("PROCESSOR_TESTS: {err}"))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        suite.sort();
+        files.extend(suite);
+    }
+    files
+}
+
+#[test]
+fn test_processor_tests() {
+    let variant = match std::env::var("PROCESSOR_TESTS_CHIP").as_deref() {
+        Ok("6502") => CpuVariant::Nmos6502,
+        Ok("nes6502") | Err(_) => CpuVariant::Ricoh2A03,
+        Ok(chip) => panic!("PROCESSOR_TESTS_CHIP: unknown chip {chip:?}"),
+    };
+
+    let mut total = 0;
+    let mut report = Vec::new();
+    for path in suite_files() {
+        let (run, failures) = run_file(&path, variant);
+        total += run;
+        if failures.is_empty() {
+            continue;
+        }
+        report.push(format!(
+            "{}: {} of {run} failed",
+            path.display(),
+            failures.len()
+        ));
+        report.extend(failures.iter().take(MAX_REPORTED).map(|f| format!("  {f}")));
+    }
+
+    assert!(total > 0, "no cases ran");
+    assert!(report.is_empty(), "\n{}", report.join("\n"));
+}